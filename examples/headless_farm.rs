@@ -0,0 +1,77 @@
+// Runs many headless instances across threads and collects their RAM.
+//
+//   cargo run --release --example headless_farm -- <ROM> [instances] [threads] [frames]
+//
+// Instances are varied by the power-on RAM pattern and random inputs.
+use std::collections::HashSet;
+use std::env;
+use std::thread;
+
+use rustnes::{Button, RAMPattern, NES, ROM};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <ROM> [instances] [threads] [frames]", args[0]);
+        std::process::exit(1);
+    }
+    let arg =
+        |i: usize, default: usize| args.get(i).and_then(|a| a.parse().ok()).unwrap_or(default);
+    let instances = arg(2, 64);
+    let threads = arg(3, 4).max(1);
+    let frames = arg(4, 600);
+
+    let data = std::fs::read(&args[1])?;
+    let mut queues: Vec<Vec<(usize, NES)>> = (0..threads).map(|_| Vec::new()).collect();
+    for i in 0..instances {
+        let mut nes = NES::default();
+        nes.set_ram_pattern(RAMPattern::Random(i as u64 + 1));
+        nes.load(ROM::from_bytes(&data)?)?;
+        nes.power_on();
+        nes.reset();
+        queues[i % threads].push((i, nes));
+    }
+
+    let workers: Vec<_> = queues
+        .into_iter()
+        .map(|queue| {
+            thread::spawn(move || {
+                let mut observations = Vec::new();
+                for (i, mut nes) in queue {
+                    let mut input = i as u32 + 1;
+                    for _ in 0..frames {
+                        // xorshift32
+                        input ^= input << 13;
+                        input ^= input >> 17;
+                        input ^= input << 5;
+                        nes.set_controller(0, Button::from_bits(input as u8));
+                        nes.frame();
+                    }
+                    observations.push((i, nes.ram()));
+                }
+                observations
+            })
+        })
+        .collect();
+
+    let mut observations: Vec<(usize, Vec<u8>)> = workers
+        .into_iter()
+        .flat_map(|w| w.join().unwrap())
+        .collect();
+    observations.sort_by_key(|(i, _)| *i);
+
+    for (i, ram) in &observations {
+        let checksum = ram
+            .iter()
+            .fold(0u32, |s, b| s.wrapping_mul(31).wrapping_add(*b as u32));
+        println!("instance {:>4}: RAM checksum {:08X}", i, checksum);
+    }
+    let distinct: HashSet<&Vec<u8>> = observations.iter().map(|(_, ram)| ram).collect();
+    println!(
+        "{} instances, {} frames each, {} distinct RAM states",
+        observations.len(),
+        frames,
+        distinct.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,598 @@
+mod dmc;
+mod envelope;
+mod filter;
+mod mixer;
+mod noise;
+mod pulse;
+mod resampler;
+mod triangle;
+
+use std::io;
+
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory};
+
+use dmc::DMC;
+use filter::OutputFilter;
+use mixer::Mixer;
+pub use mixer::MixerMode;
+use noise::Noise;
+use pulse::{Pulse, PulseChannel};
+use resampler::Resampler;
+use triangle::Triangle;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Sound channels of the APU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+    /// Expansion audio of the cartridge, such as the FM channels of VRC7.
+    Expansion,
+}
+
+impl Channel {
+    pub const ALL: [Self; 6] = [
+        Self::Pulse1,
+        Self::Pulse2,
+        Self::Triangle,
+        Self::Noise,
+        Self::DMC,
+        Self::Expansion,
+    ];
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+// Position of the audio output taken by `APU::snapshot`. The state of the channels is taken by
+// `APU::save_state`.
+#[derive(Clone)]
+pub(crate) struct APUSnapshot {
+    resampler: Resampler,
+}
+
+pub struct APU {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+
+    frame_counter: FrameCounter,
+    frame_interrupted: bool,
+
+    // CPU cycles
+    cycles: u64,
+    region: Region,
+
+    mixer: Mixer,
+    // output of the cartridge, set every CPU cycle
+    expansion_output: f32,
+    // bit flags of Channel, only affects the output
+    enabled_channels: u8,
+    resampler: Resampler,
+    output_filter: OutputFilter,
+    filter_enabled: bool,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        Self {
+            pulse1: Pulse::new(PulseChannel::One),
+            pulse2: Pulse::new(PulseChannel::Two),
+            triangle: Default::default(),
+            noise: Default::default(),
+            dmc: Default::default(),
+            frame_counter: Default::default(),
+            frame_interrupted: false,
+            cycles: 0,
+            region: Default::default(),
+            mixer: Default::default(),
+            expansion_output: 0.0,
+            enabled_channels: 0b111111,
+            resampler: Resampler::new(Region::default().cpu_clock_rate(), DEFAULT_SAMPLE_RATE),
+            output_filter: OutputFilter::new(DEFAULT_SAMPLE_RATE),
+            filter_enabled: true,
+        }
+    }
+
+    // carries over host-side settings when the APU is rebuilt for a new ROM
+    pub fn inherit_settings(&mut self, other: &APU) {
+        self.set_sample_rate(other.sample_rate());
+        self.filter_enabled = other.filter_enabled;
+        self.mixer.mode = other.mixer.mode;
+        self.enabled_channels = other.enabled_channels;
+    }
+
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0x00.into());
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.set_sample_rate(self.sample_rate());
+    }
+
+    // 0 is taken as 1, the step of the resampler being infinite otherwise
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let sample_rate = sample_rate.max(1);
+        self.resampler = Resampler::new(self.region.cpu_clock_rate(), sample_rate);
+        self.output_filter = OutputFilter::new(sample_rate);
+    }
+
+    pub fn adjust_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_output_rate(sample_rate.max(1));
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.output_rate()
+    }
+
+    /// Returns the number of samples produced so far at the host sample rate.
+    pub fn samples_produced(&self) -> u64 {
+        self.resampler.produced()
+    }
+
+    /// Returns the fraction (0.0-1.0) of the next sample accumulated so far.
+    pub fn sample_remainder(&self) -> f64 {
+        self.resampler.remainder()
+    }
+
+    pub fn set_mixer_mode(&mut self, mode: MixerMode) {
+        self.mixer.mode = mode;
+    }
+
+    pub fn mixer_mode(&self) -> MixerMode {
+        self.mixer.mode
+    }
+
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        if enabled {
+            self.enabled_channels |= channel.bit();
+        } else {
+            self.enabled_channels &= !channel.bit();
+        }
+    }
+
+    pub fn channel_enabled(&self, channel: Channel) -> bool {
+        self.enabled_channels & channel.bit() != 0
+    }
+
+    pub fn set_filter_enabled(&mut self, enabled: bool) {
+        self.filter_enabled = enabled;
+    }
+
+    pub fn filter_enabled(&self) -> bool {
+        self.filter_enabled
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        let mut samples = self.resampler.take();
+        if self.filter_enabled {
+            for s in samples.iter_mut() {
+                *s = self.output_filter.process(*s);
+            }
+        }
+        samples
+    }
+
+    /// The state of the channels and the frame counter, apart from the settings, for save
+    /// states.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        self.pulse1.save_state(&mut w);
+        self.pulse2.save_state(&mut w);
+        self.triangle.save_state(&mut w);
+        self.noise.save_state(&mut w);
+        self.dmc.save_state(&mut w);
+        self.frame_counter.save_state(&mut w);
+        w.bool(self.frame_interrupted);
+        w.u64(self.cycles);
+        w.f32(self.expansion_output);
+        w.into_bytes()
+    }
+
+    /// Restores the state written by `APU::save_state`.
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut r = StateReader::new(data);
+        self.pulse1.load_state(&mut r)?;
+        self.pulse2.load_state(&mut r)?;
+        self.triangle.load_state(&mut r)?;
+        self.noise.load_state(&mut r)?;
+        self.dmc.load_state(&mut r)?;
+        self.frame_counter.load_state(&mut r)?;
+        self.frame_interrupted = r.bool()?;
+        self.cycles = r.u64()?;
+        self.expansion_output = r.f32()?;
+        Ok(())
+    }
+
+    /// Takes the position of the audio output, for run-ahead and rollback.
+    pub fn snapshot(&self) -> APUSnapshot {
+        APUSnapshot {
+            resampler: self.resampler.clone(),
+        }
+    }
+
+    /// Goes back to the position of `APU::snapshot`, dropping the samples produced since that
+    /// are not taken yet.
+    pub fn restore(&mut self, s: &APUSnapshot) {
+        self.resampler.restore(&s.resampler);
+    }
+
+    /// Drops the samples not taken yet that were produced after the first `produced` in total.
+    pub fn drop_samples_since(&mut self, produced: u64) {
+        self.resampler.drop_output_since(produced);
+    }
+
+    pub fn set_expansion_output(&mut self, output: f32) {
+        self.expansion_output = output;
+    }
+
+    /// Returns true while the frame counter or DMC asserts the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.frame_interrupted || self.dmc.interrupted
+    }
+
+    // clocked every CPU cycle, on the bus of the DMC memory reader
+    pub fn step<M: Memory>(&mut self, bus: &mut M) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer(bus);
+        if self.cycles % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        let (step, interrupt) = self.frame_counter.step();
+        if interrupt {
+            self.frame_interrupted = true;
+        }
+        match step {
+            FrameStep::Quarter => self.clock_quarter_frame(),
+            FrameStep::Half => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            FrameStep::None => {}
+        }
+
+        let sample = self.mix();
+        self.resampler.push(sample);
+
+        self.cycles = self.cycles.wrapping_add(1);
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    fn mix(&self) -> f32 {
+        self.mixer.mix(
+            self.channel_output(Channel::Pulse1, self.pulse1.output()),
+            self.channel_output(Channel::Pulse2, self.pulse2.output()),
+            self.channel_output(Channel::Triangle, self.triangle.output()),
+            self.channel_output(Channel::Noise, self.noise.output()),
+            self.channel_output(Channel::DMC, self.dmc.output()),
+        ) + if self.channel_enabled(Channel::Expansion) {
+            self.expansion_output
+        } else {
+            0.0
+        }
+    }
+
+    fn channel_output(&self, channel: Channel, output: u8) -> u8 {
+        if self.channel_enabled(channel) {
+            output
+        } else {
+            0
+        }
+    }
+}
+
+// register access from CPU
+impl APU {
+    pub fn read_status(&mut self) -> Byte {
+        // IF-D NT21
+        let mut value = 0u8;
+        if self.pulse1.length_counter.active() {
+            value |= 0b00000001;
+        }
+        if self.pulse2.length_counter.active() {
+            value |= 0b00000010;
+        }
+        if self.triangle.length_counter.active() {
+            value |= 0b00000100;
+        }
+        if self.noise.length_counter.active() {
+            value |= 0b00001000;
+        }
+        if self.dmc.active() {
+            value |= 0b00010000;
+        }
+        if self.frame_interrupted {
+            value |= 0b01000000;
+        }
+        if self.dmc.interrupted {
+            value |= 0b10000000;
+        }
+        // reading clears the frame interrupt flag, but not the DMC interrupt flag
+        self.frame_interrupted = false;
+        value.into()
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: Byte) {
+        let value: u8 = value.into();
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr, value),
+            0x4004..=0x4007 => self.pulse2.write(addr, value),
+            0x4008..=0x400B => self.triangle.write(addr, value),
+            0x400C..=0x400F => self.noise.write(addr, value),
+            0x4010..=0x4013 => self.dmc.write(addr, value),
+            0x4015 => {
+                // ---D NT21
+                self.pulse1.length_counter.set_enabled(value & 0b00001 != 0);
+                self.pulse2.length_counter.set_enabled(value & 0b00010 != 0);
+                self.triangle
+                    .length_counter
+                    .set_enabled(value & 0b00100 != 0);
+                self.noise.length_counter.set_enabled(value & 0b01000 != 0);
+                self.dmc.set_enabled(value & 0b10000 != 0);
+                self.dmc.interrupted = false;
+            }
+            0x4017 => {
+                // MI-- ----
+                // the write happened on the previous cycle
+                self.frame_counter
+                    .write(value, self.cycles.is_multiple_of(2));
+                if self.frame_counter.irq_inhibit {
+                    self.frame_interrupted = false;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+struct FrameCounter {
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    cycles: u32,
+
+    // the mode written to $4017 and CPU cycles until it takes effect
+    next_five_step_mode: bool,
+    reset_delay: u8,
+}
+
+enum FrameStep {
+    None,
+    Quarter,
+    Half,
+}
+
+impl FrameCounter {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.five_step_mode);
+        w.bool(self.irq_inhibit);
+        w.u32(self.cycles);
+        w.bool(self.next_five_step_mode);
+        w.u8(self.reset_delay);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.five_step_mode = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.cycles = r.u32()?;
+        self.next_five_step_mode = r.bool()?;
+        self.reset_delay = r.u8()?;
+        Ok(())
+    }
+
+    fn write(&mut self, value: u8, apu_cycle: bool) {
+        self.irq_inhibit = value & 0b01000000 != 0;
+        self.next_five_step_mode = value & 0b10000000 != 0;
+        // The sequencer is reset 3 CPU cycles after the write during an APU cycle,
+        // and 4 CPU cycles after the write between APU cycles
+        self.reset_delay = if apu_cycle { 3 } else { 4 };
+    }
+
+    // returns the sequencer step and whether the frame interrupt flag should be set
+    fn step(&mut self) -> (FrameStep, bool) {
+        if 0 < self.reset_delay {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.five_step_mode = self.next_five_step_mode;
+                self.cycles = 0;
+                // quarter and half frame are clocked immediately in 5-step mode
+                if self.five_step_mode {
+                    return (FrameStep::Half, false);
+                }
+            }
+        }
+
+        self.cycles += 1;
+        let (step, interrupt) = match (self.cycles, self.five_step_mode) {
+            (7457, _) => (FrameStep::Quarter, false),
+            (14913, _) => (FrameStep::Half, false),
+            (22371, _) => (FrameStep::Quarter, false),
+            // the frame interrupt flag is set on the last 3 cycles of the 4-step sequence
+            (29828, false) => (FrameStep::None, true),
+            (29829, false) => (FrameStep::Half, true),
+            (29830, false) => {
+                self.cycles = 0;
+                (FrameStep::None, true)
+            }
+            (37281, true) => (FrameStep::Half, false),
+            (37282, true) => {
+                self.cycles = 0;
+                (FrameStep::None, false)
+            }
+            _ => (FrameStep::None, false),
+        };
+        (step, interrupt && !self.irq_inhibit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_map::APUBus;
+
+    fn new_apu() -> APU {
+        APU::new()
+    }
+
+    // without a cartridge for the DMC to read
+    fn step(apu: &mut APU) {
+        apu.step(&mut APUBus { mapper: None });
+    }
+
+    #[test]
+    fn channel_enabled() {
+        let mut apu = new_apu();
+        apu.write_register(0x4015, 0b00000100.into());
+        apu.write_register(0x4008, 0b01111111.into());
+        apu.write_register(0x400B, 0b00001000.into());
+        for _ in 0..7457 {
+            step(&mut apu);
+        }
+        // triangle starts at the highest level
+        assert!(0.0 < apu.mix());
+
+        apu.set_channel_enabled(Channel::Triangle, false);
+        assert!(!apu.channel_enabled(Channel::Triangle));
+        assert!(apu.channel_enabled(Channel::Pulse1));
+        assert_eq!(apu.mix(), 0.0);
+    }
+
+    #[test]
+    fn zero_sample_rate() {
+        let mut apu = new_apu();
+        apu.set_sample_rate(0);
+        assert_eq!(apu.sample_rate(), 1);
+        apu.write_register(0x4015, 0b00000100.into());
+        apu.write_register(0x4008, 0b01111111.into());
+        apu.write_register(0x400B, 0b00001000.into());
+        for _ in 0..(2 * 1789773) {
+            step(&mut apu);
+        }
+        let samples = apu.take_samples();
+        assert!(!samples.is_empty());
+        assert!(samples.iter().all(|s| s.is_finite()));
+    }
+
+    #[test]
+    fn status_length_counters() {
+        let mut apu = new_apu();
+
+        // disabled channels don't load the length counter
+        apu.write_register(0x4003, 0b00001000.into());
+        assert_eq!(apu.read_status(), 0x00.into());
+
+        apu.write_register(0x4015, 0b00001101.into());
+        apu.write_register(0x4003, 0b00001000.into());
+        apu.write_register(0x400B, 0b00001000.into());
+        apu.write_register(0x400F, 0b00001000.into());
+        assert_eq!(apu.read_status(), 0b00001101.into());
+
+        apu.write_register(0x4015, 0b00000001.into());
+        assert_eq!(apu.read_status(), 0b00000001.into());
+    }
+
+    #[test]
+    fn status_frame_interrupt() {
+        let mut apu = new_apu();
+        for _ in 0..29828 {
+            step(&mut apu);
+        }
+        assert_eq!(apu.read_status(), 0b01000000.into());
+        // cleared by reading, but set again on the following cycles
+        assert_eq!(apu.read_status(), 0x00.into());
+        step(&mut apu);
+        assert_eq!(apu.read_status(), 0b01000000.into());
+
+        // 5-step mode never sets the flag, after the 4-step sequence ends by the write
+        apu.write_register(0x4017, 0b10000000.into());
+        for _ in 0..4 {
+            step(&mut apu);
+        }
+        apu.read_status();
+        for _ in 0..37282 * 2 {
+            step(&mut apu);
+        }
+        assert_eq!(apu.read_status(), 0x00.into());
+    }
+
+    #[test]
+    fn frame_irq_inhibit() {
+        let mut apu = new_apu();
+        for _ in 0..29829 {
+            step(&mut apu);
+        }
+        assert!(apu.irq());
+
+        // setting the inhibit flag clears the interrupt flag
+        apu.write_register(0x4017, 0b01000000.into());
+        assert!(!apu.irq());
+        for _ in 0..29830 * 2 {
+            step(&mut apu);
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn frame_counter_reset_delay() {
+        let mut apu = new_apu();
+        step(&mut apu);
+        // written between APU cycles
+        apu.write_register(0x4017, 0b00000000.into());
+        for _ in 0..3 {
+            step(&mut apu);
+        }
+        assert_eq!(apu.frame_counter.cycles, 4);
+        step(&mut apu);
+        assert_eq!(apu.frame_counter.cycles, 1);
+
+        // 5-step mode clocks the half frame when the sequencer is reset
+        apu.write_register(0x4015, 0b00000001.into());
+        apu.write_register(0x4003, 0b00001000.into());
+        step(&mut apu);
+        // written during an APU cycle
+        apu.write_register(0x4017, 0b10000000.into());
+        for _ in 0..2 {
+            step(&mut apu);
+        }
+        assert_eq!(apu.pulse1.length_counter.count, 254);
+        step(&mut apu);
+        assert_eq!(apu.pulse1.length_counter.count, 253);
+    }
+}
@@ -0,0 +1,213 @@
+use std::io;
+
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Memory, Word};
+
+// https://wiki.nesdev.com/w/index.php/APU_DMC
+const NTSC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Clone)]
+pub(super) struct DMC {
+    irq_enabled: bool,
+    loop_flag: bool,
+    pub(super) interrupted: bool,
+
+    timer: u16,
+    timer_period: u16,
+    rate_table: &'static [u16; 16],
+
+    // Memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8,
+}
+
+impl Default for DMC {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            loop_flag: false,
+            interrupted: false,
+            timer: 0,
+            timer_period: NTSC_RATE_TABLE[0],
+            rate_table: &NTSC_RATE_TABLE,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
+        }
+    }
+}
+
+impl DMC {
+    pub fn set_region(&mut self, region: Region) {
+        self.rate_table = match region {
+            Region::NTSC => &NTSC_RATE_TABLE,
+            Region::PAL => &PAL_RATE_TABLE,
+        };
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr & 0b11 {
+            0 => {
+                // IL-- RRRR
+                self.irq_enabled = value & 0b10000000 != 0;
+                if !self.irq_enabled {
+                    self.interrupted = false;
+                }
+                self.loop_flag = value & 0b01000000 != 0;
+                self.timer_period = self.rate_table[(value & 0b1111) as usize];
+            }
+            1 => {
+                // -DDD DDDD
+                self.output_level = value & 0b01111111;
+            }
+            2 => {
+                // AAAA AAAA
+                self.sample_address = 0xC000 | ((value as u16) << 6);
+            }
+            3 => {
+                // LLLL LLLL
+                self.sample_length = ((value as u16) << 4) | 1;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        0 < self.bytes_remaining
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // clocked every CPU cycle, the rate table is in CPU cycles
+    pub fn clock_timer(&mut self, bus: &mut dyn Memory) {
+        self.fill_sample_buffer(bus);
+
+        if 0 < self.timer {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period.wrapping_sub(1);
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if 2 <= self.output_level {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        if 0 < self.bits_remaining {
+            self.bits_remaining -= 1;
+        }
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn fill_sample_buffer(&mut self, bus: &mut dyn Memory) {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 {
+            return;
+        }
+
+        self.sample_buffer = Some(bus.read(Word::from(self.current_address)).into());
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupted = true;
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    // the rate table is of the region, set apart from the state
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.irq_enabled);
+        w.bool(self.loop_flag);
+        w.bool(self.interrupted);
+        w.u16(self.timer);
+        w.u16(self.timer_period);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u16(self.current_address);
+        w.u16(self.bytes_remaining);
+        w.bool(self.sample_buffer.is_some());
+        w.u8(self.sample_buffer.unwrap_or(0));
+        w.u8(self.shift_register);
+        w.u8(self.bits_remaining);
+        w.bool(self.silence);
+        w.u8(self.output_level);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.irq_enabled = r.bool()?;
+        self.loop_flag = r.bool()?;
+        self.interrupted = r.bool()?;
+        self.timer = r.u16()?;
+        self.timer_period = r.u16()?;
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_address = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        let buffered = r.bool()?;
+        let sample = r.u8()?;
+        self.sample_buffer = if buffered { Some(sample) } else { None };
+        self.shift_register = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        self.silence = r.bool()?;
+        self.output_level = r.u8()? & 0x7F;
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Envelope
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub(super) struct Envelope {
+    start: bool,
+    divider: u8,
+    decay_level: u8,
+
+    pub(super) loop_flag: bool,
+    constant_volume: bool,
+    volume: u8,
+}
+
+impl Envelope {
+    // --LC VVVV
+    pub fn write(&mut self, value: u8) {
+        self.loop_flag = value & 0b00100000 != 0;
+        self.constant_volume = value & 0b00010000 != 0;
+        self.volume = value & 0b1111;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if 0 < self.divider {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.volume;
+        if 0 < self.decay_level {
+            self.decay_level -= 1;
+        } else if self.loop_flag {
+            self.decay_level = 15;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.start);
+        w.u8(self.divider);
+        w.u8(self.decay_level);
+        w.bool(self.loop_flag);
+        w.bool(self.constant_volume);
+        w.u8(self.volume);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.start = r.bool()?;
+        self.divider = r.u8()?;
+        self.decay_level = r.u8()? & 0x0F;
+        self.loop_flag = r.bool()?;
+        self.constant_volume = r.bool()?;
+        self.volume = r.u8()? & 0x0F;
+        Ok(())
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub(super) struct LengthCounter {
+    enabled: bool,
+    pub(super) halt: bool,
+    pub(super) count: u8,
+}
+
+impl LengthCounter {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.count = 0;
+        }
+    }
+
+    // LLLL L---
+    pub fn reload(&mut self, value: u8) {
+        if self.enabled {
+            self.count = LENGTH_TABLE[(value >> 3) as usize];
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if !self.halt && 0 < self.count {
+            self.count -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        0 < self.count
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bool(self.halt);
+        w.u8(self.count);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.enabled = r.bool()?;
+        self.halt = r.bool()?;
+        self.count = r.u8()?;
+        Ok(())
+    }
+}
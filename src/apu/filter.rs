@@ -0,0 +1,103 @@
+use std::f32::consts::PI;
+
+// https://wiki.nesdev.com/w/index.php/APU_Mixer
+//
+// The NES hardware follows the DACs with a surprisingly involved circuit that adds several
+// low-pass and high-pass filters:
+// - A first-order high-pass filter at 90 Hz
+// - Another first-order high-pass filter at 440 Hz
+// - A first-order low-pass filter at 14 kHz
+pub(super) struct OutputFilter {
+    filters: [Filter; 3],
+}
+
+impl OutputFilter {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            filters: [
+                Filter::high_pass(sample_rate, 90.0),
+                Filter::high_pass(sample_rate, 440.0),
+                Filter::low_pass(sample_rate, 14_000.0),
+            ],
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(sample, |sample, f| f.process(sample))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl Filter {
+    fn new(kind: FilterKind, alpha: f32) -> Self {
+        Self {
+            kind,
+            alpha,
+            prev_input: 0.0,
+            prev_output: 0.0,
+        }
+    }
+
+    fn high_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Self::new(FilterKind::HighPass, rc / (rc + dt))
+    }
+
+    fn low_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Self::new(FilterKind::LowPass, dt / (rc + dt))
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_output + input - self.prev_input),
+            FilterKind::LowPass => self.prev_output + self.alpha * (input - self.prev_output),
+        };
+        self.prev_input = input;
+        self.prev_output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_dc_offset() {
+        let mut f = OutputFilter::new(44_100);
+        let mut last = 1.0;
+        for _ in 0..44_100 {
+            last = f.process(0.5);
+        }
+        assert!(last.abs() < 1e-3);
+    }
+
+    #[test]
+    fn low_pass_settles() {
+        let mut f = Filter::low_pass(44_100.0, 14_000.0);
+        let mut last = 0.0;
+        for _ in 0..100 {
+            last = f.process(1.0);
+        }
+        assert!((last - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,74 @@
+// https://wiki.nesdev.com/w/index.php/APU_Mixer
+
+/// How the channel outputs are combined into a single sample.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum MixerMode {
+    /// Lookup tables of the non-linear DAC formula, same as the hardware.
+    #[default]
+    NonLinear,
+    /// Linear approximation of the DAC, slightly cheaper.
+    Linear,
+}
+
+pub(super) struct Mixer {
+    pub(super) mode: MixerMode,
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, e) in pulse_table.iter_mut().enumerate().skip(1) {
+            *e = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (n, e) in tnd_table.iter_mut().enumerate().skip(1) {
+            *e = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Self {
+            mode: Default::default(),
+            pulse_table,
+            tnd_table,
+        }
+    }
+}
+
+impl Mixer {
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        match self.mode {
+            MixerMode::NonLinear => {
+                let pulse_out = self.pulse_table[(pulse1 + pulse2) as usize];
+                let tnd_out =
+                    self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+                pulse_out + tnd_out
+            }
+            MixerMode::Linear => {
+                let pulse_out = 0.00752 * (pulse1 + pulse2) as f32;
+                let tnd_out =
+                    0.00851 * triangle as f32 + 0.00494 * noise as f32 + 0.00335 * dmc as f32;
+                pulse_out + tnd_out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix() {
+        let mut mixer = Mixer::default();
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+
+        let max = mixer.mix(15, 15, 15, 15, 127);
+        assert!((max - 1.0).abs() < 0.001);
+        assert!(mixer.mix(15, 15, 15, 15, 126) < max);
+
+        mixer.mode = MixerMode::Linear;
+        assert!((mixer.mix(1, 0, 0, 0, 0) - 0.00752).abs() < 1e-6);
+    }
+}
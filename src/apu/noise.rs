@@ -0,0 +1,119 @@
+use std::io;
+
+use super::envelope::{Envelope, LengthCounter};
+use crate::region::Region;
+use crate::savestate::{StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Noise
+const NTSC_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Clone)]
+pub(super) struct Noise {
+    shift_register: u16,
+    mode: bool,
+
+    timer: u16,
+    timer_period: u16,
+    period_table: &'static [u16; 16],
+
+    pub(super) envelope: Envelope,
+    pub(super) length_counter: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            shift_register: 1,
+            mode: false,
+            timer: 0,
+            timer_period: NTSC_PERIOD_TABLE[0],
+            period_table: &NTSC_PERIOD_TABLE,
+            envelope: Default::default(),
+            length_counter: Default::default(),
+        }
+    }
+}
+
+impl Noise {
+    pub fn set_region(&mut self, region: Region) {
+        self.period_table = match region {
+            Region::NTSC => &NTSC_PERIOD_TABLE,
+            Region::PAL => &PAL_PERIOD_TABLE,
+        };
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr & 0b11 {
+            0 => {
+                // --LC VVVV
+                self.length_counter.halt = value & 0b00100000 != 0;
+                self.envelope.write(value);
+            }
+            2 => {
+                // M--- PPPP
+                self.mode = value & 0b10000000 != 0;
+                self.timer_period = self.period_table[(value & 0b1111) as usize];
+            }
+            3 => {
+                // LLLL L---
+                self.length_counter.reload(value);
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    // clocked every CPU cycle, the period table is in CPU cycles
+    pub fn clock_timer(&mut self) {
+        if 0 < self.timer {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period.wrapping_sub(1);
+
+        let other = if self.mode { 6 } else { 1 };
+        let feedback = (self.shift_register & 1) ^ ((self.shift_register >> other) & 1);
+        self.shift_register >>= 1;
+        self.shift_register |= feedback << 14;
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.active() || self.shift_register & 1 == 1 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    // the period table is of the region, set apart from the state
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.shift_register);
+        w.bool(self.mode);
+        w.u16(self.timer);
+        w.u16(self.timer_period);
+        self.envelope.save_state(w);
+        self.length_counter.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.shift_register = r.u16()?;
+        self.mode = r.bool()?;
+        self.timer = r.u16()?;
+        self.timer_period = r.u16()?;
+        self.envelope.load_state(r)?;
+        self.length_counter.load_state(r)
+    }
+}
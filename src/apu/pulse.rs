@@ -0,0 +1,178 @@
+use std::io;
+
+use super::envelope::{Envelope, LengthCounter};
+use crate::savestate::{StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Pulse
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum PulseChannel {
+    One,
+    Two,
+}
+
+#[derive(Clone)]
+pub(super) struct Pulse {
+    channel: PulseChannel,
+
+    duty: u8,
+    sequence: u8,
+
+    timer: u16,
+    timer_period: u16,
+
+    pub(super) envelope: Envelope,
+    pub(super) length_counter: LengthCounter,
+
+    // https://wiki.nesdev.com/w/index.php/APU_Sweep
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Self {
+        Self {
+            channel,
+            duty: 0,
+            sequence: 0,
+            timer: 0,
+            timer_period: 0,
+            envelope: Default::default(),
+            length_counter: Default::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr & 0b11 {
+            0 => {
+                // DDLC VVVV
+                self.duty = value >> 6;
+                self.length_counter.halt = value & 0b00100000 != 0;
+                self.envelope.write(value);
+            }
+            1 => {
+                // EPPP NSSS
+                self.sweep_enabled = value & 0b10000000 != 0;
+                self.sweep_period = (value >> 4) & 0b111;
+                self.sweep_negate = value & 0b1000 != 0;
+                self.sweep_shift = value & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => {
+                // TTTT TTTT
+                self.timer_period = (self.timer_period & 0xFF00) | value as u16;
+            }
+            3 => {
+                // LLLL LTTT
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length_counter.reload(value);
+                self.sequence = 0;
+                self.envelope.restart();
+            }
+            _ => {}
+        }
+    }
+
+    // clocked every APU cycle
+    pub fn clock_timer(&mut self) {
+        if 0 < self.timer {
+            self.timer -= 1;
+        } else {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) % 8;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && 0 < self.sweep_shift && !self.muted() {
+            self.timer_period = self.sweep_target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            match self.channel {
+                // Pulse 1 adds the ones' complement
+                PulseChannel::One => self.timer_period.wrapping_sub(change).wrapping_sub(1),
+                PulseChannel::Two => self.timer_period.wrapping_sub(change),
+            }
+        } else {
+            self.timer_period.wrapping_add(change)
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || 0x7FF < self.sweep_target_period()
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.active()
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.sequence as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.duty);
+        w.u8(self.sequence);
+        w.u16(self.timer);
+        w.u16(self.timer_period);
+        self.envelope.save_state(w);
+        self.length_counter.save_state(w);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_period);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.bool(self.sweep_reload);
+        w.u8(self.sweep_divider);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.duty = r.u8()? & 0b11;
+        self.sequence = r.u8()? & 0b111;
+        self.timer = r.u16()?;
+        self.timer_period = r.u16()?;
+        self.envelope.load_state(r)?;
+        self.length_counter.load_state(r)?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_period = r.u8()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()?;
+        self.sweep_reload = r.bool()?;
+        self.sweep_divider = r.u8()?;
+        Ok(())
+    }
+}
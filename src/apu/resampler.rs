@@ -0,0 +1,152 @@
+// Downsamples the APU output, which is produced once per CPU cycle, to a host sample rate.
+//
+// Every input sample falling into one output period is averaged (a box filter),
+// and the boundary sample is split between adjacent periods by its fractional weight.
+// This is cheap and removes most of the aliasing of the ultrasonic content.
+#[derive(Clone)]
+pub struct Resampler {
+    input_rate: f64,
+    output_rate: u32,
+
+    // input samples per output sample
+    step: f64,
+    // remaining input samples to accumulate until the next output sample
+    remaining: f64,
+    sum: f64,
+
+    output: Vec<f32>,
+    // output samples produced in total
+    produced: u64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: f64, output_rate: u32) -> Self {
+        let step = input_rate / output_rate as f64;
+        Self {
+            input_rate,
+            output_rate,
+            step,
+            remaining: step,
+            sum: 0.0,
+            output: Vec::new(),
+            produced: 0,
+        }
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    // Changes the output rate without dropping the sample in progress, which is stretched to the
+    // new step so a constant input stays constant.
+    pub fn set_output_rate(&mut self, output_rate: u32) {
+        let step = self.input_rate / output_rate as f64;
+        let ratio = step / self.step;
+        self.remaining *= ratio;
+        self.sum *= ratio;
+        self.step = step;
+        self.output_rate = output_rate;
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        let sample = sample as f64;
+        if 1.0 <= self.remaining {
+            self.sum += sample;
+            self.remaining -= 1.0;
+            if self.remaining == 0.0 {
+                self.emit(0.0, sample);
+            }
+        } else {
+            let head = self.remaining;
+            self.sum += sample * head;
+            self.emit(1.0 - head, sample);
+        }
+    }
+
+    fn emit(&mut self, carry: f64, sample: f64) {
+        self.output.push((self.sum / self.step) as f32);
+        self.produced += 1;
+        self.sum = sample * carry;
+        self.remaining = self.step - carry;
+    }
+
+    pub fn produced(&self) -> u64 {
+        self.produced
+    }
+
+    // fraction of the next output sample accumulated so far
+    pub fn remainder(&self) -> f64 {
+        1.0 - self.remaining / self.step
+    }
+
+    pub fn take(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.output)
+    }
+
+    // Goes back to `snapshot`, a clone taken before. The output produced since is dropped, and
+    // the output taken since is not given back.
+    pub fn restore(&mut self, snapshot: &Resampler) {
+        self.drop_output_since(snapshot.produced);
+        self.input_rate = snapshot.input_rate;
+        self.output_rate = snapshot.output_rate;
+        self.step = snapshot.step;
+        self.remaining = snapshot.remaining;
+        self.sum = snapshot.sum;
+        self.produced = snapshot.produced;
+    }
+
+    // drops the output not taken yet that was produced after the first `produced` samples
+    pub fn drop_output_since(&mut self, produced: u64) {
+        let since = self.produced.saturating_sub(produced) as usize;
+        self.output
+            .truncate(self.output.len().saturating_sub(since));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_count() {
+        let mut r = Resampler::new(1_789_773.0, 44_100);
+        for _ in 0..1_789_773 {
+            r.push(0.5);
+        }
+        let out = r.take();
+        assert!((out.len() as i64 - 44_100).abs() <= 1);
+        assert!(out.iter().all(|s| (s - 0.5).abs() < 1e-4));
+
+        assert!(r.take().is_empty());
+    }
+
+    #[test]
+    fn averages_input() {
+        let mut r = Resampler::new(4.0, 1);
+        for s in &[0.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0] {
+            r.push(*s);
+        }
+        assert_eq!(r.take(), vec![0.5, 1.0]);
+    }
+
+    #[test]
+    fn fractional_step() {
+        let mut r = Resampler::new(2.5, 1);
+        for s in &[1.0, 1.0, 0.0, 0.0, 0.0] {
+            r.push(*s);
+        }
+        assert_eq!(r.take(), vec![0.8, 0.0]);
+    }
+
+    #[test]
+    fn set_output_rate() {
+        let mut r = Resampler::new(4.0, 2);
+        r.push(1.0);
+        r.set_output_rate(1);
+        assert_eq!(r.output_rate(), 1);
+        for _ in 0..6 {
+            r.push(1.0);
+        }
+        assert_eq!(r.take(), vec![1.0, 1.0]);
+    }
+}
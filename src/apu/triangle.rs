@@ -0,0 +1,103 @@
+use std::io;
+
+use super::envelope::LengthCounter;
+use crate::savestate::{StateReader, StateWriter};
+
+// https://wiki.nesdev.com/w/index.php/APU_Triangle
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Clone, Default)]
+pub(super) struct Triangle {
+    sequence: u8,
+
+    timer: u16,
+    timer_period: u16,
+
+    pub(super) length_counter: LengthCounter,
+
+    linear_counter: u8,
+    linear_counter_period: u8,
+    linear_counter_reload: bool,
+    control: bool,
+}
+
+impl Triangle {
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr & 0b11 {
+            0 => {
+                // CRRR RRRR
+                self.control = value & 0b10000000 != 0;
+                self.length_counter.halt = self.control;
+                self.linear_counter_period = value & 0b01111111;
+            }
+            2 => {
+                // TTTT TTTT
+                self.timer_period = (self.timer_period & 0xFF00) | value as u16;
+            }
+            3 => {
+                // LLLL LTTT
+                self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                self.length_counter.reload(value);
+                self.linear_counter_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    // clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if 0 < self.timer {
+            self.timer -= 1;
+        } else {
+            self.timer = self.timer_period;
+            if self.length_counter.active() && 0 < self.linear_counter {
+                self.sequence = (self.sequence + 1) % 32;
+            }
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_period;
+        } else if 0 < self.linear_counter {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence as usize]
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.sequence);
+        w.u16(self.timer);
+        w.u16(self.timer_period);
+        self.length_counter.save_state(w);
+        w.u8(self.linear_counter);
+        w.u8(self.linear_counter_period);
+        w.bool(self.linear_counter_reload);
+        w.bool(self.control);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.sequence = r.u8()? & 0x1F;
+        self.timer = r.u16()?;
+        self.timer_period = r.u16()?;
+        self.length_counter.load_state(r)?;
+        self.linear_counter = r.u8()?;
+        self.linear_counter_period = r.u8()?;
+        self.linear_counter_reload = r.bool()?;
+        self.control = r.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Creates a ring buffer of up to `capacity` audio samples, to pass the samples of
+/// `NES::take_audio_samples` to the callback of an audio device on another thread.
+///
+/// The buffer is lock-free, so the audio callback never waits for the emulation.
+///
+/// ```
+/// let (mut producer, mut consumer) = rustnes::audio_ring(4);
+/// assert_eq!(producer.push(&[0.1, 0.2, 0.3, 0.4, 0.5]), 4);
+///
+/// let mut out = [0.0; 3];
+/// assert_eq!(consumer.pop_into(&mut out), 3);
+/// assert_eq!(out, [0.1, 0.2, 0.3]);
+/// assert_eq!(consumer.len(), 1);
+/// ```
+pub fn audio_ring(capacity: usize) -> (AudioProducer, AudioConsumer) {
+    assert!(0 < capacity, "an audio ring of no capacity");
+    let ring = Arc::new(Ring {
+        samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        written: AtomicUsize::new(0),
+    });
+    (AudioProducer { ring: ring.clone() }, AudioConsumer { ring })
+}
+
+// A single producer and a single consumer share the buffer. The positions count the samples
+// read and written in total, wrapping around, so the length is their difference.
+struct Ring {
+    // bits of f32
+    samples: Box<[AtomicU32]>,
+    read: AtomicUsize,
+    written: AtomicUsize,
+}
+
+impl Ring {
+    fn len(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        let read = self.read.load(Ordering::Acquire);
+        written.wrapping_sub(read)
+    }
+
+    fn capacity(&self) -> usize {
+        self.samples.len()
+    }
+}
+
+/// The writing end of `audio_ring`.
+pub struct AudioProducer {
+    ring: Arc<Ring>,
+}
+
+impl AudioProducer {
+    /// Writes as many of `samples` as fit, returning the number written. The rest is dropped.
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let ring = &self.ring;
+        let written = ring.written.load(Ordering::Relaxed);
+        let n = samples.len().min(ring.capacity() - ring.len());
+        for (i, s) in samples[..n].iter().enumerate() {
+            let index = written.wrapping_add(i) % ring.capacity();
+            ring.samples[index].store(s.to_bits(), Ordering::Relaxed);
+        }
+        ring.written
+            .store(written.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+}
+
+/// The reading end of `audio_ring`.
+pub struct AudioConsumer {
+    ring: Arc<Ring>,
+}
+
+impl AudioConsumer {
+    /// Reads samples into `out`, returning the number read.
+    pub fn pop_into(&mut self, out: &mut [f32]) -> usize {
+        let ring = &self.ring;
+        let read = ring.read.load(Ordering::Relaxed);
+        let n = out.len().min(ring.len());
+        for (i, o) in out[..n].iter_mut().enumerate() {
+            let index = read.wrapping_add(i) % ring.capacity();
+            *o = f32::from_bits(ring.samples[index].load(Ordering::Relaxed));
+        }
+        ring.read.store(read.wrapping_add(n), Ordering::Release);
+        n
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_around() {
+        let (mut producer, mut consumer) = audio_ring(3);
+        let mut out = [0.0; 2];
+        for i in 0..10 {
+            let s = i as f32;
+            assert_eq!(producer.push(&[s, s + 0.5]), 2);
+            assert_eq!(consumer.pop_into(&mut out), 2);
+            assert_eq!(out, [s, s + 0.5]);
+        }
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.pop_into(&mut out), 0);
+
+        let handle = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut out = [0.0; 2];
+            while received.len() < 100 {
+                let n = consumer.pop_into(&mut out);
+                received.extend_from_slice(&out[..n]);
+            }
+            received
+        });
+        let samples: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let mut sent = 0;
+        while sent < samples.len() {
+            sent += producer.push(&samples[sent..]);
+        }
+        assert_eq!(handle.join().unwrap(), samples);
+    }
+}
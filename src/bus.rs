@@ -0,0 +1,216 @@
+use std::ops::RangeInclusive;
+
+use crate::types::{Byte, Memory, Word};
+
+impl Memory for [u8; 0x10000] {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        self[addr as usize].into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        self[addr as usize] = value.into()
+    }
+}
+
+/// Readable and writable memory. Addresses past the end wrap around.
+pub struct RamRegion(Vec<u8>);
+
+impl RamRegion {
+    pub fn new(size: usize) -> Self {
+        Self(vec![0; size.max(1)])
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        if data.is_empty() {
+            Self::new(1)
+        } else {
+            Self(data)
+        }
+    }
+}
+
+impl Memory for RamRegion {
+    fn peek(&self, addr: Word) -> Byte {
+        self.0[u16::from(addr) as usize % self.0.len()].into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let i = u16::from(addr) as usize % self.0.len();
+        self.0[i] = value.into();
+    }
+}
+
+/// Read-only memory, which ignores writes. Addresses past the end wrap around.
+pub struct RomRegion(Vec<u8>);
+
+impl RomRegion {
+    pub fn new(data: Vec<u8>) -> Self {
+        if data.is_empty() {
+            Self(vec![0])
+        } else {
+            Self(data)
+        }
+    }
+}
+
+impl Memory for RomRegion {
+    fn peek(&self, addr: Word) -> Byte {
+        self.0[u16::from(addr) as usize % self.0.len()].into()
+    }
+
+    fn write(&mut self, _addr: Word, _value: Byte) {}
+}
+
+/// Repeats the first `size` bytes of the inner memory across the address space.
+pub struct Mirrored<M> {
+    inner: M,
+    size: u16,
+}
+
+impl<M: Memory> Mirrored<M> {
+    pub fn new(inner: M, size: u16) -> Self {
+        Self {
+            inner,
+            size: size.max(1),
+        }
+    }
+
+    fn mirror(&self, addr: Word) -> Word {
+        (u16::from(addr) % self.size).into()
+    }
+}
+
+impl<M: Memory> Memory for Mirrored<M> {
+    fn peek(&self, addr: Word) -> Byte {
+        self.inner.peek(self.mirror(addr))
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr = self.mirror(addr);
+        self.inner.write(addr, value)
+    }
+
+    fn read(&mut self, addr: Word) -> Byte {
+        let addr = self.mirror(addr);
+        self.inner.read(addr)
+    }
+}
+
+/// Routes address ranges to handlers.
+///
+/// Handlers receive the address relative to the start of the range.
+/// The first matching range wins, and unmapped addresses read as 0.
+#[derive(Default)]
+pub struct Mapped {
+    regions: Vec<(RangeInclusive<u16>, Box<dyn Memory>)>,
+}
+
+impl Mapped {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn map<M: Memory + 'static>(mut self, range: RangeInclusive<u16>, handler: M) -> Self {
+        self.regions.push((range, Box::new(handler)));
+        self
+    }
+
+    // the index of the region and the address relative to its start
+    fn find(&self, addr: Word) -> Option<(usize, Word)> {
+        let addr = u16::from(addr);
+        self.regions
+            .iter()
+            .position(|(r, _)| r.contains(&addr))
+            .map(|i| (i, (addr - self.regions[i].0.start()).into()))
+    }
+}
+
+impl Memory for Mapped {
+    fn peek(&self, addr: Word) -> Byte {
+        match self.find(addr) {
+            Some((i, addr)) => self.regions[i].1.peek(addr),
+            None => 0.into(),
+        }
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        if let Some((i, addr)) = self.find(addr) {
+            self.regions[i].1.write(addr, value)
+        }
+    }
+
+    fn read(&mut self, addr: Word) -> Byte {
+        match self.find(addr) {
+            Some((i, addr)) => self.regions[i].1.read(addr),
+            None => 0.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bus: &mut impl Memory, addr: u16) -> u8 {
+        bus.read(addr.into()).into()
+    }
+
+    fn write(bus: &mut impl Memory, addr: u16, value: u8) {
+        bus.write(addr.into(), value.into())
+    }
+
+    #[test]
+    fn mapped() {
+        let mut bus = Mapped::new()
+            .map(
+                0x0000..=0x1FFF,
+                Mirrored::new(RamRegion::new(0x0800), 0x0800),
+            )
+            .map(0x6000..=0x7FFF, RamRegion::new(0x2000))
+            .map(0x8000..=0xFFFF, RomRegion::new(vec![1, 2, 3, 4]));
+
+        write(&mut bus, 0x1801, 0x42);
+        assert_eq!(read(&mut bus, 0x0001), 0x42);
+
+        write(&mut bus, 0x6000, 0x10);
+        assert_eq!(read(&mut bus, 0x6000), 0x10);
+        assert_eq!(read(&mut bus, 0x0000), 0x00);
+
+        write(&mut bus, 0x8001, 0xFF);
+        assert_eq!(read(&mut bus, 0x8001), 2);
+        assert_eq!(read(&mut bus, 0x8005), 2);
+
+        assert_eq!(read(&mut bus, 0x4000), 0);
+    }
+
+    // counts the reads, like a register cleared or shifted on read
+    #[derive(Default)]
+    struct ReadCounter(u8);
+
+    impl Memory for ReadCounter {
+        fn peek(&self, _addr: Word) -> Byte {
+            self.0.into()
+        }
+
+        fn write(&mut self, _addr: Word, _value: Byte) {}
+
+        fn read(&mut self, addr: Word) -> Byte {
+            let value = self.peek(addr);
+            self.0 += 1;
+            value
+        }
+    }
+
+    #[test]
+    fn read_side_effects() {
+        let mut bus = Mapped::new().map(0x4000..=0x401F, Mirrored::new(ReadCounter::default(), 1));
+
+        assert_eq!(read(&mut bus, 0x4000), 0);
+        assert_eq!(read(&mut bus, 0x4011), 1);
+        assert_eq!(bus.peek(0x4000u16.into()), 2.into());
+        assert_eq!(bus.peek(0x4000u16.into()), 2.into());
+        assert_eq!(read(&mut bus, 0x4000), 2);
+    }
+}
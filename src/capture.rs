@@ -0,0 +1,223 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::nes::NES;
+use crate::screenshot::Screenshot;
+
+/// File format of the video of a `Capture`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// YUV4MPEG2 in 4:4:4, readable by ffmpeg and most encoders.
+    Y4M,
+    /// Frames of 8-bit RGB pixels without any header.
+    RawRGB,
+}
+
+impl CaptureFormat {
+    /// `Y4M` for paths ending with `.y4m`, otherwise `RawRGB`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("y4m") => Self::Y4M,
+            _ => Self::RawRGB,
+        }
+    }
+}
+
+// size of the RIFF header of a 16-bit PCM WAV file up to the sample data
+const WAV_HEADER_SIZE: u32 = 44;
+
+// the most 16-bit samples whose RIFF chunk size fits in its 32 bits, about 4 GiB
+const MAX_WAV_SAMPLES: u32 = (u32::MAX - (WAV_HEADER_SIZE - 8)) / 2;
+
+/// Records the video frames and audio of a `NES` into files, for capturing gameplay
+/// deterministically without a frontend.
+///
+/// Audio is written as a 16-bit mono WAV file at the sample rate of the `NES`. Its size in the
+/// header is written by `Capture::finish`, and a WAV file holds at most 4 GiB of samples.
+pub struct Capture {
+    video: BufWriter<File>,
+    audio: BufWriter<File>,
+    format: CaptureFormat,
+    width: u32,
+    height: u32,
+    samples: u32,
+}
+
+impl Capture {
+    /// Starts a capture of frames of the size of `NES::video_frame` at the frame rate of the
+    /// region of `nes`.
+    pub fn create<P: AsRef<Path>, Q: AsRef<Path>>(
+        video_path: P,
+        audio_path: Q,
+        format: CaptureFormat,
+        nes: &NES,
+    ) -> io::Result<Self> {
+        let (width, height) = nes.video_frame_size();
+        let mut video = BufWriter::new(File::create(video_path)?);
+        if format == CaptureFormat::Y4M {
+            let (num, den) = nes.region().frame_rate();
+            writeln!(
+                video,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                width, height, num, den
+            )?;
+        }
+        let mut audio = BufWriter::new(File::create(audio_path)?);
+        write_wav_header(&mut audio, nes.sample_rate(), 0)?;
+        Ok(Self {
+            video,
+            audio,
+            format,
+            width,
+            height,
+            samples: 0,
+        })
+    }
+
+    /// Writes the current frame and takes the audio samples produced with it, so this is called
+    /// after each `NES::frame` in place of `NES::take_audio_samples`.
+    ///
+    /// Fails if the size of the frame changed since the capture started, or if the audio of the
+    /// frame would not fit in the WAV file, in which case the capture is to be finished.
+    pub fn write_frame(&mut self, nes: &mut NES) -> io::Result<()> {
+        let frame = nes.video_frame();
+        if (frame.width, frame.height) != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the frame size changed during the capture",
+            ));
+        }
+        let samples = nes.take_audio_samples();
+        if ((MAX_WAV_SAMPLES - self.samples) as usize) < samples.len() {
+            return Err(io::Error::other(
+                "the audio of the capture is past the 4 GiB of a WAV file",
+            ));
+        }
+        match self.format {
+            CaptureFormat::Y4M => self.write_y4m_frame(&frame)?,
+            CaptureFormat::RawRGB => {
+                for p in frame.pixels.chunks_exact(4) {
+                    self.video.write_all(&p[..3])?;
+                }
+            }
+        }
+
+        for s in samples {
+            let s = (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.audio.write_all(&s.to_le_bytes())?;
+            self.samples += 1;
+        }
+        Ok(())
+    }
+
+    // BT.601 in the limited range
+    fn write_y4m_frame(&mut self, frame: &Screenshot) -> io::Result<()> {
+        let len = frame.pixels.len() / 4;
+        let mut planes = vec![0u8; len * 3];
+        for (i, p) in frame.pixels.chunks_exact(4).enumerate() {
+            let (r, g, b) = (p[0] as i32, p[1] as i32, p[2] as i32);
+            planes[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            planes[len + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            planes[len * 2 + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        self.video.write_all(b"FRAME\n")?;
+        self.video.write_all(&planes)
+    }
+
+    /// Flushes the files and completes the WAV header.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.video.flush()?;
+        self.audio.flush()?;
+        let audio = self.audio.get_mut();
+        audio.seek(SeekFrom::Start(4))?;
+        audio.write_all(&(WAV_HEADER_SIZE - 8 + self.samples * 2).to_le_bytes())?;
+        audio.seek(SeekFrom::Start(WAV_HEADER_SIZE as u64 - 4))?;
+        audio.write_all(&(self.samples * 2).to_le_bytes())?;
+        audio.flush()
+    }
+}
+
+fn write_wav_header<W: Write>(w: &mut W, sample_rate: u32, samples: u32) -> io::Result<()> {
+    let data_size = samples * 2;
+    w.write_all(b"RIFF")?;
+    w.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    w.write_all(b"WAVEfmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    // bytes per second, bytes per sample and bits per sample
+    let byte_rate = sample_rate.checked_mul(2).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the sample rate is too high for a WAV file",
+        )
+    })?;
+    w.write_all(&byte_rate.to_le_bytes())?;
+    w.write_all(&2u16.to_le_bytes())?;
+    w.write_all(&16u16.to_le_bytes())?;
+    w.write_all(b"data")?;
+    w.write_all(&data_size.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::tests::counter_rom;
+
+    #[test]
+    fn capture() {
+        let dir = std::env::temp_dir().join(format!("rustnes-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let video_path = dir.join("out.y4m");
+        let audio_path = dir.join("out.wav");
+        assert_eq!(CaptureFormat::from_path(&video_path), CaptureFormat::Y4M);
+
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        let mut capture =
+            Capture::create(&video_path, &audio_path, CaptureFormat::Y4M, &nes).unwrap();
+        for _ in 0..2 {
+            nes.frame();
+            capture.write_frame(&mut nes).unwrap();
+        }
+        capture.finish().unwrap();
+
+        let video = std::fs::read(&video_path).unwrap();
+        let header = b"YUV4MPEG2 W256 H224 F39375000:655171 Ip A1:1 C444\n";
+        assert!(video.starts_with(header));
+        assert_eq!(video.len(), header.len() + 2 * (6 + 256 * 224 * 3));
+
+        let audio = std::fs::read(&audio_path).unwrap();
+        let data_size = audio.len() as u32 - WAV_HEADER_SIZE;
+        assert!(0 < data_size);
+        assert_eq!(&audio[40..44], &data_size.to_le_bytes());
+        assert_eq!(&audio[4..8], &(audio.len() as u32 - 8).to_le_bytes());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn full_wav() {
+        let dir = std::env::temp_dir().join(format!("rustnes-full-wav-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        let mut capture = Capture::create(
+            dir.join("out.rgb"),
+            dir.join("out.wav"),
+            CaptureFormat::RawRGB,
+            &nes,
+        )
+        .unwrap();
+        capture.samples = MAX_WAV_SAMPLES;
+        nes.frame();
+        assert!(capture.write_frame(&mut nes).is_err());
+        assert_eq!(capture.samples, MAX_WAV_SAMPLES);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
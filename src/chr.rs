@@ -0,0 +1,63 @@
+use crate::screenshot::Screenshot;
+
+/// The number of tiles in a row of a sheet made by `chr_sheet`, 128 pixels wide.
+pub const CHR_SHEET_TILES_PER_ROW: u32 = 16;
+
+// 2bpp color to a gray level, darkest for 0
+const SHADES: [u8; 4] = [0x00, 0x55, 0xAA, 0xFF];
+
+/// Decodes 2bpp tiles of CHR data in 16-byte units into a grayscale sheet, 16 tiles per row.
+///
+/// Each tile has two bit planes of 8 rows, the low bit of each pixel first.
+/// https://wiki.nesdev.com/w/index.php/PPU_pattern_tables
+pub fn chr_sheet(chr: &[u8]) -> Screenshot {
+    let tiles = (chr.len() / 16) as u32;
+    let width = CHR_SHEET_TILES_PER_ROW * 8;
+    let height = tiles.div_ceil(CHR_SHEET_TILES_PER_ROW) * 8;
+    let mut pixels = vec![0; (width * height * 4) as usize];
+
+    for (n, tile) in chr.chunks_exact(16).enumerate() {
+        let n = n as u32;
+        let left = (n % CHR_SHEET_TILES_PER_ROW) * 8;
+        let top = (n / CHR_SHEET_TILES_PER_ROW) * 8;
+        for row in 0..8 {
+            let (low, high) = (tile[row], tile[row + 8]);
+            for col in 0..8 {
+                let bit = 7 - col;
+                let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                let shade = SHADES[color as usize];
+                let i = (((top + row as u32) * width + left + col as u32) * 4) as usize;
+                pixels[i..i + 4].copy_from_slice(&[shade, shade, shade, 0xFF]);
+            }
+        }
+    }
+
+    Screenshot {
+        width,
+        height,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        let mut chr = vec![0; 32];
+        // the second tile: color 1 at the left edge of the first row, 2 at the right edge, 3 below
+        chr[16] = 0b1000_0000;
+        chr[16 + 8] = 0b0000_0001;
+        chr[17] = 0b1000_0000;
+        chr[17 + 8] = 0b1000_0000;
+
+        let sheet = chr_sheet(&chr);
+        assert_eq!((sheet.width, sheet.height), (128, 8));
+        let pixel = |x: u32, y: u32| sheet.pixels[((y * 128 + x) * 4) as usize];
+        assert_eq!(pixel(0, 0), 0x00);
+        assert_eq!(pixel(8, 0), 0x55);
+        assert_eq!(pixel(15, 0), 0xAA);
+        assert_eq!(pixel(8, 1), 0xFF);
+    }
+}
@@ -0,0 +1,295 @@
+use std::io;
+use std::ops;
+
+use crate::savestate::{StateReader, StateWriter};
+
+/// Buttons of a standard controller, combined with `|`.
+///
+/// ```
+/// use rustnes::Button;
+///
+/// let buttons = Button::A | Button::RIGHT;
+/// assert!(buttons.contains(Button::RIGHT));
+/// assert_eq!(buttons.bits(), 0b1000_0001);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash)]
+pub struct Button(u8);
+
+impl Button {
+    pub const A: Self = Self(1 << 0);
+    pub const B: Self = Self(1 << 1);
+    pub const SELECT: Self = Self(1 << 2);
+    pub const START: Self = Self(1 << 3);
+    pub const UP: Self = Self(1 << 4);
+    pub const DOWN: Self = Self(1 << 5);
+    pub const LEFT: Self = Self(1 << 6);
+    pub const RIGHT: Self = Self(1 << 7);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Buttons from bits in the order the controller reports them, A in bit 0 to Right in bit 7.
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, Self(b): Self) -> bool {
+        self.0 & b == b
+    }
+
+    pub fn insert(&mut self, Self(b): Self) {
+        self.0 |= b
+    }
+
+    pub fn remove(&mut self, Self(b): Self) {
+        self.0 &= !b
+    }
+}
+
+impl ops::BitOr for Button {
+    type Output = Self;
+
+    fn bitor(self, Self(rhs): Self) -> Self::Output {
+        Self(self.0 | rhs)
+    }
+}
+
+impl ops::BitOrAssign for Button {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.insert(rhs)
+    }
+}
+
+/// The number of controller ports, read through $4016 and $4017.
+pub const CONTROLLER_PORTS: usize = 2;
+
+/// The number of controllers, including the 3rd and 4th ones connected through a Four Score.
+pub const CONTROLLERS: usize = 4;
+
+// Four Score signatures read after the 3rd and 4th controllers, from the high bit
+const FOUR_SCORE_SIGNATURES: [u8; CONTROLLER_PORTS] = [0b0001_0000, 0b0010_0000];
+
+// Controllers plugged into the ports. All are strobed by $4016 at the same time.
+// https://wiki.nesdev.com/w/index.php/Input_devices
+//
+// With a Four Score, each port reports 24 bits: the 1st (or 2nd) controller, the 3rd (or 4th)
+// controller, then the signature.
+// https://wiki.nesdev.com/w/index.php/Four_player_adapters
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ControllerPorts {
+    controllers: [Controller; CONTROLLERS],
+    four_score: bool,
+    // reads since the strobe on each port with a Four Score
+    reads: [u8; CONTROLLER_PORTS],
+    strobe: bool,
+}
+
+impl ControllerPorts {
+    // keeps the buttons held and whether a Four Score is plugged in
+    pub fn inherit_settings(&mut self, other: &ControllerPorts) {
+        for (c, o) in self.controllers.iter_mut().zip(other.controllers.iter()) {
+            c.set_buttons(o.buttons());
+        }
+        self.four_score = other.four_score;
+    }
+
+    pub fn set_buttons(&mut self, n: usize, buttons: Button) {
+        self.controllers[n].set_buttons(buttons);
+    }
+
+    pub fn buttons(&self, n: usize) -> Button {
+        self.controllers[n].buttons()
+    }
+
+    pub fn set_four_score(&mut self, enabled: bool) {
+        self.four_score = enabled;
+    }
+
+    pub fn four_score(&self) -> bool {
+        self.four_score
+    }
+
+    // the Four Score is a setting, apart from the state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        for c in &self.controllers {
+            w.u8(c.buttons.bits());
+            w.u8(c.shift);
+            w.u8(c.reads);
+            w.bool(c.strobe);
+        }
+        w.bytes(&self.reads);
+        w.bool(self.strobe);
+        w.into_bytes()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut r = StateReader::new(data);
+        for c in self.controllers.iter_mut() {
+            c.buttons = Button::from_bits(r.u8()?);
+            c.shift = r.u8()?;
+            c.reads = r.u8()?;
+            c.strobe = r.bool()?;
+        }
+        r.bytes_into(&mut self.reads)?;
+        self.strobe = r.bool()?;
+        Ok(())
+    }
+
+    pub fn write_strobe(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        self.reads = [0; CONTROLLER_PORTS];
+        for c in &mut self.controllers {
+            c.write_strobe(value);
+        }
+    }
+
+    pub fn read(&mut self, port: usize) -> u8 {
+        if !self.four_score || self.strobe {
+            return self.controllers[port].read();
+        }
+        let n = self.reads[port];
+        self.reads[port] = n.saturating_add(1);
+        match n {
+            0..=7 => self.controllers[port].read(),
+            8..=15 => self.controllers[port + CONTROLLER_PORTS].read(),
+            16..=23 => (FOUR_SCORE_SIGNATURES[port] >> (23 - n)) & 1,
+            _ => 1,
+        }
+    }
+}
+
+// Standard controller
+// https://wiki.nesdev.com/w/index.php/Standard_controller
+//
+// The buttons are latched into a shift register while the strobe ($4016 bit 0) is high,
+// then read one by one from bit 0 in the order A, B, Select, Start, Up, Down, Left, Right.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Controller {
+    buttons: Button,
+    shift: u8,
+    // reads after the 8 buttons return 1
+    reads: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn set_buttons(&mut self, buttons: Button) {
+        self.buttons = buttons;
+    }
+
+    pub fn buttons(&self) -> Button {
+        self.buttons
+    }
+
+    pub fn write_strobe(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            // keeps reloading while the strobe is high
+            self.latch();
+            return self.buttons.bits() & 1;
+        }
+        if 8 <= self.reads {
+            return 1;
+        }
+        let bit = self.shift & 1;
+        self.shift >>= 1;
+        self.reads += 1;
+        bit
+    }
+
+    fn latch(&mut self) {
+        self.shift = self.buttons.bits();
+        self.reads = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift() {
+        let mut c = Controller::default();
+        c.set_buttons(Button::A | Button::START | Button::RIGHT);
+
+        c.write_strobe(1);
+        assert_eq!(c.read(), 1);
+        assert_eq!(c.read(), 1);
+        c.write_strobe(0);
+
+        let bits: Vec<u8> = (0..10).map(|_| c.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+        // latched at the strobe
+        c.write_strobe(1);
+        c.write_strobe(0);
+        c.set_buttons(Button::empty());
+        assert_eq!(c.read(), 1);
+    }
+
+    #[test]
+    fn ports() {
+        let mut ports = ControllerPorts::default();
+        ports.set_buttons(0, Button::A);
+        ports.set_buttons(1, Button::B);
+
+        ports.write_strobe(1);
+        ports.write_strobe(0);
+        assert_eq!((ports.read(0), ports.read(1)), (1, 0));
+        assert_eq!((ports.read(0), ports.read(1)), (0, 1));
+        // reading one port does not shift the other
+        assert_eq!(ports.read(0), 0);
+        assert_eq!(ports.buttons(1), Button::B);
+    }
+
+    #[test]
+    fn four_score() {
+        let mut ports = ControllerPorts::default();
+        ports.set_four_score(true);
+        ports.set_buttons(0, Button::A);
+        ports.set_buttons(2, Button::RIGHT);
+        ports.set_buttons(3, Button::B);
+
+        ports.write_strobe(1);
+        ports.write_strobe(0);
+        let bits: Vec<u8> = (0..25).map(|_| ports.read(0)).collect();
+        let mut expected = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        expected.extend(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend(&[0, 0, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(bits, expected);
+
+        let bits: Vec<u8> = (0..24).map(|_| ports.read(1)).collect();
+        let mut expected = vec![0; 8];
+        expected.extend(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        expected.extend(&[0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(bits, expected);
+    }
+
+    #[test]
+    fn button() {
+        let mut b = Button::UP | Button::B;
+        assert_eq!(b, Button::from_bits(0b0001_0010));
+        assert!(b.contains(Button::UP));
+        assert!(!b.contains(Button::UP | Button::DOWN));
+        b |= Button::DOWN;
+        b.remove(Button::UP);
+        assert_eq!(b, Button::B | Button::DOWN);
+        assert!(Button::empty().is_empty());
+    }
+}
@@ -2,15 +2,34 @@ mod addressing_modes;
 mod instructions;
 mod status;
 mod trace;
+#[cfg(feature = "vcd")]
+mod vcd;
 
 use crate::types::{Byte, Memory, Word};
 
 use instructions::{decode, execute};
 use status::CPUStatus;
 pub use trace::Trace;
+#[cfg(feature = "vcd")]
+pub use vcd::BusRecorder;
 
 pub type CPUCycle = u128;
 
+/// Registers of the CPU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CPUSnapshot {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub cycles: u128,
+}
+
+/// The registers of the 6502 core. The bus is passed to each step, generic so that its accesses
+/// are inlined into the instructions; a `Box<dyn Memory>` takes any bus at the cost of a virtual
+/// call.
 pub struct CPU {
     pub(super) a: Byte,
     pub(super) x: Byte,
@@ -21,11 +40,12 @@ pub struct CPU {
 
     pub cycles: CPUCycle,
 
-    bus: Box<dyn Memory>,
+    #[cfg(feature = "vcd")]
+    pub bus_recorder: Option<BusRecorder>,
 }
 
-impl CPU {
-    pub fn new(cpu_bus: Box<dyn Memory>) -> Self {
+impl Default for CPU {
+    fn default() -> Self {
         Self {
             a: 0x00.into(),
             x: 0x00.into(),
@@ -34,120 +54,203 @@ impl CPU {
             p: CPUStatus::from(0),
             pc: 0x00u16.into(),
             cycles: 0,
-            bus: cpu_bus,
+            #[cfg(feature = "vcd")]
+            bus_recorder: None,
         }
     }
+}
 
-    pub fn step(&mut self) {
-        let instruction = self.fetch();
+impl CPU {
+    pub fn step<M: Memory>(&mut self, bus: &mut M) {
+        let instruction = self.fetch(bus);
         let opcode = decode(instruction);
-        execute(self, opcode);
+        execute(self, bus, opcode);
     }
 
-    fn fetch(&mut self) -> Byte {
-        let opcode = self.read(self.pc);
+    fn fetch<M: Memory>(&mut self, bus: &mut M) -> Byte {
+        let opcode = self.read(bus, self.pc);
         self.pc += 1;
         opcode
     }
 
-    pub(super) fn read(&mut self, addr: impl Into<Word>) -> Byte {
+    pub(super) fn read<M: Memory>(&mut self, bus: &mut M, addr: impl Into<Word>) -> Byte {
         let addr: Word = addr.into();
         self.cycles += 1;
-        self.bus.read(addr)
+        let value = bus.read(addr);
+        #[cfg(feature = "vcd")]
+        self.record_bus(addr, value, true);
+        value
     }
 
-    pub(super) fn read_word(&mut self, addr: impl Into<Word>) -> Word {
+    pub(super) fn read_word<M: Memory>(&mut self, bus: &mut M, addr: impl Into<Word>) -> Word {
         let addr: Word = addr.into();
-        Word::from(self.read(addr)) | (Word::from(self.read(addr + 1)) << 8)
+        Word::from(self.read(bus, addr)) | (Word::from(self.read(bus, addr + 1)) << 8)
     }
 
-    pub(super) fn read_on_indirect(&mut self, operand: Word) -> Word {
-        let low = Word::from(self.read(operand));
+    pub(super) fn read_on_indirect<M: Memory>(&mut self, bus: &mut M, operand: Word) -> Word {
+        let low = Word::from(self.read(bus, operand));
         // Reproduce 6502 bug; http://nesdev.com/6502bugs.txt
         let addr = operand & 0xFF00 | ((operand + 1) & 0x00FF);
-        let high = Word::from(self.read(addr)) << 8;
+        let high = Word::from(self.read(bus, addr)) << 8;
         low | high
     }
 
-    pub(super) fn write(&mut self, addr: impl Into<Word>, value: impl Into<Byte>) {
+    pub(super) fn write<M: Memory>(
+        &mut self,
+        bus: &mut M,
+        addr: impl Into<Word>,
+        value: impl Into<Byte>,
+    ) {
         let addr: Word = addr.into();
         let value: Byte = value.into();
         self.cycles += 1;
-        self.bus.write(addr, value)
+        #[cfg(feature = "vcd")]
+        self.record_bus(addr, value, false);
+        bus.write(addr, value)
+    }
+
+    // A cycle whose read the 6502 throws away, e.g. while it adds an index to an address. The
+    // devices do not see it, so only the bus recorder does, with the value a read would return.
+    pub(super) fn dummy_read<M: Memory>(&mut self, bus: &mut M, addr: impl Into<Word>) {
+        self.cycles += 1;
+        #[cfg(feature = "vcd")]
+        if self.bus_recorder.is_some() {
+            let addr: Word = addr.into();
+            self.record_bus(addr, bus.peek(addr), true);
+        }
+        #[cfg(not(feature = "vcd"))]
+        let _ = (bus, addr);
+    }
+
+    // The write of the unmodified value before a read-modify-write instruction writes the result,
+    // which the devices do not see either.
+    pub(super) fn dummy_write(&mut self, addr: impl Into<Word>, value: impl Into<Byte>) {
+        self.cycles += 1;
+        #[cfg(feature = "vcd")]
+        self.record_bus(addr.into(), value.into(), false);
+        #[cfg(not(feature = "vcd"))]
+        let _ = (addr, value);
+    }
+
+    #[cfg(feature = "vcd")]
+    fn record_bus(&mut self, addr: Word, value: Byte, read: bool) {
+        if let Some(recorder) = self.bus_recorder.as_mut() {
+            recorder.record(self.cycles, addr, value, read);
+        }
     }
 }
 
 // stack operation
 impl CPU {
-    pub(super) fn push_stack(&mut self, value: impl Into<Byte>) {
+    pub(super) fn push_stack<M: Memory>(&mut self, bus: &mut M, value: impl Into<Byte>) {
         let value = value.into();
-        self.write(Word::from(self.s) + 0x100, value);
+        self.write(bus, Word::from(self.s) + 0x100, value);
         self.s -= 1;
     }
 
-    pub(super) fn push_stack_word(&mut self, word: impl Into<Word>) {
+    pub(super) fn push_stack_word<M: Memory>(&mut self, bus: &mut M, word: impl Into<Word>) {
         let value = word.into();
-        self.push_stack((value >> 8).byte());
-        self.push_stack((value & 0xFF).byte());
+        self.push_stack(bus, (value >> 8).byte());
+        self.push_stack(bus, (value & 0xFF).byte());
     }
 
-    pub(super) fn pull_stack(&mut self) -> Byte {
+    // the read of the top of the stack while S is incremented for a pull
+    pub(super) fn dummy_read_stack<M: Memory>(&mut self, bus: &mut M) {
+        self.dummy_read(bus, Word::from(self.s) + 0x100);
+    }
+
+    pub(super) fn pull_stack<M: Memory>(&mut self, bus: &mut M) -> Byte {
         self.s += 1;
-        self.read(Word::from(self.s) + 0x100)
+        self.read(bus, Word::from(self.s) + 0x100)
     }
 
-    pub(super) fn pull_stack_word(&mut self) -> Word {
-        let l: Word = self.pull_stack().into();
-        let h: Word = self.pull_stack().into();
+    pub(super) fn pull_stack_word<M: Memory>(&mut self, bus: &mut M) -> Word {
+        let l: Word = self.pull_stack(bus).into();
+        let h: Word = self.pull_stack(bus).into();
         h << 8 | l
     }
 }
 
 // handling interrupt
 impl CPU {
+    pub fn snapshot(&self) -> CPUSnapshot {
+        CPUSnapshot {
+            a: self.a.into(),
+            x: self.x.into(),
+            y: self.y.into(),
+            s: self.s.into(),
+            p: Byte::from(self.p).into(),
+            pc: self.pc.into(),
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &CPUSnapshot) {
+        self.a = snapshot.a.into();
+        self.x = snapshot.x.into();
+        self.y = snapshot.y.into();
+        self.s = snapshot.s.into();
+        self.p = snapshot.p.into();
+        self.pc = snapshot.pc.into();
+        self.cycles = snapshot.cycles;
+    }
+
     pub fn interrupted(&self) -> bool {
         self.p.is_set(CPUStatus::I)
     }
 
-    pub fn reset(&mut self) {
-        self.cycles += 5;
-        self.pc = self.read_word(0xFFFCu16);
+    // Interrupt sequences take 7 cycles:
+    // 2 reads of PC where the next opcode would be fetched, 3 stack pushes and 2 vector reads.
+    // https://wiki.nesdev.com/w/index.php/CPU_interrupts#IRQ_and_NMI_tick-by-tick_execution
+    fn interrupt_entry_reads<M: Memory>(&mut self, bus: &mut M) {
+        self.read(bus, self.pc);
+        self.read(bus, self.pc);
+    }
+
+    pub fn reset<M: Memory>(&mut self, bus: &mut M) {
+        self.interrupt_entry_reads(bus);
+        // the pushes are reads on reset
+        for _ in 0..3 {
+            self.read(bus, Word::from(self.s) + 0x100);
+            self.s -= 1;
+        }
+        self.pc = self.read_word(bus, 0xFFFCu16);
         self.p.set(CPUStatus::I);
-        self.s -= 3
     }
 
     // NMI
-    pub fn non_markable_interrupt(&mut self) {
-        self.cycles += 2;
-        self.push_stack_word(self.pc);
+    pub fn non_markable_interrupt<M: Memory>(&mut self, bus: &mut M) {
+        self.interrupt_entry_reads(bus);
+        self.push_stack_word(bus, self.pc);
         // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
         // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-        self.push_stack(self.p | CPUStatus::INTERRUPTED_B);
+        self.push_stack(bus, self.p | CPUStatus::INTERRUPTED_B);
         self.p.set(CPUStatus::I);
-        self.pc = self.read_word(0xFFFAu16)
+        self.pc = self.read_word(bus, 0xFFFAu16)
     }
 
     // IRQ
-    pub fn interrupt_request(&mut self) {
-        self.cycles += 2;
-        self.push_stack_word(self.pc);
+    pub fn interrupt_request<M: Memory>(&mut self, bus: &mut M) {
+        self.interrupt_entry_reads(bus);
+        self.push_stack_word(bus, self.pc);
         // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
         // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-        self.push_stack(self.p | CPUStatus::INTERRUPTED_B);
+        self.push_stack(bus, self.p | CPUStatus::INTERRUPTED_B);
         self.p.set(CPUStatus::I);
-        self.pc = self.read_word(0xFFFEu16)
+        self.pc = self.read_word(bus, 0xFFFEu16)
     }
 
     // BRK
-    pub fn break_interrupt(&mut self) {
-        self.cycles += 2;
+    pub fn break_interrupt<M: Memory>(&mut self, bus: &mut M) {
+        self.dummy_read(bus, self.pc);
         self.pc += 1;
-        self.push_stack_word(self.pc);
+        self.dummy_read(bus, self.pc);
+        self.push_stack_word(bus, self.pc);
         // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
         // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-        self.push_stack(self.p | CPUStatus::INTERRUPTED_B);
+        self.push_stack(bus, self.p | CPUStatus::INTERRUPTED_B);
         self.p.set(CPUStatus::I);
-        self.pc = self.read_word(0xFFFEu16)
+        self.pc = self.read_word(bus, 0xFFFEu16)
     }
 }
 
@@ -167,31 +270,30 @@ pub fn page_crossed<A: Into<i64>, B: Into<i64>>(value: A, from: B) -> bool {
 mod tests {
     use super::*;
 
-    fn new_cpu() -> CPU {
-        let test_mem: Box<dyn Memory> = Box::new([0; 0x10000]);
-        CPU::new(test_mem)
+    fn new_cpu() -> (CPU, Box<dyn Memory>) {
+        (CPU::default(), Box::new([0; 0x10000]))
     }
 
     #[test]
     fn fetch() {
-        let mut cpu = new_cpu();
-        cpu.write(0x9051u16, 0x90);
-        cpu.write(0x9052u16, 0x3F);
-        cpu.write(0x9053u16, 0x81);
-        cpu.write(0x9054u16, 0x90);
+        let (mut cpu, mut bus) = new_cpu();
+        cpu.write(&mut bus, 0x9051u16, 0x90);
+        cpu.write(&mut bus, 0x9052u16, 0x3F);
+        cpu.write(&mut bus, 0x9053u16, 0x81);
+        cpu.write(&mut bus, 0x9054u16, 0x90);
 
         cpu.pc = 0x9052u16.into();
 
-        let instruction = cpu.fetch();
+        let instruction = cpu.fetch(&mut bus);
         assert_eq!(instruction, 0x3F.into());
 
-        let instruction = cpu.fetch();
+        let instruction = cpu.fetch(&mut bus);
         assert_eq!(instruction, 0x81.into());
     }
 
     #[test]
     fn reset() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         cpu.a = 0xFA.into();
         cpu.x = 0x1F.into();
@@ -200,12 +302,13 @@ mod tests {
         cpu.p = CPUStatus::N | CPUStatus::V;
         cpu.pc = 0b01010110_10001101u16.into();
 
-        cpu.write(0xFFFBu16, 1);
-        cpu.write(0xFFFCu16, 32);
-        cpu.write(0xFFFDu16, 127);
-        cpu.write(0xFFFEu16, 64);
+        cpu.write(&mut bus, 0xFFFBu16, 1);
+        cpu.write(&mut bus, 0xFFFCu16, 32);
+        cpu.write(&mut bus, 0xFFFDu16, 127);
+        cpu.write(&mut bus, 0xFFFEu16, 64);
+        cpu.cycles = 0;
 
-        cpu.reset();
+        cpu.reset(&mut bus);
 
         assert_eq!(cpu.a, 0xFA.into());
         assert_eq!(cpu.x, 0x1F.into());
@@ -213,29 +316,46 @@ mod tests {
         assert_eq!(cpu.s, 0x34.into());
         assert_eq!(cpu.p, CPUStatus::N | CPUStatus::V | CPUStatus::I);
         assert_eq!(cpu.pc, 0b01111111_00100000u16.into());
+        assert_eq!(cpu.cycles, 7);
+    }
+
+    #[test]
+    fn interrupt_cycles() {
+        let (mut cpu, mut bus) = new_cpu();
+        cpu.s = 0xFF.into();
+        cpu.pc = 0x8123u16.into();
+        cpu.write(&mut bus, 0xFFFAu16, 0x00);
+        cpu.write(&mut bus, 0xFFFBu16, 0x90);
+        cpu.cycles = 0;
+
+        cpu.non_markable_interrupt(&mut bus);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.pc, 0x9000u16.into());
+        assert_eq!(cpu.pull_stack(&mut bus), (CPUStatus::INTERRUPTED_B).into());
+        assert_eq!(cpu.pull_stack_word(&mut bus), 0x8123u16.into());
     }
 
     #[test]
     fn stack() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
         cpu.s = 0xFF.into();
 
-        cpu.push_stack(0x83);
-        cpu.push_stack(0x14);
+        cpu.push_stack(&mut bus, 0x83);
+        cpu.push_stack(&mut bus, 0x14);
 
-        assert_eq!(cpu.pull_stack(), 0x14.into());
-        assert_eq!(cpu.pull_stack(), 0x83.into());
+        assert_eq!(cpu.pull_stack(&mut bus), 0x14.into());
+        assert_eq!(cpu.pull_stack(&mut bus), 0x83.into());
     }
 
     #[test]
     fn stack_word() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
         cpu.s = 0xFF.into();
 
-        cpu.push_stack_word(0x98AFu16);
-        cpu.push_stack_word(0x003Au16);
+        cpu.push_stack_word(&mut bus, 0x98AFu16);
+        cpu.push_stack_word(&mut bus, 0x003Au16);
 
-        assert_eq!(cpu.pull_stack_word(), 0x003Au16.into());
-        assert_eq!(cpu.pull_stack_word(), 0x98AFu16.into());
+        assert_eq!(cpu.pull_stack_word(&mut bus), 0x003Au16.into());
+        assert_eq!(cpu.pull_stack_word(&mut bus), 0x98AFu16.into());
     }
 }
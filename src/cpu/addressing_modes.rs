@@ -1,4 +1,4 @@
-use crate::types::Word;
+use crate::types::{Memory, Word};
 
 use super::{page_crossed_u16, CPU};
 
@@ -19,92 +19,91 @@ pub enum AddressingMode {
     Relative,
     Indirect,
     IndexedIndirect,
-    IndirectIndexed,
+    IndirectIndexed { penalty: bool },
 }
 
 impl AddressingMode {
-    pub fn get_operand(&self, cpu: &mut CPU) -> Operand {
+    pub fn get_operand<M: Memory>(&self, cpu: &mut CPU, bus: &mut M) -> Operand {
         match self {
-            Self::Implicit => Word::from(0x00u16),
-            Self::Accumulator => cpu.a.into(),
+            Self::Implicit => {
+                // the byte after the opcode is read and thrown away
+                cpu.dummy_read(bus, cpu.pc);
+                Word::from(0x00u16)
+            }
+            Self::Accumulator => {
+                cpu.dummy_read(bus, cpu.pc);
+                cpu.a.into()
+            }
             Self::Immediate => {
                 let operand = cpu.pc;
                 cpu.pc += 1;
                 operand
             }
             Self::ZeroPage => {
-                let operand = Word::from(cpu.read(cpu.pc)) & 0xFF;
+                let operand = Word::from(cpu.read(bus, cpu.pc)) & 0xFF;
                 cpu.pc += 1;
                 operand
             }
             Self::ZeroPageX => {
-                let operand = (Word::from(cpu.read(cpu.pc)) + Word::from(cpu.x)) & 0xFF;
+                let data = Word::from(cpu.read(bus, cpu.pc)) & 0xFF;
                 cpu.pc += 1;
-                cpu.cycles += 1;
-                operand
+                cpu.dummy_read(bus, data);
+                (data + Word::from(cpu.x)) & 0xFF
             }
             Self::ZeroPageY => {
-                let operand = (Word::from(cpu.read(cpu.pc)) + Word::from(cpu.y)) & 0xFF;
+                let data = Word::from(cpu.read(bus, cpu.pc)) & 0xFF;
                 cpu.pc += 1;
-                cpu.cycles += 1;
-                operand
+                cpu.dummy_read(bus, data);
+                (data + Word::from(cpu.y)) & 0xFF
             }
             Self::Absolute => {
-                let operand = cpu.read_word(cpu.pc);
+                let operand = cpu.read_word(bus, cpu.pc);
                 cpu.pc += 2;
                 operand
             }
             Self::AbsoluteX { penalty } => {
-                let data = cpu.read_word(cpu.pc);
+                let data = cpu.read_word(bus, cpu.pc);
                 let operand = data + Word::from(cpu.x);
                 cpu.pc += 2;
-                if *penalty {
-                    if page_crossed_u16(cpu.x, data) {
-                        cpu.cycles += 1;
-                    }
-                } else {
-                    cpu.cycles += 1;
+                if !*penalty || page_crossed_u16(cpu.x, data) {
+                    cpu.dummy_read(bus, unfixed(data, operand));
                 }
                 operand
             }
             Self::AbsoluteY { penalty } => {
-                let data = cpu.read_word(cpu.pc);
+                let data = cpu.read_word(bus, cpu.pc);
                 let operand = data + Word::from(cpu.y);
                 cpu.pc += 2;
-                if *penalty {
-                    if page_crossed_u16(cpu.y, data) {
-                        cpu.cycles += 1;
-                    }
-                } else {
-                    cpu.cycles += 1;
+                if !*penalty || page_crossed_u16(cpu.y, data) {
+                    cpu.dummy_read(bus, unfixed(data, operand));
                 }
                 operand
             }
             Self::Relative => {
-                let operand: Word = cpu.read(cpu.pc).into();
+                let operand: Word = cpu.read(bus, cpu.pc).into();
                 cpu.pc += 1;
                 operand
             }
             Self::Indirect => {
-                let data = cpu.read_word(cpu.pc);
-                let operand = cpu.read_on_indirect(data);
+                let data = cpu.read_word(bus, cpu.pc);
+                let operand = cpu.read_on_indirect(bus, data);
                 cpu.pc += 2;
                 operand
             }
             Self::IndexedIndirect => {
-                let data = cpu.read(cpu.pc);
-                let operand = cpu.read_on_indirect(Word::from(data + cpu.x) & 0xFF);
+                let data = Word::from(cpu.read(bus, cpu.pc));
                 cpu.pc += 1;
-                cpu.cycles += 1;
-                operand
+                cpu.dummy_read(bus, data);
+                cpu.read_on_indirect(bus, (data + Word::from(cpu.x)) & 0xFF)
             }
-            Self::IndirectIndexed => {
+            Self::IndirectIndexed { penalty } => {
                 let y: Word = cpu.y.into();
-                let data: Word = cpu.read(cpu.pc).into();
-                let operand = cpu.read_on_indirect(data) + y;
+                let data: Word = cpu.read(bus, cpu.pc).into();
+                let base = cpu.read_on_indirect(bus, data);
+                let operand = base + y;
                 cpu.pc += 1;
-                if page_crossed_u16(y, operand - y) {
-                    cpu.cycles += 1;
+                if !*penalty || page_crossed_u16(y, base) {
+                    cpu.dummy_read(bus, unfixed(base, operand));
                 }
                 operand
             }
@@ -112,162 +111,170 @@ impl AddressingMode {
     }
 }
 
+// the address read before the carry of an index into the high byte
+fn unfixed(base: Word, operand: Word) -> Word {
+    base & 0xFF00 | operand & 0x00FF
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::cpu::CPU;
     use crate::types::Memory;
 
-    fn new_cpu() -> CPU {
-        let test_mem: Box<dyn Memory> = Box::new([0; 0x10000]);
-        let mut cpu = CPU::new(test_mem);
-        cpu.x = 0x05.into();
-        cpu.y = 0x80.into();
-        cpu.pc = 0x8234u16.into();
-        cpu.write(0x8234u16, 0x90u8);
-        cpu.write(0x8235u16, 0x94u8);
-        cpu.write(0x9490u16, 0x33u8);
-        cpu.write(0x9491u16, 0x81u8);
-        cpu.write(0x8234u16, 0x90u8);
-        cpu.write(0x8235u16, 0x94u8);
-        cpu.write(0x9490u16, 0x33u8);
-        cpu.write(0x9491u16, 0x81u8);
-        cpu
+    fn new_cpu() -> (CPU, Box<dyn Memory>) {
+        let mut bus: Box<dyn Memory> = Box::new([0; 0x10000]);
+        let mut cpu = CPU {
+            x: 0x05.into(),
+            y: 0x80.into(),
+            pc: 0x8234u16.into(),
+            ..Default::default()
+        };
+        cpu.write(&mut bus, 0x8234u16, 0x90u8);
+        cpu.write(&mut bus, 0x8235u16, 0x94u8);
+        cpu.write(&mut bus, 0x9490u16, 0x33u8);
+        cpu.write(&mut bus, 0x9491u16, 0x81u8);
+        cpu.write(&mut bus, 0x8234u16, 0x90u8);
+        cpu.write(&mut bus, 0x8235u16, 0x94u8);
+        cpu.write(&mut bus, 0x9490u16, 0x33u8);
+        cpu.write(&mut bus, 0x9491u16, 0x81u8);
+        (cpu, bus)
     }
 
     #[test]
     fn implicit() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::Implicit.get_operand(&mut cpu);
+        let operand = AddressingMode::Implicit.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x00u16.into());
         assert_eq!(cpu.pc - before, 0u16.into());
     }
 
     #[test]
     fn accumulator() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
         cpu.a = 0xFA.into();
 
         let before = cpu.pc;
-        let operand = AddressingMode::Accumulator.get_operand(&mut cpu);
+        let operand = AddressingMode::Accumulator.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0xFAu16.into());
         assert_eq!(cpu.pc - before, 0u16.into());
     }
 
     #[test]
     fn immediate() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::Immediate.get_operand(&mut cpu);
+        let operand = AddressingMode::Immediate.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x8234u16.into());
         assert_eq!(cpu.pc - before, 1u16.into());
     }
 
     #[test]
     fn zero_page() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::ZeroPage.get_operand(&mut cpu);
+        let operand = AddressingMode::ZeroPage.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x0090u16.into());
         assert_eq!(cpu.pc - before, 1u16.into());
     }
 
     #[test]
     fn zero_page_x() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::ZeroPageX.get_operand(&mut cpu);
+        let operand = AddressingMode::ZeroPageX.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x0095u16.into()); // 0x90 + 0x05 & 0xFF
         assert_eq!(cpu.pc - before, 1u16.into());
     }
 
     #[test]
     fn zero_page_y() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::ZeroPageY.get_operand(&mut cpu);
+        let operand = AddressingMode::ZeroPageY.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x0010u16.into()); // (0x90 + 0x80) & 0xFF
         assert_eq!(cpu.pc - before, 1u16.into());
     }
 
     #[test]
     fn absolute() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::Absolute.get_operand(&mut cpu);
+        let operand = AddressingMode::Absolute.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x9490u16.into());
         assert_eq!(cpu.pc - before, 2u16.into());
     }
 
     #[test]
     fn absolute_x() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::AbsoluteX { penalty: false }.get_operand(&mut cpu);
+        let operand = AddressingMode::AbsoluteX { penalty: false }.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x9495u16.into()); // 0x9490 + 0x05
         assert_eq!(cpu.pc - before, 2u16.into());
     }
 
     #[test]
     fn absolute_y() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::AbsoluteY { penalty: false }.get_operand(&mut cpu);
+        let operand = AddressingMode::AbsoluteY { penalty: false }.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x9510u16.into()); // 0x9490 + 0x80
         assert_eq!(cpu.pc - before, 2u16.into());
     }
 
     #[test]
     fn relative() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
         cpu.pc = 0x0050u16.into();
-        cpu.write(0x0050u16, 0x78);
+        cpu.write(&mut bus, 0x0050u16, 0x78);
 
         let before = cpu.pc;
-        let operand = AddressingMode::Relative.get_operand(&mut cpu);
+        let operand = AddressingMode::Relative.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x78u16.into());
         assert_eq!(cpu.pc - before, 1u16.into());
     }
 
     #[test]
     fn indirect() {
-        let mut cpu = new_cpu();
+        let (mut cpu, mut bus) = new_cpu();
 
         let before = cpu.pc;
-        let operand = AddressingMode::Indirect.get_operand(&mut cpu);
+        let operand = AddressingMode::Indirect.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0x8133u16.into()); // 0x33 + (0x81 << 8)
         assert_eq!(cpu.pc - before, 2u16.into());
     }
 
     #[test]
     fn indexed_indirect() {
-        let mut cpu = new_cpu();
-        cpu.write(0x0095u16, 0xFF);
-        cpu.write(0x0096u16, 0xF0);
+        let (mut cpu, mut bus) = new_cpu();
+        cpu.write(&mut bus, 0x0095u16, 0xFF);
+        cpu.write(&mut bus, 0x0096u16, 0xF0);
 
         let before = cpu.pc;
-        let operand = AddressingMode::IndexedIndirect.get_operand(&mut cpu);
+        let operand = AddressingMode::IndexedIndirect.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0xF0FFu16.into()); // 0xFF + (0xF0 << 8)
         assert_eq!(cpu.pc - before, 1u16.into());
     }
 
     #[test]
     fn indirect_indexed() {
-        let mut cpu = new_cpu();
-        cpu.write(0x0090u16, 0x43);
-        cpu.write(0x0091u16, 0xC0);
+        let (mut cpu, mut bus) = new_cpu();
+        cpu.write(&mut bus, 0x0090u16, 0x43);
+        cpu.write(&mut bus, 0x0091u16, 0xC0);
 
         let before = cpu.pc;
-        let operand = AddressingMode::IndirectIndexed.get_operand(&mut cpu);
+        let operand =
+            AddressingMode::IndirectIndexed { penalty: true }.get_operand(&mut cpu, &mut bus);
         assert_eq!(operand, 0xC0C3u16.into()); // 0xC043 + Y
         assert_eq!(cpu.pc - before, 1u16.into());
     }
@@ -1,4 +1,4 @@
-use crate::types::{Byte, Word};
+use crate::types::{Byte, Memory, Word};
 
 use super::addressing_modes::{AddressingMode, Operand};
 use super::status::CPUStatus;
@@ -100,7 +100,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0xBD => (Mnemonic::LDA, AddressingMode::AbsoluteX { penalty: true }),
         0xB9 => (Mnemonic::LDA, AddressingMode::AbsoluteY { penalty: true }),
         0xA1 => (Mnemonic::LDA, AddressingMode::IndexedIndirect),
-        0xB1 => (Mnemonic::LDA, AddressingMode::IndirectIndexed),
+        0xB1 => (
+            Mnemonic::LDA,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0xA2 => (Mnemonic::LDX, AddressingMode::Immediate),
         0xA6 => (Mnemonic::LDX, AddressingMode::ZeroPage),
         0xB6 => (Mnemonic::LDX, AddressingMode::ZeroPageY),
@@ -117,7 +120,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x9D => (Mnemonic::STA, AddressingMode::AbsoluteX { penalty: false }),
         0x99 => (Mnemonic::STA, AddressingMode::AbsoluteY { penalty: false }),
         0x81 => (Mnemonic::STA, AddressingMode::IndexedIndirect),
-        0x91 => (Mnemonic::STA, AddressingMode::IndirectIndexed),
+        0x91 => (
+            Mnemonic::STA,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0x86 => (Mnemonic::STX, AddressingMode::ZeroPage),
         0x96 => (Mnemonic::STX, AddressingMode::ZeroPageY),
         0x8E => (Mnemonic::STX, AddressingMode::Absolute),
@@ -143,7 +149,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x3D => (Mnemonic::AND, AddressingMode::AbsoluteX { penalty: true }),
         0x39 => (Mnemonic::AND, AddressingMode::AbsoluteY { penalty: true }),
         0x21 => (Mnemonic::AND, AddressingMode::IndexedIndirect),
-        0x31 => (Mnemonic::AND, AddressingMode::IndirectIndexed),
+        0x31 => (
+            Mnemonic::AND,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0x49 => (Mnemonic::EOR, AddressingMode::Immediate),
         0x45 => (Mnemonic::EOR, AddressingMode::ZeroPage),
         0x55 => (Mnemonic::EOR, AddressingMode::ZeroPageX),
@@ -151,7 +160,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x5D => (Mnemonic::EOR, AddressingMode::AbsoluteX { penalty: true }),
         0x59 => (Mnemonic::EOR, AddressingMode::AbsoluteY { penalty: true }),
         0x41 => (Mnemonic::EOR, AddressingMode::IndexedIndirect),
-        0x51 => (Mnemonic::EOR, AddressingMode::IndirectIndexed),
+        0x51 => (
+            Mnemonic::EOR,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0x09 => (Mnemonic::ORA, AddressingMode::Immediate),
         0x05 => (Mnemonic::ORA, AddressingMode::ZeroPage),
         0x15 => (Mnemonic::ORA, AddressingMode::ZeroPageX),
@@ -159,7 +171,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x1D => (Mnemonic::ORA, AddressingMode::AbsoluteX { penalty: true }),
         0x19 => (Mnemonic::ORA, AddressingMode::AbsoluteY { penalty: true }),
         0x01 => (Mnemonic::ORA, AddressingMode::IndexedIndirect),
-        0x11 => (Mnemonic::ORA, AddressingMode::IndirectIndexed),
+        0x11 => (
+            Mnemonic::ORA,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0x24 => (Mnemonic::BIT, AddressingMode::ZeroPage),
         0x2C => (Mnemonic::BIT, AddressingMode::Absolute),
 
@@ -170,7 +185,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x7D => (Mnemonic::ADC, AddressingMode::AbsoluteX { penalty: true }),
         0x79 => (Mnemonic::ADC, AddressingMode::AbsoluteY { penalty: true }),
         0x61 => (Mnemonic::ADC, AddressingMode::IndexedIndirect),
-        0x71 => (Mnemonic::ADC, AddressingMode::IndirectIndexed),
+        0x71 => (
+            Mnemonic::ADC,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0xE9 => (Mnemonic::SBC, AddressingMode::Immediate),
         0xE5 => (Mnemonic::SBC, AddressingMode::ZeroPage),
         0xF5 => (Mnemonic::SBC, AddressingMode::ZeroPageX),
@@ -178,7 +196,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0xFD => (Mnemonic::SBC, AddressingMode::AbsoluteX { penalty: true }),
         0xF9 => (Mnemonic::SBC, AddressingMode::AbsoluteY { penalty: true }),
         0xE1 => (Mnemonic::SBC, AddressingMode::IndexedIndirect),
-        0xF1 => (Mnemonic::SBC, AddressingMode::IndirectIndexed),
+        0xF1 => (
+            Mnemonic::SBC,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0xC9 => (Mnemonic::CMP, AddressingMode::Immediate),
         0xC5 => (Mnemonic::CMP, AddressingMode::ZeroPage),
         0xD5 => (Mnemonic::CMP, AddressingMode::ZeroPageX),
@@ -186,7 +207,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0xDD => (Mnemonic::CMP, AddressingMode::AbsoluteX { penalty: true }),
         0xD9 => (Mnemonic::CMP, AddressingMode::AbsoluteY { penalty: true }),
         0xC1 => (Mnemonic::CMP, AddressingMode::IndexedIndirect),
-        0xD1 => (Mnemonic::CMP, AddressingMode::IndirectIndexed),
+        0xD1 => (
+            Mnemonic::CMP,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0xE0 => (Mnemonic::CPX, AddressingMode::Immediate),
         0xE4 => (Mnemonic::CPX, AddressingMode::ZeroPage),
         0xEC => (Mnemonic::CPX, AddressingMode::Absolute),
@@ -269,7 +293,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0xA3 => (Mnemonic::LAX, AddressingMode::IndexedIndirect),
         0xA7 => (Mnemonic::LAX, AddressingMode::ZeroPage),
         0xAF => (Mnemonic::LAX, AddressingMode::Absolute),
-        0xB3 => (Mnemonic::LAX, AddressingMode::IndirectIndexed),
+        0xB3 => (
+            Mnemonic::LAX,
+            AddressingMode::IndirectIndexed { penalty: true },
+        ),
         0xB7 => (Mnemonic::LAX, AddressingMode::ZeroPageY),
         0xBF => (Mnemonic::LAX, AddressingMode::AbsoluteY { penalty: true }),
 
@@ -281,7 +308,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0xC3 => (Mnemonic::DCP, AddressingMode::IndexedIndirect),
         0xC7 => (Mnemonic::DCP, AddressingMode::ZeroPage),
         0xCF => (Mnemonic::DCP, AddressingMode::Absolute),
-        0xD3 => (Mnemonic::DCP, AddressingMode::IndirectIndexed),
+        0xD3 => (
+            Mnemonic::DCP,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0xD7 => (Mnemonic::DCP, AddressingMode::ZeroPageX),
         0xDB => (Mnemonic::DCP, AddressingMode::AbsoluteY { penalty: false }),
         0xDF => (Mnemonic::DCP, AddressingMode::AbsoluteX { penalty: false }),
@@ -289,7 +319,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0xE3 => (Mnemonic::ISB, AddressingMode::IndexedIndirect),
         0xE7 => (Mnemonic::ISB, AddressingMode::ZeroPage),
         0xEF => (Mnemonic::ISB, AddressingMode::Absolute),
-        0xF3 => (Mnemonic::ISB, AddressingMode::IndirectIndexed),
+        0xF3 => (
+            Mnemonic::ISB,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0xF7 => (Mnemonic::ISB, AddressingMode::ZeroPageX),
         0xFB => (Mnemonic::ISB, AddressingMode::AbsoluteY { penalty: false }),
         0xFF => (Mnemonic::ISB, AddressingMode::AbsoluteX { penalty: false }),
@@ -297,7 +330,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x03 => (Mnemonic::SLO, AddressingMode::IndexedIndirect),
         0x07 => (Mnemonic::SLO, AddressingMode::ZeroPage),
         0x0F => (Mnemonic::SLO, AddressingMode::Absolute),
-        0x13 => (Mnemonic::SLO, AddressingMode::IndirectIndexed),
+        0x13 => (
+            Mnemonic::SLO,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0x17 => (Mnemonic::SLO, AddressingMode::ZeroPageX),
         0x1B => (Mnemonic::SLO, AddressingMode::AbsoluteY { penalty: false }),
         0x1F => (Mnemonic::SLO, AddressingMode::AbsoluteX { penalty: false }),
@@ -305,7 +341,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x23 => (Mnemonic::RLA, AddressingMode::IndexedIndirect),
         0x27 => (Mnemonic::RLA, AddressingMode::ZeroPage),
         0x2F => (Mnemonic::RLA, AddressingMode::Absolute),
-        0x33 => (Mnemonic::RLA, AddressingMode::IndirectIndexed),
+        0x33 => (
+            Mnemonic::RLA,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0x37 => (Mnemonic::RLA, AddressingMode::ZeroPageX),
         0x3B => (Mnemonic::RLA, AddressingMode::AbsoluteY { penalty: false }),
         0x3F => (Mnemonic::RLA, AddressingMode::AbsoluteX { penalty: false }),
@@ -313,7 +352,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x43 => (Mnemonic::SRE, AddressingMode::IndexedIndirect),
         0x47 => (Mnemonic::SRE, AddressingMode::ZeroPage),
         0x4F => (Mnemonic::SRE, AddressingMode::Absolute),
-        0x53 => (Mnemonic::SRE, AddressingMode::IndirectIndexed),
+        0x53 => (
+            Mnemonic::SRE,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0x57 => (Mnemonic::SRE, AddressingMode::ZeroPageX),
         0x5B => (Mnemonic::SRE, AddressingMode::AbsoluteY { penalty: false }),
         0x5F => (Mnemonic::SRE, AddressingMode::AbsoluteX { penalty: false }),
@@ -321,7 +363,10 @@ pub fn decode(opcode: Byte) -> Opcode {
         0x63 => (Mnemonic::RRA, AddressingMode::IndexedIndirect),
         0x67 => (Mnemonic::RRA, AddressingMode::ZeroPage),
         0x6F => (Mnemonic::RRA, AddressingMode::Absolute),
-        0x73 => (Mnemonic::RRA, AddressingMode::IndirectIndexed),
+        0x73 => (
+            Mnemonic::RRA,
+            AddressingMode::IndirectIndexed { penalty: false },
+        ),
         0x77 => (Mnemonic::RRA, AddressingMode::ZeroPageX),
         0x7B => (Mnemonic::RRA, AddressingMode::AbsoluteY { penalty: false }),
         0x7F => (Mnemonic::RRA, AddressingMode::AbsoluteX { penalty: false }),
@@ -334,65 +379,64 @@ pub fn decode(opcode: Byte) -> Opcode {
     }
 }
 
-pub fn execute(cpu: &mut CPU, opcode: Opcode) {
-    let operand = opcode.addressing_mode.get_operand(cpu);
+pub fn execute<M: Memory>(cpu: &mut CPU, bus: &mut M, opcode: Opcode) {
+    if opcode.mnemonic == Mnemonic::JSR {
+        return jsr(cpu, bus);
+    }
+    let operand = opcode.addressing_mode.get_operand(cpu, bus);
 
     match (opcode.mnemonic, opcode.addressing_mode) {
-        (Mnemonic::LDA, _) => lda(cpu, operand),
-        (Mnemonic::LDX, _) => ldx(cpu, operand),
-        (Mnemonic::LDY, _) => ldy(cpu, operand),
-        (Mnemonic::STA, AddressingMode::IndirectIndexed) => {
-            sta(cpu, operand);
-            cpu.cycles += 1;
-        }
-        (Mnemonic::STA, _) => sta(cpu, operand),
-        (Mnemonic::STX, _) => stx(cpu, operand),
-        (Mnemonic::STY, _) => sty(cpu, operand),
+        (Mnemonic::LDA, _) => lda(cpu, bus, operand),
+        (Mnemonic::LDX, _) => ldx(cpu, bus, operand),
+        (Mnemonic::LDY, _) => ldy(cpu, bus, operand),
+        (Mnemonic::STA, _) => sta(cpu, bus, operand),
+        (Mnemonic::STX, _) => stx(cpu, bus, operand),
+        (Mnemonic::STY, _) => sty(cpu, bus, operand),
         (Mnemonic::TAX, _) => tax(cpu),
         (Mnemonic::TSX, _) => tsx(cpu),
         (Mnemonic::TAY, _) => tay(cpu),
         (Mnemonic::TXA, _) => txa(cpu),
         (Mnemonic::TXS, _) => txs(cpu),
         (Mnemonic::TYA, _) => tya(cpu),
-        (Mnemonic::PHA, _) => pha(cpu),
-        (Mnemonic::PHP, _) => php(cpu),
-        (Mnemonic::PLA, _) => pla(cpu),
-        (Mnemonic::PLP, _) => plp(cpu),
-        (Mnemonic::AND, _) => and(cpu, operand),
-        (Mnemonic::EOR, _) => eor(cpu, operand),
-        (Mnemonic::ORA, _) => ora(cpu, operand),
-        (Mnemonic::BIT, _) => bit(cpu, operand),
-        (Mnemonic::ADC, _) => adc(cpu, operand),
-        (Mnemonic::SBC, _) => sbc(cpu, operand),
-        (Mnemonic::CMP, _) => cmp(cpu, operand),
-        (Mnemonic::CPX, _) => cpx(cpu, operand),
-        (Mnemonic::CPY, _) => cpy(cpu, operand),
-        (Mnemonic::INC, _) => inc(cpu, operand),
+        (Mnemonic::PHA, _) => pha(cpu, bus),
+        (Mnemonic::PHP, _) => php(cpu, bus),
+        (Mnemonic::PLA, _) => pla(cpu, bus),
+        (Mnemonic::PLP, _) => plp(cpu, bus),
+        (Mnemonic::AND, _) => and(cpu, bus, operand),
+        (Mnemonic::EOR, _) => eor(cpu, bus, operand),
+        (Mnemonic::ORA, _) => ora(cpu, bus, operand),
+        (Mnemonic::BIT, _) => bit(cpu, bus, operand),
+        (Mnemonic::ADC, _) => adc(cpu, bus, operand),
+        (Mnemonic::SBC, _) => sbc(cpu, bus, operand),
+        (Mnemonic::CMP, _) => cmp(cpu, bus, operand),
+        (Mnemonic::CPX, _) => cpx(cpu, bus, operand),
+        (Mnemonic::CPY, _) => cpy(cpu, bus, operand),
+        (Mnemonic::INC, _) => inc(cpu, bus, operand),
         (Mnemonic::INX, _) => inx(cpu),
         (Mnemonic::INY, _) => iny(cpu),
-        (Mnemonic::DEC, _) => dec(cpu, operand),
+        (Mnemonic::DEC, _) => dec(cpu, bus, operand),
         (Mnemonic::DEX, _) => dex(cpu),
         (Mnemonic::DEY, _) => dey(cpu),
         (Mnemonic::ASL, AddressingMode::Accumulator) => asl_for_accumelator(cpu),
-        (Mnemonic::ASL, _) => asl(cpu, operand),
+        (Mnemonic::ASL, _) => asl(cpu, bus, operand),
         (Mnemonic::LSR, AddressingMode::Accumulator) => lsr_for_accumelator(cpu),
-        (Mnemonic::LSR, _) => lsr(cpu, operand),
+        (Mnemonic::LSR, _) => lsr(cpu, bus, operand),
         (Mnemonic::ROL, AddressingMode::Accumulator) => rol_for_accumelator(cpu),
-        (Mnemonic::ROL, _) => rol(cpu, operand),
+        (Mnemonic::ROL, _) => rol(cpu, bus, operand),
         (Mnemonic::ROR, AddressingMode::Accumulator) => ror_for_accumelator(cpu),
-        (Mnemonic::ROR, _) => ror(cpu, operand),
+        (Mnemonic::ROR, _) => ror(cpu, bus, operand),
         (Mnemonic::JMP, _) => jmp(cpu, operand),
-        (Mnemonic::JSR, _) => jsr(cpu, operand),
-        (Mnemonic::RTS, _) => rts(cpu),
-        (Mnemonic::RTI, _) => rti(cpu),
-        (Mnemonic::BCC, _) => bcc(cpu, operand),
-        (Mnemonic::BCS, _) => bcs(cpu, operand),
-        (Mnemonic::BEQ, _) => beq(cpu, operand),
-        (Mnemonic::BMI, _) => bmi(cpu, operand),
-        (Mnemonic::BNE, _) => bne(cpu, operand),
-        (Mnemonic::BPL, _) => bpl(cpu, operand),
-        (Mnemonic::BVC, _) => bvc(cpu, operand),
-        (Mnemonic::BVS, _) => bvs(cpu, operand),
+        (Mnemonic::JSR, _) => unreachable!("JSR reads its operand itself"),
+        (Mnemonic::RTS, _) => rts(cpu, bus),
+        (Mnemonic::RTI, _) => rti(cpu, bus),
+        (Mnemonic::BCC, _) => bcc(cpu, bus, operand),
+        (Mnemonic::BCS, _) => bcs(cpu, bus, operand),
+        (Mnemonic::BEQ, _) => beq(cpu, bus, operand),
+        (Mnemonic::BMI, _) => bmi(cpu, bus, operand),
+        (Mnemonic::BNE, _) => bne(cpu, bus, operand),
+        (Mnemonic::BPL, _) => bpl(cpu, bus, operand),
+        (Mnemonic::BVC, _) => bvc(cpu, bus, operand),
+        (Mnemonic::BVS, _) => bvs(cpu, bus, operand),
         (Mnemonic::CLC, _) => clc(cpu),
         (Mnemonic::CLD, _) => cld(cpu),
         (Mnemonic::CLI, _) => cli(cpu),
@@ -400,146 +444,139 @@ pub fn execute(cpu: &mut CPU, opcode: Opcode) {
         (Mnemonic::SEC, _) => sec(cpu),
         (Mnemonic::SED, _) => sed(cpu),
         (Mnemonic::SEI, _) => sei(cpu),
-        (Mnemonic::BRK, _) => brk(cpu),
-        (Mnemonic::NOP, _) => nop(cpu),
-        (Mnemonic::LAX, _) => lax(cpu, operand),
-        (Mnemonic::SAX, _) => sax(cpu, operand),
-        (Mnemonic::DCP, _) => dcp(cpu, operand),
-        (Mnemonic::ISB, _) => isb(cpu, operand),
-        (Mnemonic::SLO, _) => slo(cpu, operand),
-        (Mnemonic::RLA, _) => rla(cpu, operand),
-        (Mnemonic::SRE, _) => sre(cpu, operand),
-        (Mnemonic::RRA, _) => rra(cpu, operand),
+        (Mnemonic::BRK, _) => brk(cpu, bus),
+        (Mnemonic::NOP, AddressingMode::Implicit) => {}
+        (Mnemonic::NOP, _) => nop(cpu, bus, operand),
+        (Mnemonic::LAX, _) => lax(cpu, bus, operand),
+        (Mnemonic::SAX, _) => sax(cpu, bus, operand),
+        (Mnemonic::DCP, _) => dcp(cpu, bus, operand),
+        (Mnemonic::ISB, _) => isb(cpu, bus, operand),
+        (Mnemonic::SLO, _) => slo(cpu, bus, operand),
+        (Mnemonic::RLA, _) => rla(cpu, bus, operand),
+        (Mnemonic::SRE, _) => sre(cpu, bus, operand),
+        (Mnemonic::RRA, _) => rra(cpu, bus, operand),
     }
 }
 
 // LoaD Accumulator
-fn lda(cpu: &mut CPU, operand: Operand) {
-    cpu.a = cpu.read(operand);
+fn lda<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.a = cpu.read(bus, operand);
     cpu.p.update_zn(cpu.a)
 }
 
 // LoaD X register
-fn ldx(cpu: &mut CPU, operand: Operand) {
-    cpu.x = cpu.read(operand);
+fn ldx<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.x = cpu.read(bus, operand);
     cpu.p.update_zn(cpu.x)
 }
 
 // LoaD Y register
-fn ldy(cpu: &mut CPU, operand: Operand) {
-    cpu.y = cpu.read(operand);
+fn ldy<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.y = cpu.read(bus, operand);
     cpu.p.update_zn(cpu.y)
 }
 
 // STore Accumulator
-fn sta(cpu: &mut CPU, operand: Operand) {
-    cpu.write(operand, cpu.a)
+fn sta<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.write(bus, operand, cpu.a)
 }
 
 // STore X register
-fn stx(cpu: &mut CPU, operand: Operand) {
-    cpu.write(operand, cpu.x)
+fn stx<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.write(bus, operand, cpu.x)
 }
 
 // STore Y register
-fn sty(cpu: &mut CPU, operand: Operand) {
-    cpu.write(operand, cpu.y)
+fn sty<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.write(bus, operand, cpu.y)
 }
 
 // Transfer Accumulator to X
 fn tax(cpu: &mut CPU) {
     cpu.x = cpu.a;
     cpu.p.update_zn(cpu.x);
-    cpu.cycles += 1;
 }
 
 // Transfer Stack pointer to X
 fn tsx(cpu: &mut CPU) {
     cpu.x = cpu.s;
     cpu.p.update_zn(cpu.x);
-    cpu.cycles += 1;
 }
 
 // Transfer Accumulator to Y
 fn tay(cpu: &mut CPU) {
     cpu.y = cpu.a;
     cpu.p.update_zn(cpu.y);
-    cpu.cycles += 1;
 }
 
 // Transfer X to Accumulator
 fn txa(cpu: &mut CPU) {
     cpu.a = cpu.x;
     cpu.p.update_zn(cpu.a);
-    cpu.cycles += 1;
 }
 
 // Transfer X to Stack pointer
 fn txs(cpu: &mut CPU) {
     cpu.s = cpu.x;
-    cpu.cycles += 1;
 }
 
 // Transfer Y to Accumulator
 fn tya(cpu: &mut CPU) {
     cpu.a = cpu.y;
     cpu.p.update_zn(cpu.a);
-    cpu.cycles += 1;
 }
 
 // PusH Accumulator
-fn pha(cpu: &mut CPU) {
-    cpu.push_stack(cpu.a);
-    cpu.cycles += 1;
+fn pha<M: Memory>(cpu: &mut CPU, bus: &mut M) {
+    cpu.push_stack(bus, cpu.a);
 }
 
 // PusH Processor status
-fn php(cpu: &mut CPU) {
+fn php<M: Memory>(cpu: &mut CPU, bus: &mut M) {
     // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
     // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-    cpu.push_stack(cpu.p | CPUStatus::OPERATED_B);
-    cpu.cycles += 1;
+    cpu.push_stack(bus, cpu.p | CPUStatus::OPERATED_B);
 }
 
 // PulL Accumulator
-fn pla(cpu: &mut CPU) {
-    cpu.a = cpu.pull_stack();
+fn pla<M: Memory>(cpu: &mut CPU, bus: &mut M) {
+    cpu.dummy_read_stack(bus);
+    cpu.a = cpu.pull_stack(bus);
     cpu.p.update_zn(cpu.a);
-    cpu.cycles += 2;
 }
 
 // PulL Processor status
-fn plp(cpu: &mut CPU) {
+fn plp<M: Memory>(cpu: &mut CPU, bus: &mut M) {
     // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
     // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-    cpu.p = CPUStatus::from(cpu.pull_stack()) & !CPUStatus::B | CPUStatus::R;
-    cpu.cycles += 2
+    cpu.dummy_read_stack(bus);
+    cpu.p = CPUStatus::from(cpu.pull_stack(bus)) & !CPUStatus::B | CPUStatus::R;
 }
 
 // bitwise AND with accumulator
-fn and(cpu: &mut CPU, operand: Operand) {
-    let value = cpu.read(operand);
+fn and<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
     cpu.a &= value;
     cpu.p.update_zn(cpu.a);
 }
 
 // bitwise Exclusive OR
-fn eor(cpu: &mut CPU, operand: Operand) {
-    let value = cpu.read(operand);
+fn eor<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
     cpu.a ^= value;
     cpu.p.update_zn(cpu.a);
 }
 
 // bitwise OR with Accumulator
-fn ora(cpu: &mut CPU, operand: Operand) {
-    let value = cpu.read(operand);
+fn ora<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
     cpu.a |= value;
     cpu.p.update_zn(cpu.a);
 }
 
 // test BITs
-fn bit(cpu: &mut CPU, operand: Operand) {
-    let value = cpu.read(operand);
+fn bit<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
     let data = cpu.a & value;
     cpu.p.update(CPUStatus::Z, data.u8() == 0);
     cpu.p.update(CPUStatus::V, value.nth(6) == 1);
@@ -547,9 +584,13 @@ fn bit(cpu: &mut CPU, operand: Operand) {
 }
 
 // ADd with Carry
-fn adc(cpu: &mut CPU, operand: Operand) {
+fn adc<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
+    add_with_carry(cpu, value)
+}
+
+fn add_with_carry(cpu: &mut CPU, val: Byte) {
     let a = cpu.a;
-    let val = cpu.read(operand);
     let mut result = a + val;
 
     if cpu.p.is_set(CPUStatus::C) {
@@ -570,31 +611,19 @@ fn adc(cpu: &mut CPU, operand: Operand) {
 }
 
 // SuBtract with carry
-fn sbc(cpu: &mut CPU, operand: Operand) {
-    let a = cpu.a;
-    let val = !cpu.read(operand);
-    let mut result = a + val;
-
-    if cpu.p.is_set(CPUStatus::C) {
-        result += 1;
-    }
-
-    // http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-    let a7 = a.nth(7);
-    let v7 = val.nth(7);
-    let c6 = a7 ^ v7 ^ result.nth(7);
-    let c7 = (a7 & v7) | (a7 & c6) | (v7 & c6);
-
-    cpu.p.update(CPUStatus::C, c7 == 1);
-    cpu.p.update(CPUStatus::V, (c6 ^ c7) == 1);
-
-    cpu.a = result;
-    cpu.p.update_zn(cpu.a)
+fn sbc<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
+    add_with_carry(cpu, !value)
 }
 
 // CoMPare accumulator
-fn cmp(cpu: &mut CPU, operand: Operand) {
-    let cmp = Word::from(cpu.a) - Word::from(cpu.read(operand));
+fn cmp<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
+    compare(cpu, value)
+}
+
+fn compare(cpu: &mut CPU, value: Byte) {
+    let cmp = Word::from(cpu.a) - Word::from(value);
     let cmp_i16 = <Word as Into<i16>>::into(cmp);
 
     cpu.p.update(CPUStatus::C, 0 <= cmp_i16);
@@ -602,8 +631,8 @@ fn cmp(cpu: &mut CPU, operand: Operand) {
 }
 
 // ComPare X register
-fn cpx(cpu: &mut CPU, operand: Operand) {
-    let value = cpu.read(operand);
+fn cpx<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
     let cmp = cpu.x - value;
 
     cpu.p.update(CPUStatus::C, value <= cpu.x);
@@ -611,8 +640,8 @@ fn cpx(cpu: &mut CPU, operand: Operand) {
 }
 
 // ComPare Y register
-fn cpy(cpu: &mut CPU, operand: Operand) {
-    let value = cpu.read(operand);
+fn cpy<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let value = cpu.read(bus, operand);
     let cmp = cpu.y - value;
 
     cpu.p.update(CPUStatus::C, value <= cpu.y);
@@ -620,94 +649,89 @@ fn cpy(cpu: &mut CPU, operand: Operand) {
 }
 
 // INCrement memory
-fn inc(cpu: &mut CPU, operand: Operand) {
-    let result = cpu.read(operand) + 1;
+fn inc<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
+    let result = data + 1;
 
     cpu.p.update_zn(result);
-    cpu.write(operand, result);
-    cpu.cycles += 1
+    cpu.write(bus, operand, result);
 }
 
 // INcrement X register
 fn inx(cpu: &mut CPU) {
     cpu.x += 1;
     cpu.p.update_zn(cpu.x);
-    cpu.cycles += 1
 }
 
 // INcrement Y register
 fn iny(cpu: &mut CPU) {
     cpu.y += 1;
     cpu.p.update_zn(cpu.y);
-    cpu.cycles += 1
 }
 
 // DECrement memory
-fn dec(cpu: &mut CPU, operand: Operand) {
-    let result = cpu.read(operand) - 1;
+fn dec<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
+    let result = data - 1;
 
     cpu.p.update_zn(result);
-    cpu.write(operand, result);
-    cpu.cycles += 1
+    cpu.write(bus, operand, result);
 }
 
 // DEcrement X register
 fn dex(cpu: &mut CPU) {
     cpu.x -= 1;
     cpu.p.update_zn(cpu.x);
-    cpu.cycles += 1
 }
 
 // DEcrement Y register
 fn dey(cpu: &mut CPU) {
     cpu.y -= 1;
     cpu.p.update_zn(cpu.y);
-    cpu.cycles += 1
 }
 
 // Arithmetic Shift Left
-fn asl(cpu: &mut CPU, operand: Operand) {
-    let mut data = cpu.read(operand);
+fn asl<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
 
     cpu.p.update(CPUStatus::C, data.nth(7) == 1);
     data <<= 1;
     cpu.p.update_zn(data);
 
-    cpu.write(operand, data);
-    cpu.cycles += 1;
+    cpu.write(bus, operand, data);
 }
 
 fn asl_for_accumelator(cpu: &mut CPU) {
     cpu.p.update(CPUStatus::C, cpu.a.nth(7) == 1);
     cpu.a <<= 1;
     cpu.p.update_zn(cpu.a);
-
-    cpu.cycles += 1;
 }
 
 // Logical Shift Right
-fn lsr(cpu: &mut CPU, operand: Operand) {
-    let mut data = cpu.read(operand);
+fn lsr<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
 
     cpu.p.update(CPUStatus::C, data.nth(0) == 1);
     data >>= 1;
     cpu.p.update_zn(data);
 
-    cpu.write(operand, data);
-    cpu.cycles += 1;
+    cpu.write(bus, operand, data);
 }
 
 fn lsr_for_accumelator(cpu: &mut CPU) {
     cpu.p.update(CPUStatus::C, cpu.a.nth(0) == 1);
     cpu.a >>= 1;
     cpu.p.update_zn(cpu.a);
-
-    cpu.cycles += 1;
 }
 
 // ROtate Left
-fn rol(cpu: &mut CPU, operand: Operand) {
-    let mut data = cpu.read(operand);
+fn rol<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
     let c = data.nth(7);
 
     data <<= 1;
@@ -716,8 +740,7 @@ fn rol(cpu: &mut CPU, operand: Operand) {
     }
     cpu.p.update(CPUStatus::C, c == 1);
     cpu.p.update_zn(data);
-    cpu.write(operand, data);
-    cpu.cycles += 1;
+    cpu.write(bus, operand, data);
 }
 
 fn rol_for_accumelator(cpu: &mut CPU) {
@@ -730,12 +753,12 @@ fn rol_for_accumelator(cpu: &mut CPU) {
     cpu.a = a;
     cpu.p.update(CPUStatus::C, c == 1);
     cpu.p.update_zn(cpu.a);
-    cpu.cycles += 1;
 }
 
 // ROtate Right
-fn ror(cpu: &mut CPU, operand: Operand) {
-    let mut data = cpu.read(operand);
+fn ror<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
     let c = data.nth(0);
 
     data >>= 1;
@@ -744,8 +767,7 @@ fn ror(cpu: &mut CPU, operand: Operand) {
     }
     cpu.p.update(CPUStatus::C, c == 1);
     cpu.p.update_zn(data);
-    cpu.write(operand, data);
-    cpu.cycles += 1;
+    cpu.write(bus, operand, data);
 }
 
 fn ror_for_accumelator(cpu: &mut CPU) {
@@ -758,7 +780,6 @@ fn ror_for_accumelator(cpu: &mut CPU) {
     cpu.a = a;
     cpu.p.update(CPUStatus::C, c == 1);
     cpu.p.update_zn(cpu.a);
-    cpu.cycles += 1;
 }
 
 // JuMP
@@ -767,196 +788,203 @@ fn jmp(cpu: &mut CPU, operand: Operand) {
 }
 
 // Jump to SubRoutine
-fn jsr(cpu: &mut CPU, operand: Operand) {
-    cpu.push_stack_word(cpu.pc - 1);
-    cpu.cycles += 1;
-    cpu.pc = operand
+// The high byte of the address is fetched after the return address is pushed.
+fn jsr<M: Memory>(cpu: &mut CPU, bus: &mut M) {
+    let low = Word::from(cpu.read(bus, cpu.pc));
+    cpu.dummy_read_stack(bus);
+    cpu.push_stack_word(bus, cpu.pc + 1);
+    let high = Word::from(cpu.read(bus, cpu.pc + 1));
+    cpu.pc = high << 8 | low
 }
 
 // ReTurn from Subroutine
-fn rts(cpu: &mut CPU) {
-    cpu.cycles += 3;
-    cpu.pc = cpu.pull_stack_word() + 1
+fn rts<M: Memory>(cpu: &mut CPU, bus: &mut M) {
+    cpu.dummy_read_stack(bus);
+    let pc = cpu.pull_stack_word(bus);
+    cpu.dummy_read(bus, pc);
+    cpu.pc = pc + 1
 }
 
 // ReTurn from Interrupt
-fn rti(cpu: &mut CPU) {
+fn rti<M: Memory>(cpu: &mut CPU, bus: &mut M) {
     // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
     // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-    cpu.cycles += 2;
-    cpu.p = CPUStatus::from(cpu.pull_stack()) & !CPUStatus::B | CPUStatus::R;
-    cpu.pc = cpu.pull_stack_word()
+    cpu.dummy_read_stack(bus);
+    cpu.p = CPUStatus::from(cpu.pull_stack(bus)) & !CPUStatus::B | CPUStatus::R;
+    cpu.pc = cpu.pull_stack_word(bus)
 }
 
 // Branch if Carry Clear
-fn bcc(cpu: &mut CPU, operand: Operand) {
+fn bcc<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if !cpu.p.is_set(CPUStatus::C) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if Carry Set
-fn bcs(cpu: &mut CPU, operand: Operand) {
+fn bcs<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if cpu.p.is_set(CPUStatus::C) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if EQual
-fn beq(cpu: &mut CPU, operand: Operand) {
+fn beq<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if cpu.p.is_set(CPUStatus::Z) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if MInus
-fn bmi(cpu: &mut CPU, operand: Operand) {
+fn bmi<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if cpu.p.is_set(CPUStatus::N) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if NotEqual
-fn bne(cpu: &mut CPU, operand: Operand) {
+fn bne<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if !cpu.p.is_set(CPUStatus::Z) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if PLus
-fn bpl(cpu: &mut CPU, operand: Operand) {
+fn bpl<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if !cpu.p.is_set(CPUStatus::N) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if oVerflow Clear
-fn bvc(cpu: &mut CPU, operand: Operand) {
+fn bvc<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if !cpu.p.is_set(CPUStatus::V) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // Branch if oVerflow Set
-fn bvs(cpu: &mut CPU, operand: Operand) {
+fn bvs<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     if cpu.p.is_set(CPUStatus::V) {
-        branch(cpu, operand)
+        branch(cpu, bus, operand)
     }
 }
 
 // CLear Carry
 fn clc(cpu: &mut CPU) {
     cpu.p.unset(CPUStatus::C);
-    cpu.cycles += 1
 }
 
 // CLear Decimal
 fn cld(cpu: &mut CPU) {
     cpu.p.unset(CPUStatus::D);
-    cpu.cycles += 1
 }
 
 // Clear Interrupt
 fn cli(cpu: &mut CPU) {
     cpu.p.unset(CPUStatus::I);
-    cpu.cycles += 1
 }
 
 // CLear oVerflow
 fn clv(cpu: &mut CPU) {
     cpu.p.unset(CPUStatus::V);
-    cpu.cycles += 1
 }
 
 // SEt Carry flag
 fn sec(cpu: &mut CPU) {
     cpu.p.set(CPUStatus::C);
-    cpu.cycles += 1
 }
 
 // SEt Decimal flag
 fn sed(cpu: &mut CPU) {
     cpu.p.set(CPUStatus::D);
-    cpu.cycles += 1
 }
 
 // SEt Interrupt disable
 fn sei(cpu: &mut CPU) {
     cpu.p.set(CPUStatus::I);
-    cpu.cycles += 1
 }
 
 // BReaK(force interrupt)
-fn brk(cpu: &mut CPU) {
-    cpu.push_stack_word(cpu.pc);
+fn brk<M: Memory>(cpu: &mut CPU, bus: &mut M) {
+    cpu.push_stack_word(bus, cpu.pc);
     // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
     // http://visual6502.org/wiki/index.php?title=6502_BRK_and_B_bit
-    cpu.push_stack(cpu.p | CPUStatus::INTERRUPTED_B);
-    cpu.cycles += 1;
-    cpu.pc = cpu.read_word(0xFFFEu16);
+    cpu.push_stack(bus, cpu.p | CPUStatus::INTERRUPTED_B);
+    cpu.pc = cpu.read_word(bus, 0xFFFEu16);
 }
 
 // No OPeration
-fn nop(cpu: &mut CPU) {
-    cpu.cycles += 1;
+// The unofficial ones with an operand read it and throw it away.
+fn nop<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.dummy_read(bus, operand);
 }
 
-fn branch(cpu: &mut CPU, operand: Operand) {
-    cpu.cycles += 1;
+fn branch<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    // the next opcode is read while the offset is added to the low byte of PC
+    cpu.dummy_read(bus, cpu.pc);
     let offset = <Word as Into<u16>>::into(operand) as i8;
+    let pc = cpu.pc + offset as u16;
     if page_crossed(offset, cpu.pc) {
-        cpu.cycles += 1;
+        cpu.dummy_read(bus, cpu.pc & 0xFF00 | pc & 0x00FF);
     }
-    cpu.pc += offset as u16
+    cpu.pc = pc
 }
 
 // Load Accumulator and X register
-fn lax(cpu: &mut CPU, operand: Operand) {
-    let data = cpu.read(operand);
+fn lax<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let data = cpu.read(bus, operand);
     cpu.a = data;
     cpu.x = data;
     cpu.p.update_zn(data);
 }
 
 // Store Accumulator and X register
-fn sax(cpu: &mut CPU, operand: Operand) {
-    cpu.write(operand, cpu.a & cpu.x)
+fn sax<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    cpu.write(bus, operand, cpu.a & cpu.x)
 }
 
 // Decrement memory and ComPare to accumulator
-fn dcp(cpu: &mut CPU, operand: Operand) {
-    let result = cpu.read(operand) - 1;
+fn dcp<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
+    let result = data - 1;
     cpu.p.update_zn(result);
-    cpu.write(operand, result);
+    cpu.write(bus, operand, result);
 
-    cmp(cpu, operand)
+    compare(cpu, result)
 }
 
 // Increment memory and SuBtract with carry
-fn isb(cpu: &mut CPU, operand: Operand) {
-    let result = cpu.read(operand) + 1;
+fn isb<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
+    let result = data + 1;
     cpu.p.update_zn(result);
-    cpu.write(operand, result);
+    cpu.write(bus, operand, result);
 
-    sbc(cpu, operand)
+    add_with_carry(cpu, !result)
 }
 
 // arithmetic Shift Left and bitwise Or with accumulator
-fn slo(cpu: &mut CPU, operand: Operand) {
-    let mut data = cpu.read(operand);
+fn slo<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
 
     cpu.p.update(CPUStatus::C, data.nth(7) == 1);
     data <<= 1;
     cpu.p.update_zn(data);
-    cpu.write(operand, data);
+    cpu.write(bus, operand, data);
 
-    ora(cpu, operand)
+    cpu.a |= data;
+    cpu.p.update_zn(cpu.a);
 }
 
 // Rotate Left and bitwise And with accumulator
-fn rla(cpu: &mut CPU, operand: Operand) {
+fn rla<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     // rotateLeft excluding tick
-    let mut data = cpu.read(operand);
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
     let c = data & 0x80;
 
     data <<= 1;
@@ -967,28 +995,32 @@ fn rla(cpu: &mut CPU, operand: Operand) {
     cpu.p.update(CPUStatus::C, c.u8() == 0x80);
     cpu.p.update_zn(data);
 
-    cpu.write(operand, data);
+    cpu.write(bus, operand, data);
 
-    and(cpu, operand)
+    cpu.a &= data;
+    cpu.p.update_zn(cpu.a);
 }
 
 // logical Shift Right and bitwise Exclusive or
-fn sre(cpu: &mut CPU, operand: Operand) {
+fn sre<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     // logicalShiftRight excluding tick
-    let mut data = cpu.read(operand);
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
 
     cpu.p.update(CPUStatus::C, data.nth(0) == 1);
     data >>= 1;
     cpu.p.update_zn(data);
-    cpu.write(operand, data);
+    cpu.write(bus, operand, data);
 
-    eor(cpu, operand)
+    cpu.a ^= data;
+    cpu.p.update_zn(cpu.a);
 }
 
 // Rotate Right and Add with carry
-fn rra(cpu: &mut CPU, operand: Operand) {
+fn rra<M: Memory>(cpu: &mut CPU, bus: &mut M, operand: Operand) {
     // rotateRight excluding tick
-    let mut data = cpu.read(operand);
+    let mut data = cpu.read(bus, operand);
+    cpu.dummy_write(operand, data);
     let c = data.nth(0);
 
     data >>= 1;
@@ -998,9 +1030,9 @@ fn rra(cpu: &mut CPU, operand: Operand) {
     cpu.p.update(CPUStatus::C, c == 1);
     cpu.p.update_zn(data);
 
-    cpu.write(operand, data);
+    cpu.write(bus, operand, data);
 
-    adc(cpu, operand)
+    add_with_carry(cpu, data)
 }
 
 impl CPUStatus {
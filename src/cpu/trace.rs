@@ -24,15 +24,15 @@ pub struct Trace {
 }
 
 impl Trace {
-    pub fn trace(cpu: &CPU) -> Self {
-        let instruction = cpu.bus.read(cpu.pc);
+    pub fn trace<M: Memory>(cpu: &CPU, bus: &M) -> Self {
+        let instruction = bus.peek(cpu.pc);
         let opcode = decode(instruction);
-        let assembly_code = to_assembly_code(instruction, opcode, &cpu);
+        let assembly_code = to_assembly_code(instruction, opcode, cpu, bus);
         Self {
             pc: cpu.pc,
-            operation: cpu.bus.read(cpu.pc),
-            operand_1: cpu.bus.read(cpu.pc + 1),
-            operand_2: cpu.bus.read(cpu.pc + 2),
+            operation: bus.peek(cpu.pc),
+            operand_1: bus.peek(cpu.pc + 1),
+            operand_2: bus.peek(cpu.pc + 2),
             a: cpu.a,
             x: cpu.x,
             y: cpu.y,
@@ -43,6 +43,28 @@ impl Trace {
             assembly_code,
         }
     }
+
+    /// Formats the trace as a JSON object in a line, for tools that diff traces.
+    pub fn to_json(&self) -> String {
+        let len = self.opcode.addressing_mode.instruction_length();
+        let bytes = [self.operation, self.operand_1, self.operand_2];
+        let bytes: Vec<String> = bytes[..len as usize]
+            .iter()
+            .map(|b| format!("{}", b.u8()))
+            .collect();
+        format!(
+            r#"{{"pc":{},"bytes":[{}],"asm":"{}","a":{},"x":{},"y":{},"p":{},"sp":{},"cycle":{}}}"#,
+            <Word as Into<u16>>::into(self.pc),
+            bytes.join(","),
+            self.assembly_code.trim(),
+            self.a.u8(),
+            self.x.u8(),
+            self.y.u8(),
+            self.p.u8(),
+            self.sp.u8(),
+            self.cycle
+        )
+    }
 }
 
 impl fmt::Display for Trace {
@@ -73,21 +95,29 @@ impl fmt::Display for Trace {
 }
 
 impl CPU {
-    fn operand_1(&self) -> Byte {
-        self.bus.read(self.pc + 1)
+    fn operand_1<M: Memory>(&self, bus: &M) -> Byte {
+        bus.peek(self.pc + 1)
     }
 
-    fn operand_2(&self) -> Byte {
-        self.bus.read(self.pc + 2)
+    fn operand_2<M: Memory>(&self, bus: &M) -> Byte {
+        bus.peek(self.pc + 2)
     }
 
-    fn operand_16(&self) -> Word {
-        <Byte as Into<Word>>::into(self.operand_1())
-            | <Byte as Into<Word>>::into(self.operand_2()) << 8
+    fn operand_16<M: Memory>(&self, bus: &M) -> Word {
+        <Byte as Into<Word>>::into(self.operand_1(bus))
+            | <Byte as Into<Word>>::into(self.operand_2(bus)) << 8
+    }
+
+    fn peek_on_indirect<M: Memory>(&self, bus: &M, operand: Word) -> Word {
+        let low = Word::from(bus.peek(operand));
+        // Reproduce 6502 bug; http://nesdev.com/6502bugs.txt
+        let addr = operand & 0xFF00 | ((operand + 1) & 0x00FF);
+        let high = Word::from(bus.peek(addr)) << 8;
+        low | high
     }
 }
 
-fn to_assembly_code(operation: Byte, opcode: Opcode, cpu: &CPU) -> String {
+fn to_assembly_code<M: Memory>(operation: Byte, opcode: Opcode, cpu: &CPU, bus: &M) -> String {
     let name = opcode.mnemonic.to_string();
     let prefix = if UNDOCUMENTED_OPCODES.contains(&operation.u8()) {
         "*"
@@ -97,7 +127,7 @@ fn to_assembly_code(operation: Byte, opcode: Opcode, cpu: &CPU) -> String {
 
     let operand = match (opcode.mnemonic, opcode.addressing_mode) {
         (Mnemonic::JMP, AddressingMode::Absolute) | (Mnemonic::JSR, AddressingMode::Absolute) => {
-            format!("${:4X}", decode_address(opcode.addressing_mode, &cpu))
+            format!("${:4X}", decode_address(opcode.addressing_mode, cpu, bus))
         }
         (Mnemonic::LSR, AddressingMode::Accumulator)
         | (Mnemonic::ASL, AddressingMode::Accumulator)
@@ -106,70 +136,70 @@ fn to_assembly_code(operation: Byte, opcode: Opcode, cpu: &CPU) -> String {
 
         (_, addressing_mode) => match addressing_mode {
             AddressingMode::Implicit | AddressingMode::Accumulator => " ".to_string(),
-            AddressingMode::Immediate => format!("#${:02X}", cpu.operand_1()),
+            AddressingMode::Immediate => format!("#${:02X}", cpu.operand_1(bus)),
             AddressingMode::ZeroPage => format!(
                 "${:02X} = {:02X}",
-                cpu.operand_1(),
-                cpu.bus.read(decode_address(addressing_mode, &cpu))
+                cpu.operand_1(bus),
+                bus.peek(decode_address(addressing_mode, cpu, bus))
             ),
             AddressingMode::ZeroPageX => format!(
                 "${:02X},X @ {:02X} = {:02X}",
-                cpu.operand_1(),
-                cpu.operand_1() + cpu.x,
-                cpu.bus.read(decode_address(addressing_mode, &cpu))
+                cpu.operand_1(bus),
+                cpu.operand_1(bus) + cpu.x,
+                bus.peek(decode_address(addressing_mode, cpu, bus))
             ),
             AddressingMode::ZeroPageY => format!(
                 "${:02X},Y @ {:02X} = {:02X}",
-                cpu.operand_1(),
-                cpu.operand_1() + cpu.y,
-                cpu.bus.read(decode_address(addressing_mode, &cpu))
+                cpu.operand_1(bus),
+                cpu.operand_1(bus) + cpu.y,
+                bus.peek(decode_address(addressing_mode, cpu, bus))
             ),
             AddressingMode::Absolute => format!(
                 "${:04X} = {:02X}",
-                cpu.operand_16(),
-                cpu.bus.read(decode_address(addressing_mode, &cpu))
+                cpu.operand_16(bus),
+                bus.peek(decode_address(addressing_mode, cpu, bus))
             ),
             AddressingMode::AbsoluteX { .. } => format!(
                 "${:04X},X @ {:04X} = {:02X}",
-                cpu.operand_16(),
-                cpu.operand_16() + cpu.x,
-                cpu.bus.read(decode_address(addressing_mode, &cpu))
+                cpu.operand_16(bus),
+                cpu.operand_16(bus) + cpu.x,
+                bus.peek(decode_address(addressing_mode, cpu, bus))
             ),
             AddressingMode::AbsoluteY { .. } => format!(
                 "${:04X},Y @ {:04X} = {:02X}",
-                cpu.operand_16(),
-                cpu.operand_16() + cpu.y,
-                cpu.bus.read(decode_address(addressing_mode, &cpu))
+                cpu.operand_16(bus),
+                cpu.operand_16(bus) + cpu.y,
+                bus.peek(decode_address(addressing_mode, cpu, bus))
             ),
             AddressingMode::Relative => {
                 let pc = <Word as Into<i16>>::into(cpu.pc);
-                let offset = <Byte as Into<i8>>::into(cpu.operand_1());
+                let offset = <Byte as Into<i8>>::into(cpu.operand_1(bus));
                 format!("${:04X}", pc.wrapping_add(2).wrapping_add(offset as i16))
             }
             AddressingMode::Indirect => format!(
                 "(${:04X}) = {:04X}",
-                cpu.operand_16(),
-                cpu.bus.read_on_indirect(cpu.operand_16())
+                cpu.operand_16(bus),
+                cpu.peek_on_indirect(bus, cpu.operand_16(bus))
             ),
             AddressingMode::IndexedIndirect => {
-                let operand_x = cpu.operand_1() + cpu.x;
-                let addr = cpu.bus.read_on_indirect(operand_x.into());
+                let operand_x = cpu.operand_1(bus) + cpu.x;
+                let addr = cpu.peek_on_indirect(bus, operand_x.into());
                 format!(
                     "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
-                    cpu.operand_1(),
+                    cpu.operand_1(bus),
                     operand_x,
                     addr,
-                    cpu.bus.read(addr)
+                    bus.peek(addr)
                 )
             }
-            AddressingMode::IndirectIndexed => {
-                let addr = cpu.bus.read_on_indirect(cpu.operand_1().into());
+            AddressingMode::IndirectIndexed { .. } => {
+                let addr = cpu.peek_on_indirect(bus, cpu.operand_1(bus).into());
                 format!(
                     "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
-                    cpu.operand_1(),
+                    cpu.operand_1(bus),
                     addr,
                     addr + cpu.y,
-                    cpu.bus.read(addr + cpu.y)
+                    bus.peek(addr + cpu.y)
                 )
             }
         },
@@ -177,22 +207,24 @@ fn to_assembly_code(operation: Byte, opcode: Opcode, cpu: &CPU) -> String {
     format!("{}{} {:<28}", prefix, name, operand)
 }
 
-fn decode_address(addressing_mode: AddressingMode, cpu: &CPU) -> Word {
+fn decode_address<M: Memory>(addressing_mode: AddressingMode, cpu: &CPU, bus: &M) -> Word {
     match addressing_mode {
         AddressingMode::Implicit => 0x00u16.into(),
         AddressingMode::Immediate => cpu.pc,
-        AddressingMode::ZeroPage => cpu.operand_1().into(),
-        AddressingMode::ZeroPageX => <Byte as Into<Word>>::into(cpu.operand_1() + cpu.x) & 0xFF,
-        AddressingMode::ZeroPageY => <Byte as Into<Word>>::into(cpu.operand_1() + cpu.y) & 0xFF,
-        AddressingMode::Absolute => cpu.operand_16(),
-        AddressingMode::AbsoluteX { .. } => cpu.operand_16() + cpu.x,
-        AddressingMode::AbsoluteY { .. } => cpu.operand_16() + cpu.y,
+        AddressingMode::ZeroPage => cpu.operand_1(bus).into(),
+        AddressingMode::ZeroPageX => <Byte as Into<Word>>::into(cpu.operand_1(bus) + cpu.x) & 0xFF,
+        AddressingMode::ZeroPageY => <Byte as Into<Word>>::into(cpu.operand_1(bus) + cpu.y) & 0xFF,
+        AddressingMode::Absolute => cpu.operand_16(bus),
+        AddressingMode::AbsoluteX { .. } => cpu.operand_16(bus) + cpu.x,
+        AddressingMode::AbsoluteY { .. } => cpu.operand_16(bus) + cpu.y,
         AddressingMode::Relative => cpu.pc,
-        AddressingMode::Indirect => cpu.bus.read_on_indirect(cpu.operand_16()),
+        AddressingMode::Indirect => cpu.peek_on_indirect(bus, cpu.operand_16(bus)),
         AddressingMode::IndexedIndirect => {
-            cpu.bus.read_on_indirect((cpu.operand_16() + cpu.x) & 0xFF)
+            cpu.peek_on_indirect(bus, (cpu.operand_16(bus) + cpu.x) & 0xFF)
+        }
+        AddressingMode::IndirectIndexed { .. } => {
+            cpu.peek_on_indirect(bus, cpu.operand_16(bus)) + cpu.y
         }
-        AddressingMode::IndirectIndexed => cpu.bus.read_on_indirect(cpu.operand_16()) + cpu.y,
         _ => 0x00u16.into(),
     }
 }
@@ -219,7 +251,7 @@ impl AddressingMode {
             | Self::ZeroPageX
             | Self::ZeroPageY
             | Self::Relative
-            | Self::IndirectIndexed
+            | Self::IndirectIndexed { .. }
             | Self::IndexedIndirect => 2,
             Self::Indirect | Self::Absolute | Self::AbsoluteX { .. } | Self::AbsoluteY { .. } => 3,
             _ => 1,
@@ -240,13 +272,3 @@ impl fmt::UpperHex for Word {
         fmt::UpperHex::fmt(&v, f)
     }
 }
-
-impl dyn Memory {
-    pub(super) fn read_on_indirect(&self, operand: Word) -> Word {
-        let low = Word::from(self.read(operand));
-        // Reproduce 6502 bug; http://nesdev.com/6502bugs.txt
-        let addr = operand & 0xFF00 | ((operand + 1) & 0x00FF);
-        let high = Word::from(self.read(addr)) << 8;
-        low | high
-    }
-}
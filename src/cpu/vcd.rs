@@ -0,0 +1,211 @@
+use std::io::{self, Write};
+
+use crate::types::{Byte, Word};
+
+use super::CPUCycle;
+
+// Records CPU bus activity as a VCD (value change dump) for waveform viewers.
+// https://en.wikipedia.org/wiki/Value_change_dump
+//
+// The time unit is one CPU cycle, and only the cycles in [start, end) are recorded.
+pub struct BusRecorder {
+    out: Box<dyn Write + Send>,
+    start: CPUCycle,
+    end: CPUCycle,
+
+    last_cycle: Option<CPUCycle>,
+    last_addr: Option<u16>,
+    last_data: Option<u8>,
+    last_read: Option<bool>,
+
+    error: Option<io::Error>,
+}
+
+const ADDR_ID: char = '!';
+const DATA_ID: char = '"';
+const RW_ID: char = '#';
+
+impl BusRecorder {
+    pub fn new(
+        mut out: Box<dyn Write + Send>,
+        start: CPUCycle,
+        cycles: CPUCycle,
+    ) -> io::Result<Self> {
+        writeln!(out, "$version rustnes $end")?;
+        writeln!(out, "$comment 1 time unit = 1 CPU cycle $end")?;
+        writeln!(out, "$scope module cpu $end")?;
+        writeln!(out, "$var wire 16 {} addr $end", ADDR_ID)?;
+        writeln!(out, "$var wire 8 {} data $end", DATA_ID)?;
+        writeln!(out, "$var wire 1 {} rw $end", RW_ID)?;
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        Ok(Self {
+            out,
+            start,
+            end: start.saturating_add(cycles),
+            last_cycle: None,
+            last_addr: None,
+            last_data: None,
+            last_read: None,
+            error: None,
+        })
+    }
+
+    pub fn done(&self, cycle: CPUCycle) -> bool {
+        self.end <= cycle
+    }
+
+    pub fn record(&mut self, cycle: CPUCycle, addr: Word, data: Byte, read: bool) {
+        if cycle < self.start || self.end <= cycle || self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.write_change(cycle, addr.into(), data.into(), read) {
+            self.error = Some(e);
+        }
+    }
+
+    fn write_change(&mut self, cycle: CPUCycle, addr: u16, data: u8, read: bool) -> io::Result<()> {
+        if self.last_cycle != Some(cycle) {
+            writeln!(self.out, "#{}", cycle - self.start)?;
+            self.last_cycle = Some(cycle);
+        }
+        if self.last_addr != Some(addr) {
+            writeln!(self.out, "b{:016b} {}", addr, ADDR_ID)?;
+            self.last_addr = Some(addr);
+        }
+        if self.last_data != Some(data) {
+            writeln!(self.out, "b{:08b} {}", data, DATA_ID)?;
+            self.last_data = Some(data);
+        }
+        if self.last_read != Some(read) {
+            // 6502 R/W: high on read, low on write
+            writeln!(self.out, "{}{}", if read { 1 } else { 0 }, RW_ID)?;
+            self.last_read = Some(read);
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        writeln!(self.out, "#{}", self.end - self.start)?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::types::Memory;
+    use std::sync::{Arc, Mutex};
+
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_window() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let mut r = BusRecorder::new(Box::new(SharedBuffer(buf.clone())), 10, 3).unwrap();
+
+        r.record(9, 0x8000u16.into(), 0xA9.into(), true);
+        r.record(10, 0x8000u16.into(), 0xA9.into(), true);
+        r.record(11, 0x8001u16.into(), 0xA9.into(), true);
+        r.record(12, 0x0200u16.into(), 0x01.into(), false);
+        r.record(13, 0x8002u16.into(), 0xEA.into(), true);
+        assert!(r.done(13));
+        r.finish().unwrap();
+
+        let out = String::from_utf8(buf.lock().unwrap().clone()).unwrap();
+        let body: Vec<&str> = out
+            .lines()
+            .skip_while(|l| !l.starts_with("$enddefinitions"))
+            .skip(1)
+            .collect();
+        assert_eq!(
+            body,
+            vec![
+                "#0",
+                "b1000000000000000 !",
+                "b10101001 \"",
+                "1#",
+                "#1",
+                "b1000000000000001 !",
+                "#2",
+                "b0000001000000000 !",
+                "b00000001 \"",
+                "0#",
+                "#3",
+            ]
+        );
+    }
+
+    #[test]
+    fn record_dummy_cycles() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let out = Box::new(SharedBuffer(buf.clone()));
+        let mut cpu = CPU {
+            pc: 0x0200u16.into(),
+            s: 0xFD.into(),
+            bus_recorder: Some(BusRecorder::new(out, 1, 8).unwrap()),
+            ..Default::default()
+        };
+        let mut bus: Box<dyn Memory> = Box::new([0; 0x10000]);
+        // INC $10 and PHA
+        for (i, b) in [0xE6, 0x10, 0x48].iter().enumerate() {
+            bus.write(Word::from(0x0200 + i as u16), (*b).into());
+        }
+        bus.write(0x0010u16.into(), 0x41.into());
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        cpu.bus_recorder.take().unwrap().finish().unwrap();
+
+        let out = String::from_utf8(buf.lock().unwrap().clone()).unwrap();
+        let body: Vec<&str> = out
+            .lines()
+            .skip_while(|l| !l.starts_with("$enddefinitions"))
+            .skip(1)
+            .collect();
+        assert_eq!(
+            body,
+            vec![
+                "#0",
+                "b0000001000000000 !",
+                "b11100110 \"",
+                "1#",
+                "#1",
+                "b0000001000000001 !",
+                "b00010000 \"",
+                "#2",
+                "b0000000000010000 !",
+                "b01000001 \"",
+                // the unmodified value is written back first
+                "#3",
+                "0#",
+                "#4",
+                "b01000010 \"",
+                "#5",
+                "b0000001000000010 !",
+                "b01001000 \"",
+                "1#",
+                // the byte after PHA is read and thrown away
+                "#6",
+                "b0000001000000011 !",
+                "b00000000 \"",
+                "#7",
+                "b0000000111111101 !",
+                "0#",
+                "#8",
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::region::Region;
+
+// Frames behind the schedule before giving up catching up, such as after the window was dragged.
+const MAX_LAG_FRAMES: u32 = 5;
+// The end of the wait is spun, since sleeping overshoots by up to a millisecond or so.
+const SPIN: Duration = Duration::from_millis(1);
+
+/// Paces a loop running a frame at a time to the frame rate of the console in real time, about
+/// 60.0988 Hz on NTSC and 50.0070 Hz on PAL.
+///
+/// The frames are scheduled from the start, so the time taken by each frame and the error of
+/// sleeping do not accumulate into drift:
+///
+/// ```no_run
+/// use rustnes::{FrameTimer, Region, NES};
+///
+/// let mut nes = NES::default();
+/// let mut timer = FrameTimer::new(Region::NTSC);
+/// loop {
+///     nes.frame();
+///     // present the frame and play the audio
+///     timer.wait();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FrameTimer {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl FrameTimer {
+    pub fn new(region: Region) -> Self {
+        let (num, den) = region.frame_rate();
+        Self {
+            period: Duration::from_secs_f64(den as f64 / num as f64),
+            next: None,
+        }
+    }
+
+    /// Duration of a frame.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Sleeps until the next frame is due. The first call returns immediately and starts the
+    /// schedule.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let deadline = self.schedule(now);
+        if SPIN < deadline - now {
+            thread::sleep(deadline - now - SPIN);
+        }
+        while Instant::now() < deadline {
+            thread::yield_now();
+        }
+    }
+
+    /// Starts the schedule again from the next call of `FrameTimer::wait`, after pausing the
+    /// emulation.
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+
+    // Moves the schedule a frame ahead and returns when the frame is due. Far behind the
+    // schedule, it restarts from `now` instead of running frames as fast as possible.
+    fn schedule(&mut self, now: Instant) -> Instant {
+        let deadline = match self.next {
+            Some(next) if now < next + self.period * MAX_LAG_FRAMES => next,
+            _ => now,
+        };
+        self.next = Some(deadline + self.period);
+        deadline.max(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule() {
+        let mut timer = FrameTimer::new(Region::NTSC);
+        let period = timer.period();
+        assert_eq!(period.as_micros(), 16_639);
+        assert_eq!(FrameTimer::new(Region::PAL).period().as_micros(), 19_997);
+
+        let start = Instant::now();
+        assert_eq!(timer.schedule(start), start);
+        // a frame finished early waits until its time
+        let now = start + Duration::from_millis(3);
+        assert_eq!(timer.schedule(now), start + period);
+        // a late frame does not wait, but the next ones keep the schedule
+        let now = start + period * 2 + Duration::from_millis(1);
+        assert_eq!(timer.schedule(now), now);
+        assert_eq!(timer.schedule(now), start + period * 3);
+
+        // far behind, the schedule restarts
+        let now = start + period * 20;
+        assert_eq!(timer.schedule(now), now);
+        assert_eq!(timer.schedule(now), now + period);
+
+        timer.reset();
+        assert_eq!(timer.schedule(start), start);
+    }
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use rustnes::{audio_ring, AudioConsumer, AudioProducer};
+
+// frames of audio buffered at most, half of which are kept buffered by the rate control
+const BUFFERED_FRAMES: u32 = 6;
+
+/// Audio output of the default device of the host through cpal.
+///
+/// The samples of the frames are passed to the callback of the device through a ring buffer,
+/// which should be kept half full by `Frontend::adjust_sample_rate` with `Audio::fill`.
+pub struct Audio {
+    // plays while it lives
+    _stream: Stream,
+    producer: AudioProducer,
+    sample_rate: u32,
+}
+
+impl Audio {
+    pub fn open() -> Result<Self, Box<dyn Error>> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device")?;
+        let supported = device.default_output_config()?;
+        let format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+        let sample_rate = config.sample_rate.0;
+
+        let (mut producer, consumer) = audio_ring((sample_rate / 60 * BUFFERED_FRAMES) as usize);
+        // start half full, so it does not run dry before the first frames
+        producer.push(&vec![0.0; producer.capacity() / 2]);
+        let stream = match format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, consumer)?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, consumer)?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, consumer)?,
+            f => return Err(format!("unsupported sample format: {}", f).into()),
+        };
+        stream.play()?;
+        Ok(Self {
+            _stream: stream,
+            producer,
+            sample_rate,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Queues samples to play. The samples not fitting in the buffer are dropped.
+    pub fn push(&mut self, samples: &[f32]) {
+        self.producer.push(samples);
+    }
+
+    /// Fraction of the buffer in use, from 0.0 to 1.0.
+    pub fn fill(&self) -> f32 {
+        self.producer.len() as f32 / self.producer.capacity() as f32
+    }
+}
+
+// The samples are mono, played on all the channels. A buffer run dry plays silence.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut consumer: AudioConsumer,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut mono = Vec::new();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            mono.resize(data.len() / channels, 0.0);
+            let n = consumer.pop_into(&mut mono);
+            for s in &mut mono[n..] {
+                *s = 0.0;
+            }
+            for (frame, s) in data.chunks_mut(channels).zip(&mono) {
+                for d in frame {
+                    *d = T::from_sample(*s);
+                }
+            }
+        },
+        |e| eprintln!("audio error: {}", e),
+        None,
+    )
+}
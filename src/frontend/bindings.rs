@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use rustnes::{Button, SAVE_SLOTS};
+use serde::{Deserialize, Serialize};
+
+use super::Key;
+
+const BUTTONS: [(&str, Button); 8] = [
+    ("a", Button::A),
+    ("b", Button::B),
+    ("select", Button::SELECT),
+    ("start", Button::START),
+    ("up", Button::UP),
+    ("down", Button::DOWN),
+    ("left", Button::LEFT),
+    ("right", Button::RIGHT),
+];
+
+const SLOTS: [&str; SAVE_SLOTS] = [
+    "slot0", "slot1", "slot2", "slot3", "slot4", "slot5", "slot6", "slot7", "slot8", "slot9",
+];
+
+/// Actions of the emulator itself on the keyboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    Quit,
+    Pause,
+    /// Runs a frame while paused, or pauses.
+    FrameAdvance,
+    /// Runs as fast as possible while held.
+    FastForward,
+    /// Runs backwards while held.
+    Rewind,
+    Reset,
+    SaveState,
+    LoadState,
+    /// Selects the next slot of `SaveState` and `LoadState`.
+    NextSlot,
+    /// Selects the slot of `SaveState` and `LoadState`, 0 to 9.
+    Slot(usize),
+    /// Shows or hides the frames per second and the speed.
+    Stats,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 20] = [
+        Hotkey::Quit,
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::FastForward,
+        Hotkey::Rewind,
+        Hotkey::Reset,
+        Hotkey::SaveState,
+        Hotkey::LoadState,
+        Hotkey::NextSlot,
+        Hotkey::Slot(0),
+        Hotkey::Slot(1),
+        Hotkey::Slot(2),
+        Hotkey::Slot(3),
+        Hotkey::Slot(4),
+        Hotkey::Slot(5),
+        Hotkey::Slot(6),
+        Hotkey::Slot(7),
+        Hotkey::Slot(8),
+        Hotkey::Slot(9),
+        Hotkey::Stats,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Hotkey::Quit => "quit",
+            Hotkey::Pause => "pause",
+            Hotkey::FrameAdvance => "frame_advance",
+            Hotkey::FastForward => "fast_forward",
+            Hotkey::Rewind => "rewind",
+            Hotkey::Reset => "reset",
+            Hotkey::SaveState => "save_state",
+            Hotkey::LoadState => "load_state",
+            Hotkey::NextSlot => "next_slot",
+            Hotkey::Slot(n) => SLOTS[n],
+            Hotkey::Stats => "stats",
+        }
+    }
+}
+
+/// What a key does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A button of the controller of a port, 0 or 1.
+    Button(usize, Button),
+    Hotkey(Hotkey),
+}
+
+/// Actions bound to the keys of the keyboard.
+///
+/// The bindings are read from TOML of the tables `controller1`, `controller2` and `hotkeys`,
+/// binding the buttons and hotkeys by their names to keys by the names of `Key`:
+///
+/// ```toml
+/// [controller1]
+/// a = "K"
+/// b = "J"
+///
+/// [hotkeys]
+/// quit = "F12"
+///
+/// [gamepads]
+/// "8BitDo" = 2
+/// ```
+///
+/// Those not in the file keep the default bindings, and an empty name unbinds one. The default
+/// bindings are the arrow keys, X (A), Z (B), Right Shift (Select) and Return (Start) for the
+/// controller 1, and the hotkeys in `Bindings::default` with the number keys selecting the slots.
+///
+/// The table `gamepads` puts the gamepads with the names containing the keys on the controller
+/// ports 1 or 2. The others are on the ports free in the order connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bindings {
+    actions: HashMap<Key, Action>,
+    gamepads: Vec<(String, usize)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let controller1 = [
+            (Key::X, Button::A),
+            (Key::Z, Button::B),
+            (Key::RShift, Button::SELECT),
+            (Key::Return, Button::START),
+            (Key::Up, Button::UP),
+            (Key::Down, Button::DOWN),
+            (Key::Left, Button::LEFT),
+            (Key::Right, Button::RIGHT),
+        ];
+        let mut actions: HashMap<_, _> = controller1
+            .iter()
+            .map(|&(k, b)| (k, Action::Button(0, b)))
+            .collect();
+        let hotkeys = [
+            (Key::Escape, Hotkey::Quit),
+            (Key::P, Hotkey::Pause),
+            (Key::N, Hotkey::FrameAdvance),
+            (Key::Tab, Hotkey::FastForward),
+            (Key::Backspace, Hotkey::Rewind),
+            (Key::F2, Hotkey::Reset),
+            (Key::F3, Hotkey::Stats),
+            (Key::F5, Hotkey::SaveState),
+            (Key::F6, Hotkey::NextSlot),
+            (Key::F7, Hotkey::LoadState),
+        ];
+        actions.extend(hotkeys.iter().map(|&(k, h)| (k, Action::Hotkey(h))));
+        let slots = [
+            Key::Num0,
+            Key::Num1,
+            Key::Num2,
+            Key::Num3,
+            Key::Num4,
+            Key::Num5,
+            Key::Num6,
+            Key::Num7,
+            Key::Num8,
+            Key::Num9,
+        ];
+        actions.extend(
+            slots
+                .iter()
+                .enumerate()
+                .map(|(n, &k)| (k, Action::Hotkey(Hotkey::Slot(n)))),
+        );
+        Self {
+            actions,
+            gamepads: Vec::new(),
+        }
+    }
+}
+
+/// The tables of `Bindings` as written in TOML, by the names of the buttons, hotkeys and keys.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub controller1: BTreeMap<String, String>,
+    pub controller2: BTreeMap<String, String>,
+    pub hotkeys: BTreeMap<String, String>,
+    pub gamepads: BTreeMap<String, usize>,
+}
+
+impl Bindings {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_config(&toml::from_str(s)?)
+    }
+
+    pub fn from_config(config: &InputConfig) -> Result<Self, Box<dyn Error>> {
+        let mut bindings = Self::default();
+        // keys bound in the config, which must not be bound twice
+        let mut bound = HashMap::new();
+        let tables = [&config.controller1, &config.controller2];
+        for (port, table) in tables.iter().enumerate() {
+            for (name, key) in table.iter() {
+                let button = BUTTONS
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|&(_, b)| b)
+                    .ok_or_else(|| format!("unknown button: {}", name))?;
+                bindings.bind(&mut bound, key, Action::Button(port, button))?;
+            }
+        }
+        for (name, key) in config.hotkeys.iter() {
+            let hotkey = Hotkey::ALL
+                .iter()
+                .copied()
+                .find(|h| h.name() == name)
+                .ok_or_else(|| format!("unknown hotkey: {}", name))?;
+            bindings.bind(&mut bound, key, Action::Hotkey(hotkey))?;
+        }
+        for (name, &port) in config.gamepads.iter() {
+            if !(1..=2).contains(&port) {
+                return Err(format!("no controller port {} for {}", port, name).into());
+            }
+            bindings.gamepads.push((name.clone(), port - 1));
+        }
+        Ok(bindings)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    fn bind(
+        &mut self,
+        bound: &mut HashMap<Key, Action>,
+        key: &str,
+        action: Action,
+    ) -> Result<(), Box<dyn Error>> {
+        self.actions.retain(|_, a| *a != action);
+        if key.is_empty() {
+            return Ok(());
+        }
+        let key = key.parse()?;
+        if bound.insert(key, action).is_some() {
+            return Err(format!("{} is bound twice", key).into());
+        }
+        self.actions.insert(key, action);
+        Ok(())
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.actions.get(&key).copied()
+    }
+
+    /// Controller port, 0 or 1, of the gamepad of `name`.
+    #[cfg_attr(not(feature = "gilrs"), allow(dead_code))]
+    pub fn gamepad_port(&self, name: &str) -> Option<usize> {
+        self.gamepads
+            .iter()
+            .find(|(n, _)| name.contains(n.as_str()))
+            .map(|&(_, port)| port)
+    }
+}
+
+/// A file of `Bindings` to read again when it changes.
+#[derive(Debug)]
+pub struct BindingsFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl BindingsFile {
+    /// Reads the bindings of the file.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<(Self, Bindings), Box<dyn Error>> {
+        let path = path.into();
+        let modified = modified(&path);
+        let bindings = Bindings::load(&path)?;
+        Ok((Self { path, modified }, bindings))
+    }
+
+    /// Reads the bindings again if the file has changed since the last time.
+    pub fn reload(&mut self) -> Option<Result<Bindings, Box<dyn Error>>> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(Bindings::load(&self.path))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let bindings = Bindings::parse(
+            r#"
+            [controller1]
+            a = "K"
+            select = ""
+
+            [controller2]
+            start = "Num2"
+            up = "X"
+
+            [hotkeys]
+            quit = "F12"
+            slot9 = "F9"
+
+            [gamepads]
+            "8BitDo" = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(bindings.action(Key::K), Some(Action::Button(0, Button::A)));
+        assert_eq!(bindings.action(Key::RShift), None);
+        assert_eq!(bindings.action(Key::Z), Some(Action::Button(0, Button::B)));
+        assert_eq!(
+            bindings.action(Key::Num2),
+            Some(Action::Button(1, Button::START))
+        );
+        // taken from the default
+        assert_eq!(bindings.action(Key::X), Some(Action::Button(1, Button::UP)));
+        assert_eq!(bindings.action(Key::Escape), None);
+        assert_eq!(
+            bindings.action(Key::F12),
+            Some(Action::Hotkey(Hotkey::Quit))
+        );
+        assert_eq!(
+            bindings.action(Key::F9),
+            Some(Action::Hotkey(Hotkey::Slot(9)))
+        );
+        assert_eq!(bindings.action(Key::Num9), None);
+        assert_eq!(bindings.gamepad_port("8BitDo SN30 Pro"), Some(1));
+        assert_eq!(bindings.gamepad_port("Xbox Controller"), None);
+
+        assert_eq!(Bindings::parse("").unwrap(), Bindings::default());
+        for s in &[
+            "[controller1]\nturbo = \"T\"",
+            "[controller1]\na = \"Enter\"",
+            "[controller3]\na = \"A\"",
+            "[controller1]\na = \"A\"\nb = \"A\"",
+            "[hotkeys]\nexit = \"Q\"",
+            "[gamepads]\nXbox = 3",
+        ] {
+            assert!(Bindings::parse(s).is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustnes::{Overscan, Region};
+use serde::{Deserialize, Serialize};
+
+use super::bindings::InputConfig;
+
+/// Settings of the emulator, read from `~/.config/rustnes/config.toml` by `Config::load_default`
+/// or built in code. Everything is optional:
+///
+/// ```toml
+/// region = "pal"
+/// palette = "/home/me/palettes/smooth.pal"
+/// save_dir = "/home/me/saves"
+/// run_ahead = 1
+///
+/// [video]
+/// scale = 4
+/// overscan = { top = 8, bottom = 8, left = 8, right = 8 }
+///
+/// [audio]
+/// enabled = false
+///
+/// [rewind]
+/// memory_budget_mb = 64
+///
+/// [input.controller1]
+/// a = "K"
+/// ```
+///
+/// `input` has the tables of `Bindings`, and the options of the command line take precedence.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub region: Region,
+    /// Palette in the .pal format, the default of the region if not set.
+    pub palette: Option<PathBuf>,
+    /// Directory of the battery-backed RAM of the games, next to the ROM files if not set.
+    pub save_dir: Option<PathBuf>,
+    /// Frames run ahead of the input by `NES::set_run_ahead`.
+    pub run_ahead: u32,
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub rewind: RewindConfig,
+    pub input: InputConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    /// Size of the window in multiples of the picture.
+    pub scale: u32,
+    /// Frames not presented between each presented while fast-forwarding.
+    pub fast_forward_skip: u32,
+    /// Pixels cropped from the edges, the default of the region if not set.
+    pub overscan: Option<Overscan>,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        Self {
+            scale: 3,
+            fast_forward_skip: 0,
+            overscan: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub enabled: bool,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Settings of the buffer of the states to rewind to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewindConfig {
+    pub enabled: bool,
+    /// Frames between the states kept.
+    pub interval: u32,
+    /// Megabytes of the states kept at most.
+    pub memory_budget_mb: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        let buffer = rustnes::RewindConfig::default();
+        Self {
+            enabled: true,
+            interval: buffer.interval,
+            memory_budget_mb: buffer.memory_budget >> 20,
+        }
+    }
+}
+
+impl RewindConfig {
+    /// The config of `NES::set_rewind`.
+    pub fn buffer(&self) -> Option<rustnes::RewindConfig> {
+        if !self.enabled {
+            return None;
+        }
+        Some(rustnes::RewindConfig {
+            interval: self.interval,
+            memory_budget: self.memory_budget_mb << 20,
+        })
+    }
+}
+
+impl Config {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let config: Self = toml::from_str(s)?;
+        if config.video.scale == 0 {
+            return Err("the scale must be 1 or more".into());
+        }
+        if config.rewind.interval == 0 {
+            return Err("the rewind interval must be 1 or more".into());
+        }
+        Ok(config)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// Reads the config from `Config::default_path`, or the default if there is none.
+    pub fn load_default() -> Result<Self, Box<dyn Error>> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// `$XDG_CONFIG_HOME/rustnes/config.toml`, or `~/.config/rustnes/config.toml` without it.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("rustnes").join("config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let config = Config::parse(
+            r#"
+            region = "pal"
+            save_dir = "saves"
+            run_ahead = 1
+
+            [video]
+            overscan = { top = 8, bottom = 8 }
+
+            [rewind]
+            interval = 4
+
+            [input.controller1]
+            a = "K"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.region, Region::PAL);
+        assert_eq!(config.palette, None);
+        assert_eq!(config.save_dir, Some(PathBuf::from("saves")));
+        assert_eq!(config.run_ahead, 1);
+        assert_eq!(config.video.scale, 3);
+        assert_eq!(
+            config.video.overscan,
+            Some(Overscan {
+                top: 8,
+                bottom: 8,
+                ..Overscan::NONE
+            })
+        );
+        assert!(config.audio.enabled);
+        assert_eq!(
+            config.rewind.buffer(),
+            Some(rustnes::RewindConfig {
+                interval: 4,
+                memory_budget: 32 << 20,
+            })
+        );
+        assert_eq!(config.input.controller1["a"], "K");
+
+        assert_eq!(
+            Config::parse(&toml::to_string(&config).unwrap()).unwrap(),
+            config
+        );
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        for s in &[
+            "region = \"secam\"",
+            "scale = 2",
+            "[video]\nscale = 0",
+            "[rewind]\ninterval = 0",
+        ] {
+            assert!(Config::parse(s).is_err(), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::error::Error;
+
+use gilrs::{Axis, EventType, GamepadId, Gilrs};
+use rustnes::Button;
+
+use super::bindings::Bindings;
+
+// buttons of the gamepads, in the layout of the XInput ones: the NES B and A buttons are on the
+// left and right as the south and east buttons
+const BUTTONS: [(gilrs::Button, Button); 8] = [
+    (gilrs::Button::East, Button::A),
+    (gilrs::Button::South, Button::B),
+    (gilrs::Button::Select, Button::SELECT),
+    (gilrs::Button::Start, Button::START),
+    (gilrs::Button::DPadUp, Button::UP),
+    (gilrs::Button::DPadDown, Button::DOWN),
+    (gilrs::Button::DPadLeft, Button::LEFT),
+    (gilrs::Button::DPadRight, Button::RIGHT),
+];
+
+// tilt of the left stick pressing the direction
+const STICK_THRESHOLD: f32 = 0.5;
+
+/// Gamepads on the controller ports.
+///
+/// A gamepad connected goes to the port named for it in `Bindings`, or to the first one free,
+/// and leaves the port when disconnected.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    ports: [Option<GamepadId>; 2],
+}
+
+impl Gamepads {
+    pub fn new(bindings: &Bindings) -> Result<Self, Box<dyn Error>> {
+        let mut gamepads = Self {
+            gilrs: Gilrs::new()?,
+            ports: [None; 2],
+        };
+        let connected: Vec<_> = gamepads.gilrs.gamepads().map(|(id, _)| id).collect();
+        for id in connected {
+            gamepads.connect(id, bindings);
+        }
+        Ok(gamepads)
+    }
+
+    /// Handles the gamepads connected and disconnected, returning the buttons pressed on the
+    /// ports.
+    pub fn poll(&mut self, bindings: &Bindings) -> [Button; 2] {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected => self.connect(event.id, bindings),
+                EventType::Disconnected => self.disconnect(event.id),
+                _ => {}
+            }
+        }
+
+        let mut buttons = [Button::empty(); 2];
+        for (port, id) in self.ports.iter().enumerate() {
+            let gamepad = match id.and_then(|id| self.gilrs.connected_gamepad(id)) {
+                Some(gamepad) => gamepad,
+                None => continue,
+            };
+            for &(button, b) in &BUTTONS {
+                if gamepad.is_pressed(button) {
+                    buttons[port].insert(b);
+                }
+            }
+            let (x, y) = (
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            );
+            if y > STICK_THRESHOLD {
+                buttons[port].insert(Button::UP);
+            } else if y < -STICK_THRESHOLD {
+                buttons[port].insert(Button::DOWN);
+            }
+            if x < -STICK_THRESHOLD {
+                buttons[port].insert(Button::LEFT);
+            } else if x > STICK_THRESHOLD {
+                buttons[port].insert(Button::RIGHT);
+            }
+        }
+        buttons
+    }
+
+    fn connect(&mut self, id: GamepadId, bindings: &Bindings) {
+        if self.ports.contains(&Some(id)) {
+            return;
+        }
+        let name = self.gilrs.gamepad(id).name().to_string();
+        let port = match bindings.gamepad_port(&name) {
+            Some(port) if self.ports[port].is_none() => Some(port),
+            _ => self.ports.iter().position(Option::is_none),
+        };
+        match port {
+            Some(port) => {
+                eprintln!("gamepad {} on the controller {}", name, port + 1);
+                self.ports[port] = Some(id);
+            }
+            None => eprintln!("gamepad {} not used, both controllers taken", name),
+        }
+    }
+
+    fn disconnect(&mut self, id: GamepadId) {
+        for port in self.ports.iter_mut() {
+            if *port == Some(id) {
+                *port = None;
+            }
+        }
+    }
+}
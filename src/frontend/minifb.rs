@@ -0,0 +1,146 @@
+use minifb::{KeyRepeat, Scale, Window, WindowOptions};
+
+#[cfg(feature = "cpal")]
+use super::audio::Audio;
+use super::{Frontend, Key};
+
+fn key(key: minifb::Key) -> Option<Key> {
+    match key {
+        minifb::Key::A => Some(Key::A),
+        minifb::Key::B => Some(Key::B),
+        minifb::Key::C => Some(Key::C),
+        minifb::Key::D => Some(Key::D),
+        minifb::Key::E => Some(Key::E),
+        minifb::Key::F => Some(Key::F),
+        minifb::Key::G => Some(Key::G),
+        minifb::Key::H => Some(Key::H),
+        minifb::Key::I => Some(Key::I),
+        minifb::Key::J => Some(Key::J),
+        minifb::Key::K => Some(Key::K),
+        minifb::Key::L => Some(Key::L),
+        minifb::Key::M => Some(Key::M),
+        minifb::Key::N => Some(Key::N),
+        minifb::Key::O => Some(Key::O),
+        minifb::Key::P => Some(Key::P),
+        minifb::Key::Q => Some(Key::Q),
+        minifb::Key::R => Some(Key::R),
+        minifb::Key::S => Some(Key::S),
+        minifb::Key::T => Some(Key::T),
+        minifb::Key::U => Some(Key::U),
+        minifb::Key::V => Some(Key::V),
+        minifb::Key::W => Some(Key::W),
+        minifb::Key::X => Some(Key::X),
+        minifb::Key::Y => Some(Key::Y),
+        minifb::Key::Z => Some(Key::Z),
+        minifb::Key::Key0 => Some(Key::Num0),
+        minifb::Key::Key1 => Some(Key::Num1),
+        minifb::Key::Key2 => Some(Key::Num2),
+        minifb::Key::Key3 => Some(Key::Num3),
+        minifb::Key::Key4 => Some(Key::Num4),
+        minifb::Key::Key5 => Some(Key::Num5),
+        minifb::Key::Key6 => Some(Key::Num6),
+        minifb::Key::Key7 => Some(Key::Num7),
+        minifb::Key::Key8 => Some(Key::Num8),
+        minifb::Key::Key9 => Some(Key::Num9),
+        minifb::Key::F1 => Some(Key::F1),
+        minifb::Key::F2 => Some(Key::F2),
+        minifb::Key::F3 => Some(Key::F3),
+        minifb::Key::F4 => Some(Key::F4),
+        minifb::Key::F5 => Some(Key::F5),
+        minifb::Key::F6 => Some(Key::F6),
+        minifb::Key::F7 => Some(Key::F7),
+        minifb::Key::F8 => Some(Key::F8),
+        minifb::Key::F9 => Some(Key::F9),
+        minifb::Key::F10 => Some(Key::F10),
+        minifb::Key::F11 => Some(Key::F11),
+        minifb::Key::F12 => Some(Key::F12),
+        minifb::Key::Up => Some(Key::Up),
+        minifb::Key::Down => Some(Key::Down),
+        minifb::Key::Left => Some(Key::Left),
+        minifb::Key::Right => Some(Key::Right),
+        minifb::Key::Enter => Some(Key::Return),
+        minifb::Key::Space => Some(Key::Space),
+        minifb::Key::Tab => Some(Key::Tab),
+        minifb::Key::Backspace => Some(Key::Backspace),
+        minifb::Key::Escape => Some(Key::Escape),
+        minifb::Key::LeftShift => Some(Key::LShift),
+        minifb::Key::RightShift => Some(Key::RShift),
+        minifb::Key::LeftCtrl => Some(Key::LCtrl),
+        minifb::Key::RightCtrl => Some(Key::RCtrl),
+        minifb::Key::LeftAlt => Some(Key::LAlt),
+        minifb::Key::RightAlt => Some(Key::RAlt),
+        _ => None,
+    }
+}
+
+// minifb scales by powers of 2 only
+fn scale(scale: u32) -> Scale {
+    match scale {
+        0..=1 => Scale::X1,
+        2..=3 => Scale::X2,
+        4..=7 => Scale::X4,
+        _ => Scale::X8,
+    }
+}
+
+/// Runs `frontend` in a window until it is closed. minifb has no audio, so this plays sound
+/// through cpal with the `cpal` feature only.
+pub fn run(mut frontend: Frontend) -> Result<(), Box<dyn std::error::Error>> {
+    let result = play(&mut frontend);
+    frontend.shutdown();
+    result
+}
+
+fn play(frontend: &mut Frontend) -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "cpal")]
+    let mut audio = if frontend.options().audio {
+        let audio = Audio::open()?;
+        frontend.set_sample_rate(audio.sample_rate());
+        Some(audio)
+    } else {
+        None
+    };
+
+    let (width, height) = frontend.nes().video_frame_size();
+    let (width, height) = (width as usize, height as usize);
+    let mut window = Window::new(
+        "rustnes",
+        width,
+        height,
+        WindowOptions {
+            scale: scale(frontend.options().scale),
+            ..WindowOptions::default()
+        },
+    )?;
+    // paced by the frontend
+    window.set_target_fps(0);
+
+    // 0RGB pixels
+    let mut buffer = vec![0u32; width * height];
+    while window.is_open() && !frontend.quit() {
+        for k in window.get_keys_pressed(KeyRepeat::No) {
+            if let Some(k) = key(k) {
+                frontend.key_down(k);
+            }
+        }
+        for k in window.get_keys_released() {
+            if let Some(k) = key(k) {
+                frontend.key_up(k);
+            }
+        }
+
+        let (frame, _samples) = frontend.frame();
+        for (b, p) in buffer.iter_mut().zip(frame.pixels.chunks_exact(4)) {
+            *b = u32::from_be_bytes([0, p[0], p[1], p[2]]);
+        }
+        window.update_with_buffer(&buffer, width, height)?;
+
+        #[cfg(feature = "cpal")]
+        if let Some(audio) = &mut audio {
+            audio.push(&_samples);
+            frontend.adjust_sample_rate(audio.fill());
+        }
+        frontend.wait_frame();
+    }
+    Ok(())
+}
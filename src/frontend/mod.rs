@@ -0,0 +1,465 @@
+// Windowed frontends of the binary.
+//
+// This part is independent of the window and audio libraries, which only translate their events
+// into `Key` and present what `Frontend::frame` returns.
+#![cfg_attr(
+    not(any(feature = "sdl2", all(feature = "minifb", feature = "cpal"))),
+    allow(dead_code)
+)]
+
+// SDL2 is preferred when both are enabled, playing the audio by itself
+#[cfg(all(feature = "cpal", feature = "minifb", not(feature = "sdl2")))]
+pub mod audio;
+pub mod bindings;
+pub mod config;
+#[cfg(feature = "gilrs")]
+mod gamepad;
+mod key;
+#[cfg(all(feature = "minifb", not(feature = "sdl2")))]
+pub mod minifb;
+mod overlay;
+#[cfg(feature = "sdl2")]
+pub mod sdl;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustnes::{Button, FrameTimer, Screenshot, NES, ROM, SAVE_SLOTS};
+
+use bindings::{Action, Bindings, BindingsFile, Hotkey};
+pub use key::Key;
+
+// frames between checking the bindings file for changes
+const RELOAD_INTERVAL: u32 = 60;
+// frames gone back for each frame presented while rewinding, twice the speed of playing with the
+// frame run to present it
+const REWIND_FRAMES: u32 = 3;
+
+// Largest change of the sample rate to keep the audio buffer half full, small enough not to be
+// heard as a change of the pitch
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// Sample rate producing the samples a little faster when the audio buffer is less than half full,
+// and slower when more, so the buffer neither runs dry nor overflows with the frames paced by a
+// timer apart from the clock of the audio device. `fill` is the fraction of the buffer in use.
+fn adjusted_sample_rate(sample_rate: u32, fill: f32) -> u32 {
+    let fill = f64::from(fill.clamp(0.0, 1.0));
+    let delta = (1.0 - 2.0 * fill) * MAX_RATE_DELTA;
+    (f64::from(sample_rate) * (1.0 + delta)).round() as u32
+}
+
+/// Options of the window and audio of the frontends.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Size of the window in multiples of the picture.
+    pub scale: u32,
+    pub audio: bool,
+    /// Frames run without being presented between those presented while fast-forwarding.
+    pub fast_forward_skip: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scale: 3,
+            audio: true,
+            fast_forward_skip: 0,
+        }
+    }
+}
+
+/// Loads the ROM of `path`, printing the problems found in it. The battery-backed RAM is in
+/// `save_dir` if given.
+pub fn load_rom<P: AsRef<Path>>(path: P, save_dir: Option<&Path>) -> Result<ROM, Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut rom = ROM::load(path)?;
+    for diagnostic in rom.diagnostics() {
+        eprintln!("warning: {}", diagnostic);
+    }
+    if let (Some(dir), Some(name)) = (save_dir, path.file_stem()) {
+        fs::create_dir_all(dir)?;
+        rom.set_sram_path(dir.join(name).with_extension("sav"))?;
+    }
+    Ok(rom)
+}
+
+pub struct Frontend {
+    nes: NES,
+    options: Options,
+    timer: FrameTimer,
+    sample_rate: u32,
+    bindings: Bindings,
+    bindings_file: Option<BindingsFile>,
+    reload_countdown: u32,
+    // keys held down, bound to buttons
+    pressed: HashSet<Key>,
+    #[cfg(feature = "gilrs")]
+    gamepads: Option<gamepad::Gamepads>,
+    // buttons held down on the gamepads
+    gamepad_buttons: [Button; 2],
+    // slot of the save state hotkeys
+    slot: usize,
+    // runs a frame while paused
+    advance: bool,
+    fast_forward: bool,
+    rewinding: bool,
+    show_stats: bool,
+    save_dir: Option<PathBuf>,
+    quit: bool,
+}
+
+impl Frontend {
+    /// Powers on `nes` with a ROM loaded.
+    pub fn new(mut nes: NES, options: Options) -> Self {
+        nes.power_on();
+        nes.reset();
+        let bindings = Bindings::default();
+        Self {
+            timer: FrameTimer::new(nes.region()),
+            sample_rate: nes.sample_rate(),
+            nes,
+            options,
+            #[cfg(feature = "gilrs")]
+            gamepads: gamepad::Gamepads::new(&bindings)
+                .map_err(|e| eprintln!("warning: no gamepads: {}", e))
+                .ok(),
+            bindings,
+            bindings_file: None,
+            reload_countdown: RELOAD_INTERVAL,
+            pressed: HashSet::new(),
+            gamepad_buttons: [Button::empty(); 2],
+            slot: 0,
+            advance: false,
+            fast_forward: false,
+            rewinding: false,
+            show_stats: false,
+            save_dir: None,
+            quit: false,
+        }
+    }
+
+    pub fn nes(&self) -> &NES {
+        &self.nes
+    }
+
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Sets the sample rate of the audio device.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.nes.set_sample_rate(sample_rate);
+    }
+
+    /// Adjusts the sample rate to how full the audio buffer is, from 0.0 to 1.0, after playing
+    /// the samples of a frame.
+    pub fn adjust_sample_rate(&mut self, fill: f32) {
+        self.nes
+            .adjust_sample_rate(adjusted_sample_rate(self.sample_rate, fill));
+    }
+
+    /// Puts the battery-backed RAM of the ROMs swapped in `dir`.
+    pub fn set_save_dir(&mut self, dir: Option<PathBuf>) {
+        self.save_dir = dir;
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+        self.update_controllers();
+    }
+
+    /// Uses the bindings of `file`, read again when it changes while playing.
+    pub fn watch_bindings(&mut self, file: BindingsFile, bindings: Bindings) {
+        self.bindings_file = Some(file);
+        self.set_bindings(bindings);
+    }
+
+    pub fn key_down(&mut self, key: Key) {
+        match self.bindings.action(key) {
+            Some(Action::Button(..)) => {
+                self.pressed.insert(key);
+                self.update_controllers();
+            }
+            Some(Action::Hotkey(hotkey)) => self.hotkey(hotkey),
+            None => {}
+        }
+    }
+
+    fn hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::Quit => self.quit = true,
+            Hotkey::Pause => {
+                let paused = !self.nes.paused();
+                self.nes.set_paused(paused);
+                eprintln!("{}", if paused { "paused" } else { "resumed" });
+            }
+            Hotkey::FrameAdvance => {
+                if self.nes.paused() {
+                    self.advance = true;
+                } else {
+                    self.nes.set_paused(true);
+                    eprintln!("paused");
+                }
+            }
+            Hotkey::FastForward => self.fast_forward = true,
+            Hotkey::Rewind => {
+                if self.nes.rewind_config().is_none() {
+                    eprintln!("rewind is disabled");
+                }
+                self.rewinding = true;
+            }
+            Hotkey::Reset => self.nes.reset(),
+            Hotkey::SaveState => match self.nes.save_slot(self.slot) {
+                Ok(()) => eprintln!("state saved to the slot {}", self.slot),
+                Err(e) => eprintln!("failed to save the slot {}: {:#}", self.slot, e),
+            },
+            Hotkey::LoadState => match self.nes.load_slot(self.slot) {
+                Ok(true) => eprintln!("state loaded from the slot {}", self.slot),
+                Ok(false) => eprintln!("no state in the slot {}", self.slot),
+                Err(e) => eprintln!("failed to load the slot {}: {:#}", self.slot, e),
+            },
+            Hotkey::NextSlot => {
+                self.slot = (self.slot + 1) % SAVE_SLOTS;
+                eprintln!("slot {}", self.slot);
+            }
+            Hotkey::Slot(n) => {
+                self.slot = n;
+                eprintln!("slot {}", self.slot);
+            }
+            Hotkey::Stats => self.show_stats = !self.show_stats,
+        }
+    }
+
+    pub fn key_up(&mut self, key: Key) {
+        if self.bindings.action(key) == Some(Action::Hotkey(Hotkey::FastForward)) {
+            self.fast_forward = false;
+            self.timer.reset();
+        }
+        if self.bindings.action(key) == Some(Action::Hotkey(Hotkey::Rewind)) {
+            self.rewinding = false;
+        }
+        if self.pressed.remove(&key) {
+            self.update_controllers();
+        }
+    }
+
+    // sets the buttons of the keys held down, which may have been rebound since pressed, and of
+    // the gamepads
+    fn update_controllers(&mut self) {
+        let mut buttons = self.gamepad_buttons;
+        for &key in &self.pressed {
+            if let Some(Action::Button(port, b)) = self.bindings.action(key) {
+                buttons[port].insert(b);
+            }
+        }
+        for (port, &b) in buttons.iter().enumerate() {
+            self.nes.set_controller(port, b);
+        }
+    }
+
+    #[cfg(feature = "gilrs")]
+    fn poll_gamepads(&mut self) {
+        if let Some(gamepads) = &mut self.gamepads {
+            let buttons = gamepads.poll(&self.bindings);
+            if buttons != self.gamepad_buttons {
+                self.gamepad_buttons = buttons;
+                self.update_controllers();
+            }
+        }
+    }
+
+    fn reload_bindings(&mut self) {
+        let file = match &mut self.bindings_file {
+            Some(file) => file,
+            None => return,
+        };
+        self.reload_countdown -= 1;
+        if 0 < self.reload_countdown {
+            return;
+        }
+        self.reload_countdown = RELOAD_INTERVAL;
+        match file.reload() {
+            Some(Ok(bindings)) => {
+                eprintln!("key bindings reloaded");
+                self.set_bindings(bindings);
+            }
+            Some(Err(e)) => eprintln!("warning: {}", e),
+            None => {}
+        }
+    }
+
+    /// Plays the ROM of `path`, such as one dropped on the window, in place of the current one.
+    // minifb has no drag-and-drop
+    #[cfg_attr(not(feature = "sdl2"), allow(dead_code))]
+    pub fn swap_rom<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        let rom = match load_rom(path, self.save_dir.as_deref()) {
+            Ok(rom) => rom,
+            Err(e) => {
+                eprintln!("warning: {}: {}", path.display(), e);
+                return;
+            }
+        };
+        if let Err(e) = self.nes.swap_rom(rom) {
+            eprintln!("warning: the current ROM is kept: {}", e);
+            return;
+        }
+        self.timer.reset();
+        eprintln!("playing {}", path.display());
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Writes the save data of the cartridge, such as battery-backed RAM, when the window is
+    /// closed. Called by the frontends on every way out of their loop.
+    pub fn shutdown(&mut self) {
+        if let Err(e) = self.nes.flush_save_data() {
+            eprintln!("warning: failed to write the save data: {:#}", e);
+        }
+    }
+
+    /// Runs a frame, returning the picture to present and the audio samples to play.
+    ///
+    /// While fast-forwarding, the frames skipped by `Options::fast_forward_skip` are run too, and
+    /// no audio is played. While rewinding, the emulation goes back before the frame is run, also
+    /// without audio.
+    pub fn frame(&mut self) -> (Screenshot, Vec<f32>) {
+        self.reload_bindings();
+        #[cfg(feature = "gilrs")]
+        self.poll_gamepads();
+        let frames = if self.fast_forward {
+            1 + self.options.fast_forward_skip
+        } else {
+            1
+        };
+        if self.rewinding && !self.nes.paused() {
+            self.nes.rewind(REWIND_FRAMES);
+        }
+        for _ in 0..frames {
+            if self.advance {
+                self.advance = false;
+                self.nes.step_frame();
+            } else {
+                self.nes.frame();
+            }
+            for lint in self.nes.take_lints() {
+                eprintln!("warning: {}", lint);
+            }
+        }
+        let mut samples = self.nes.take_audio_samples();
+        if self.fast_forward || self.rewinding {
+            samples.clear();
+        }
+        let mut frame = self.nes.video_frame();
+        if self.show_stats {
+            overlay::draw_stats(&mut frame, &self.nes.stats());
+        }
+        (frame, samples)
+    }
+
+    /// Waits until the next frame is due in real time, or not at all while fast-forwarding.
+    pub fn wait_frame(&mut self) {
+        if !self.fast_forward {
+            self.timer.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustnes::ROM;
+
+    #[test]
+    fn keys() {
+        // NROM of 16 KB PRG-ROM and 8 KB CHR-ROM
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(data.len() + 0x6000, 0);
+        let mut nes = NES::default();
+        nes.load(ROM::from_bytes(&data).unwrap()).unwrap();
+        let mut frontend = Frontend::new(nes, Options::default());
+        frontend.key_down(Key::X);
+        frontend.key_down(Key::Right);
+        frontend.key_up(Key::X);
+        assert_eq!(frontend.nes().controller(0), Button::RIGHT);
+        assert!(!frontend.quit());
+
+        // the held key is released from the button it was bound to
+        frontend.set_bindings(Bindings::parse("[controller2]\nleft = \"Right\"").unwrap());
+        assert_eq!(frontend.nes().controller(0), Button::empty());
+        assert_eq!(frontend.nes().controller(1), Button::LEFT);
+        frontend.key_up(Key::Right);
+        assert_eq!(frontend.nes().controller(1), Button::empty());
+
+        frontend.key_down(Key::P);
+        assert!(frontend.nes().paused());
+        frontend.key_down(Key::N);
+        assert!(frontend.advance);
+        frontend.frame();
+        assert!(!frontend.advance);
+        frontend.key_down(Key::F6);
+        assert_eq!(frontend.slot, 1);
+        frontend.key_down(Key::Num9);
+        frontend.key_down(Key::F6);
+        assert_eq!(frontend.slot, 0);
+
+        frontend.key_down(Key::P);
+        frontend.key_down(Key::Tab);
+        let (_, samples) = frontend.frame();
+        assert!(samples.is_empty());
+        frontend.key_up(Key::Tab);
+        assert!(!frontend.fast_forward);
+        frontend.key_down(Key::Backspace);
+        let (_, samples) = frontend.frame();
+        assert!(samples.is_empty());
+        frontend.key_up(Key::Backspace);
+        assert!(!frontend.rewinding);
+
+        frontend.key_down(Key::F3);
+        let (frame, _) = frontend.frame();
+        assert!(frame.pixels.chunks_exact(4).any(|p| p[..3] == [0xFF; 3]));
+
+        frontend.key_down(Key::Escape);
+        assert!(frontend.quit());
+        let (frame, _) = frontend.frame();
+        assert_eq!(
+            (frame.width, frame.height),
+            frontend.nes().video_frame_size()
+        );
+    }
+
+    #[test]
+    fn shutdown() {
+        let dir = std::env::temp_dir().join(format!("rustnes-shutdown-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.sav");
+        // NROM with battery-backed RAM
+        let mut data = vec![
+            0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        data.resize(data.len() + 0x6000, 0);
+        let mut rom = ROM::from_bytes(&data).unwrap();
+        rom.set_sram_path(&path).unwrap();
+        let mut nes = NES::default();
+        nes.load(rom).unwrap();
+        let mut frontend = Frontend::new(nes, Options::default());
+        frontend.nes.import_save_ram(&[0xA5; 0x10]).unwrap();
+        frontend.shutdown();
+        assert_eq!(fs::read(&path).unwrap()[..0x10], [0xA5; 0x10]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sample_rate() {
+        assert_eq!(adjusted_sample_rate(48000, 0.5), 48000);
+        assert_eq!(adjusted_sample_rate(48000, 0.0), 48240);
+        assert_eq!(adjusted_sample_rate(48000, 1.0), 47760);
+        assert_eq!(adjusted_sample_rate(48000, 0.75), 47880);
+        assert_eq!(adjusted_sample_rate(48000, 2.0), 47760);
+    }
+}
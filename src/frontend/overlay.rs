@@ -0,0 +1,66 @@
+use rustnes::{Screenshot, Stats};
+
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+// pixels around the text, darkened behind it
+const MARGIN: u32 = 1;
+
+// rows of 3 pixels from the top, the leftmost in bit 2
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+/// Draws the frames per second and the speed at the top left of `frame`.
+pub fn draw_stats(frame: &mut Screenshot, stats: &Stats) {
+    let text = format!("{:.1} FPS {:.0}%", stats.fps, stats.speed * 100.0);
+    draw_text(frame, 0, 0, &text);
+}
+
+// Draws `text` in white on a dark background, clipped to the frame. Characters without a glyph
+// are spaces.
+fn draw_text(frame: &mut Screenshot, x: u32, y: u32, text: &str) {
+    let chars = text.chars().count() as u32;
+    let width = chars * (GLYPH_WIDTH + 1) - 1 + MARGIN * 2;
+    let height = GLYPH_HEIGHT + MARGIN * 2;
+    for py in y..(y + height).min(frame.height) {
+        for px in x..(x + width).min(frame.width) {
+            let i = ((py * frame.width + px) * 4) as usize;
+            for c in &mut frame.pixels[i..i + 3] {
+                *c /= 4;
+            }
+        }
+    }
+    for (n, c) in text.chars().enumerate() {
+        let left = x + MARGIN + n as u32 * (GLYPH_WIDTH + 1);
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                let (px, py) = (left + col, y + MARGIN + row as u32);
+                if frame.width <= px || frame.height <= py {
+                    continue;
+                }
+                let i = ((py * frame.width + px) * 4) as usize;
+                frame.pixels[i..i + 3].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+            }
+        }
+    }
+}
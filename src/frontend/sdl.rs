@@ -0,0 +1,166 @@
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+use super::{Frontend, Key};
+
+// frames of audio queued at most, half of which are kept queued by the rate control, dropped
+// beyond to keep the latency low
+const MAX_QUEUED_FRAMES: u32 = 6;
+
+fn key(keycode: Keycode) -> Option<Key> {
+    match keycode {
+        Keycode::A => Some(Key::A),
+        Keycode::B => Some(Key::B),
+        Keycode::C => Some(Key::C),
+        Keycode::D => Some(Key::D),
+        Keycode::E => Some(Key::E),
+        Keycode::F => Some(Key::F),
+        Keycode::G => Some(Key::G),
+        Keycode::H => Some(Key::H),
+        Keycode::I => Some(Key::I),
+        Keycode::J => Some(Key::J),
+        Keycode::K => Some(Key::K),
+        Keycode::L => Some(Key::L),
+        Keycode::M => Some(Key::M),
+        Keycode::N => Some(Key::N),
+        Keycode::O => Some(Key::O),
+        Keycode::P => Some(Key::P),
+        Keycode::Q => Some(Key::Q),
+        Keycode::R => Some(Key::R),
+        Keycode::S => Some(Key::S),
+        Keycode::T => Some(Key::T),
+        Keycode::U => Some(Key::U),
+        Keycode::V => Some(Key::V),
+        Keycode::W => Some(Key::W),
+        Keycode::X => Some(Key::X),
+        Keycode::Y => Some(Key::Y),
+        Keycode::Z => Some(Key::Z),
+        Keycode::Num0 => Some(Key::Num0),
+        Keycode::Num1 => Some(Key::Num1),
+        Keycode::Num2 => Some(Key::Num2),
+        Keycode::Num3 => Some(Key::Num3),
+        Keycode::Num4 => Some(Key::Num4),
+        Keycode::Num5 => Some(Key::Num5),
+        Keycode::Num6 => Some(Key::Num6),
+        Keycode::Num7 => Some(Key::Num7),
+        Keycode::Num8 => Some(Key::Num8),
+        Keycode::Num9 => Some(Key::Num9),
+        Keycode::F1 => Some(Key::F1),
+        Keycode::F2 => Some(Key::F2),
+        Keycode::F3 => Some(Key::F3),
+        Keycode::F4 => Some(Key::F4),
+        Keycode::F5 => Some(Key::F5),
+        Keycode::F6 => Some(Key::F6),
+        Keycode::F7 => Some(Key::F7),
+        Keycode::F8 => Some(Key::F8),
+        Keycode::F9 => Some(Key::F9),
+        Keycode::F10 => Some(Key::F10),
+        Keycode::F11 => Some(Key::F11),
+        Keycode::F12 => Some(Key::F12),
+        Keycode::Up => Some(Key::Up),
+        Keycode::Down => Some(Key::Down),
+        Keycode::Left => Some(Key::Left),
+        Keycode::Right => Some(Key::Right),
+        Keycode::Return => Some(Key::Return),
+        Keycode::Space => Some(Key::Space),
+        Keycode::Tab => Some(Key::Tab),
+        Keycode::Backspace => Some(Key::Backspace),
+        Keycode::Escape => Some(Key::Escape),
+        Keycode::LShift => Some(Key::LShift),
+        Keycode::RShift => Some(Key::RShift),
+        Keycode::LCtrl => Some(Key::LCtrl),
+        Keycode::RCtrl => Some(Key::RCtrl),
+        Keycode::LAlt => Some(Key::LAlt),
+        Keycode::RAlt => Some(Key::RAlt),
+        _ => None,
+    }
+}
+
+/// Runs `frontend` in a window until it is closed.
+pub fn run(mut frontend: Frontend) -> Result<(), String> {
+    let result = play(&mut frontend);
+    frontend.shutdown();
+    result
+}
+
+fn play(frontend: &mut Frontend) -> Result<(), String> {
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let audio = sdl.audio()?;
+
+    let (width, height) = frontend.nes().video_frame_size();
+    let scale = frontend.options().scale;
+    let window = video
+        .window("rustnes", width * scale, height * scale)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    // RGBA bytes, which are ABGR in a little-endian u32
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::ABGR8888, width, height)
+        .map_err(|e| e.to_string())?;
+
+    let sample_rate = frontend.nes().sample_rate();
+    let desired = AudioSpecDesired {
+        freq: Some(sample_rate as i32),
+        channels: Some(1),
+        samples: None,
+    };
+    let queue: AudioQueue<f32> = audio.open_queue(None, &desired)?;
+    // the device may play at another rate
+    let sample_rate = queue.spec().freq as u32;
+    frontend.set_sample_rate(sample_rate);
+    if frontend.options().audio {
+        queue.resume();
+    }
+    let max_queued = sample_rate / 60 * MAX_QUEUED_FRAMES * std::mem::size_of::<f32>() as u32;
+
+    let mut events = sdl.event_pump()?;
+    while !frontend.quit() {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. } => return Ok(()),
+                Event::DropFile { filename, .. } => frontend.swap_rom(filename),
+                Event::KeyDown {
+                    keycode: Some(k),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(k) = key(k) {
+                        frontend.key_down(k);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(k), ..
+                } => {
+                    if let Some(k) = key(k) {
+                        frontend.key_up(k);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (frame, samples) = frontend.frame();
+        texture
+            .update(None, &frame.pixels, frame.width as usize * 4)
+            .map_err(|e| e.to_string())?;
+        canvas.clear();
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+
+        if frontend.options().audio {
+            if max_queued < queue.size() {
+                queue.clear();
+            }
+            queue.queue_audio(&samples)?;
+            frontend.adjust_sample_rate(queue.size() as f32 / max_queued as f32);
+        }
+        frontend.wait_frame();
+    }
+    Ok(())
+}
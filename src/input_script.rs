@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use crate::controller::{Button, CONTROLLERS};
+
+/// Controller input scripted as a sequence of steps, for tests navigating game menus.
+///
+/// Attached with `NES::set_input_script`, the script is consumed frame by frame by `NES::frame`.
+/// Buttons pressed are held until released, and only take effect through `wait_frames`:
+///
+/// ```
+/// use rustnes::{Button, InputScript};
+///
+/// let script = InputScript::new()
+///     .wait_frames(60)
+///     .tap(Button::START)
+///     .press(Button::RIGHT)
+///     .wait_frames(30)
+///     .release(Button::RIGHT)
+///     .port(1)
+///     .tap(Button::A);
+/// assert_eq!(script.remaining_frames(), 60 + 1 + 30 + 1);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    // buttons of each port held for a number of frames
+    frames: VecDeque<(u32, [Button; CONTROLLERS])>,
+    held: [Button; CONTROLLERS],
+    port: usize,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Makes the following steps act on the controller in `port`, as `NES::set_controller`.
+    /// The steps act on port 0 until changed.
+    ///
+    /// Panics if `port` is not less than `CONTROLLERS`.
+    pub fn port(mut self, port: usize) -> Self {
+        assert!(port < CONTROLLERS, "no controller port {}", port);
+        self.port = port;
+        self
+    }
+
+    pub fn press(mut self, buttons: Button) -> Self {
+        self.held[self.port].insert(buttons);
+        self
+    }
+
+    pub fn release(mut self, buttons: Button) -> Self {
+        self.held[self.port].remove(buttons);
+        self
+    }
+
+    /// Releases all buttons on every port.
+    pub fn release_all(mut self) -> Self {
+        self.held = Default::default();
+        self
+    }
+
+    /// Runs `frames` frames with the buttons held.
+    pub fn wait_frames(mut self, frames: u32) -> Self {
+        if frames == 0 {
+            return self;
+        }
+        match self.frames.back_mut() {
+            Some((n, held)) if *held == self.held => *n += frames,
+            _ => self.frames.push_back((frames, self.held)),
+        }
+        self
+    }
+
+    /// Presses `buttons` for a frame, then releases them.
+    pub fn tap(self, buttons: Button) -> Self {
+        self.press(buttons).wait_frames(1).release(buttons)
+    }
+
+    /// The number of frames left to run.
+    pub fn remaining_frames(&self) -> u32 {
+        self.frames.iter().map(|(n, _)| n).sum()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Takes the buttons of each port for the next frame, or `None` if finished.
+    pub fn next_frame(&mut self) -> Option<[Button; CONTROLLERS]> {
+        let (n, held) = self.frames.front_mut()?;
+        let held = *held;
+        *n -= 1;
+        if *n == 0 {
+            self.frames.pop_front();
+        }
+        Some(held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let mut script = InputScript::new()
+            .press(Button::A)
+            .wait_frames(2)
+            .wait_frames(0)
+            .port(1)
+            .tap(Button::B)
+            .release_all()
+            .wait_frames(1);
+        assert_eq!(script.remaining_frames(), 4);
+
+        let a = [Button::A, Button::empty(), Button::empty(), Button::empty()];
+        let b = [Button::A, Button::B, Button::empty(), Button::empty()];
+        assert_eq!(script.next_frame(), Some(a));
+        assert_eq!(script.next_frame(), Some(a));
+        assert_eq!(script.next_frame(), Some(b));
+        assert_eq!(script.next_frame(), Some(Default::default()));
+        assert_eq!(script.next_frame(), None);
+        assert!(script.is_finished());
+    }
+}
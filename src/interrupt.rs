@@ -35,7 +35,44 @@ impl Interrupt {
         self.0 &= !s.0
     }
 
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & 0b1111)
+    }
+
     // pub fn is_interrupted(&self) -> bool {
     //     self.0 != 0
     // }
 }
+
+/// Kind of an interrupt serviced by the CPU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InterruptKind {
+    Reset,
+    NMI,
+    IRQ,
+}
+
+/// An interrupt serviced by the CPU, reported by `NES::set_interrupt_hook`.
+///
+/// Cycles are counted from the power on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterruptEvent {
+    pub kind: InterruptKind,
+    /// CPU cycle when the interrupt was requested.
+    pub requested: u128,
+    /// CPU cycle when the interrupt sequence started, after the instruction in progress.
+    pub entered: u128,
+    /// CPU cycles taken by the interrupt sequence, 7 on hardware.
+    pub cycles: u128,
+}
+
+impl InterruptEvent {
+    /// CPU cycles from the request until the handler starts.
+    pub fn latency(&self) -> u128 {
+        self.entered - self.requested + self.cycles
+    }
+}
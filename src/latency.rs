@@ -0,0 +1,46 @@
+use crate::nes::NES;
+
+/// Measures the input latency in frames with a calibration ROM.
+///
+/// `press` is called once to inject the input, typically with `NES::set_controller`, then
+/// frames are run until the value at `addr` changes, which the calibration ROM does in response
+/// to the input. Returns the number of frames run, or `None` if the value does not change within
+/// `max_frames`.
+///
+/// Frames are run with `NES::frame`, so the result includes the latency of the emulator
+/// configuration as a frontend would run it.
+pub fn measure_latency<F: FnOnce(&mut NES)>(
+    nes: &mut NES,
+    addr: u16,
+    max_frames: u32,
+    press: F,
+) -> Option<u32> {
+    let before = nes.peek(addr);
+    press(nes);
+    for frames in 1..=max_frames {
+        nes.frame();
+        if nes.peek(addr) != before {
+            return Some(frames);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::tests::counter_rom;
+
+    #[test]
+    fn measure() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+        nes.frame();
+
+        assert_eq!(measure_latency(&mut nes, 0x10, 5, |_| {}), Some(1));
+        assert_eq!(measure_latency(&mut nes, 0x11, 5, |_| {}), None);
+    }
+}
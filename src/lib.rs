@@ -1,13 +1,75 @@
+mod apu;
+mod audio_ring;
+mod bus;
+mod capture;
+mod chr;
+mod controller;
 mod cpu;
+mod frame_timer;
+mod input_script;
 mod interrupt;
+mod latency;
+mod lint;
 mod memory_map;
+mod movie;
 mod nes;
+mod ntsc;
+mod palette;
+mod png;
 mod ppu;
+mod region;
+mod register_log;
+mod rewind;
+mod rollback;
 mod rom;
+mod savestate;
+mod screenshot;
+#[cfg(feature = "serde")]
+mod session;
+mod stats;
+mod trace_log;
 mod types;
+mod wide_canvas;
 
 extern crate anyhow;
 extern crate thiserror;
 
-pub use nes::NES;
-pub use rom::ROM;
+pub use apu::{Channel, MixerMode};
+pub use audio_ring::{audio_ring, AudioConsumer, AudioProducer};
+pub use bus::{Mapped, Mirrored, RamRegion, RomRegion};
+pub use capture::{Capture, CaptureFormat};
+pub use chr::{chr_sheet, CHR_SHEET_TILES_PER_ROW};
+pub use controller::{Button, CONTROLLERS, CONTROLLER_PORTS};
+pub use cpu::{CPUSnapshot, Trace};
+pub use frame_timer::FrameTimer;
+pub use input_script::InputScript;
+pub use interrupt::{InterruptEvent, InterruptKind};
+pub use latency::measure_latency;
+pub use lint::HardwareLint;
+pub use memory_map::RAMPattern;
+pub use movie::{Movie, MovieFrame, MovieRecorder, MovieStart};
+pub use nes::{Config, Frame, Parts, NES};
+pub use ntsc::NTSC_FILTER_WIDTH;
+pub use palette::{Palette, PALETTE_COLORS};
+pub use ppu::{
+    Emphasis, Layer, OAMEntry, PPUSnapshot, PartialFrame, ScanlineEvent, HEIGHT as FRAME_HEIGHT,
+    NAMETABLES_HEIGHT, NAMETABLES_WIDTH, WIDTH as FRAME_WIDTH,
+};
+pub use region::Region;
+pub use register_log::{Device, RegisterWrite};
+pub use rewind::RewindConfig;
+pub use rollback::{Rollback, MAX_PREDICTION};
+pub use rom::{
+    Cartridge, ConsoleType, GameDatabase, GameInfo, Mapper, MapperRegistry, ROMDiagnostic,
+    VRAMSource, ROM,
+};
+pub use savestate::{
+    SaveState, SaveStateMetadata, StateReader, StateWriter, SAVE_SLOTS, SAVE_STATE_THUMBNAIL_SCALE,
+};
+pub use screenshot::{thumbnail, FrameDiff, Overscan, Screenshot, THUMBNAIL_FRAMES};
+#[cfg(feature = "serde")]
+pub use session::{Session, RECENT_ROMS};
+pub use stats::Stats;
+pub use trace_log::{parse_size, TraceFormat, TraceLog};
+pub use types::{Byte, Memory, Mirroring, Word};
+pub use wide_canvas::WideCanvas;
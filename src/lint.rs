@@ -0,0 +1,22 @@
+use std::fmt;
+
+/// Use of the hardware that works in the emulator but is harmful or unreliable on a real console.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HardwareLint {
+    /// PPUCTRL bit 6 was set, which makes the PPU output to its EXT pins grounded on the console
+    /// and can damage it.
+    /// https://wiki.nesdev.com/w/index.php/PPU_registers#Master.2Fslave_mode_and_the_EXT_pins
+    PPUSlaveMode { frame: u64 },
+}
+
+impl fmt::Display for HardwareLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PPUSlaveMode { frame } => write!(
+                f,
+                "PPUCTRL slave mode bit set at frame {}; this can damage a real console",
+                frame
+            ),
+        }
+    }
+}
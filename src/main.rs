@@ -1,10 +1,141 @@
-use rustnes::{NES, ROM};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let rom = ROM::load("nestest.nes")?;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
+mod frontend;
+
+use frontend::bindings::{Bindings, BindingsFile};
+use frontend::config::Config;
+
+use rustnes::{
+    parse_size, Capture, CaptureFormat, Palette, Region, TraceFormat, TraceLog, NES, ROM,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> Result<()> {
+    let region = Arg::with_name("region")
+        .long("region")
+        .value_name("REGION")
+        .possible_values(&["ntsc", "pal"])
+        .global(true)
+        .help("TV system of the console [default: ntsc]");
+    let palette = Arg::with_name("palette")
+        .long("palette")
+        .value_name("PATH")
+        .global(true)
+        .help("Palette in the .pal format");
+    let config = Arg::with_name("config")
+        .long("config")
+        .value_name("PATH")
+        .global(true)
+        .help("Config in TOML [default: ~/.config/rustnes/config.toml]");
+    let matches = App::new("rustnes")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("NES emulator")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(
+            Arg::with_name("rom")
+                .value_name("ROM")
+                .required_unless("nestest")
+                .help("ROM to play in a window"),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .value_name("N")
+                .help("Scale of the window [default: 3]"),
+        )
+        .arg(
+            Arg::with_name("no-audio")
+                .long("no-audio")
+                .help("Plays without sound"),
+        )
+        .arg(
+            value_arg("fast-forward-skip", "N").help(
+                "Frames not presented between each presented while fast-forwarding [default: 0]",
+            ),
+        )
+        .arg(value_arg("run-ahead", "N").help(
+            "Frames run ahead of the input to cut the input lag, twice as slow with 1 [default: 0]",
+        ))
+        .arg(value_arg("bindings", "PATH").help("Key bindings in TOML, read again when changed"))
+        .arg(
+            Arg::with_name("nestest")
+                .long("nestest")
+                .help("Prints the trace of nestest, from nestest.nes unless ROM is given"),
+        )
+        .arg(region)
+        .arg(palette)
+        .arg(config)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs a ROM without a window")
+                .arg(Arg::with_name("rom").value_name("ROM").required(true))
+                .arg(value_arg("frames", "N").default_value("3600"))
+                .arg(value_arg("trace", "PATH").help("Writes the CPU trace"))
+                .arg(
+                    value_arg("trace-limit", "SIZE")
+                        .default_value("100M")
+                        .help("Size of the trace to keep, such as 100M"),
+                )
+                .arg(
+                    value_arg("trace-format", "FORMAT")
+                        .possible_values(&["nestest", "json"])
+                        .default_value("nestest"),
+                )
+                .arg(value_arg("import-sav", "PATH"))
+                .arg(value_arg("export-sav", "PATH"))
+                .arg(value_arg("export-chr", "PATH"))
+                .arg(
+                    value_arg("capture", "PATH")
+                        .help("Captures the video in Y4M or raw RGB by the extension, and WAV"),
+                ),
+        )
+        .get_matches();
+
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    if let Some(matches) = matches.subcommand_matches("run") {
+        return run(RunOptions::parse(matches, &config)?);
+    }
+    let nes = configured_nes(&matches, &config)?;
+    if matches.is_present("nestest") {
+        return nestest(nes, matches.value_of("rom").unwrap_or("nestest.nes"));
+    }
+    play(nes, &matches, &config)
+}
+
+fn value_arg<'a, 'b>(name: &'a str, value_name: &'a str) -> Arg<'a, 'b> {
+    Arg::with_name(name).long(name).value_name(value_name)
+}
+
+// the settings of `--region` and `--palette` over the config
+fn configured_nes(matches: &ArgMatches, config: &Config) -> Result<NES> {
     let mut nes = NES::default();
-    nes.load(rom);
+    nes.set_region(match matches.value_of("region") {
+        Some("pal") => Region::PAL,
+        Some(_) => Region::NTSC,
+        None => config.region,
+    });
+    let palette = matches
+        .value_of("palette")
+        .map(Path::new)
+        .or(config.palette.as_deref());
+    if let Some(path) = palette {
+        nes.set_palette(Some(Palette::load(path)?));
+    }
+    nes.set_overscan(config.video.overscan);
+    Ok(nes)
+}
+
+fn nestest(mut nes: NES, path: &str) -> Result<()> {
+    nes.load(ROM::load(path)?)?;
 
     nes.power_on();
 
@@ -14,3 +145,182 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// plays in a window with sound and the keyboard
+fn play(mut nes: NES, matches: &ArgMatches, config: &Config) -> Result<()> {
+    let scale = match matches.value_of("scale") {
+        Some(scale) => scale.parse()?,
+        None => config.video.scale,
+    };
+    if scale == 0 {
+        return Err("the scale must be 1 or more".into());
+    }
+    let options = frontend::Options {
+        scale,
+        audio: config.audio.enabled && !matches.is_present("no-audio"),
+        fast_forward_skip: match matches.value_of("fast-forward-skip") {
+            Some(n) => n.parse()?,
+            None => config.video.fast_forward_skip,
+        },
+    };
+    nes.set_rewind(config.rewind.buffer());
+    nes.set_run_ahead(match matches.value_of("run-ahead") {
+        Some(n) => n.parse()?,
+        None => config.run_ahead,
+    });
+    let save_dir = config.save_dir.as_deref();
+    nes.load(frontend::load_rom(
+        matches.value_of("rom").unwrap_or_default(),
+        save_dir,
+    )?)?;
+    let mut frontend = frontend::Frontend::new(nes, options);
+    frontend.set_save_dir(save_dir.map(Path::to_path_buf));
+    match matches.value_of("bindings") {
+        Some(path) => {
+            let (file, bindings) = BindingsFile::open(path)?;
+            frontend.watch_bindings(file, bindings);
+        }
+        None => frontend.set_bindings(Bindings::from_config(&config.input)?),
+    }
+    run_frontend(frontend)
+}
+
+#[cfg(feature = "sdl2")]
+fn run_frontend(frontend: frontend::Frontend) -> Result<()> {
+    Ok(frontend::sdl::run(frontend)?)
+}
+
+#[cfg(all(feature = "minifb", not(feature = "sdl2")))]
+fn run_frontend(frontend: frontend::Frontend) -> Result<()> {
+    frontend::minifb::run(frontend)
+}
+
+#[cfg(not(any(feature = "sdl2", feature = "minifb")))]
+fn run_frontend(_: frontend::Frontend) -> Result<()> {
+    Err("rustnes was built without a frontend; rebuild with --features sdl2 or minifb".into())
+}
+
+struct RunOptions<'a> {
+    nes: NES,
+    rom: &'a str,
+    frames: u64,
+    trace: Option<&'a str>,
+    trace_limit: u64,
+    trace_format: TraceFormat,
+    import_sav: Option<&'a str>,
+    export_sav: Option<&'a str>,
+    export_chr: Option<&'a str>,
+    capture: Option<&'a str>,
+    save_dir: Option<PathBuf>,
+}
+
+impl<'a> RunOptions<'a> {
+    fn parse(matches: &'a ArgMatches, config: &Config) -> Result<Self> {
+        let value = |name| matches.value_of(name).unwrap_or_default();
+        let trace_limit = value("trace-limit");
+        Ok(Self {
+            nes: configured_nes(matches, config)?,
+            rom: value("rom"),
+            frames: value("frames").parse()?,
+            trace: matches.value_of("trace"),
+            trace_limit: parse_size(trace_limit)
+                .ok_or_else(|| format!("invalid size: {}", trace_limit))?,
+            trace_format: match value("trace-format") {
+                "json" => TraceFormat::Json,
+                _ => TraceFormat::Nestest,
+            },
+            import_sav: matches.value_of("import-sav"),
+            export_sav: matches.value_of("export-sav"),
+            export_chr: matches.value_of("export-chr"),
+            capture: matches.value_of("capture"),
+            save_dir: config.save_dir.clone(),
+        })
+    }
+}
+
+fn run(options: RunOptions) -> Result<()> {
+    let mut nes = options.nes;
+    nes.load(frontend::load_rom(
+        options.rom,
+        options.save_dir.as_deref(),
+    )?)?;
+    if let Some(ref path) = options.import_sav {
+        nes.import_save_ram(&fs::read(path)?)?;
+    }
+    nes.power_on();
+    nes.reset();
+
+    let log = match options.trace {
+        Some(ref path) => Some(Arc::new(Mutex::new(TraceLog::create(
+            path,
+            options.trace_limit,
+            options.trace_format,
+        )?))),
+        None => None,
+    };
+    // the first write error, reported after the run
+    let error = Arc::new(Mutex::new(None));
+    if let Some(ref log) = log {
+        let log = log.clone();
+        let error = error.clone();
+        nes.set_trace_hook(move |trace| {
+            let mut error = error.lock().unwrap();
+            if error.is_some() {
+                return;
+            }
+            if let Err(e) = log.lock().unwrap().write(trace) {
+                *error = Some(e);
+            }
+        });
+    }
+
+    // video in Y4M or raw RGB by the extension, and audio in WAV next to it
+    let mut capture = match options.capture {
+        Some(ref path) => {
+            let audio_path = std::path::Path::new(path).with_extension("wav");
+            let format = CaptureFormat::from_path(path);
+            Some(Capture::create(path, audio_path, format, &nes)?)
+        }
+        None => None,
+    };
+
+    for _ in 0..options.frames {
+        nes.frame();
+        if let Some(ref mut capture) = capture {
+            capture.write_frame(&mut nes)?;
+        }
+        for lint in nes.take_lints() {
+            eprintln!("warning: {}", lint);
+        }
+        if error.lock().unwrap().is_some() {
+            break;
+        }
+    }
+    nes.clear_trace_hook();
+    if let Some(capture) = capture {
+        capture.finish()?;
+    }
+
+    if let Some(e) = error.lock().unwrap().take() {
+        return Err(e.into());
+    }
+    if let Some(log) = log {
+        log.lock().unwrap().flush()?;
+    }
+    nes.flush_save_data()?;
+    if let Some(ref path) = options.export_sav {
+        let ram = nes
+            .export_save_ram()
+            .ok_or("the cartridge has no save RAM")?;
+        fs::write(path, ram)?;
+    }
+    if let Some(ref path) = options.export_chr {
+        // CHR RAM as written by the game
+        let sheet = match nes.rom().and_then(ROM::chr_sheet) {
+            Some(sheet) => sheet,
+            None => nes.chr_sheet(),
+        };
+        sheet.write_png(io::BufWriter::new(fs::File::create(path)?))?;
+    }
+    Ok(())
+}
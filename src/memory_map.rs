@@ -1,131 +1,459 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
-use crate::rom::Mapper;
+use crate::rom::{Mapper, VRAMSource};
 use crate::types::{Byte, Memory, Mirroring, Word};
 
+use crate::apu::APU;
+use crate::controller::ControllerPorts;
 use crate::ppu::PPU;
+use crate::register_log::RegisterLog;
+
+/// The address space of the CPU, borrowing the parts of the NES for the accesses of a step.
+pub struct CPUBus<'a> {
+    pub wram: &'a mut [u8; 0x0800],
+    pub mapper: Option<&'a mut dyn Mapper>,
+    pub ppu: &'a mut PPU,
+    pub vram: &'a mut VRAM,
+    pub apu: &'a mut APU,
+    pub controllers: &'a mut ControllerPorts,
+    pub register_log: &'a mut RegisterLog,
+}
 
-pub struct CPUBus {
-    wram: [u8; 0x2000],
-    mapper: Rc<RefCell<dyn Mapper>>,
+// The upper bits of controller ports are open bus, which is usually $40 from the address.
+const CONTROLLER_OPEN_BUS: u8 = 0x40;
 
-    ppu: Rc<RefCell<PPU>>,
+/// Initial contents of the work RAM at power-on.
+///
+/// The RAM of a real console holds an unreliable pattern at power-on,
+/// and some games behave differently depending on it.
+///
+/// With the `serde` feature, it is the name in lowercase, such as `"striped"`, or the seed of
+/// `Random`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(into = "SerdeRAMPattern", try_from = "SerdeRAMPattern")
+)]
+pub enum RAMPattern {
+    #[default]
+    AllZero,
+    AllFF,
+    /// Alternating runs of four 0x00 and four 0xFF, as seen on many consoles.
+    Striped,
+    /// Pseudo random bytes generated from the seed, deterministic across runs.
+    Random(u64),
 }
 
-impl CPUBus {
-    pub fn new(mapper: Rc<RefCell<dyn Mapper>>, ppu: Rc<RefCell<PPU>>) -> CPUBus {
-        Self {
-            wram: [0; 0x2000],
-            mapper,
-            ppu,
+impl RAMPattern {
+    pub(crate) fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            Self::AllZero => ram.iter_mut().for_each(|b| *b = 0x00),
+            Self::AllFF => ram.iter_mut().for_each(|b| *b = 0xFF),
+            Self::Striped => {
+                for (i, b) in ram.iter_mut().enumerate() {
+                    *b = if i & 0b100 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            Self::Random(seed) => {
+                // xorshift64*; the state must not be zero
+                let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+                if state == 0 {
+                    state = 1;
+                }
+                for b in ram.iter_mut() {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *b = (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+                }
+            }
+        }
+    }
+}
+
+// RAMPattern as a name or a seed, which formats such as TOML take without tagging the variant
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum SerdeRAMPattern {
+    Name(String),
+    Seed(u64),
+}
+
+#[cfg(feature = "serde")]
+impl From<RAMPattern> for SerdeRAMPattern {
+    fn from(pattern: RAMPattern) -> Self {
+        match pattern {
+            RAMPattern::AllZero => Self::Name("allzero".to_string()),
+            RAMPattern::AllFF => Self::Name("allff".to_string()),
+            RAMPattern::Striped => Self::Name("striped".to_string()),
+            RAMPattern::Random(seed) => Self::Seed(seed),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<SerdeRAMPattern> for RAMPattern {
+    type Error = String;
+
+    fn try_from(pattern: SerdeRAMPattern) -> Result<Self, Self::Error> {
+        match pattern {
+            SerdeRAMPattern::Name(name) => match name.as_str() {
+                "allzero" => Ok(Self::AllZero),
+                "allff" => Ok(Self::AllFF),
+                "striped" => Ok(Self::Striped),
+                _ => Err(format!("unknown RAM pattern {:?}", name)),
+            },
+            SerdeRAMPattern::Seed(seed) => Ok(Self::Random(seed)),
         }
     }
 }
 
 fn to_ppu_addr(addr: u16) -> u16 {
     // repears every 8 bytes
-    0x2000u16.wrapping_add(addr) % 8
+    0x2000u16.wrapping_add(addr % 8)
 }
 
-impl Memory for CPUBus {
-    fn read(&self, addr: Word) -> Byte {
+impl Memory for CPUBus<'_> {
+    fn read(&mut self, addr: Word) -> Byte {
         let addr_u16: u16 = addr.into();
         match addr_u16 {
-            0x0000..=0x1FFF => self.wram[addr_u16 as usize].into(),
-            0x2000..=0x3FFF => self.ppu.borrow_mut().read_register(to_ppu_addr(addr_u16)),
-            0x4020..=0xFFFF => self.mapper.borrow().read(addr),
+            0x0000..=0x1FFF => self.wram[(addr_u16 & 0x07FF) as usize].into(),
+            0x2000..=0x3FFF => {
+                let mut bus = ppu_bus(self.vram, &mut self.mapper);
+                self.ppu.read_register(to_ppu_addr(addr_u16), &mut bus)
+            }
+            0x4015 => self.apu.read_status(),
+            0x4016 => (CONTROLLER_OPEN_BUS | self.controllers.read(0)).into(),
+            0x4017 => (CONTROLLER_OPEN_BUS | self.controllers.read(1)).into(),
+            0x4020..=0xFFFF => self
+                .mapper
+                .as_mut()
+                .map_or(0.into(), |mapper| mapper.read(addr)),
             _ => 0.into(),
         }
     }
 
+    fn peek(&self, addr: Word) -> Byte {
+        peek_cpu_bus(self.wram, self.mapper.as_deref(), addr)
+    }
+
     fn write(&mut self, addr: Word, value: Byte) {
         let addr_u16: u16 = addr.into();
+        if self.register_log.enabled() {
+            let position = self.ppu.position();
+            self.register_log.record(position, addr_u16, value.into());
+        }
         match addr_u16 {
-            0x0000..=0x1FFF => self.wram[addr_u16 as usize] = value.into(),
-            0x2000..=0x3FFF => self
-                .ppu
-                .borrow_mut()
-                .write_register(to_ppu_addr(addr_u16), value),
-            0x4020..=0xFFFF => self.mapper.borrow_mut().write(addr, value),
+            0x0000..=0x1FFF => self.wram[(addr_u16 & 0x07FF) as usize] = value.into(),
+            0x2000..=0x3FFF => {
+                let mut bus = ppu_bus(self.vram, &mut self.mapper);
+                self.ppu
+                    .write_register(to_ppu_addr(addr_u16), value, &mut bus)
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr_u16, value),
+            // $4017 is the frame counter of the APU on writes
+            0x4016 => self.controllers.write_strobe(value.into()),
+            0x4020..=0xFFFF => {
+                if let Some(mapper) = self.mapper.as_mut() {
+                    mapper.write(addr, value)
+                }
+            }
             _ => {}
         }
     }
 }
 
-pub struct PPUBus {
+// The bus of the PPU for the accesses to its registers, reborrowing the VRAM and the mapper of
+// the CPU bus
+fn ppu_bus<'b>(vram: &'b mut VRAM, mapper: &'b mut Option<&mut dyn Mapper>) -> PPUBus<'b> {
+    let mapper = mapper.as_mut().map(|m| &mut **m as &mut dyn Mapper);
+    PPUBus::new(vram, mapper)
+}
+
+/// Reads the CPU bus without the parts of the NES borrowed. I/O registers read as 0, since
+/// reading them changes their state.
+pub fn peek_cpu_bus(wram: &[u8], mapper: Option<&dyn Mapper>, addr: Word) -> Byte {
+    let addr_u16: u16 = addr.into();
+    match addr_u16 {
+        0x0000..=0x1FFF => wram[(addr_u16 & 0x07FF) as usize].into(),
+        0x4020..=0xFFFF => mapper.map_or(0.into(), |mapper| mapper.peek(addr)),
+        _ => 0.into(),
+    }
+}
+
+// The cartridge slot without a cartridge, where nothing answers
+pub struct NoCartridge;
+
+impl Memory for NoCartridge {
+    fn peek(&self, _addr: Word) -> Byte {
+        0.into()
+    }
+
+    fn write(&mut self, _addr: Word, _value: Byte) {}
+}
+
+impl Mapper for NoCartridge {
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal()
+    }
+}
+
+// DMC sample fetching, borrowing the mapper for a step
+pub struct APUBus<'a> {
+    pub mapper: Option<&'a dyn Mapper>,
+}
+
+impl Memory for APUBus<'_> {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr_u16: u16 = addr.into();
+        match (addr_u16, self.mapper) {
+            (0x8000..=0xFFFF, Some(mapper)) => mapper.peek(addr),
+            _ => 0.into(),
+        }
+    }
+
+    fn write(&mut self, _addr: Word, _value: Byte) {}
+}
+
+/// The name tables and palette RAM of the NES, on the bus of the PPU along with the cartridge.
+pub struct VRAM {
     name_table: [Byte; 0x1000],
     pallete_ram_idx: [Byte; 0x0020],
-
-    mapper: Rc<RefCell<dyn Mapper>>,
-    mirroring: Mirroring,
 }
 
-impl PPUBus {
-    pub fn new(mapper: Rc<RefCell<dyn Mapper>>) -> Self {
-        let mirroring = mapper.borrow().mirroring();
+impl Default for VRAM {
+    fn default() -> Self {
         Self {
             name_table: [Default::default(); 0x1000],
             pallete_ram_idx: [Default::default(); 0x0020],
-            mapper,
-            mirroring,
         }
     }
+}
 
-    fn to_name_table_address(&self, base: u16) -> usize {
-        match self.mirroring {
-            Mirroring::Vertical() => base & 0x0800,
+impl VRAM {
+    fn to_name_table_address(mapper: &dyn Mapper, base: u16) -> usize {
+        // https://wiki.nesdev.com/w/index.php/Mirroring#Nametable_Mirroring
+        match mapper.mirroring() {
+            Mirroring::Vertical() => base & 0x07FF,
             Mirroring::Horizontal() => {
                 if 0x2800 <= base {
-                    0x0800u16.wrapping_add(base) % 0x0400
+                    0x0800 | (base & 0x03FF)
                 } else {
-                    base % 0x0400
+                    base & 0x03FF
                 }
             }
+            Mirroring::SingleScreen(page) => (page as u16 & 1) << 10 | (base & 0x03FF),
+            Mirroring::FourScreen() => base & 0x0FFF,
         }
         .into()
     }
 
-    fn to_pallete_address(&self, base: u16) -> usize {
+    fn to_pallete_address(base: u16) -> usize {
         // http://wiki.nesdev.com/w/index.php/PPU_palettes#Memory_Map
         let addr = base % 32;
         if addr % 4 == 0 { addr | 0x10 } else { addr }.into()
     }
-}
 
-impl Memory for PPUBus {
-    fn read(&self, addr: Word) -> Byte {
+    fn ciram_address(page: u8, addr: u16) -> usize {
+        (page as usize & 1) * 0x400 + (addr as usize & 0x3FF)
+    }
+
+    fn peek(&self, mapper: &dyn Mapper, addr: Word) -> Byte {
         let addr_u16: u16 = addr.into();
         match addr_u16 {
-            0x0000..=0x1FFF => self.mapper.borrow().read(addr),
-            0x2000..=0x2FFF => self.name_table[self.to_name_table_address(addr_u16)],
-            0x3000..=0x3EFF => self.name_table[self.to_name_table_address(addr_u16 - 0x1000)],
-            0x3F00..=0x3FFF => self.pallete_ram_idx[self.to_pallete_address(addr_u16)],
+            0x0000..=0x2FFF => self.read_vram(mapper, addr_u16),
+            0x3000..=0x3EFF => self.read_vram(mapper, addr_u16 - 0x1000),
+            0x3F00..=0x3FFF => self.pallete_ram_idx[Self::to_pallete_address(addr_u16)],
             _ => 0.into(),
         }
     }
 
+    // $0000-$2FFF
+    fn read_vram(&self, mapper: &dyn Mapper, addr: u16) -> Byte {
+        match mapper.vram_source(addr) {
+            VRAMSource::CIRAM(page) => self.name_table[Self::ciram_address(page, addr)],
+            VRAMSource::Default if 0x2000 <= addr => {
+                self.name_table[Self::to_name_table_address(mapper, addr)]
+            }
+            _ => mapper.peek(addr.into()),
+        }
+    }
+
+    fn write_vram(&mut self, mapper: &mut dyn Mapper, addr: u16, value: Byte) {
+        match mapper.vram_source(addr) {
+            VRAMSource::CIRAM(page) => self.name_table[Self::ciram_address(page, addr)] = value,
+            VRAMSource::Default if 0x2000 <= addr => {
+                self.name_table[Self::to_name_table_address(mapper, addr)] = value
+            }
+            _ => mapper.write(addr.into(), value),
+        }
+    }
+}
+
+/// The address space of the PPU, borrowing the VRAM and the mapper of the NES for the accesses
+/// of a step. The slot without a cartridge answers as `NoCartridge`.
+pub struct PPUBus<'a> {
+    vram: &'a mut VRAM,
+    mapper: Option<&'a mut dyn Mapper>,
+}
+
+impl<'a> PPUBus<'a> {
+    pub fn new(vram: &'a mut VRAM, mapper: Option<&'a mut dyn Mapper>) -> Self {
+        Self { vram, mapper }
+    }
+}
+
+impl Memory for PPUBus<'_> {
+    fn read(&mut self, addr: Word) -> Byte {
+        let value = self.peek(addr);
+        let addr_u16: u16 = addr.into();
+        if let (true, Some(mapper)) = (addr_u16 < 0x3F00, self.mapper.as_mut()) {
+            // notified after the fetch, since some mappers switch banks by the address being read
+            mapper.ppu_address(addr_u16);
+        }
+        value
+    }
+
+    fn peek(&self, addr: Word) -> Byte {
+        self.vram
+            .peek(self.mapper.as_deref().unwrap_or(&NoCartridge), addr)
+    }
+
     fn write(&mut self, addr: Word, value: Byte) {
         let addr_u16: u16 = addr.into();
+        let mut no_cartridge = NoCartridge;
+        let mapper = match self.mapper.as_deref_mut() {
+            Some(mapper) => mapper,
+            None => &mut no_cartridge,
+        };
+        if addr_u16 < 0x3F00 {
+            mapper.ppu_address(addr_u16);
+        }
+        let vram = &mut self.vram;
         match addr_u16 {
-            0x0000..=0x1FFF => self.mapper.borrow_mut().write(addr, value),
-            0x2000..=0x2FFF => self.name_table[self.to_name_table_address(addr_u16)] = value,
-            0x3000..=0x3EFF => {
-                self.name_table[self.to_name_table_address(addr_u16 - 0x1000)] = value;
-            }
-            0x3F00..=0x3FFF => self.pallete_ram_idx[self.to_pallete_address(addr_u16)] = value,
+            0x0000..=0x2FFF => vram.write_vram(mapper, addr_u16, value),
+            0x3000..=0x3EFF => vram.write_vram(mapper, addr_u16 - 0x1000, value),
+            0x3F00..=0x3FFF => vram.pallete_ram_idx[VRAM::to_pallete_address(addr_u16)] = value,
             _ => {}
         }
     }
 }
 
-impl Memory for [u8; 0x10000] {
-    fn read(&self, addr: Word) -> Byte {
-        let addr: u16 = addr.into();
-        self[addr as usize].into()
+/// Reads the PPU bus without the parts of the NES borrowed mutably, for snapshots and debug views.
+/// Writes are ignored.
+pub struct PeekPPUBus<'a> {
+    pub vram: &'a VRAM,
+    pub mapper: Option<&'a dyn Mapper>,
+}
+
+impl Memory for PeekPPUBus<'_> {
+    fn peek(&self, addr: Word) -> Byte {
+        self.vram.peek(self.mapper.unwrap_or(&NoCartridge), addr)
     }
-    fn write(&mut self, addr: Word, value: Byte) {
-        let addr: u16 = addr.into();
-        self[addr as usize] = value.into()
+
+    fn write(&mut self, _addr: Word, _value: Byte) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_pattern() {
+        let mut ram = [0x12; 16];
+
+        RAMPattern::AllFF.fill(&mut ram);
+        assert_eq!(ram, [0xFF; 16]);
+
+        RAMPattern::AllZero.fill(&mut ram);
+        assert_eq!(ram, [0x00; 16]);
+
+        RAMPattern::Striped.fill(&mut ram);
+        assert_eq!(
+            &ram[0..8],
+            &[0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(&ram[0..8], &ram[8..16]);
+
+        let mut other = [0; 16];
+        RAMPattern::Random(42).fill(&mut ram);
+        RAMPattern::Random(42).fill(&mut other);
+        assert_eq!(ram, other);
+        RAMPattern::Random(43).fill(&mut other);
+        assert_ne!(ram, other);
+    }
+
+    #[test]
+    fn ppu_register_mirroring() {
+        assert_eq!(to_ppu_addr(0x2002), 0x2002);
+        assert_eq!(to_ppu_addr(0x2008), 0x2000);
+        assert_eq!(to_ppu_addr(0x3FFF), 0x2007);
+    }
+
+    struct MirroringMapper(Mirroring);
+
+    impl Memory for MirroringMapper {
+        fn peek(&self, _addr: Word) -> Byte {
+            0.into()
+        }
+
+        fn write(&mut self, _addr: Word, _value: Byte) {}
+    }
+
+    impl Mapper for MirroringMapper {
+        fn mirroring(&self) -> Mirroring {
+            self.0
+        }
+    }
+
+    #[test]
+    fn name_table_mirroring() {
+        let mut vram = VRAM::default();
+        let mut mapper = MirroringMapper(Mirroring::Horizontal());
+        let mut bus = PPUBus::new(&mut vram, Some(&mut mapper));
+        bus.write(0x2401u16.into(), 1.into());
+        bus.write(0x2802u16.into(), 2.into());
+        assert_eq!(bus.read(0x2001u16.into()), 1.into());
+        assert_eq!(bus.read(0x2C02u16.into()), 2.into());
+        assert_eq!(bus.read(0x2402u16.into()), 0.into());
+
+        let mut vram = VRAM::default();
+        let mut mapper = MirroringMapper(Mirroring::Vertical());
+        let mut bus = PPUBus::new(&mut vram, Some(&mut mapper));
+        bus.write(0x2801u16.into(), 1.into());
+        bus.write(0x2C02u16.into(), 2.into());
+        assert_eq!(bus.read(0x2001u16.into()), 1.into());
+        assert_eq!(bus.read(0x2402u16.into()), 2.into());
+        assert_eq!(bus.read(0x2002u16.into()), 0.into());
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut vram = VRAM::default();
+        let mut mapper = MirroringMapper(Mirroring::Vertical());
+        for (i, addr) in [0x2000u16, 0x2400, 0x2800, 0x2C00].iter().enumerate() {
+            PPUBus::new(&mut vram, Some(&mut mapper)).write((*addr).into(), (i as u8).into());
+        }
+        let read = |vram: &VRAM, mapper: &MirroringMapper, addr: u16| -> u8 {
+            let bus = PeekPPUBus {
+                vram,
+                mapper: Some(mapper),
+            };
+            bus.peek(addr.into()).into()
+        };
+
+        // the mirroring is queried on every access
+        assert_eq!(read(&vram, &mapper, 0x2000), 2);
+        mapper.0 = Mirroring::Horizontal();
+        assert_eq!(read(&vram, &mapper, 0x2400), 2);
+        assert_eq!(read(&vram, &mapper, 0x2800), 0);
+        mapper.0 = Mirroring::SingleScreen(1);
+        assert_eq!(read(&vram, &mapper, 0x2000), 3);
+        assert_eq!(read(&vram, &mapper, 0x2800), 3);
+
+        mapper.0 = Mirroring::FourScreen();
+        PPUBus::new(&mut vram, Some(&mut mapper)).write(0x2C00u16.into(), 4.into());
+        assert_eq!(read(&vram, &mapper, 0x2C00), 4);
+        assert_eq!(read(&vram, &mapper, 0x2400), 3);
     }
 }
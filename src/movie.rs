@@ -0,0 +1,260 @@
+use std::io::{self, Read, Write};
+
+use crate::controller::{Button, CONTROLLERS};
+use crate::memory_map::RAMPattern;
+use crate::nes::{Frame, NES};
+use crate::region::Region;
+use crate::savestate::{read_u32, read_u64, SaveState};
+
+const MAGIC_NUMBER: &[u8; 4] = b"RNMV";
+const VERSION: u32 = 1;
+
+// flags of a frame
+const RESET: u8 = 1 << 0;
+
+/// Where a movie starts playing from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieStart {
+    /// Power-on right after loading the ROM.
+    PowerOn,
+    /// A save state, restoring the whole console including the APU and the mapper.
+    SaveState(Box<SaveState>),
+}
+
+/// Input of a frame in a movie.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MovieFrame {
+    /// Buttons held on each controller, indexed like `NES::set_controller`.
+    pub controllers: [Button; CONTROLLERS],
+    /// Reset pressed before the frame.
+    pub reset: bool,
+}
+
+/// Controller input recorded frame by frame with `MovieRecorder`, for archiving gameplay and
+/// replaying it deterministically.
+///
+/// The settings affecting emulation are kept with the input, since the same input gives a
+/// different result in another region or with another power-on RAM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// SHA-1 of PRG-ROM and CHR-ROM of the game, as `ROM::sha1`.
+    pub rom_sha1: [u8; 20],
+    pub region: Region,
+    pub ram_pattern: RAMPattern,
+    pub four_score_enabled: bool,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC_NUMBER {
+            return Err(invalid_data("not a movie"));
+        }
+        if read_u32(&mut r)? != VERSION {
+            return Err(invalid_data("unsupported movie version"));
+        }
+        let mut rom_sha1 = [0; 20];
+        r.read_exact(&mut rom_sha1)?;
+
+        let mut settings = [0; 3];
+        r.read_exact(&mut settings)?;
+        let [region, ram_pattern, four_score_enabled] = settings;
+        let region = match region {
+            0 => Region::NTSC,
+            1 => Region::PAL,
+            _ => return Err(invalid_data("invalid region")),
+        };
+        let seed = read_u64(&mut r)?;
+        let ram_pattern = match ram_pattern {
+            0 => RAMPattern::AllZero,
+            1 => RAMPattern::AllFF,
+            2 => RAMPattern::Striped,
+            3 => RAMPattern::Random(seed),
+            _ => return Err(invalid_data("invalid RAM pattern")),
+        };
+
+        let mut start = [0; 1];
+        r.read_exact(&mut start)?;
+        let start = match start[0] {
+            0 => MovieStart::PowerOn,
+            1 => MovieStart::SaveState(Box::new(SaveState::read(&mut r)?)),
+            _ => return Err(invalid_data("invalid start")),
+        };
+
+        let len = read_u32(&mut r)?;
+        let mut frames = Vec::new();
+        for _ in 0..len {
+            let mut b = [0; 1 + CONTROLLERS];
+            r.read_exact(&mut b)?;
+            let mut frame = MovieFrame {
+                reset: b[0] & RESET != 0,
+                ..Default::default()
+            };
+            for (c, bits) in frame.controllers.iter_mut().zip(&b[1..]) {
+                *c = Button::from_bits(*bits);
+            }
+            frames.push(frame);
+        }
+
+        Ok(Self {
+            rom_sha1,
+            region,
+            ram_pattern,
+            four_score_enabled: four_score_enabled != 0,
+            start,
+            frames,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC_NUMBER)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.rom_sha1)?;
+
+        let region = match self.region {
+            Region::NTSC => 0,
+            Region::PAL => 1,
+        };
+        let (ram_pattern, seed) = match self.ram_pattern {
+            RAMPattern::AllZero => (0, 0),
+            RAMPattern::AllFF => (1, 0),
+            RAMPattern::Striped => (2, 0),
+            RAMPattern::Random(seed) => (3, seed),
+        };
+        w.write_all(&[region, ram_pattern, self.four_score_enabled as u8])?;
+        w.write_all(&seed.to_le_bytes())?;
+
+        match &self.start {
+            MovieStart::PowerOn => w.write_all(&[0])?,
+            MovieStart::SaveState(state) => {
+                w.write_all(&[1])?;
+                state.write(&mut w)?;
+            }
+        }
+
+        w.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        for frame in &self.frames {
+            let flags = if frame.reset { RESET } else { 0 };
+            w.write_all(&[flags])?;
+            for c in &frame.controllers {
+                w.write_all(&[c.bits()])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records the controller input of a `NES` into a `Movie`.
+///
+/// The frontend sets the controllers as usual and runs frames through `MovieRecorder::frame`
+/// instead of `NES::frame`.
+pub struct MovieRecorder {
+    movie: Movie,
+    reset: bool,
+}
+
+impl MovieRecorder {
+    /// Starts recording from power-on. Call right after `NES::load`, as this powers on and
+    /// resets `nes`.
+    pub fn power_on(nes: &mut NES) -> Self {
+        nes.power_on();
+        nes.reset();
+        Self::new(nes, MovieStart::PowerOn)
+    }
+
+    /// Starts recording from the current state of `nes`. Fails if the mapper does not support
+    /// save states.
+    pub fn from_state(nes: &NES) -> anyhow::Result<Self> {
+        Ok(Self::new(
+            nes,
+            MovieStart::SaveState(Box::new(nes.save_state()?)),
+        ))
+    }
+
+    fn new(nes: &NES, start: MovieStart) -> Self {
+        Self {
+            movie: Movie {
+                rom_sha1: nes.rom().map_or([0; 20], |rom| rom.sha1()),
+                region: nes.region(),
+                ram_pattern: nes.ram_pattern(),
+                four_score_enabled: nes.four_score_enabled(),
+                start,
+                frames: Vec::new(),
+            },
+            reset: false,
+        }
+    }
+
+    /// Resets `nes`, recorded with the next frame.
+    pub fn reset(&mut self, nes: &mut NES) {
+        nes.reset();
+        self.reset = true;
+    }
+
+    /// Runs a frame of `nes`, recording the buttons held on the controllers.
+    pub fn frame(&mut self, nes: &mut NES) -> Frame {
+        let mut frame = MovieFrame {
+            reset: self.reset,
+            ..Default::default()
+        };
+        for (port, c) in frame.controllers.iter_mut().enumerate() {
+            *c = nes.controller(port);
+        }
+        self.movie.frames.push(frame);
+        self.reset = false;
+        nes.frame()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::tests::counter_rom;
+
+    #[test]
+    fn record() {
+        let mut nes = NES::default();
+        nes.set_ram_pattern(RAMPattern::Random(7));
+        nes.load(counter_rom()).unwrap();
+        let mut recorder = MovieRecorder::power_on(&mut nes);
+        nes.set_controller(0, Button::START);
+        recorder.frame(&mut nes);
+        nes.set_controller(3, Button::A | Button::UP);
+        recorder.reset(&mut nes);
+        recorder.frame(&mut nes);
+        let movie = recorder.finish();
+
+        assert_eq!(movie.rom_sha1, counter_rom().sha1());
+        assert_eq!(movie.ram_pattern, RAMPattern::Random(7));
+        assert_eq!(movie.frames.len(), 2);
+        assert_eq!(movie.frames[0].controllers[0], Button::START);
+        assert!(!movie.frames[0].reset);
+        assert_eq!(movie.frames[1].controllers[3], Button::A | Button::UP);
+        assert!(movie.frames[1].reset);
+
+        let mut b = Vec::new();
+        movie.write(&mut b).unwrap();
+        assert_eq!(Movie::read(&b[..]).unwrap(), movie);
+        assert!(Movie::read(&b[1..]).is_err());
+
+        let movie = MovieRecorder::from_state(&nes).unwrap().finish();
+        let mut b = Vec::new();
+        movie.write(&mut b).unwrap();
+        assert_eq!(Movie::read(&b[..]).unwrap(), movie);
+    }
+}
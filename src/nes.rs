@@ -1,55 +1,1019 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
 
-use crate::cpu::{CPUCycle, Trace, CPU};
-use crate::interrupt::Interrupt;
-use crate::memory_map::{CPUBus, PPUBus};
-use crate::ppu::PPU;
-use crate::rom::ROM;
+use anyhow::{anyhow, Context};
+
+use crate::apu::{APUSnapshot, Channel, MixerMode, APU};
+use crate::chr;
+use crate::controller::{Button, ControllerPorts, CONTROLLERS};
+use crate::cpu::{CPUCycle, CPUSnapshot, Trace, CPU};
+use crate::input_script::InputScript;
+use crate::interrupt::{Interrupt, InterruptEvent, InterruptKind};
+use crate::lint::HardwareLint;
+use crate::memory_map::{peek_cpu_bus, APUBus, CPUBus, PPUBus, PeekPPUBus, RAMPattern, VRAM};
+use crate::ntsc;
+use crate::palette::Palette;
+use crate::ppu::{self, Emphasis, Layer, OAMEntry, PPUSnapshot, PartialFrame, ScanlineEvent, PPU};
+use crate::region::Region;
+use crate::register_log::{RegisterLog, RegisterWrite};
+use crate::rewind::{RewindBuffer, RewindConfig};
+use crate::rom::{Mapper, ROM};
+use crate::savestate::{
+    InterruptState, SaveState, SaveStateError, SaveStateMetadata, SAVE_SLOTS,
+    SAVE_STATE_THUMBNAIL_SCALE,
+};
+use crate::screenshot::{Fnv1a, Overscan, Screenshot};
+use crate::stats::{Stats, StatsCounter};
+use crate::types::Memory;
+use crate::wide_canvas::WideCanvas;
 
 pub struct NES {
     cpu: CPU,
-    ppu: Rc<RefCell<PPU>>,
+    ppu: PPU,
+    // name tables and palette RAM on the bus of the PPU
+    vram: VRAM,
+    apu: APU,
+    controllers: ControllerPorts,
+    register_log: RegisterLog,
+    // 2KB internal RAM, mirrored up to $1FFF
+    wram: [u8; 0x0800],
+    rom: Option<ROM>,
 
     interrupt: Interrupt,
 
     cycles: u128,
+
+    region: Region,
+    ram_pattern: RAMPattern,
+
+    trace_hook: Option<TraceHook>,
+    video_sink: Option<VideoSink>,
+    interrupt_hook: Option<InterruptHook>,
+    // cycles when the pending interrupts were requested
+    reset_requested: CPUCycle,
+    nmi_requested: CPUCycle,
+    irq_requested: CPUCycle,
+
+    wide_canvas: Option<WideCanvas>,
+    input_script: Option<InputScript>,
+    // the default of the region if not set
+    palette: Option<Palette>,
+    region_palette: Palette,
+    ntsc_filter_enabled: bool,
+    // the default of the region if not set
+    overscan: Option<Overscan>,
+    paused: bool,
+    stats: StatsCounter,
+    rewind: Option<RewindBuffer>,
+    // frames run ahead of the input by `NES::step_frame`
+    run_ahead: u32,
+}
+
+type TraceHook = Box<dyn FnMut(&Trace) + Send>;
+type VideoSink = Box<dyn FnMut(&Screenshot) + Send>;
+type InterruptHook = Box<dyn FnMut(&InterruptEvent) + Send>;
+
+// The bus of the CPU borrowing the parts of the NES `$nes` for a step, as a macro so that the
+// borrows stay on the fields, leaving the CPU and the hooks free.
+macro_rules! cpu_bus {
+    ($nes:expr) => {
+        CPUBus {
+            wram: &mut $nes.wram,
+            mapper: $nes
+                .rom
+                .as_mut()
+                .map(|rom| &mut *rom.mapper as &mut dyn Mapper),
+            ppu: &mut $nes.ppu,
+            vram: &mut $nes.vram,
+            apu: &mut $nes.apu,
+            controllers: &mut $nes.controllers,
+            register_log: &mut $nes.register_log,
+        }
+    };
+}
+
+// The buses of the PPU and the APU borrowing the mapper of `$nes`, as `cpu_bus!`
+macro_rules! ppu_bus {
+    ($nes:expr) => {
+        PPUBus::new(
+            &mut $nes.vram,
+            $nes.rom
+                .as_mut()
+                .map(|rom| &mut *rom.mapper as &mut dyn Mapper),
+        )
+    };
+}
+
+macro_rules! apu_bus {
+    ($nes:expr) => {
+        APUBus {
+            mapper: $nes.rom.as_ref().map(|rom| &*rom.mapper as &dyn Mapper),
+        }
+    };
 }
 
 impl Default for NES {
     fn default() -> Self {
-        let cpu_bus = Box::new([0; 0x10000]);
-        let ppu_bus = Box::new([0; 0x10000]);
         Self {
-            cpu: CPU::new(cpu_bus),
-            ppu: Rc::new(RefCell::new(PPU::new(ppu_bus))),
+            cpu: Default::default(),
+            ppu: PPU::new(),
+            vram: Default::default(),
+            apu: APU::new(),
+            controllers: Default::default(),
+            register_log: Default::default(),
+            wram: [0; 0x0800],
+            rom: None,
             interrupt: Interrupt::NO_INTERRUPT,
             cycles: 0,
+            region: Default::default(),
+            ram_pattern: Default::default(),
+            trace_hook: None,
+            video_sink: None,
+            interrupt_hook: None,
+            reset_requested: 0,
+            nmi_requested: 0,
+            irq_requested: 0,
+            wide_canvas: None,
+            input_script: None,
+            palette: None,
+            region_palette: Default::default(),
+            ntsc_filter_enabled: false,
+            overscan: None,
+            paused: false,
+            stats: Default::default(),
+            rewind: None,
+            run_ahead: 0,
         }
     }
 }
 
+/// The whole state of a `NES` taken by `NES::snapshot`, kept in memory.
+#[derive(Clone)]
+pub(crate) struct Snapshot {
+    pub(crate) state: SaveState,
+    // to drop the samples produced since
+    apu: APUSnapshot,
+}
+
+/// Host-side settings of a `NES`, taken by `NES::config` and set by `NES::apply_config`.
+///
+/// With the `serde` feature, the fields missing when deserialized are taken from the default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Config {
+    pub region: Region,
+    pub ram_pattern: RAMPattern,
+    pub sample_rate: u32,
+    pub mixer_mode: MixerMode,
+    pub enabled_channels: Vec<Channel>,
+    pub audio_filter_enabled: bool,
+    pub palette_write_through: bool,
+    pub sprite_limit_enabled: bool,
+    pub four_score_enabled: bool,
+    pub ntsc_filter_enabled: bool,
+    /// `None` for the default of the region.
+    pub overscan: Option<Overscan>,
+}
+
+/// The settings of a new `NES`.
+impl Default for Config {
+    fn default() -> Self {
+        NES::default().config()
+    }
+}
+
+/// What happened in a frame run by `NES::frame`, for frontends doing their own A/V sync.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame {
+    /// CPU cycles run in the frame.
+    pub cpu_cycles: u64,
+    /// Audio samples produced in the frame at the host sample rate.
+    pub samples: usize,
+    /// Fraction (0.0-1.0) of the next sample accumulated at the end of the frame,
+    /// which carries over to the next frame.
+    pub sample_remainder: f64,
+    /// Samples the CPU cycles of the frame amount to without rounding,
+    /// e.g. about 734.4 at 44.1 kHz on NTSC.
+    pub exact_samples: f64,
+}
+
+/// A `NES` taken apart by `NES::into_parts`.
+///
+/// The APU and pending interrupts are not included, so the parts should be taken between frames.
+pub struct Parts {
+    pub cpu: CPUSnapshot,
+    pub ppu: PPUSnapshot,
+    /// 2KB internal RAM
+    pub ram: Vec<u8>,
+    /// The cartridge, keeping the state of its mapper.
+    pub rom: Option<ROM>,
+    pub config: Config,
+}
+
 impl NES {
-    pub fn frame(&mut self) {
-        let current = self.ppu.borrow_mut().frames;
+    /// Takes apart into the CPU and PPU state, the cartridge and the settings,
+    /// for custom persistence or running the parts on an instrumented bus.
+    pub fn into_parts(self) -> Parts {
+        Parts {
+            cpu: self.cpu.snapshot(),
+            ppu: self.ppu.snapshot(&self.peek_ppu_bus()),
+            ram: self.ram(),
+            config: self.config(),
+            // moved out last, after the parts read through it
+            rom: self.rom,
+        }
+    }
+
+    /// Puts together a `NES` from `parts`, the inverse of `NES::into_parts`.
+    pub fn from_parts(parts: Parts) -> Self {
+        let mut nes = Self::default();
+        nes.apply_config(&parts.config);
+        if let Some(rom) = parts.rom {
+            nes.insert(rom);
+        }
+        nes.cpu.restore(&parts.cpu);
+        nes.restore_ram(&parts.ram);
+        nes.ppu.restore(&parts.ppu, &mut ppu_bus!(nes));
+        nes
+    }
+
+    /// Takes a save state with a thumbnail of the current frame. Fails if the mapper does not
+    /// support save states.
+    pub fn save_state(&self) -> anyhow::Result<SaveState> {
+        let thumbnail = self.screenshot().downscale(SAVE_STATE_THUMBNAIL_SCALE);
+        self.state(SaveStateMetadata::now(thumbnail))
+            .ok_or_else(|| anyhow!("The mapper does not support save states"))
+    }
+
+    // `None` if the mapper cannot be saved
+    fn state(&self, metadata: SaveStateMetadata) -> Option<SaveState> {
+        let mapper = match &self.rom {
+            Some(rom) => rom.mapper.save_state()?,
+            None => Vec::new(),
+        };
+        Some(SaveState {
+            rom_sha1: self.rom().map_or([0; 20], |rom| rom.sha1()),
+            metadata,
+            cpu: self.cpu.snapshot(),
+            ppu: self.ppu.snapshot(&self.peek_ppu_bus()),
+            ram: self.ram(),
+            apu: self.apu.save_state(),
+            mapper,
+            controllers: self.controllers.save_state(),
+            interrupt: InterruptState {
+                interrupt: self.interrupt,
+                cycles: self.cycles,
+                reset_requested: self.reset_requested,
+                nmi_requested: self.nmi_requested,
+                irq_requested: self.irq_requested,
+            },
+        })
+    }
+
+    /// Restores a state taken by `NES::save_state`. Fails without changing anything if the state
+    /// was taken with another ROM than the one loaded, or cannot be read.
+    pub fn load_state(&mut self, state: &SaveState) -> anyhow::Result<()> {
+        if state.rom_sha1 != self.rom().map_or([0; 20], |rom| rom.sha1()) {
+            return Err(SaveStateError::ROMMismatch.into());
+        }
+        let current = self
+            .state(SaveStateMetadata::none())
+            .ok_or_else(|| anyhow!("The mapper does not support save states"))?;
+        if let Err(e) = self.restore_state(state) {
+            self.restore_state(&current)
+                .expect("the state just taken loads back");
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // restores the parts that may fail first
+    fn restore_state(&mut self, state: &SaveState) -> anyhow::Result<()> {
+        if let Some(rom) = self.rom.as_mut() {
+            rom.mapper.load_state(&state.mapper)?;
+        }
+        self.apu.load_state(&state.apu)?;
+        self.controllers.load_state(&state.controllers)?;
+        self.cpu.restore(&state.cpu);
+        self.restore_ram(&state.ram);
+        self.ppu.restore(&state.ppu, &mut ppu_bus!(self));
+        let interrupt = &state.interrupt;
+        self.interrupt = interrupt.interrupt;
+        self.cycles = interrupt.cycles;
+        self.reset_requested = interrupt.reset_requested;
+        self.nmi_requested = interrupt.nmi_requested;
+        self.irq_requested = interrupt.irq_requested;
+        Ok(())
+    }
+
+    fn restore_ram(&mut self, ram: &[u8]) {
+        let len = ram.len().min(self.wram.len());
+        self.wram[..len].copy_from_slice(&ram[..len]);
+    }
+
+    /// Saves the state to the slot `n` of `SAVE_SLOTS`, the file `ROM::slot_path`.
+    pub fn save_slot(&self, n: usize) -> anyhow::Result<()> {
+        let path = self.slot_path(n)?;
+        let f = File::create(&path)
+            .with_context(|| format!("Failed to write save state: {}", path.display()))?;
+        let mut w = BufWriter::new(f);
+        self.save_state()?.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Loads the state saved to the slot `n` by `NES::save_slot`. Returns false if there is none.
+    pub fn load_slot(&mut self, n: usize) -> anyhow::Result<bool> {
+        let path = self.slot_path(n)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        let f = File::open(&path)
+            .with_context(|| format!("Failed to read save state: {}", path.display()))?;
+        let state = SaveState::read(BufReader::new(f))
+            .with_context(|| format!("Failed to read save state: {}", path.display()))?;
+        self.load_state(&state)?;
+        Ok(true)
+    }
+
+    fn slot_path(&self, n: usize) -> anyhow::Result<PathBuf> {
+        if SAVE_SLOTS <= n {
+            return Err(anyhow!("No save state slot {}", n));
+        }
+        self.rom()
+            .and_then(|rom| rom.slot_path(n))
+            .ok_or_else(|| anyhow!("No ROM file is loaded"))
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            region: self.region,
+            ram_pattern: self.ram_pattern,
+            sample_rate: self.sample_rate(),
+            mixer_mode: self.mixer_mode(),
+            enabled_channels: Channel::ALL
+                .iter()
+                .copied()
+                .filter(|c| self.channel_enabled(*c))
+                .collect(),
+            audio_filter_enabled: self.audio_filter_enabled(),
+            palette_write_through: self.palette_write_through(),
+            sprite_limit_enabled: self.sprite_limit_enabled(),
+            four_score_enabled: self.four_score_enabled(),
+            ntsc_filter_enabled: self.ntsc_filter_enabled(),
+            overscan: self.overscan,
+        }
+    }
+
+    pub fn apply_config(&mut self, config: &Config) {
+        self.set_region(config.region);
+        self.set_ram_pattern(config.ram_pattern);
+        self.set_sample_rate(config.sample_rate);
+        self.set_mixer_mode(config.mixer_mode);
+        for c in Channel::ALL.iter() {
+            self.set_channel_enabled(*c, config.enabled_channels.contains(c));
+        }
+        self.set_audio_filter_enabled(config.audio_filter_enabled);
+        self.set_palette_write_through(config.palette_write_through);
+        self.set_sprite_limit_enabled(config.sprite_limit_enabled);
+        self.set_four_score_enabled(config.four_score_enabled);
+        self.set_ntsc_filter_enabled(config.ntsc_filter_enabled);
+        self.set_overscan(config.overscan);
+    }
+
+    /// Runs until the PPU finishes the current frame, or nothing while paused.
+    pub fn frame(&mut self) -> Frame {
+        if self.paused {
+            return Frame {
+                cpu_cycles: 0,
+                samples: 0,
+                sample_remainder: self.apu.sample_remainder(),
+                exact_samples: 0.0,
+            };
+        }
+        self.step_frame()
+    }
+
+    /// Runs a frame even while paused, to advance a frame at a time.
+    pub fn step_frame(&mut self) -> Frame {
+        let current = self.ppu.frames;
+        let cycles = self.cycles;
+        let samples = self.apu.samples_produced();
+        if self.input_script.is_some() {
+            self.next_scripted_input();
+        }
+
+        self.run_frame(current);
+        if self.wide_canvas.is_some() {
+            self.update_wide_canvas();
+        }
+        self.stats.record_frame(Instant::now(), self.region);
+        if self.rewind.as_mut().is_some_and(RewindBuffer::count_frame) {
+            let snapshot = self.snapshot();
+            if let (Some(buffer), Some(snapshot)) = (self.rewind.as_mut(), snapshot) {
+                buffer.push(snapshot);
+            }
+        }
+
+        let apu = &self.apu;
+        let cpu_cycles = self.cycles.wrapping_sub(cycles) as u64;
+        let frame = Frame {
+            cpu_cycles,
+            samples: (apu.samples_produced() - samples) as usize,
+            sample_remainder: apu.sample_remainder(),
+            exact_samples: cpu_cycles as f64 * apu.sample_rate() as f64
+                / self.region.cpu_clock_rate(),
+        };
+        if 0 < self.run_ahead {
+            self.run_ahead_frames();
+        }
+        frame
+    }
+
+    // runs until the PPU finishes the frame `current`
+    fn run_frame(&mut self, current: u64) {
+        loop {
+            self.step();
+            if current != self.ppu.frames {
+                break;
+            }
+        }
+    }
+
+    // Runs the frames ahead with the same input, then goes back to the state before them keeping
+    // the picture of the last one.
+    fn run_ahead_frames(&mut self) {
+        let snapshot = match self.snapshot() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        self.quietly(|nes| {
+            for _ in 0..nes.run_ahead {
+                let current = nes.ppu.frames;
+                nes.run_frame(current);
+            }
+        });
+        self.restore(&snapshot);
+    }
+
+    /// Takes the whole state for run-ahead and rollback, `None` if the mapper cannot be restored.
+    pub(crate) fn snapshot(&self) -> Option<Snapshot> {
+        Some(Snapshot {
+            state: self.state(SaveStateMetadata::none())?,
+            apu: self.apu.snapshot(),
+        })
+    }
+
+    /// Restores the state of `NES::snapshot` apart from the picture, which stays of the last frame
+    /// run. Panics if the snapshot was taken with another cartridge.
+    pub(crate) fn restore(&mut self, snapshot: &Snapshot) {
+        self.restore_state(&snapshot.state)
+            .expect("a snapshot taken from this NES loads back");
+        self.apu.restore(&snapshot.apu);
+    }
+
+    /// Runs `f` out of sight of the hooks, the register log and the lints, for frames run again
+    /// or ahead.
+    pub(crate) fn quietly<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        let trace_hook = self.trace_hook.take();
+        let video_sink = self.video_sink.take();
+        let interrupt_hook = self.interrupt_hook.take();
+        let scanline_hook = self.ppu.take_scanline_hook();
+        let lints = self.ppu.take_lints();
+        let register_log = self.register_log_enabled();
+        self.set_register_log_enabled(false);
+
+        f(self);
+
+        self.trace_hook = trace_hook;
+        self.video_sink = video_sink;
+        self.interrupt_hook = interrupt_hook;
+        self.ppu.set_scanline_hook(scanline_hook);
+        self.ppu.set_lints(lints);
+        self.set_register_log_enabled(register_log);
+    }
+
+    /// Runs a frame quietly with the audio dropped, such as one run again by a rollback.
+    pub(crate) fn run_frame_again(&mut self) {
+        let produced = self.apu.samples_produced();
+        self.quietly(|nes| {
+            let current = nes.ppu.frames;
+            nes.run_frame(current);
+        });
+        self.apu.drop_samples_since(produced);
+    }
+
+    /// Stops `NES::frame` from running the emulation until resumed. Loading a ROM resumes.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.stats.restart();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Frames per second and speed of the emulation in real time.
+    pub fn stats(&self) -> Stats {
+        self.stats.stats()
+    }
+
+    /// Runs `frames` frames ahead of the input in each frame, presenting the picture of the last
+    /// one and going back to the state before them, to cut the input lag of the games reacting a
+    /// few frames after the input. 0 stops running ahead.
+    ///
+    /// The frames run ahead take as long as those kept. Nothing is run ahead with a mapper of
+    /// `MapperRegistry` without `Mapper::save_state`.
+    pub fn set_run_ahead(&mut self, frames: u32) {
+        self.run_ahead = frames;
+    }
+
+    pub fn run_ahead(&self) -> u32 {
+        self.run_ahead
+    }
+
+    /// Keeps the states of the frames run in a buffer for `NES::rewind`, or stops with `None`.
+    /// Loading a ROM clears the buffer.
+    pub fn set_rewind(&mut self, config: Option<RewindConfig>) {
+        self.rewind = config.map(RewindBuffer::new);
+    }
+
+    pub fn rewind_config(&self) -> Option<RewindConfig> {
+        self.rewind.as_ref().map(RewindBuffer::config)
+    }
+
+    /// Goes back `frames` frames, or to the latest state in the buffer before them, returning the
+    /// frames gone back. Stops at the oldest state and returns 0 without one.
+    ///
+    /// The picture is not restored and stays of the last frame run until the next one.
+    pub fn rewind(&mut self, frames: u32) -> u32 {
+        match self
+            .rewind
+            .as_mut()
+            .and_then(|buffer| buffer.rewind(frames))
+        {
+            Some((rewound, snapshot)) => {
+                self.restore(&snapshot);
+                rewound
+            }
+            None => 0,
+        }
+    }
+
+    /// Frames `NES::rewind` can go back at most.
+    pub fn rewind_frames(&self) -> u64 {
+        self.rewind.as_ref().map_or(0, RewindBuffer::frames)
+    }
+
+    /// Feeds the controllers from `script` at the start of each frame, replacing the buttons set
+    /// with `NES::set_controller`. When the script finishes, it is dropped and all buttons are
+    /// released.
+    pub fn set_input_script(&mut self, script: InputScript) {
+        self.input_script = Some(script);
+    }
+
+    pub fn clear_input_script(&mut self) {
+        self.input_script = None;
+    }
+
+    /// The input script attached and not finished yet.
+    pub fn input_script(&self) -> Option<&InputScript> {
+        self.input_script.as_ref()
+    }
+
+    fn next_scripted_input(&mut self) {
+        let buttons = self
+            .input_script
+            .as_mut()
+            .and_then(|s| s.next_frame())
+            .unwrap_or_default();
+        for (port, b) in buttons.iter().enumerate() {
+            self.set_controller(port, *b);
+        }
+        if self.input_script.as_ref().is_some_and(|s| s.is_finished()) {
+            self.input_script = None;
+        }
+    }
+
+    /// Experimental: stitches every frame onto a canvas growing with the scroll, like wideNES.
+    ///
+    /// Disabling drops the canvas. Loading a ROM clears it.
+    pub fn set_wide_canvas_enabled(&mut self, enabled: bool) {
+        if enabled != self.wide_canvas.is_some() {
+            self.wide_canvas = if enabled {
+                Some(WideCanvas::new())
+            } else {
+                None
+            };
+        }
+    }
 
+    pub fn wide_canvas(&self) -> Option<&WideCanvas> {
+        self.wide_canvas.as_ref()
+    }
+
+    /// For the reference line and crop of the canvas.
+    pub fn wide_canvas_mut(&mut self) -> Option<&mut WideCanvas> {
+        self.wide_canvas.as_mut()
+    }
+
+    fn update_wide_canvas(&mut self) {
+        let frame = self.screenshot();
+        if let Some(canvas) = self.wide_canvas.as_mut() {
+            let scroll = self.ppu.line_scroll(canvas.reference_line());
+            canvas.update(scroll, &frame);
+        }
+    }
+
+    /// Runs until the PPU reaches the scanline `line` (0-261), to break in the middle of a frame.
+    ///
+    /// The emulation stops at the first CPU instruction boundary after the line starts.
+    /// Panics if `line` is past 261, which the PPU never reaches on either region.
+    pub fn run_until_scanline(&mut self, line: u16) {
+        assert!(
+            line <= ppu::MAX_LINE,
+            "no scanline {} in a frame of {} lines",
+            line,
+            ppu::MAX_LINE + 1
+        );
+        let mut prev = self.ppu.current_line();
         loop {
             self.step();
-            if current != self.ppu.borrow_mut().frames {
+            let current = self.ppu.current_line();
+            if current == line && prev != line {
                 break;
             }
+            prev = current;
+        }
+    }
+
+    /// Returns the frame buffer as drawn so far, including the position of the PPU.
+    pub fn partial_frame(&self) -> PartialFrame {
+        self.ppu.partial_frame()
+    }
+
+    /// Calls `hook` at the start of every scanline with the line number and scroll registers,
+    /// for prototyping raster effects.
+    pub fn set_scanline_hook<F: FnMut(&ScanlineEvent) + 'static + Send>(&mut self, hook: F) {
+        self.ppu.set_scanline_hook(Some(Box::new(hook)));
+    }
+
+    pub fn clear_scanline_hook(&mut self) {
+        self.ppu.set_scanline_hook(None);
+    }
+
+    /// Sets the buttons held on the standard controller in `port`, 0 for player 1 on $4016 and
+    /// 1 for player 2 on $4017. 2 and 3 are players 3 and 4, read only with a Four Score.
+    ///
+    /// The game latches the buttons when it polls the controller, so a frontend sets them once
+    /// before each `frame`. They are kept until changed, across `load` as well.
+    ///
+    /// Panics if `port` is not less than `CONTROLLERS`.
+    pub fn set_controller(&mut self, port: usize, buttons: Button) {
+        assert!(port < CONTROLLERS, "no controller port {}", port);
+        self.controllers.set_buttons(port, buttons);
+    }
+
+    pub fn controller(&self, port: usize) -> Button {
+        assert!(port < CONTROLLERS, "no controller port {}", port);
+        self.controllers.buttons(port)
+    }
+
+    /// Connects the controllers through a Four Score for 4-player games, which detect it by the
+    /// signature following the buttons. Disabled by default.
+    pub fn set_four_score_enabled(&mut self, enabled: bool) {
+        self.controllers.set_four_score(enabled);
+    }
+
+    pub fn four_score_enabled(&self) -> bool {
+        self.controllers.four_score()
+    }
+
+    /// Calls `hook` before every instruction with the CPU state, for capturing execution traces.
+    pub fn set_trace_hook<F: FnMut(&Trace) + 'static + Send>(&mut self, hook: F) {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    /// Calls `hook` when the CPU services an interrupt, with the cycles of the request and
+    /// the interrupt sequence, for debugging timing-sensitive code.
+    pub fn set_interrupt_hook<F: FnMut(&InterruptEvent) + 'static + Send>(&mut self, hook: F) {
+        self.interrupt_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_interrupt_hook(&mut self) {
+        self.interrupt_hook = None;
+    }
+
+    /// Returns the 2KB internal RAM ($0000-$07FF).
+    pub fn ram(&self) -> Vec<u8> {
+        self.wram.to_vec()
+    }
+
+    /// Reads the CPU address space without side effects.
+    pub fn peek(&self, addr: u16) -> u8 {
+        let mapper = self.rom.as_ref().map(|rom| &*rom.mapper as &dyn Mapper);
+        peek_cpu_bus(&self.wram, mapper, addr.into()).into()
+    }
+
+    fn peek_ppu_bus(&self) -> PeekPPUBus<'_> {
+        PeekPPUBus {
+            vram: &self.vram,
+            mapper: self.rom.as_ref().map(|rom| &*rom.mapper as &dyn Mapper),
+        }
+    }
+
+    /// The current frame as palette indices (0x00-0x3F), `FRAME_WIDTH` x `FRAME_HEIGHT` row-major.
+    ///
+    /// The frame is complete after `NES::frame`. Frontends with their own palette or shaders
+    /// start from here.
+    pub fn frame_buffer(&self) -> Vec<u8> {
+        self.ppu.frame_buffer().to_vec()
+    }
+
+    /// The current frame in RGBA 8 bits per channel, colored with `NES::palette` and tinted by
+    /// the color emphasis.
+    pub fn frame_buffer_rgba(&self) -> Vec<u8> {
+        self.screenshot().pixels
+    }
+
+    /// Emphasis bits of each pixel of `NES::frame_buffer`, as `Emphasis::bits`.
+    pub fn emphasis_buffer(&self) -> Vec<u8> {
+        self.ppu.emphasis_buffer().to_vec()
+    }
+
+    /// The current frame as 9-bit pixels, `FRAME_WIDTH` x `FRAME_HEIGHT` row-major, for frontends
+    /// doing their own palette or NTSC processing.
+    ///
+    /// Bits 0-5 are the palette index and bits 6-8 the emphasis of red, green and blue, the layout
+    /// NTSC filters such as nes_ntsc take.
+    pub fn indexed_frame(&self) -> Vec<u16> {
+        let mut buf = vec![0; ppu::WIDTH as usize * ppu::HEIGHT as usize];
+        self.render_indexed_into(&mut buf);
+        buf
+    }
+
+    /// Writes `NES::indexed_frame` into `buf` without allocating.
+    ///
+    /// Panics if `buf` is shorter than `FRAME_WIDTH` x `FRAME_HEIGHT`.
+    pub fn render_indexed_into(&self, buf: &mut [u16]) {
+        let ppu = &self.ppu;
+        let len = ppu.frame_buffer().len();
+        assert!(
+            len <= buf.len(),
+            "buffer of {} pixels for a frame of {} pixels",
+            buf.len(),
+            len
+        );
+        let pixels = ppu.frame_buffer().iter().zip(ppu.emphasis_buffer());
+        for (p, (i, e)) in buf.iter_mut().zip(pixels) {
+            *p = *i as u16 | (*e as u16) << 6;
+        }
+    }
+
+    /// The current frame for display, through the NTSC filter when enabled and with the overscan
+    /// cropped.
+    pub fn video_frame(&self) -> Screenshot {
+        let (_, x_scale) = self.video_frame_width();
+        let frame = if self.ntsc_filter_enabled {
+            let ppu = &self.ppu;
+            ntsc::filter(
+                ppu.frame_buffer(),
+                ppu.emphasis_buffer(),
+                ppu::WIDTH as usize,
+                ppu::HEIGHT as usize,
+                ppu.frames,
+            )
+        } else {
+            self.screenshot()
+        };
+        frame.crop(self.overscan(), x_scale)
+    }
+
+    /// Hash of the palette indices and emphasis of the current frame, like `Screenshot::hash`.
+    ///
+    /// Unlike the hash of a screenshot, this does not change with the palette or the filters.
+    pub fn frame_hash(&self) -> u64 {
+        let ppu = &self.ppu;
+        let mut h = Fnv1a::new();
+        h.write(ppu.frame_buffer());
+        h.write(ppu.emphasis_buffer());
+        h.finish()
+    }
+
+    /// Width and height of `NES::video_frame`.
+    pub fn video_frame_size(&self) -> (u32, u32) {
+        let (width, x_scale) = self.video_frame_width();
+        let (_, _, width, height) =
+            self.overscan()
+                .visible_rect(width, ppu::HEIGHT as u32, x_scale);
+        (width, height)
+    }
+
+    // width before the crop and its scale from the PPU output
+    fn video_frame_width(&self) -> (u32, u32) {
+        if self.ntsc_filter_enabled {
+            (
+                ntsc::NTSC_FILTER_WIDTH,
+                ntsc::NTSC_FILTER_WIDTH / ppu::WIDTH as u32,
+            )
+        } else {
+            (ppu::WIDTH as u32, 1)
+        }
+    }
+
+    /// Writes `NES::video_frame` in RGBA into `buf`, such as a texture upload buffer, without
+    /// allocating a frame unless the NTSC filter is enabled.
+    ///
+    /// Panics if `buf` is shorter than 4 bytes for each pixel of `NES::video_frame_size`.
+    pub fn render_into(&self, buf: &mut [u8]) {
+        let (width, height) = self.video_frame_size();
+        let len = (width * height * 4) as usize;
+        assert!(
+            len <= buf.len(),
+            "buffer of {} bytes for a frame of {}x{}",
+            buf.len(),
+            width,
+            height
+        );
+        if self.ntsc_filter_enabled {
+            buf[..len].copy_from_slice(&self.video_frame().pixels);
+            return;
+        }
+
+        let ppu = &self.ppu;
+        let (left, top, width, height) =
+            self.overscan()
+                .visible_rect(ppu::WIDTH as u32, ppu::HEIGHT as u32, 1);
+        let (indices, emphasis) = (ppu.frame_buffer(), ppu.emphasis_buffer());
+        let palette = self.palette();
+        let mut out = buf.chunks_exact_mut(4);
+        for y in top..top + height {
+            for x in left..left + width {
+                let i = (y * ppu::WIDTH as u32 + x) as usize;
+                let [r, g, b] =
+                    palette.rgb_with_emphasis(indices[i], Emphasis::from_bits(emphasis[i]));
+                if let Some(p) = out.next() {
+                    p.copy_from_slice(&[r, g, b, 0xFF]);
+                }
+            }
         }
     }
 
+    /// Calls `sink` with `NES::video_frame` every time the PPU completes a frame, for streaming
+    /// frontends and encoders.
+    pub fn set_video_sink<F: FnMut(&Screenshot) + 'static + Send>(&mut self, sink: F) {
+        self.video_sink = Some(Box::new(sink));
+    }
+
+    pub fn clear_video_sink(&mut self) {
+        self.video_sink = None;
+    }
+
+    /// Takes a screenshot of the current frame buffer, tinted by the color emphasis of each pixel.
+    pub fn screenshot(&self) -> Screenshot {
+        let ppu = &self.ppu;
+        Screenshot::with_emphasis(
+            ppu::WIDTH as u32,
+            ppu::HEIGHT as u32,
+            ppu.frame_buffer(),
+            ppu.emphasis_buffer(),
+            self.palette(),
+        )
+    }
+
+    /// Decodes the pattern tables currently mapped at PPU $0000-$1FFF into a grayscale sheet,
+    /// which also works for cartridges with CHR RAM.
+    pub fn chr_sheet(&self) -> Screenshot {
+        chr::chr_sheet(&self.chr())
+    }
+
+    // the pattern tables currently mapped at PPU $0000-$1FFF
+    fn chr(&self) -> Vec<u8> {
+        let bus = self.peek_ppu_bus();
+        (0..0x2000u16)
+            .map(|addr| bus.peek(addr.into()).into())
+            .collect()
+    }
+
+    /// Decodes the 4 nametables laid out in the scroll space into a 512x480 image, like the
+    /// nametable viewers of debuggers.
+    pub fn debug_nametables(&self) -> Screenshot {
+        Screenshot::new(
+            ppu::NAMETABLES_WIDTH,
+            ppu::NAMETABLES_HEIGHT,
+            &self.ppu.debug_nametables(&self.peek_ppu_bus()),
+            self.palette(),
+        )
+    }
+
+    /// The sprites in OAM, for sprite viewers.
+    pub fn debug_oam(&self) -> Vec<OAMEntry> {
+        self.ppu.debug_oam()
+    }
+
+    /// Renders `entry` of `NES::debug_oam` with its palette into an 8x8 or 8x16 image as the
+    /// sprite size selected by the game. Transparent pixels have the alpha of 0.
+    pub fn debug_sprite(&self, entry: &OAMEntry) -> Screenshot {
+        let bus = self.peek_ppu_bus();
+        let colors = self.ppu.debug_sprite(entry, &bus);
+        let mut pixels = Vec::with_capacity(colors.len() * 4);
+        for c in colors {
+            let addr = 0x3F10 + entry.palette as u16 * 4 + c as u16;
+            let index = bus.peek(addr.into()).into();
+            let [r, g, b] = self.palette().rgb(index);
+            pixels.extend_from_slice(&[r, g, b, if c == 0 { 0 } else { 0xFF }]);
+        }
+        Screenshot {
+            width: 8,
+            height: (pixels.len() / 4 / 8) as u32,
+            pixels,
+        }
+    }
+
+    /// Renders the frame of the save state `state` from its PPU registers, nametables and palette,
+    /// with the pattern tables its mapper had banked in and its color emphasis.
+    ///
+    /// Mid-frame changes of the registers are not reproduced. Fails if the state was taken with
+    /// another ROM than the one loaded or its mapper cannot be read, leaving the mapper unchanged.
+    pub fn ghost_frame(&mut self, state: &SaveState) -> anyhow::Result<Screenshot> {
+        if state.rom_sha1 != self.rom().map_or([0; 20], |rom| rom.sha1()) {
+            return Err(SaveStateError::ROMMismatch.into());
+        }
+        // the banks of the state are switched in only to read the pattern tables
+        let current = match &self.rom {
+            Some(rom) => Some(
+                rom.mapper
+                    .save_state()
+                    .ok_or_else(|| anyhow!("The mapper does not support save states"))?,
+            ),
+            None => None,
+        };
+        let loaded = match self.rom.as_mut() {
+            Some(rom) => rom.mapper.load_state(&state.mapper),
+            None => Ok(()),
+        };
+        let chr = loaded.map(|()| self.chr());
+        if let (Some(rom), Some(current)) = (self.rom.as_mut(), current) {
+            rom.mapper
+                .load_state(&current)
+                .expect("the mapper state just taken loads back");
+        }
+        let (indices, emphasis) = PPU::render_snapshot(&chr?, &state.ppu, self.region);
+        Ok(Screenshot::with_emphasis(
+            ppu::WIDTH as u32,
+            ppu::HEIGHT as u32,
+            &indices,
+            &emphasis,
+            self.palette(),
+        ))
+    }
+
+    /// Composites translucent frames of save states over the current frame, for onion-skinning
+    /// of previous attempts in TAS frontends.
+    ///
+    /// `states` are ordered from the oldest; the latest one is drawn with `opacity`
+    /// and older ones fade out linearly. Fails as `NES::ghost_frame` does.
+    pub fn onion_skin(&mut self, states: &[SaveState], opacity: f32) -> anyhow::Result<Screenshot> {
+        let mut frame = self.screenshot();
+        let count = states.len();
+        for (i, state) in states.iter().enumerate() {
+            let alpha = opacity * (i + 1) as f32 / count as f32;
+            frame.blend(&self.ghost_frame(state)?, alpha);
+        }
+        Ok(frame)
+    }
+
     fn step(&mut self) {
         let cpu_cycles = self.cpu_step();
         self.cycles = self.cycles.wrapping_add(cpu_cycles);
 
-        let mut ppu = self.ppu.borrow_mut();
-        for _ in 0..(cpu_cycles * 3) {
+        for _ in 0..cpu_cycles {
+            // the mapper is clocked before the APU, which reads DMC samples through it
+            let expansion = self.rom.as_mut().map_or(0.0, |rom| {
+                rom.mapper.cpu_cycle();
+                rom.mapper.audio_output()
+            });
+            self.apu.set_expansion_output(expansion);
+            self.apu.step(&mut apu_bus!(self));
+        }
+
+        let ppu = &mut self.ppu;
+        let mut bus = ppu_bus!(self);
+        let frames = ppu.frames;
+        for dot in 0..(cpu_cycles * 3) {
             let line = ppu.current_line();
 
-            if let Some(interrupt) = ppu.step() {
+            if let Some(interrupt) = ppu.step(&mut bus) {
+                if interrupt == Interrupt::NMI {
+                    self.nmi_requested = self.cycles - cpu_cycles + dot / 3;
+                }
                 self.interrupt.set(interrupt);
             }
 
@@ -57,13 +1021,34 @@ impl NES {
                 //TODO render
             }
         }
+        let frame_completed = frames != ppu.frames;
+        if frame_completed && self.video_sink.is_some() {
+            let frame = self.video_frame();
+            if let Some(sink) = self.video_sink.as_mut() {
+                sink(&frame);
+            }
+        }
+
+        // IRQ is level triggered
+        let mapper_irq = self.rom.as_ref().is_some_and(|rom| rom.mapper.irq());
+        if mapper_irq || self.apu.irq() {
+            if !self.interrupt.is_set(Interrupt::IRQ) {
+                self.irq_requested = self.cycles;
+            }
+            self.interrupt.set(Interrupt::IRQ);
+        } else {
+            self.interrupt.unset(Interrupt::IRQ);
+        }
     }
 
     fn cpu_step(&mut self) -> CPUCycle {
         let before = self.cpu.cycles;
 
         self.handle_interrupt();
-        self.cpu.step();
+        if let Some(hook) = self.trace_hook.as_mut() {
+            hook(&Trace::trace(&self.cpu, &cpu_bus!(self)));
+        }
+        self.cpu.step(&mut cpu_bus!(self));
 
         let after = self.cpu.cycles;
         Self::diff_cycles(before, after)
@@ -87,49 +1072,367 @@ impl NES {
 
     pub fn reset(&mut self) {
         self.interrupt.set(Interrupt::RESET);
-        self.ppu.borrow_mut().reset();
+        self.reset_requested = self.cycles;
+        self.ppu.reset();
+        self.apu.reset();
     }
 
-    pub fn load(&mut self, rom: ROM) {
-        let ppu_bus = Box::new(PPUBus::new(rom.mapper.clone()));
-        let ppu = Rc::new(RefCell::new(PPU::new(ppu_bus)));
-        let cpu_bus = Box::new(CPUBus::new(rom.mapper.clone(), ppu.clone()));
-        *self = Self {
-            cpu: CPU::new(cpu_bus),
-            ppu,
-            interrupt: Interrupt::NO_INTERRUPT,
-            cycles: 0,
+    /// Inserts `rom` and puts the console back to the state at power-on, keeping the host-side
+    /// settings such as the hooks, the palette and the region.
+    ///
+    /// The save data of the cartridge taken out is written first. Nothing changes if it cannot
+    /// be written.
+    pub fn load(&mut self, rom: ROM) -> anyhow::Result<()> {
+        self.flush_save_data()?;
+        self.insert(rom);
+        Ok(())
+    }
+
+    // `NES::load` without writing the save data of the cartridge taken out
+    fn insert(&mut self, rom: ROM) {
+        self.rom = Some(rom);
+
+        self.cpu = Default::default();
+        let mut ppu = PPU::new();
+        ppu.inherit_settings(&mut self.ppu);
+        self.ppu = ppu;
+        self.vram = Default::default();
+        let mut apu = APU::new();
+        apu.set_region(self.region);
+        apu.inherit_settings(&self.apu);
+        self.apu = apu;
+        // the buttons held by the player are kept
+        let mut controllers = ControllerPorts::default();
+        controllers.inherit_settings(&self.controllers);
+        self.controllers = controllers;
+        self.register_log.take();
+        self.ram_pattern.fill(&mut self.wram);
+
+        self.interrupt = Interrupt::NO_INTERRUPT;
+        self.cycles = 0;
+        self.reset_requested = 0;
+        self.nmi_requested = 0;
+        self.irq_requested = 0;
+        self.paused = false;
+        self.stats = Default::default();
+        if let Some(canvas) = self.wide_canvas.as_mut() {
+            canvas.clear();
+        }
+        if let Some(buffer) = self.rewind.as_mut() {
+            buffer.clear();
         }
     }
 
+    /// Swaps the cartridge for `rom` while running, such as for a ROM dropped on the window.
+    ///
+    /// The save data of the cartridge taken out is written first, then the console is powered on
+    /// again with `rom`, dropping its pending interrupts and the pause. Nothing changes if the
+    /// save data cannot be written.
+    pub fn swap_rom(&mut self, rom: ROM) -> anyhow::Result<()> {
+        self.load(rom)?;
+        self.power_on();
+        self.reset();
+        Ok(())
+    }
+
+    /// The loaded cartridge.
+    pub fn rom(&self) -> Option<&ROM> {
+        self.rom.as_ref()
+    }
+
+    /// Writes the cartridge's non-volatile data, such as self-flashed PRG and battery-backed RAM,
+    /// next to the ROM file.
+    ///
+    /// Self-flashed PRG is also written when the cartridge is dropped, but battery-backed RAM is not.
+    pub fn flush_save_data(&mut self) -> anyhow::Result<()> {
+        match self.rom.as_mut() {
+            Some(rom) => rom.mapper.flush()?,
+            None => return Ok(()),
+        }
+        self.save_sram()
+    }
+
+    /// Writes the battery-backed RAM to the `.sav` file next to the ROM file,
+    /// which is loaded with the ROM by `ROM::load`.
+    ///
+    /// Nothing is written for cartridges without the battery flag.
+    pub fn save_sram(&self) -> anyhow::Result<()> {
+        match self.rom() {
+            Some(rom) => rom.save_sram(),
+            None => Ok(()),
+        }
+    }
+
+    /// Reloads the battery-backed RAM from the `.sav` file. Returns false if there is none.
+    pub fn load_sram(&mut self) -> anyhow::Result<bool> {
+        match self.rom.as_mut() {
+            Some(rom) => rom.load_sram(),
+            None => Ok(false),
+        }
+    }
+
+    /// Exports the save RAM of the cartridge in the raw `.sav` layout used by other emulators.
+    pub fn export_save_ram(&self) -> Option<Vec<u8>> {
+        self.rom.as_ref().and_then(|rom| rom.mapper.save_ram())
+    }
+
+    /// Imports save RAM exported by `NES::export_save_ram` or other emulators.
+    pub fn import_save_ram(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        match self.rom.as_mut() {
+            Some(rom) => rom.import_save_ram(data),
+            None => Err(anyhow::anyhow!("No ROM is loaded")),
+        }
+    }
+
+    /// Sets the initial contents of the work RAM, applied when a ROM is loaded.
+    pub fn set_ram_pattern(&mut self, pattern: RAMPattern) {
+        self.ram_pattern = pattern;
+    }
+
+    pub fn ram_pattern(&self) -> RAMPattern {
+        self.ram_pattern
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.region_palette = Palette::for_region(region);
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Emulates the artifacts of palette writes during rendering ("rainbow" effects of some demos)
+    /// Disabled by default.
+    pub fn set_palette_write_through(&mut self, enabled: bool) {
+        self.ppu.set_palette_write_through(enabled);
+    }
+
+    pub fn palette_write_through(&self) -> bool {
+        self.ppu.palette_write_through()
+    }
+
+    /// Removes the limit of 8 sprites per line when disabled, which eliminates the flicker of games
+    /// cycling their sprites. The sprite overflow flag is still set for the game logic.
+    /// Enabled by default.
+    pub fn set_sprite_limit_enabled(&mut self, enabled: bool) {
+        self.ppu.set_sprite_limit_enabled(enabled);
+    }
+
+    pub fn sprite_limit_enabled(&self) -> bool {
+        self.ppu.sprite_limit_enabled()
+    }
+
+    /// Hides or shows a layer of the PPU output regardless of PPUMASK, for debugging rendering.
+    /// This only affects the output, so sprite 0 hits still happen with a hidden layer.
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        self.ppu.set_layer_enabled(layer, enabled);
+    }
+
+    pub fn layer_enabled(&self, layer: Layer) -> bool {
+        self.ppu.layer_enabled(layer)
+    }
+
+    /// Colors of the palette indices for screenshots and RGBA frames, or `None` for
+    /// `Palette::for_region`.
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        self.palette.as_ref().unwrap_or(&self.region_palette)
+    }
+
+    /// Pixels cropped from the edges of `NES::video_frame`, or `None` for the default of the region.
+    pub fn set_overscan(&mut self, overscan: Option<Overscan>) {
+        self.overscan = overscan;
+    }
+
+    pub fn overscan(&self) -> Overscan {
+        self.overscan
+            .unwrap_or_else(|| Overscan::for_region(self.region))
+    }
+
+    /// Simulates the artifacts of composite video in `NES::video_frame`, such as color fringes and
+    /// dot crawl. The frame is widened to `NTSC_FILTER_WIDTH`.
+    ///
+    /// Disabled by default, as filtering a frame costs much more than running it.
+    pub fn set_ntsc_filter_enabled(&mut self, enabled: bool) {
+        self.ntsc_filter_enabled = enabled;
+    }
+
+    pub fn ntsc_filter_enabled(&self) -> bool {
+        self.ntsc_filter_enabled
+    }
+
+    /// Color emphasis in effect at the end of the last rendered frame.
+    /// On PAL, the red and green bits of PPUMASK are swapped back to their actual meaning.
+    pub fn emphasis(&self) -> Emphasis {
+        self.ppu.frame_emphasis()
+    }
+
+    /// Sets the host audio sample rate (e.g. 44100 or 48000) that audio samples are resampled to.
+    /// A rate of 0 is taken as 1, as it is by `NES::adjust_sample_rate`.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    /// Changes the sample rate slightly without the click of `NES::set_sample_rate`, keeping the
+    /// sample in progress and the output filters. This is for frontends following the rate the
+    /// audio device actually consumes the samples at (dynamic rate control).
+    pub fn adjust_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.adjust_sample_rate(sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.apu.sample_rate()
+    }
+
+    /// Selects how the APU channels are mixed. Defaults to the non-linear mixing of the hardware.
+    pub fn set_mixer_mode(&mut self, mode: MixerMode) {
+        self.apu.set_mixer_mode(mode);
+    }
+
+    pub fn mixer_mode(&self) -> MixerMode {
+        self.apu.mixer_mode()
+    }
+
+    /// Mutes or unmutes an APU channel. This only affects the audio output, not the emulation.
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.apu.set_channel_enabled(channel, enabled);
+    }
+
+    pub fn channel_enabled(&self, channel: Channel) -> bool {
+        self.apu.channel_enabled(channel)
+    }
+
+    /// Mutes every APU channel except `channel`.
+    pub fn solo_channel(&mut self, channel: Channel) {
+        let apu = &mut self.apu;
+        for c in Channel::ALL.iter() {
+            apu.set_channel_enabled(*c, *c == channel);
+        }
+    }
+
+    /// Enables the emulation of the analog output filters of the NES (90 Hz and 440 Hz high-pass, 14 kHz low-pass).
+    /// Enabled by default.
+    pub fn set_audio_filter_enabled(&mut self, enabled: bool) {
+        self.apu.set_filter_enabled(enabled);
+    }
+
+    pub fn audio_filter_enabled(&self) -> bool {
+        self.apu.filter_enabled()
+    }
+
+    /// Takes the audio samples generated since the last call at the host sample rate.
+    ///
+    /// Samples are centered around 0.0 if the audio filter is enabled, otherwise in range 0.0 to 1.0.
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.take_samples()
+    }
+
+    /// Records the writes to PPU, APU and mapper registers with the frame, line and dot of the
+    /// PPU, taken by `NES::take_register_writes`. Disabled by default, and disabling it discards
+    /// the writes not taken.
+    pub fn set_register_log_enabled(&mut self, enabled: bool) {
+        self.register_log.set_enabled(enabled);
+    }
+
+    pub fn register_log_enabled(&self) -> bool {
+        self.register_log.enabled()
+    }
+
+    /// Takes the register writes recorded since the last call, in order. They pile up until
+    /// taken, so a viewer would take them every frame.
+    pub fn take_register_writes(&mut self) -> Vec<RegisterWrite> {
+        self.register_log.take()
+    }
+
+    /// Takes the hardware lints found since the last call, such as writes that can damage
+    /// a real console.
+    pub fn take_lints(&mut self) -> Vec<HardwareLint> {
+        self.ppu.take_lints()
+    }
+
     fn handle_interrupt(&mut self) {
+        let before = self.cpu.cycles;
         let interrupt = self.interrupt.get();
-        match interrupt {
+        let serviced = match interrupt {
             Interrupt::RESET => {
-                self.cpu.reset();
-                self.interrupt.unset(interrupt)
+                self.cpu.reset(&mut cpu_bus!(self));
+                self.interrupt.unset(interrupt);
+                Some((InterruptKind::Reset, self.reset_requested))
             }
             Interrupt::NMI => {
-                self.cpu.non_markable_interrupt();
-                self.interrupt.unset(interrupt)
+                self.cpu.non_markable_interrupt(&mut cpu_bus!(self));
+                self.interrupt.unset(interrupt);
+                Some((InterruptKind::NMI, self.nmi_requested))
             }
             Interrupt::IRQ => {
-                if self.cpu.interrupted() {
-                    self.cpu.interrupt_request();
-                    self.interrupt.unset(interrupt)
+                if !self.cpu.interrupted() {
+                    self.cpu.interrupt_request(&mut cpu_bus!(self));
+                    self.interrupt.unset(interrupt);
+                    Some((InterruptKind::IRQ, self.irq_requested))
+                } else {
+                    None
                 }
             }
             Interrupt::BRK => {
                 if self.cpu.interrupted() {
-                    self.cpu.break_interrupt();
+                    self.cpu.break_interrupt(&mut cpu_bus!(self));
                     self.interrupt.unset(interrupt)
                 }
+                None
             }
-            _ => {}
+            _ => None,
+        };
+
+        if let (Some((kind, requested)), Some(hook)) = (serviced, self.interrupt_hook.as_mut()) {
+            hook(&InterruptEvent {
+                kind,
+                requested,
+                entered: self.cycles,
+                cycles: Self::diff_cycles(before, self.cpu.cycles),
+            });
         }
     }
 }
 
+#[cfg(feature = "vcd")]
+impl NES {
+    /// Starts recording CPU bus activity (address, data, R/W) into a VCD file
+    /// for `cycles` CPU cycles beginning at the CPU cycle `start`.
+    pub fn record_bus_vcd<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+        start: CPUCycle,
+        cycles: CPUCycle,
+    ) -> anyhow::Result<()> {
+        let f = std::fs::File::create(path)?;
+        let out = Box::new(std::io::BufWriter::new(f));
+        self.cpu.bus_recorder = Some(crate::cpu::BusRecorder::new(out, start, cycles)?);
+        Ok(())
+    }
+
+    /// Returns true while a bus recording is active and its window has not ended yet.
+    pub fn recording_bus_vcd(&self) -> bool {
+        match &self.cpu.bus_recorder {
+            Some(r) => !r.done(self.cpu.cycles),
+            None => false,
+        }
+    }
+
+    /// Stops the bus recording and flushes the VCD file.
+    pub fn finish_bus_vcd(&mut self) -> anyhow::Result<()> {
+        if let Some(r) = self.cpu.bus_recorder.take() {
+            r.finish()?;
+        }
+        Ok(())
+    }
+}
+
 // nestest
 impl NES {
     pub fn nestest<F: FnMut(&Trace)>(&mut self, mut f: F) {
@@ -143,17 +1446,18 @@ impl NES {
 
             self.handle_interrupt();
 
-            let trace = Trace::trace(&self.cpu);
+            let trace = Trace::trace(&self.cpu, &cpu_bus!(self));
             f(&trace);
 
-            self.cpu.step();
+            self.cpu.step(&mut cpu_bus!(self));
 
             let after = self.cpu.cycles;
             let cpu_cycles = Self::diff_cycles(before, after);
 
-            let mut ppu = self.ppu.borrow_mut();
+            let ppu = &mut self.ppu;
+            let mut bus = ppu_bus!(self);
             for _ in 0..(cpu_cycles * 3) {
-                if let Some(interrupt) = ppu.step() {
+                if let Some(interrupt) = ppu.step(&mut bus) {
                     self.interrupt.set(interrupt);
                 }
             }
@@ -166,10 +1470,608 @@ impl NES {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::register_log::Device;
     use std::fs::File;
     use std::io::{self, BufRead};
+    use std::sync::{Arc, Mutex};
+
+    // NROM counting NMIs at $10
+    pub(crate) fn counter_rom() -> ROM {
+        ROM::from_bytes(&counter_rom_bytes()).unwrap()
+    }
+
+    pub(crate) fn counter_rom_bytes() -> Vec<u8> {
+        let mut prg = vec![0; 0x4000];
+        let program = [
+            0xA9, 0x80, // LDA #$80
+            0x8D, 0x00, 0x20, // STA $2000
+            0x4C, 0x05, 0xC0, // JMP $C005
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        let nmi = [
+            0xE6, 0x10, // INC $10
+            0x40, // RTI
+        ];
+        prg[0x10..0x10 + nmi.len()].copy_from_slice(&nmi);
+        prg[0x3FFA..].copy_from_slice(&[0x10, 0xC0, 0x00, 0xC0, 0x00, 0xC0]);
+
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(prg);
+        data.extend(vec![0; 0x2000]);
+        data
+    }
+
+    #[test]
+    fn register_log() {
+        let mut nes = NES::default();
+        nes.set_register_log_enabled(true);
+        nes.load(counter_rom()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+
+        let writes = nes.take_register_writes();
+        // INC $10 in the NMI handler writes to the RAM, which is not logged
+        assert_eq!(writes.len(), 1);
+        let w = writes[0];
+        assert_eq!((w.device, w.addr, w.value), (Device::PPU, 0x2000, 0x80));
+        assert_eq!(w.frame, 0);
+        assert!(nes.take_register_writes().is_empty());
+
+        nes.set_register_log_enabled(false);
+        nes.reset();
+        nes.frame();
+        assert!(nes.take_register_writes().is_empty());
+    }
+
+    #[test]
+    fn pause() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+        let counter = nes.peek(0x10);
+
+        nes.set_paused(true);
+        assert_eq!(nes.frame().cpu_cycles, 0);
+        assert_eq!(nes.peek(0x10), counter);
+        assert!(0 < nes.step_frame().cpu_cycles);
+        assert_eq!(nes.peek(0x10), counter.wrapping_add(1));
+        assert!(nes.paused());
+
+        nes.load(counter_rom()).unwrap();
+        assert!(!nes.paused());
+    }
+
+    #[test]
+    fn swap_rom() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.power_on();
+        nes.reset();
+        for _ in 0..3 {
+            nes.frame();
+        }
+        nes.set_paused(true);
+
+        nes.swap_rom(counter_rom()).unwrap();
+        assert!(!nes.paused());
+        assert!(0 < nes.frame().cpu_cycles);
+        let counter = nes.peek(0x10);
+        nes.frame();
+        assert_eq!(nes.peek(0x10), counter.wrapping_add(1));
+    }
+
+    #[test]
+    fn load() {
+        let dir = std::env::temp_dir().join(format!("rustnes-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("counter.sav");
+        // with battery-backed RAM
+        let mut data = counter_rom_bytes();
+        data[6] |= 0x02;
+        let mut rom = ROM::from_bytes(&data).unwrap();
+        rom.set_sram_path(&path).unwrap();
+
+        let mut nes = NES::default();
+        nes.set_run_ahead(2);
+        nes.set_sprite_limit_enabled(false);
+        nes.load(rom).unwrap();
+        nes.import_save_ram(&[0x5A; 0x10]).unwrap();
+        let config = nes.config();
+        nes.load(counter_rom()).unwrap();
+        assert_eq!(nes.config(), config);
+        assert_eq!(nes.run_ahead(), 2);
+        assert_eq!(std::fs::read(&path).unwrap()[..0x10], [0x5A; 0x10]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_slot() {
+        let dir = std::env::temp_dir().join(format!("rustnes-slots-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("counter.nes");
+        std::fs::write(&path, counter_rom_bytes()).unwrap();
+
+        let mut nes = NES::default();
+        nes.load(ROM::load(&path).unwrap()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+        nes.frame();
+        assert!(!nes.load_slot(3).unwrap());
+        nes.save_slot(3).unwrap();
+        assert!(dir.join("counter.state3").exists());
+        let counter = nes.peek(0x10);
+        nes.frame();
+        assert!(nes.load_slot(3).unwrap());
+        assert_eq!(nes.peek(0x10), counter);
+
+        assert!(nes.save_slot(SAVE_SLOTS).is_err());
+        let mut other = NES::default();
+        other.load(counter_rom()).unwrap();
+        assert!(other.save_slot(0).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rewind() {
+        let mut nes = NES::default();
+        nes.set_rewind(Some(RewindConfig::default()));
+        nes.load(counter_rom()).unwrap();
+        assert_eq!(nes.rewind_config(), Some(RewindConfig::default()));
+        nes.power_on();
+        nes.reset();
+        assert_eq!(nes.rewind(2), 0);
+        let mut counters = Vec::new();
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            nes.frame();
+            counters.push(nes.peek(0x10));
+            states.push(nes.save_state().unwrap());
+        }
+        assert_eq!(nes.rewind_frames(), 8);
+
+        // to the whole state after the 6th frame, the APU and the mapper included
+        assert_eq!(nes.rewind(3), 4);
+        assert_eq!(nes.peek(0x10), counters[5]);
+        let mut state = nes.save_state().unwrap();
+        state.metadata = states[5].metadata.clone();
+        assert_eq!(state, states[5]);
+        nes.frame();
+        assert_eq!(nes.peek(0x10), counters[6]);
+
+        nes.set_rewind(None);
+        assert_eq!(nes.rewind(2), 0);
+    }
+
+    #[test]
+    fn run_ahead() {
+        // the NMI handler also sets the backdrop to the counter
+        let mut data = counter_rom_bytes();
+        let nmi = [
+            0xE6, 0x10, // INC $10
+            0xA9, 0x3F, // LDA #$3F
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA5, 0x10, // LDA $10
+            0x8D, 0x07, 0x20, // STA $2007
+            0x40, // RTI
+        ];
+        data[0x10 + 0x10..0x10 + 0x10 + nmi.len()].copy_from_slice(&nmi);
+        let new_nes = |run_ahead| {
+            let mut nes = NES::default();
+            nes.set_run_ahead(run_ahead);
+            nes.load(ROM::from_bytes(&data).unwrap()).unwrap();
+            nes.power_on();
+            nes.reset();
+            nes
+        };
+        let mut nes = new_nes(0);
+        let mut ahead = new_nes(2);
+        assert_eq!(ahead.run_ahead(), 2);
+        let mut reference = new_nes(0);
+        reference.frame();
+        reference.frame();
+        for _ in 0..5 {
+            let frame = nes.frame();
+            assert_eq!(ahead.frame(), frame);
+            reference.frame();
+            assert_eq!(ahead.peek(0x10), nes.peek(0x10));
+            assert_eq!(ahead.take_audio_samples(), nes.take_audio_samples());
+            assert_eq!(ahead.frame_buffer(), reference.frame_buffer());
+            assert_ne!(ahead.frame_buffer(), nes.frame_buffer());
+        }
+    }
+
+    #[test]
+    fn interrupt_hook() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let e = events.clone();
+        nes.set_interrupt_hook(move |event| e.lock().unwrap().push(*event));
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+        nes.frame();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[0].kind, InterruptKind::Reset);
+        assert_eq!(events[0].latency(), 7);
+        let nmi = events[1];
+        assert_eq!(nmi.kind, InterruptKind::NMI);
+        assert_eq!(nmi.cycles, 7);
+        // JMP takes 3 cycles
+        assert!(nmi.entered - nmi.requested <= 3);
+    }
+
+    #[test]
+    fn controller() {
+        let mut nes = NES::default();
+        nes.set_controller(0, Button::START | Button::A);
+        nes.set_controller(1, Button::SELECT);
+        nes.set_four_score_enabled(true);
+        nes.load(counter_rom()).unwrap();
+        assert!(nes.four_score_enabled());
+        assert_eq!(nes.controller(0), Button::START | Button::A);
+        assert_eq!(nes.controller(1), Button::SELECT);
+    }
+
+    #[test]
+    fn input_script() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.set_input_script(InputScript::new().tap(Button::START).wait_frames(1));
+
+        nes.frame();
+        assert_eq!(nes.controller(0), Button::START);
+        assert_eq!(nes.input_script().map(|s| s.remaining_frames()), Some(1));
+        nes.frame();
+        assert_eq!(nes.controller(0), Button::empty());
+        assert!(nes.input_script().is_none());
+    }
+
+    #[test]
+    fn frame_buffer() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+
+        let indices = nes.frame_buffer();
+        let rgba = nes.frame_buffer_rgba();
+        assert_eq!(indices.len(), ppu::WIDTH as usize * ppu::HEIGHT as usize);
+        assert_eq!(rgba.len(), indices.len() * 4);
+        let [r, g, b] = nes.palette().rgb(indices[0]);
+        assert_eq!(&rgba[..4], &[r, g, b, 0xFF]);
+    }
+
+    #[test]
+    fn video_frame() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.frame();
+        assert_eq!(nes.video_frame(), nes.screenshot().crop(nes.overscan(), 1));
+        assert_eq!(nes.video_frame().height, 224);
+
+        nes.set_overscan(Some(Overscan {
+            left: 8,
+            ..Overscan::NONE
+        }));
+        nes.set_ntsc_filter_enabled(true);
+        let frame = nes.video_frame();
+        assert_eq!(
+            (frame.width, frame.height),
+            (ntsc::NTSC_FILTER_WIDTH - 16, 240)
+        );
+    }
+
+    #[test]
+    fn render_into() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.frame();
+        for &ntsc in &[false, true] {
+            nes.set_ntsc_filter_enabled(ntsc);
+            let frame = nes.video_frame();
+            assert_eq!(nes.video_frame_size(), (frame.width, frame.height));
+            let mut buf = vec![0; frame.pixels.len() + 4];
+            nes.render_into(&mut buf);
+            assert_eq!(&buf[..frame.pixels.len()], &frame.pixels[..]);
+        }
+    }
+
+    #[test]
+    fn frame_hash() {
+        let run = |palette: Palette| {
+            let mut nes = NES::default();
+            nes.set_palette(Some(palette));
+            nes.load(counter_rom()).unwrap();
+            nes.power_on();
+            nes.reset();
+            nes.frame();
+            (nes.frame_hash(), nes.screenshot().hash())
+        };
+        let mut palette = Palette::default();
+        palette.set_rgb(0, [1, 2, 3]);
+        let (a, b) = (run(Palette::default()), run(palette));
+        assert_eq!(a.0, b.0);
+        assert_ne!(a.1, b.1);
+    }
+
+    #[test]
+    fn indexed_frame() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.ppu
+            .write_register(0x2001, 0b1010_0000.into(), &mut ppu_bus!(nes));
+        nes.frame();
+
+        let frame = nes.indexed_frame();
+        assert_eq!(frame.len(), ppu::WIDTH as usize * ppu::HEIGHT as usize);
+        let expected = nes.frame_buffer()[0] as u16 | 0b101 << 6;
+        assert_eq!(frame[0], expected);
+    }
+
+    #[test]
+    fn video_sink() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let f = frames.clone();
+        nes.set_video_sink(move |frame| f.lock().unwrap().push(frame.clone()));
+        nes.frame();
+        nes.frame();
+        assert_eq!(frames.lock().unwrap().len(), 2);
+        assert_eq!(frames.lock().unwrap()[1], nes.video_frame());
+    }
+
+    #[test]
+    fn run_until_scanline() {
+        let mut nes = NES::default();
+        nes.power_on();
+
+        nes.run_until_scanline(100);
+        let frame = nes.partial_frame();
+        assert_eq!(frame.line, 100);
+        assert!(frame.is_drawn(255, 99));
+        assert!(!frame.is_drawn(255, 100));
+        assert!(!frame.is_drawn(0, 239));
+
+        nes.run_until_scanline(241);
+        assert!(nes.partial_frame().is_drawn(255, 239));
+    }
+
+    #[test]
+    #[should_panic(expected = "no scanline 262")]
+    fn run_until_missing_scanline() {
+        let mut nes = NES::default();
+        nes.set_region(Region::PAL);
+        nes.run_until_scanline(262);
+    }
+
+    #[test]
+    fn scanline_hook() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut nes = NES::default();
+        nes.power_on();
+        let l = lines.clone();
+        nes.set_scanline_hook(move |e| l.lock().unwrap().push(e.line));
+
+        nes.run_until_scanline(3);
+        assert_eq!(*lines.lock().unwrap(), vec![1, 2, 3]);
+
+        nes.clear_scanline_hook();
+        nes.run_until_scanline(10);
+        assert_eq!(lines.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn region_palette() {
+        let mut nes = NES::default();
+        assert_eq!(nes.palette(), &Palette::default());
+        nes.set_region(Region::PAL);
+        nes.load(counter_rom()).unwrap();
+        assert_eq!(nes.palette(), &Palette::for_region(Region::PAL));
+
+        let mut palette = Palette::default();
+        palette.set_rgb(0, [1, 2, 3]);
+        nes.set_palette(Some(palette.clone()));
+        nes.set_region(Region::NTSC);
+        assert_eq!(nes.palette(), &palette);
+        nes.set_palette(None);
+        assert_eq!(nes.palette(), &Palette::default());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_serde() {
+        let config = Config {
+            region: Region::PAL,
+            ram_pattern: RAMPattern::Random(42),
+            enabled_channels: vec![Channel::Pulse1, Channel::DMC],
+            overscan: Some(Overscan {
+                top: 8,
+                ..Overscan::NONE
+            }),
+            ..Config::default()
+        };
+        let s = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&s).unwrap(), config);
+        assert_eq!(
+            toml::from_str::<Config>("region = \"pal\"").unwrap(),
+            Config {
+                region: Region::PAL,
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn into_parts_round_trip() {
+        let mut nes = NES::default();
+        nes.set_region(Region::PAL);
+        nes.set_channel_enabled(Channel::Noise, false);
+        nes.power_on();
+        nes.frame();
+        nes.frame();
+
+        let parts = nes.into_parts();
+        let cpu = parts.cpu;
+        let ppu = parts.ppu.clone();
+        let config = parts.config.clone();
+
+        let nes = NES::from_parts(parts);
+        assert_eq!(nes.region(), Region::PAL);
+        assert!(!nes.channel_enabled(Channel::Noise));
+
+        let parts = nes.into_parts();
+        assert_eq!(parts.cpu, cpu);
+        assert_eq!(parts.ppu, ppu);
+        assert_eq!(parts.config, config);
+    }
+
+    #[test]
+    fn send() {
+        // so that a NES can run on a thread of its own
+        fn assert_send<T: Send>() {}
+        assert_send::<NES>();
+    }
+
+    #[test]
+    fn ram_mirroring() {
+        let mut nes = NES::default();
+        nes.power_on();
+        cpu_bus!(nes).write(0x1801u16.into(), 0x42u8.into());
+        assert_eq!(nes.peek(0x0001), 0x42);
+        assert_eq!(nes.peek(0x0801), 0x42);
+        assert_eq!(nes.ram()[0x0001], 0x42);
+
+        let state = nes.save_state().unwrap();
+        cpu_bus!(nes).write(0x0001u16.into(), 0x00u8.into());
+        nes.load_state(&state).unwrap();
+        assert_eq!(cpu_bus!(nes).read(0x1001u16.into()), 0x42u8.into());
+    }
+
+    #[test]
+    fn save_state_resumes() {
+        // with the battery for the PRG RAM
+        let mut data = counter_rom_bytes();
+        data[6] |= 0b10;
+        let mut nes = NES::default();
+        nes.load(ROM::from_bytes(&data).unwrap()).unwrap();
+        nes.power_on();
+        nes.reset();
+        // a tone on the pulse 1 and the frame IRQ
+        for (addr, value) in &[
+            (0x4015u16, 0x01u8),
+            (0x4000, 0xBF),
+            (0x4002, 0xFD),
+            (0x4003, 0x08),
+            (0x4017, 0x00),
+            (0x6000, 0x42),
+        ] {
+            cpu_bus!(nes).write((*addr).into(), (*value).into());
+        }
+        nes.frame();
+        let state = nes.save_state().unwrap();
+        assert_eq!(state.interrupt.cycles, nes.cycles);
+
+        let run = |nes: &mut NES| {
+            for _ in 0..3 {
+                nes.frame();
+            }
+            let mut state = nes.save_state().unwrap();
+            state.metadata = SaveStateMetadata::none();
+            state
+        };
+        let first = run(&mut nes);
+        cpu_bus!(nes).write(0x6000u16.into(), 0x00u8.into());
+        cpu_bus!(nes).write(0x4000u16.into(), 0x30u8.into());
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.peek(0x6000), 0x42);
+        assert_eq!(run(&mut nes), first);
+    }
+
+    #[test]
+    fn frame_samples() {
+        let mut nes = NES::default();
+        nes.power_on();
+        nes.frame();
+        nes.take_audio_samples();
+
+        let mut total = 0;
+        let mut exact = 0.0;
+        for _ in 0..60 {
+            let frame = nes.frame();
+            assert!((frame.samples as f64 - frame.exact_samples).abs() < 2.0);
+            assert!((0.0..1.0).contains(&frame.sample_remainder));
+            assert_eq!(nes.take_audio_samples().len(), frame.samples);
+            total += frame.samples;
+            exact += frame.exact_samples;
+        }
+        assert!((total as f64 - exact).abs() < 2.0);
+    }
+
+    #[test]
+    fn onion_skin() {
+        let mut nes = NES::default();
+        nes.power_on();
+        nes.frame();
+
+        let mut ghost = nes.save_state().unwrap();
+        ghost.ppu.palette[0] = 0x30;
+        ghost.ppu.mask = 0;
+        let frame = nes.ghost_frame(&ghost).unwrap();
+        assert_eq!(&frame.pixels[..4], &[0xFF, 0xFE, 0xFF, 0xFF]);
+        // with the emphasis of the state
+        ghost.ppu.mask = 0xE0;
+        assert_ne!(nes.ghost_frame(&ghost).unwrap(), frame);
+
+        let ghosts = [ghost];
+        let frame = nes.onion_skin(&ghosts, 0.0).unwrap();
+        assert_eq!(frame, nes.screenshot());
+        assert_ne!(nes.onion_skin(&ghosts, 0.5).unwrap(), frame);
+
+        nes.load(counter_rom()).unwrap();
+        assert!(nes.ghost_frame(&ghosts[0]).is_err());
+    }
+
+    #[test]
+    fn ghost_frame_banks() {
+        // CNROM of 2 CHR banks, the tiles of the bank 1 filled with the color 3
+        let mut data = vec![
+            0x4E, 0x45, 0x53, 0x1A, 2, 2, 0x30, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        data.extend(vec![0xFF; 0x8000]);
+        data.extend(vec![0x00; 0x2000]);
+        data.extend(vec![0xFF; 0x2000]);
+        let mut nes = NES::default();
+        nes.load(ROM::from_bytes(&data).unwrap()).unwrap();
+        let select = |nes: &mut NES, bank: u8| {
+            let mapper = &mut nes.rom.as_mut().unwrap().mapper;
+            mapper.write(0x8000u16.into(), bank.into());
+        };
+
+        select(&mut nes, 1);
+        let mut ghost = nes.save_state().unwrap();
+        ghost.ppu.mask = 0x0A;
+        ghost.ppu.palette[0] = 0x0F;
+        ghost.ppu.palette[3] = 0x30;
+        select(&mut nes, 0);
+        let frame = nes.ghost_frame(&ghost).unwrap();
+        // a pixel of the second tile
+        assert_eq!(&frame.pixels[8 * 4..9 * 4], &[0xFF, 0xFE, 0xFF, 0xFF]);
+        assert_eq!(nes.chr()[0], 0x00);
+    }
 
     #[test]
     #[cfg_attr(not(feature = "nestest"), ignore)]
@@ -177,7 +2079,7 @@ mod tests {
         let rom = ROM::load("nestest.nes").unwrap();
 
         let mut nes = NES::default();
-        nes.load(rom);
+        nes.load(rom).unwrap();
         nes.power_on();
 
         let file = File::open("nestest-cpu.log").unwrap();
@@ -0,0 +1,174 @@
+use std::f32::consts::PI;
+
+use crate::screenshot::Screenshot;
+
+/// Width of a frame through the NTSC filter, twice the PPU output.
+pub const NTSC_FILTER_WIDTH: u32 = 512;
+
+// Composite video simulation of the PPU output, decoded back to RGB.
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+//
+// Each pixel is 8 samples of a square wave at 12 phases per color subcarrier cycle, so colors
+// bleed into the neighbouring pixels and the dot crawl moves with the phase of each line.
+
+// signal levels of the low and high halves of the wave for the luminance 0-3
+const LOW_LEVELS: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH_LEVELS: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+const SAMPLES_PER_PIXEL: usize = 8;
+pub(crate) const PHASES: usize = 12;
+// 341 dots of a line are 4 phases ahead in the subcarrier
+const LINE_PHASE_SHIFT: usize = 4;
+// the phase of the subcarrier against the hue of the colors
+const HUE_SHIFT: f32 = 3.9;
+
+fn in_color_phase(color: usize, phase: usize) -> bool {
+    (color + phase) % PHASES < 6
+}
+
+// `index` of the palette with `emphasis` as `Emphasis::bits`
+fn signal(index: u8, emphasis: u8, phase: usize) -> f32 {
+    let color = (index & 0x0F) as usize;
+    let level = if 0x0D < color {
+        1
+    } else {
+        ((index >> 4) & 3) as usize
+    };
+    let mut low = LOW_LEVELS[level];
+    let mut high = HIGH_LEVELS[level];
+    if color == 0 {
+        low = high;
+    } else if 0x0C < color {
+        high = low;
+    }
+    let mut signal = if in_color_phase(color, phase) {
+        high
+    } else {
+        low
+    };
+    // red, green and blue attenuate the opposite phases of the wave
+    let attenuated = (emphasis & 1 != 0 && in_color_phase(0x0C, phase))
+        || (emphasis & 2 != 0 && in_color_phase(0x04, phase))
+        || (emphasis & 4 != 0 && in_color_phase(0x08, phase));
+    if attenuated {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    signal
+}
+
+/// Filters a frame of palette indices and emphasis bits (as `NES::frame_buffer` and
+/// `NES::emphasis_buffer`) into `NTSC_FILTER_WIDTH` x `height` RGBA.
+///
+/// `frame` is the number of the frame, whose parity shifts the phase of the subcarrier.
+pub(crate) fn filter(
+    indices: &[u8],
+    emphasis: &[u8],
+    width: usize,
+    height: usize,
+    frame: u64,
+) -> Screenshot {
+    let (cos, sin) = carrier();
+    let samples = width * SAMPLES_PER_PIXEL;
+    let out_width = NTSC_FILTER_WIDTH as usize;
+    let mut pixels = Vec::with_capacity(out_width * height * 4);
+    let mut line_signal = vec![0.0; samples];
+
+    for y in 0..height {
+        let phase = (y * LINE_PHASE_SHIFT + (frame % 2) as usize * LINE_PHASE_SHIFT) % PHASES;
+        for x in 0..width {
+            let i = y * width + x;
+            let e = emphasis.get(i).copied().unwrap_or(0);
+            for s in 0..SAMPLES_PER_PIXEL {
+                let n = x * SAMPLES_PER_PIXEL + s;
+                let level = signal(indices[i], e, (phase + n) % PHASES);
+                line_signal[n] = (level - BLACK) / (WHITE - BLACK);
+            }
+        }
+
+        for x in 0..out_width {
+            // a cycle of the subcarrier around the center of the output pixel
+            let center = x * samples / out_width + samples / out_width / 2;
+            let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+            for k in 0..PHASES {
+                let n = (center + k).saturating_sub(PHASES / 2).min(samples - 1);
+                let p = (phase + n) % PHASES;
+                let level = line_signal[n];
+                luma += level;
+                i += level * cos[p];
+                q += level * sin[p];
+            }
+            let luma = luma / PHASES as f32;
+            let i = i * 2.0 / PHASES as f32;
+            let q = q * 2.0 / PHASES as f32;
+            let rgb = [
+                luma + 0.956 * i + 0.621 * q,
+                luma - 0.272 * i - 0.647 * q,
+                luma - 1.106 * i + 1.703 * q,
+            ];
+            for c in &rgb {
+                pixels.push((c * 255.0).round().clamp(0.0, 255.0) as u8);
+            }
+            pixels.push(0xFF);
+        }
+    }
+
+    Screenshot {
+        width: NTSC_FILTER_WIDTH,
+        height: height as u32,
+        pixels,
+    }
+}
+
+fn carrier() -> ([f32; PHASES], [f32; PHASES]) {
+    let mut cos = [0.0; PHASES];
+    let mut sin = [0.0; PHASES];
+    for p in 0..PHASES {
+        let angle = PI * (p as f32 + HUE_SHIFT) / 6.0;
+        cos[p] = angle.cos();
+        sin[p] = angle.sin();
+    }
+    (cos, sin)
+}
+
+// a cycle of the signal of `index` between black (0.0) and white (1.0)
+pub(crate) fn levels(index: u8) -> [f32; PHASES] {
+    let mut levels = [0.0; PHASES];
+    for (p, level) in levels.iter_mut().enumerate() {
+        *level = (signal(index, 0, p) - BLACK) / (WHITE - BLACK);
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(index: u8, emphasis: u8) -> [u8; 3] {
+        let frame = filter(&[index; 64], &[emphasis; 64], 64, 1, 0);
+        let p = &frame.pixels[(256 * 4)..(256 * 4 + 3)];
+        [p[0], p[1], p[2]]
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(color(0x0F, 0), [0, 0, 0]);
+        let [r, g, b] = color(0x30, 0);
+        assert!(0xF0 < r && 0xF0 < g && 0xF0 < b);
+
+        // red
+        let [r, g, b] = color(0x16, 0);
+        assert!(g < r && b < r);
+        // blue
+        let [r, g, b] = color(0x12, 0);
+        assert!(r < b && g < b);
+        // green
+        let [r, g, b] = color(0x1A, 0);
+        assert!(r < g && b < g);
+
+        let [r, g, b] = color(0x30, 1);
+        assert!(g < r && b < r);
+    }
+}
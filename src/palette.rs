@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::ntsc;
+use crate::ppu::Emphasis;
+use crate::region::Region;
+
+/// The number of colors in a palette.
+pub const PALETTE_COLORS: usize = 64;
+
+// Attenuation of the channels not emphasized, for each emphasis bit set.
+// https://wiki.nesdev.com/w/index.php/NTSC_video#Color_Tint_Bits
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+/// RGB colors of the 64 palette indices the PPU outputs.
+///
+/// The default is a standard NTSC palette, and `Palette::for_region` gives one for PAL. Others can
+/// be loaded from `.pal` files of 192 bytes, 64 colors of 3 bytes in RGB order.
+/// https://wiki.nesdev.com/w/index.php/PPU_palettes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: [[u8; 3]; PALETTE_COLORS],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut colors = [[0; 3]; 64];
+        for (c, rgb) in colors.iter_mut().zip(NTSC_COLORS.iter()) {
+            *c = [(rgb >> 16) as u8, (rgb >> 8) as u8, *rgb as u8];
+        }
+        Self { colors }
+    }
+}
+
+// Colors decoded from the composite signal, where the hue of the color 1 is at this phase out of 12
+// of the subcarrier. The standard NTSC palette is within a few levels of these with the NTSC hue.
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+const NTSC_HUE: f32 = 2.9;
+// The 2C07 shifts the hues by half a phase (15 degrees) from the 2C02.
+// https://wiki.nesdev.com/w/index.php/PAL_video
+const PAL_HUE: f32 = NTSC_HUE + 0.5;
+const SATURATION: f32 = 0.8;
+
+impl Palette {
+    /// The default palette of `region`, as the colors of PAL games look wrong in the NTSC palette.
+    pub fn for_region(region: Region) -> Self {
+        match region {
+            Region::NTSC => Self::default(),
+            Region::PAL => Self::decode(PAL_HUE),
+        }
+    }
+
+    // YUV decoded from a cycle of the signal of each index
+    fn decode(hue: f32) -> Self {
+        let mut colors = [[0; 3]; PALETTE_COLORS];
+        for (index, c) in colors.iter_mut().enumerate() {
+            let (mut y, mut u, mut v) = (0.0, 0.0, 0.0);
+            for (p, level) in ntsc::levels(index as u8).iter().enumerate() {
+                let angle = std::f32::consts::PI * (p as f32 + hue) / 6.0;
+                y += level;
+                u += level * angle.sin();
+                v += level * angle.cos();
+            }
+            let phases = ntsc::PHASES as f32;
+            let y = y / phases;
+            let u = u * 2.0 / phases * SATURATION;
+            let v = v * 2.0 / phases * SATURATION;
+            let rgb = [y + 1.140 * v, y - 0.395 * u - 0.581 * v, y + 2.032 * u];
+            for (c, level) in c.iter_mut().zip(rgb.iter()) {
+                *c = (level * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        Self { colors }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path.as_ref()).with_context(|| {
+            format!(
+                "Failed to open palette: {}",
+                path.as_ref().to_str().unwrap_or("unknown")
+            )
+        })?;
+        Self::from_bytes(&data)
+    }
+
+    /// Reads a palette in the `.pal` format.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() != PALETTE_COLORS * 3 {
+            return Err(PaletteError::InvalidSize { size: data.len() }.into());
+        }
+        let mut colors = [[0; 3]; PALETTE_COLORS];
+        for (c, rgb) in colors.iter_mut().zip(data.chunks_exact(3)) {
+            c.copy_from_slice(rgb);
+        }
+        Ok(Self { colors })
+    }
+
+    /// The palette in the `.pal` format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+
+    pub fn rgb(&self, index: u8) -> [u8; 3] {
+        self.colors[(index & 0x3F) as usize]
+    }
+
+    /// The color of `index` tinted by `emphasis`, darkening the other channels of each
+    /// emphasized one.
+    ///
+    /// This approximates the attenuation of the video signal, so palettes with measured colors of
+    /// every emphasis would be closer to the hardware.
+    pub fn rgb_with_emphasis(&self, index: u8, emphasis: Emphasis) -> [u8; 3] {
+        let mut rgb = self.rgb(index);
+        if emphasis.bits() == 0 {
+            return rgb;
+        }
+        let channels = [Emphasis::RED, Emphasis::GREEN, Emphasis::BLUE];
+        for (c, channel) in rgb.iter_mut().zip(channels.iter()) {
+            for other in channels.iter().filter(|e| *e != channel) {
+                if emphasis.is_set(*other) {
+                    *c = (*c as f32 * EMPHASIS_ATTENUATION).round() as u8;
+                }
+            }
+        }
+        rgb
+    }
+
+    pub fn set_rgb(&mut self, index: u8, rgb: [u8; 3]) {
+        self.colors[(index & 0x3F) as usize] = rgb;
+    }
+}
+
+#[derive(Debug, Error)]
+enum PaletteError {
+    #[error("Palette must be 192 bytes, but {size} bytes")]
+    InvalidSize { size: usize },
+}
+
+const NTSC_COLORS: [u32; 64] = [
+    0x666666, 0x002A88, 0x1412A7, 0x3B00A4, 0x5C007E, 0x6E0040, 0x6C0600, 0x561D00, 0x333500,
+    0x0B4800, 0x005200, 0x004F08, 0x00404D, 0x000000, 0x000000, 0x000000, 0xADADAD, 0x155FD9,
+    0x4240FF, 0x7527FE, 0xA01ACC, 0xB71E7B, 0xB53120, 0x994E00, 0x6B6D00, 0x388700, 0x0C9300,
+    0x008F32, 0x007C8D, 0x000000, 0x000000, 0x000000, 0xFFFEFF, 0x64B0FF, 0x9290FF, 0xC676FF,
+    0xF36AFF, 0xFE6ECC, 0xFE8170, 0xEA9E22, 0xBCBE00, 0x88D800, 0x5CE430, 0x45E082, 0x48CDDE,
+    0x4F4F4F, 0x000000, 0x000000, 0xFFFEFF, 0xC0DFFF, 0xD3D2FF, 0xE8C8FF, 0xFBC2FF, 0xFEC4EA,
+    0xFECCC5, 0xF7D8A5, 0xE4E594, 0xCFEF96, 0xBDF4AB, 0xB3F3CC, 0xB5EBF2, 0xB8B8B8, 0x000000,
+    0x000000,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pal() {
+        let default = Palette::default();
+        assert_eq!(default.rgb(0x30), [0xFF, 0xFE, 0xFF]);
+        assert_eq!(default.rgb(0x70), default.rgb(0x30));
+
+        let mut data = default.to_bytes();
+        assert_eq!(data.len(), 192);
+        data[3..6].copy_from_slice(&[1, 2, 3]);
+        let p = Palette::from_bytes(&data).unwrap();
+        assert_eq!(p.rgb(0x01), [1, 2, 3]);
+        assert_eq!(p.rgb(0x00), default.rgb(0x00));
+
+        assert!(Palette::from_bytes(&data[..191]).is_err());
+
+        assert_eq!(Palette::for_region(Region::NTSC), default);
+        // within a few levels of the standard palette with the NTSC hue
+        let decoded = Palette::decode(NTSC_HUE);
+        for (a, b) in decoded.to_bytes().iter().zip(default.to_bytes().iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 16, "{} {}", a, b);
+        }
+        let pal = Palette::for_region(Region::PAL);
+        assert_ne!(pal, default);
+        assert_eq!(pal.rgb(0x0F), [0, 0, 0]);
+        assert_eq!(pal.rgb(0x20), [0xFF, 0xFF, 0xFF]);
+        // red stays red
+        let [r, g, b] = pal.rgb(0x16);
+        assert!(g < r && b < r);
+
+        let white = default.rgb_with_emphasis(0x30, Emphasis::RED);
+        assert_eq!(white, [0xFF, 0xCF, 0xD0]);
+        let white = default.rgb_with_emphasis(0x30, Emphasis::from_bits(0b111));
+        assert_eq!(white, [0xAA, 0xA9, 0xAA]);
+        assert_eq!(
+            default.rgb_with_emphasis(0x30, Emphasis::default()),
+            [0xFF, 0xFE, 0xFF]
+        );
+    }
+}
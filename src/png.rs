@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+
+use crate::rom::hash::crc32;
+
+// Minimal PNG encoder for RGBA images, with uncompressed deflate blocks.
+// https://www.w3.org/TR/png/
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// the maximum length of a stored deflate block
+const BLOCK_SIZE: usize = 0xFFFF;
+
+pub(crate) fn write_rgba<W: Write>(
+    mut w: W,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+) -> io::Result<()> {
+    w.write_all(&SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, no filter, no interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(&mut w, b"IHDR", &ihdr)?;
+
+    // each line starts with the filter type 0 (none)
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for line in pixels.chunks((width * 4).max(1) as usize) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+    write_chunk(&mut w, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(&mut w, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    w.write_all(&body)?;
+    w.write_all(&crc32(&body).to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for d in data {
+        a = (a + *d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let mut png = Vec::new();
+        write_rgba(&mut png, 1, 1, &[0xFF, 0, 0, 0xFF]).unwrap();
+        assert_eq!(&png[..8], &SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // the CRC of IEND is always the same
+        assert_eq!(&png[png.len() - 4..], &[0xAE, 0x42, 0x60, 0x82]);
+
+        let data = vec![7; BLOCK_SIZE + 1];
+        let z = zlib_stored(&data);
+        assert_eq!(z.len(), 2 + (5 + BLOCK_SIZE) + (5 + 1) + 4);
+        assert_eq!(z[2], 0);
+        assert_eq!(z[2 + 5 + BLOCK_SIZE], 1);
+    }
+}
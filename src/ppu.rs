@@ -4,21 +4,47 @@ mod sprite;
 mod vram_address;
 
 use crate::interrupt::Interrupt;
+use crate::lint::HardwareLint;
+use crate::region::Region;
 use crate::types::{Byte, Memory, Word};
 
 use background::{ATTRIBUTE_TABLE_FIRST, NAME_TABLE_FIRST, TILE_HEIGHT};
+pub use register::Emphasis;
 use register::{Controller, Mask, Register, Status};
 use sprite::{Sprite, SpriteAttribute, OAM_SIZE, SPRITE_COUNT, SPRITE_LIMIT};
 use vram_address::VRAMAddress;
 
 const MAX_DOT: u16 = 340;
-const MAX_LINE: u16 = 261;
+// the pre-render line, the last of a frame on both regions
+pub(crate) const MAX_LINE: u16 = 261;
 
-const WIDTH: u16 = 256;
+/// Size of a frame in pixels.
+pub const WIDTH: u16 = 256;
+pub const HEIGHT: u16 = 240;
 
+/// Size of the image of `PPU::debug_nametables`, 2x2 nametables.
+pub const NAMETABLES_WIDTH: u32 = WIDTH as u32 * 2;
+pub const NAMETABLES_HEIGHT: u32 = HEIGHT as u32 * 2;
+
+/// Layers of the PPU output.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Layer {
+    Background,
+    Sprites,
+}
+
+impl Layer {
+    pub const ALL: [Self; 2] = [Self::Background, Self::Sprites];
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// The registers, OAM and rendering pipeline of the PPU. The VRAM bus is passed to each access as
+/// to the CPU, generic so that its accesses are inlined into the rendering.
 pub struct PPU {
     reg: Register,
-    bus: Box<dyn Memory>,
 
     // Background registers
     name_table_entry: Byte,
@@ -32,21 +58,45 @@ pub struct PPU {
     // Sprite OAM
     primary_oam: [u8; OAM_SIZE],
     secondary_oam: [u8; 32],
-    sprites: [Sprite; SPRITE_LIMIT],
+    // beyond SPRITE_LIMIT only without the sprite limit
+    sprites: [Sprite; SPRITE_COUNT],
+    // pattern low/high bytes of the row fetched for each sprite
+    sprite_patterns: [(u8, u8); SPRITE_COUNT],
+    // the number of sprites fetched for the current line
+    sprite_count: usize,
     sprite_zero_on_line: bool,
+    // sprite zero is in the first slot of the sprites fetched for the current line
+    sprite_zero_in_slots: bool,
+    evaluation: sprite::Evaluation,
 
     // http://wiki.nesdev.com/w/index.php/PPU_registers#Ports
     internal_data_bus: u8,
 
     pub frames: u64,
     scan: Scan,
+
+    // palette indices of each pixel
+    frame_buffer: Vec<u8>,
+    // emphasis bits of each pixel
+    emphasis_buffer: Vec<u8>,
+
+    region: Region,
+    scanline_hook: Option<ScanlineHook>,
+    // emphasis in effect at the end of the last rendered frame
+    frame_emphasis: Emphasis,
+    palette_write_through: bool,
+    // bit flags of Layer, only affects the output
+    enabled_layers: u8,
+    sprite_limit_enabled: bool,
+    lints: Vec<HardwareLint>,
+    // scroll position in the 512x480 nametable space at the start of each visible line
+    line_scrolls: Vec<(u16, u16)>,
 }
 
 impl PPU {
-    pub fn new(ppu_bus: Box<dyn Memory>) -> Self {
+    pub fn new() -> Self {
         Self {
             reg: Default::default(),
-            bus: ppu_bus,
             name_table_entry: Default::default(),
             attr_table_entry: Default::default(),
             bg_temp_addr: Default::default(),
@@ -55,14 +105,155 @@ impl PPU {
 
             primary_oam: [0; OAM_SIZE],
             secondary_oam: [0; 32],
-            sprites: [Default::default(); SPRITE_LIMIT],
+            sprites: [Default::default(); SPRITE_COUNT],
+            sprite_patterns: [(0, 0); SPRITE_COUNT],
+            sprite_count: 0,
             sprite_zero_on_line: false,
+            sprite_zero_in_slots: false,
+            evaluation: Default::default(),
             internal_data_bus: 0,
             frames: 0,
             scan: Default::default(),
+            frame_buffer: vec![0; WIDTH as usize * HEIGHT as usize],
+            emphasis_buffer: vec![0; WIDTH as usize * HEIGHT as usize],
+            region: Default::default(),
+            scanline_hook: None,
+            frame_emphasis: Default::default(),
+            palette_write_through: false,
+            enabled_layers: 0xFF,
+            sprite_limit_enabled: true,
+            lints: Vec::new(),
+            line_scrolls: vec![(0, 0); HEIGHT as usize],
         }
     }
 
+    /// Builds a PPU in the middle of a frame, to test rendering without stepping through the dots.
+    #[cfg(test)]
+    pub(crate) fn with_state(state: PPUState) -> Self {
+        let mut ppu = Self::new();
+        ppu.scan = Scan {
+            line: state.line,
+            dot: state.dot,
+        };
+        ppu.reg.write_controller(state.controller);
+        ppu.reg.v = state.v.into();
+        ppu.reg.set_temp_vram_address(state.t.into());
+        ppu.reg.fine_x = state.fine_x.into();
+        ppu.reg.mask = Mask::new(state.mask);
+        ppu.tile = background::Tile::new(
+            state.pattern_low,
+            state.pattern_high,
+            state.attr_low,
+            state.attr_high,
+        );
+        ppu.frames = state.frames;
+        ppu
+    }
+
+    pub fn snapshot<M: Memory>(&self, bus: &M) -> PPUSnapshot {
+        let mut s = PPUSnapshot {
+            open_bus: self.internal_data_bus,
+            line: self.scan.line,
+            dot: self.scan.dot,
+            frames: self.frames,
+            oam: self.primary_oam.to_vec(),
+            name_tables: (0x2000..0x3000u16)
+                .map(|a| bus.peek(a.into()).into())
+                .collect(),
+            palette: (0x3F00..0x3F20u16)
+                .map(|a| bus.peek(a.into()).into())
+                .collect(),
+            ..Default::default()
+        };
+        self.reg.snapshot(&mut s);
+        s
+    }
+
+    /// Restores the state between frames or lines; the rendering pipeline starts empty.
+    pub fn restore<M: Memory>(&mut self, s: &PPUSnapshot, bus: &mut M) {
+        self.reg.restore(s);
+        self.internal_data_bus = s.open_bus;
+        self.scan = Scan {
+            line: s.line,
+            dot: s.dot,
+        };
+        self.frames = s.frames;
+        for (i, b) in s.oam.iter().take(OAM_SIZE).enumerate() {
+            self.primary_oam[i] = *b;
+        }
+        for (a, b) in (0x2000..0x3000u16).zip(s.name_tables.iter()) {
+            bus.write(a.into(), (*b).into());
+        }
+        for (a, b) in (0x3F00..0x3F20u16).zip(s.palette.iter()) {
+            bus.write(a.into(), (*b).into());
+        }
+    }
+
+    /// Emulates the artifacts of accessing VRAM while rendering, which some demos exploit:
+    /// the backdrop shows the palette entry at the VRAM address during forced blank,
+    /// and $2007 writes during rendering increment the scroll counters.
+    // takes the region, the scanline hook and the options of the output from the PPU of the
+    // cartridge taken out
+    pub fn inherit_settings(&mut self, other: &mut PPU) {
+        self.region = other.region;
+        self.scanline_hook = other.scanline_hook.take();
+        self.palette_write_through = other.palette_write_through;
+        self.enabled_layers = other.enabled_layers;
+        self.sprite_limit_enabled = other.sprite_limit_enabled;
+    }
+
+    pub fn set_palette_write_through(&mut self, enabled: bool) {
+        self.palette_write_through = enabled;
+    }
+
+    pub fn palette_write_through(&self) -> bool {
+        self.palette_write_through
+    }
+
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        if enabled {
+            self.enabled_layers |= layer.bit();
+        } else {
+            self.enabled_layers &= !layer.bit();
+        }
+    }
+
+    pub fn layer_enabled(&self, layer: Layer) -> bool {
+        self.enabled_layers & layer.bit() != 0
+    }
+
+    pub fn set_sprite_limit_enabled(&mut self, enabled: bool) {
+        self.sprite_limit_enabled = enabled;
+    }
+
+    pub fn sprite_limit_enabled(&self) -> bool {
+        self.sprite_limit_enabled
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    pub fn set_scanline_hook(&mut self, hook: Option<ScanlineHook>) {
+        self.scanline_hook = hook;
+    }
+
+    pub fn take_lints(&mut self) -> Vec<HardwareLint> {
+        std::mem::take(&mut self.lints)
+    }
+
+    pub fn set_lints(&mut self, lints: Vec<HardwareLint>) {
+        self.lints = lints;
+    }
+
+    pub fn take_scanline_hook(&mut self) -> Option<ScanlineHook> {
+        self.scanline_hook.take()
+    }
+
+    pub fn frame_emphasis(&self) -> Emphasis {
+        self.frame_emphasis
+    }
+
     pub fn reset(&mut self) {
         self.reg.reset();
         self.scan.clear();
@@ -73,29 +264,151 @@ impl PPU {
         self.scan.line
     }
 
-    pub fn step(&mut self) -> Option<Interrupt> {
+    // frame, line and dot
+    pub fn position(&self) -> (u64, u16, u16) {
+        (self.frames, self.scan.line, self.scan.dot)
+    }
+
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    pub fn emphasis_buffer(&self) -> &[u8] {
+        &self.emphasis_buffer
+    }
+
+    /// Decodes the 4 nametables at $2000-$2FFF into palette indices of a 512x480 image, laid out
+    /// as `NAMETABLES_WIDTH` x `NAMETABLES_HEIGHT` in the order of the scroll space.
+    ///
+    /// The mirroring of the cartridge applies, and the tiles are drawn from the background pattern
+    /// table selected by PPUCTRL.
+    pub fn debug_nametables<M: Memory>(&self, bus: &M) -> Vec<u8> {
+        let width = NAMETABLES_WIDTH as usize;
+        let mut pixels = vec![0; width * NAMETABLES_HEIGHT as usize];
+        let pattern_base: u16 = self.reg.background_pattern_table_addr_base().into();
+        let name_table_first: u16 = NAME_TABLE_FIRST.into();
+        let attribute_table_first: u16 = ATTRIBUTE_TABLE_FIRST.into();
+        let backdrop = peek_vram(bus, 0x3F00) & 0x3F;
+
+        for n in 0..4u16 {
+            let base = name_table_first + n * 0x400;
+            let attributes = attribute_table_first + n * 0x400;
+            let left = (n % 2) as usize * WIDTH as usize;
+            let top = (n / 2) as usize * HEIGHT as usize;
+            for ty in 0..30u16 {
+                for tx in 0..32u16 {
+                    let tile = peek_vram(bus, base + ty * 32 + tx) as u16;
+                    let attr = peek_vram(bus, attributes + (ty / 4) * 8 + tx / 4);
+                    let shift = ((ty % 4) / 2) * 4 + ((tx % 4) / 2) * 2;
+                    let palette = ((attr >> shift) & 3) as u16;
+                    for row in 0..8u16 {
+                        let addr = pattern_base + tile * 16 + row;
+                        let (low, high) = (peek_vram(bus, addr), peek_vram(bus, addr + 8));
+                        for col in 0..8u16 {
+                            let bit = 7 - col;
+                            let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                            let index = if color == 0 {
+                                backdrop
+                            } else {
+                                peek_vram(bus, 0x3F00 + palette * 4 + color as u16) & 0x3F
+                            };
+                            let x = left + (tx * 8 + col) as usize;
+                            let y = top + (ty * 8 + row) as usize;
+                            pixels[y * width + x] = index;
+                        }
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    /// Parses the 64 sprites in primary OAM.
+    pub fn debug_oam(&self) -> Vec<OAMEntry> {
+        (0..SPRITE_COUNT)
+            .map(|i| {
+                let b = &self.primary_oam[i * 4..i * 4 + 4];
+                let attr = SpriteAttribute::from(b[2]);
+                OAMEntry {
+                    index: i as u8,
+                    y: b[0],
+                    tile: b[1],
+                    palette: attr.pallete(),
+                    behind_background: attr.is_set(SpriteAttribute::BEHIND_BACKGROUND),
+                    flip_horizontally: attr.is_set(SpriteAttribute::FLIP_HORIZONTALLY),
+                    flip_vertically: attr.is_set(SpriteAttribute::FLIP_VERTICALLY),
+                    x: b[3],
+                }
+            })
+            .collect()
+    }
+
+    /// Decodes the pattern of `entry` with its flips into 2-bit colors of 8 x `sprite_height`
+    /// pixels, as the sprite size in PPUCTRL.
+    pub fn debug_sprite<M: Memory>(&self, entry: &OAMEntry, bus: &M) -> Vec<u8> {
+        let height = self.reg.sprite_size() as u16;
+        let tile = entry.tile as u16;
+        let (base, tile) = if self.reg.controller.sprite_8x16_pixels() {
+            ((tile & 1) * 0x1000, tile & 0xFE)
+        } else {
+            (self.reg.controller.base_sprite_table_addr(), tile)
+        };
+        let mut pixels = vec![0; 8 * height as usize];
+        for row in 0..height {
+            let addr = base + (tile + row / 8) * 16 + row % 8;
+            let (low, high) = (peek_vram(bus, addr), peek_vram(bus, addr + 8));
+            let y = if entry.flip_vertically {
+                height - 1 - row
+            } else {
+                row
+            };
+            for col in 0..8u16 {
+                let bit = 7 - col;
+                let x = if entry.flip_horizontally {
+                    7 - col
+                } else {
+                    col
+                };
+                pixels[(y * 8 + x) as usize] = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+            }
+        }
+        pixels
+    }
+
+    pub fn partial_frame(&self) -> PartialFrame {
+        PartialFrame {
+            pixels: self.frame_buffer.clone(),
+            line: self.scan.line,
+            dot: self.scan.dot,
+        }
+    }
+
+    pub fn step<M: Memory>(&mut self, bus: &mut M) -> Option<Interrupt> {
         let mut interrupt = None;
 
-        match (self.scan.line, self.scan.line == 261) {
-            (0..=239, pre_rendered) => {
+        match (self.scan.line, self.scan.line == MAX_LINE) {
+            (0..=239, pre_rendered) | (MAX_LINE, pre_rendered) => {
                 // Visible or Pre Render
                 let x = self.scan.dot.wrapping_sub(2);
 
-                let bg = self.get_background_pixel(x);
-                let sprite = self.get_sprite_pixel(x as i32, bg);
+                let bg = self.get_background_pixel(x, bus);
+                let sprite = self.get_sprite_pixel(x as i32, bg, bus);
 
                 if self.reg.rendering_enabled() {
-                    self.fetch_background_pixel();
-                    self.fetch_sprite_pixel();
+                    self.fetch_background_pixel(bus);
+                    self.fetch_sprite_pixel(bus);
                 }
 
-                if self.scan.line < MAX_LINE && x < WIDTH {
-                    let _pixel = if self.reg.rendering_enabled() {
-                        self.select_pixel(bg, sprite)
+                if self.scan.line < HEIGHT && x < WIDTH {
+                    let pixel = if self.reg.rendering_enabled() {
+                        let (bg, sprite) = self.visible_pixels(bg, sprite);
+                        self.select_pixel(bg, sprite, bus)
                     } else {
-                        0
+                        bus.read(self.forced_blank_color_address()).into()
                     };
-                    // TODO Render pixel
+                    let i = self.scan.line as usize * WIDTH as usize + x as usize;
+                    self.frame_buffer[i] = pixel as u8 & 0x3F;
+                    self.emphasis_buffer[i] = self.reg.mask.emphasis(self.region).bits();
                 }
 
                 if pre_rendered {
@@ -104,15 +417,16 @@ impl PPU {
                             Status::VBLANK | Status::SPRITE_ZERO_HIT | Status::SPRITE_OVERFLOW,
                         )
                     }
-                    if self.scan.dot == 341 && self.reg.rendering_enabled() && self.frames % 2 != 0
+                    if self.scan.dot == 339 && self.reg.rendering_enabled() && self.frames % 2 != 0
                     {
                         // Skip 0 cycle on visible frame
                         self.scan.skip();
                     }
                 }
             }
-            (240, _) => {
+            (240, _) if self.scan.dot == 0 => {
                 // Post Render
+                self.frame_emphasis = self.reg.mask.emphasis(self.region);
             }
             (241, _) => {
                 // Begin VBLANK
@@ -126,16 +440,80 @@ impl PPU {
             _ => {}
         }
 
-        if let ScanUpdate::Frame = self.scan.next_dot() {
-            self.frames += 1;
+        match self.scan.next_dot() {
+            ScanUpdate::Dot => {}
+            ScanUpdate::Line => self.notify_scanline(),
+            ScanUpdate::Frame => {
+                self.frames += 1;
+                self.notify_scanline();
+            }
         }
 
         interrupt
     }
 
-    fn select_pixel(&self, bg: background::Pixel, sprite: sprite::Pixel) -> u16 {
+    fn notify_scanline(&mut self) {
+        let event = ScanlineEvent {
+            line: self.scan.line,
+            frame: self.frames,
+            v: self.reg.v.into(),
+            t: self.reg.temp_vram_address().into(),
+            fine_x: self.reg.fine_x.into(),
+            controller: self.reg.controller.bits(),
+            mask: self.reg.mask.bits(),
+        };
+        if let Some(scroll) = self.line_scrolls.get_mut(event.line as usize) {
+            *scroll = (event.world_x(), event.world_y());
+        }
+        if let Some(hook) = self.scanline_hook.as_mut() {
+            hook(&event);
+        }
+    }
+
+    /// Scroll position of the visible line `line` in the last frame, in the 512x480 space of
+    /// the four nametables.
+    pub fn line_scroll(&self, line: u16) -> (u16, u16) {
+        self.line_scrolls[line.min(HEIGHT - 1) as usize]
+    }
+
+    // The palette entry at v is output instead of the backdrop color while v points to the palette
+    // https://wiki.nesdev.com/w/index.php/PPU_palettes#The_background_palette_hack
+    fn forced_blank_color_address(&self) -> Word {
+        let v: u16 = self.reg.v.into();
+        if self.palette_write_through && 0x3F00 <= v {
+            self.reg.v.into()
+        } else {
+            0x3F00u16.into()
+        }
+    }
+
+    // hides the disabled layers after the sprite zero hit, which sees both
+    fn visible_pixels(
+        &self,
+        bg: background::Pixel,
+        sprite: sprite::Pixel,
+    ) -> (background::Pixel, sprite::Pixel) {
+        let bg = if self.layer_enabled(Layer::Background) {
+            bg
+        } else {
+            background::Pixel::ZERO
+        };
+        let sprite = if self.layer_enabled(Layer::Sprites) {
+            sprite
+        } else {
+            sprite::Pixel::ZERO
+        };
+        (bg, sprite)
+    }
+
+    fn select_pixel<M: Memory>(
+        &self,
+        bg: background::Pixel,
+        sprite: sprite::Pixel,
+        bus: &M,
+    ) -> u16 {
         match (bg.enabled, sprite.enabled) {
-            (false, false) => self.bus.read(0x3F00u16.into()).into(),
+            (false, false) => bus.peek(0x3F00u16.into()).into(),
             (false, true) => sprite.color,
             (true, false) => bg.color,
             (true, true) => {
@@ -149,9 +527,47 @@ impl PPU {
     }
 }
 
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Reads the PPU address space without notifying the mapper.
+fn peek_vram<M: Memory>(bus: &M, addr: u16) -> u8 {
+    bus.peek(addr.into()).into()
+}
+
 // background
 impl PPU {
-    fn fetch_background_pixel(&mut self) {
+    /// Renders a whole frame from `snapshot` with the pattern tables `chr` ($0000-$1FFF),
+    /// as if the registers were left unchanged through the frame. Returns the palette indices
+    /// and the emphasis bits of the pixels, as `PPU::frame_buffer` and `PPU::emphasis_buffer`.
+    pub fn render_snapshot(
+        chr: &[u8],
+        snapshot: &PPUSnapshot,
+        region: Region,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut bus = Box::new([0; 0x10000]);
+        bus[..chr.len()].copy_from_slice(chr);
+        let mut ppu = Self::new();
+        ppu.set_region(region);
+        ppu.restore(snapshot, &mut bus);
+        // from the pre-render line, so that the scroll is reloaded from t
+        ppu.scan = Scan {
+            line: MAX_LINE,
+            dot: 0,
+        };
+        let frames = ppu.frames;
+        while ppu.frames == frames || ppu.scan.line < HEIGHT {
+            ppu.step(&mut bus);
+        }
+        (ppu.frame_buffer, ppu.emphasis_buffer)
+    }
+}
+
+impl PPU {
+    fn fetch_background_pixel<M: Memory>(&mut self, bus: &mut M) {
         match self.scan.dot {
             321 => {
                 // No reload shift
@@ -167,7 +583,7 @@ impl PPU {
                 }
                 2 => {
                     // Fetch nametable byte : step 2
-                    self.name_table_entry = self.bus.read(self.bg_temp_addr.into());
+                    self.name_table_entry = bus.read(self.bg_temp_addr.into());
                 }
                 3 => {
                     // Fetch attribute table byte : step 1
@@ -177,7 +593,7 @@ impl PPU {
                 }
                 4 => {
                     // Fetch attribute table byte : step 2
-                    self.attr_table_entry = self.bus.read(self.bg_temp_addr.into());
+                    self.attr_table_entry = bus.read(self.bg_temp_addr.into());
                     // select area
                     if self.reg.v.coarse_x_scroll().nth(1) == 1 {
                         self.attr_table_entry >>= 2
@@ -194,12 +610,12 @@ impl PPU {
                         0x0000u16
                     }
                     .into();
-                    let index = self.name_table_entry * TILE_HEIGHT * 2;
+                    let index = Word::from(self.name_table_entry) * TILE_HEIGHT as u16 * 2;
                     self.bg_temp_addr = (base + index + self.reg.v.fine_y_scroll()).into();
                 }
                 6 => {
                     // Fetch tile bitmap low byte : step 2
-                    self.next_pattern.low = self.bus.read(self.bg_temp_addr.into()).into();
+                    self.next_pattern.low = bus.read(self.bg_temp_addr.into()).into();
                 }
                 7 => {
                     // Fetch tile bitmap high byte : step 1
@@ -207,7 +623,7 @@ impl PPU {
                 }
                 0 => {
                     // Fetch tile bitmap high byte : step 2
-                    self.next_pattern.high = self.bus.read(self.bg_temp_addr.into()).into();
+                    self.next_pattern.high = bus.read(self.bg_temp_addr.into()).into();
                     if self.reg.rendering_enabled() {
                         self.reg.incr_coarse_x();
                     }
@@ -215,7 +631,7 @@ impl PPU {
                 _ => {}
             },
             256 => {
-                self.next_pattern.high = self.bus.read(self.bg_temp_addr.into()).into();
+                self.next_pattern.high = bus.read(self.bg_temp_addr.into()).into();
                 if self.reg.rendering_enabled() {
                     self.reg.incr_y();
                 }
@@ -237,13 +653,13 @@ impl PPU {
                     (NAME_TABLE_FIRST | self.reg.v.name_table_address_index().into()).into();
             }
             338 | 340 => {
-                self.name_table_entry = self.bus.read(self.bg_temp_addr.into());
+                self.name_table_entry = bus.read(self.bg_temp_addr.into());
             }
             _ => {}
         }
     }
 
-    fn get_background_pixel(&mut self, x: u16) -> background::Pixel {
+    fn get_background_pixel<M: Memory>(&mut self, x: u16, bus: &mut M) -> background::Pixel {
         let (pixel, pallete) = self.tile.pixel_pallete(self.reg.fine_x.into());
 
         if (1 <= self.scan.dot && self.scan.dot <= 256)
@@ -255,7 +671,7 @@ impl PPU {
         if self.reg.is_enabled_background(x) {
             background::Pixel {
                 enabled: <Word as Into<u16>>::into(pixel) != 0,
-                color: self.bus.read(pallete * 4 + pixel + 0x3F00).into(),
+                color: bus.read(pallete * 4 + pixel + 0x3F00).into(),
             }
         } else {
             background::Pixel::ZERO
@@ -265,92 +681,215 @@ impl PPU {
 
 // sprite
 impl PPU {
-    fn fetch_sprite_pixel(&mut self) {
+    fn fetch_sprite_pixel<M: Memory>(&mut self, bus: &mut M) {
         match self.scan.dot {
-            //TODO more cycle accumelated
-            0 => {
-                for e in self.secondary_oam.iter_mut() {
-                    *e = 0;
+            // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+            // Secondary OAM clear
+            1..=64 if self.scan.dot.is_multiple_of(2) => {
+                self.secondary_oam[(self.scan.dot as usize - 1) / 2] = 0xFF;
+            }
+            65..=256 => {
+                // Sprite evaluation
+                if self.scan.line == MAX_LINE {
+                    return;
                 }
-                self.sprite_zero_on_line = false;
-                // the sprite evaluation phase
-                let sprite_size = if self.reg.controller.is_set(Controller::SPRITE_SIZE) {
-                    16
-                } else {
-                    8
-                };
-
-                let mut iter = self.secondary_oam.iter_mut();
-
-                let mut n = 0;
-                for i in 0..SPRITE_COUNT {
-                    let first = i * 4;
-                    let y = self.primary_oam[first];
-
-                    if let Some(p) = iter.next() {
-                        let row = self.scan.line.wrapping_sub(self.primary_oam[first] as u16);
-                        if row < sprite_size {
-                            if n == 0 {
-                                self.sprite_zero_on_line = true;
-                            }
-                            *p = y;
-                            *iter.next().unwrap() = self.primary_oam[first + 1];
-                            *iter.next().unwrap() = self.primary_oam[first + 2];
-                            *iter.next().unwrap() = self.primary_oam[first + 3];
-                            n += 1;
-                        }
-                    }
+                if self.scan.dot == 65 {
+                    self.evaluation = Default::default();
+                    self.sprite_zero_on_line = false;
                 }
-                if SPRITE_LIMIT <= n && self.reg.rendering_enabled() {
-                    self.reg.status.set(Status::SPRITE_OVERFLOW);
+                if self.scan.dot % 2 == 1 {
+                    // read from primary OAM on odd cycles
+                    self.evaluation.latch = self.primary_oam[self.evaluation.index()];
+                } else {
+                    // write to secondary OAM on even cycles
+                    self.evaluate_sprite();
                 }
             }
             257..=320 => {
                 // the sprite fetch phase
-                let i = (self.scan.dot.wrapping_sub(257)) / 8;
-                let n = i.wrapping_mul(4) as usize;
-                self.sprites[i as usize] = Sprite {
-                    y: self.secondary_oam[n],
-                    tile_index: self.secondary_oam[n + 1],
-                    attr: self.secondary_oam[n + 1].into(),
-                    x: self.secondary_oam[n + 1],
+                let i = (self.scan.dot - 257) as usize / 8;
+                let n = i * 4;
+                let found = i < self.evaluation.count && self.scan.line != MAX_LINE;
+                let sprite = if found {
+                    Sprite {
+                        y: self.secondary_oam[n],
+                        tile_index: self.secondary_oam[n + 1],
+                        attr: self.secondary_oam[n + 2].into(),
+                        x: self.secondary_oam[n + 3],
+                    }
+                } else {
+                    Sprite::EMPTY
                 };
+                self.sprites[i] = sprite;
+                if self.scan.dot == 257 {
+                    self.sprite_zero_in_slots = found && self.sprite_zero_on_line;
+                    self.sprite_count = if self.scan.line == MAX_LINE {
+                        0
+                    } else {
+                        self.evaluation.count
+                    };
+                }
+                self.reg.object_attribute_memory_address = 0;
+
+                // Pattern fetches for the next line; unused slots fetch tile $FF and discard it
+                match (self.scan.dot - 257) % 8 {
+                    5 => {
+                        let addr = self.sprite_pattern_addr(&sprite);
+                        let low = bus.read(addr.into()).into();
+                        self.sprite_patterns[i].0 = if found { low } else { 0 };
+                    }
+                    7 => {
+                        let addr = self.sprite_pattern_addr(&sprite) + 8;
+                        let high = bus.read(addr.into()).into();
+                        self.sprite_patterns[i].1 = if found { high } else { 0 };
+                    }
+                    _ => {}
+                }
+                if self.scan.dot == 320
+                    && !self.sprite_limit_enabled
+                    && self.sprite_count == SPRITE_LIMIT
+                {
+                    self.fetch_extra_sprites(bus);
+                }
             }
             _ => {}
         }
     }
 
-    fn get_sprite_pixel(&mut self, x: i32, bg: background::Pixel) -> sprite::Pixel {
-        if !self.reg.is_enabled_sprite(x) {
-            return sprite::Pixel::ZERO;
+    // Sprites after the 8th on the next line, which the hardware does not fetch. They are read
+    // at once without the side effects of the bus on mappers.
+    fn fetch_extra_sprites<M: Memory>(&mut self, bus: &M) {
+        let sprite_height = self.reg.sprite_size() as u16;
+        let mut found = 0;
+        for n in 0..SPRITE_COUNT {
+            let oam = &self.primary_oam[n * 4..n * 4 + 4];
+            if sprite_height <= self.scan.line.wrapping_sub(oam[0] as u16) {
+                continue;
+            }
+            found += 1;
+            if found <= SPRITE_LIMIT {
+                continue;
+            }
+            let sprite = Sprite {
+                y: oam[0],
+                tile_index: oam[1],
+                attr: oam[2].into(),
+                x: oam[3],
+            };
+            let addr = self.sprite_pattern_addr(&sprite);
+            let low = bus.peek(addr.into()).into();
+            let high = bus.peek((addr + 8).into()).into();
+            self.sprites[self.sprite_count] = sprite;
+            self.sprite_patterns[self.sprite_count] = (low, high);
+            self.sprite_count += 1;
         }
+    }
+
+    fn sprite_pattern_addr(&self, sprite: &Sprite) -> u16 {
+        let sprite_height = self.reg.sprite_size();
+        let mut row = sprite.row(self.scan.line + 1, sprite_height) % sprite_height as u16;
+        let mut tile_idx = sprite.tile_index as u16;
 
-        let y = self.scan.line;
-        for (i, sprite) in self.sprites.iter().enumerate() {
-            if !sprite.valid() {
-                break;
+        let base = if self.reg.controller.sprite_8x16_pixels() {
+            // 8x16 sprites select the pattern table by bit 0 of the tile index
+            let base = (tile_idx & 1) * 0x1000;
+            tile_idx &= 0xFE;
+            if 7 < row {
+                tile_idx += 1;
+                row -= 8;
             }
-            if (sprite.x as i32) < x - 7 && x < sprite.x as i32 {
-                continue;
+            base
+        } else {
+            self.reg.controller.base_sprite_table_addr()
+        };
+        base + tile_idx * 16 + row
+    }
+
+    fn evaluate_sprite(&mut self) {
+        let e = &mut self.evaluation;
+        if e.done {
+            return;
+        }
+
+        let sprite_height = self.reg.sprite_size() as u16;
+        let in_range = self.scan.line.wrapping_sub(e.latch as u16) < sprite_height;
+
+        if SPRITE_LIMIT <= e.count {
+            // Sprite overflow check with the hardware bug; m is incremented along with n
+            if in_range {
+                self.reg.status.set(Status::SPRITE_OVERFLOW);
+                e.done = true;
+            } else {
+                e.m = (e.m + 1) % 4;
+                e.next_sprite();
             }
-            let mut row = sprite.row(y, self.reg.sprite_size());
-            let col = sprite.col(x as u16);
-            let mut tile_idx = sprite.tile_index as u16;
+            return;
+        }
 
-            let base = if self.reg.controller.sprite_8x16_pixels() {
-                tile_idx &= 0xFE;
-                if 7 < row {
-                    tile_idx += 1;
-                    row -= 8;
+        self.secondary_oam[e.count * 4 + e.m] = e.latch;
+        if e.m == 0 {
+            if in_range {
+                if e.n == 0 {
+                    self.sprite_zero_on_line = true;
                 }
-                tile_idx & 1
+                e.m = 1;
             } else {
-                self.reg.controller.base_sprite_table_addr()
-            };
+                e.next_sprite();
+            }
+        } else {
+            e.m += 1;
+            if e.m == 4 {
+                e.m = 0;
+                e.count += 1;
+                e.next_sprite();
+            }
+        }
+    }
 
-            let tile_addr = base + tile_idx * 16 + row;
-            let low = self.bus.read(tile_addr.into());
-            let high = self.bus.read((tile_addr + 8).into());
+    // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    fn read_oam_data(&self) -> u8 {
+        let rendering =
+            self.reg.rendering_enabled() && (self.scan.line < 240 || self.scan.line == MAX_LINE);
+        if !rendering {
+            return self.primary_oam[self.reg.object_attribute_memory_address];
+        }
+        match self.scan.dot {
+            // Secondary OAM is being cleared, which reads as $FF
+            1..=64 => 0xFF,
+            // The value being read from primary OAM for evaluation
+            65..=256 if self.scan.line != MAX_LINE => self.evaluation.latch,
+            65..=256 => self.primary_oam[self.reg.object_attribute_memory_address],
+            // Y, tile, attribute, and X of each sprite, then X is read 4 more times
+            257..=320 => {
+                let dot = (self.scan.dot - 257) as usize;
+                let i = dot / 8;
+                let m = std::cmp::min(dot % 8, 3);
+                self.secondary_oam[i * 4 + m]
+            }
+            // The first byte of secondary OAM
+            _ => self.secondary_oam[0],
+        }
+    }
+
+    fn get_sprite_pixel<M: Memory>(
+        &mut self,
+        x: i32,
+        bg: background::Pixel,
+        bus: &mut M,
+    ) -> sprite::Pixel {
+        if !self.reg.is_enabled_sprite(x) {
+            return sprite::Pixel::ZERO;
+        }
+
+        // The first opaque pixel in OAM order wins, even if it is behind the background
+        for (i, sprite) in self.sprites[..self.sprite_count].iter().enumerate() {
+            if x < sprite.x as i32 || (sprite.x as i32) + 7 < x {
+                continue;
+            }
+            let col = sprite.col(x as u16);
+            let (low, high) = self.sprite_patterns[i];
+            let low = Byte::from(low);
+            let high = Byte::from(high);
 
             let pixel = low.nth(col) + (high.nth(col) << 1);
             if pixel == 0 {
@@ -359,7 +898,7 @@ impl PPU {
             }
 
             if i == 0
-                && self.sprite_zero_on_line
+                && self.sprite_zero_in_slots
                 && !self.reg.status.is_set(Status::SPRITE_ZERO_HIT)
                 && sprite.x != 0xFF
                 && x < 0xFF
@@ -371,7 +910,7 @@ impl PPU {
             let addr = 0x3F10 + sprite.attr.pallete() as u16 * 4 + pixel as u16;
             return sprite::Pixel {
                 enabled: pixel != 0,
-                color: self.bus.read(addr.into()).into(),
+                color: bus.read(addr.into()).into(),
                 behide_background: sprite.attr.is_set(SpriteAttribute::BEHIND_BACKGROUND),
             };
         }
@@ -382,7 +921,11 @@ impl PPU {
 
 // register access from CPU
 impl PPU {
-    pub fn read_register(&mut self, addr: u16) -> Byte {
+    fn rendering_line(&self) -> bool {
+        self.reg.rendering_enabled() && (self.scan.line < HEIGHT || self.scan.line == MAX_LINE)
+    }
+
+    pub fn read_register<M: Memory>(&mut self, addr: u16, bus: &mut M) -> Byte {
         let result = match addr {
             0x2002 => {
                 let result = self.reg.read_status() | (self.internal_data_bus & 0b11111);
@@ -392,38 +935,40 @@ impl PPU {
                     result
                 }
             }
-            0x2004 => {
-                // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
-                if self.scan.line < 240 && 1 <= self.scan.dot && self.scan.dot <= 64 {
-                    // during sprite evaluation
-                    0xFF
-                } else {
-                    self.primary_oam[self.reg.object_attribute_memory_address]
-                }
-                .into()
-            }
+            0x2004 => self.read_oam_data().into(),
             0x2007 => {
                 let v: u16 = self.reg.v.into();
                 let result = if v <= 0x3EFFu16 {
                     let data = self.reg.data;
-                    self.reg.data = self.bus.read(self.reg.v.into());
+                    self.reg.data = bus.read(self.reg.v.into());
                     data
                 } else {
-                    self.bus.read(self.reg.v.into())
+                    bus.read(self.reg.v.into())
                 };
                 self.reg.incr_v();
                 result
             }
-            _ => 0x00.into(),
+            // write-only registers
+            _ => self.internal_data_bus.into(),
         };
 
         self.internal_data_bus = result.into();
         result
     }
 
-    pub fn write_register(&mut self, addr: u16, value: Byte) {
+    pub fn write_register<M: Memory>(&mut self, addr: u16, value: Byte, bus: &mut M) {
+        self.internal_data_bus = value.into();
         match addr {
-            0x2000 => self.reg.write_controller(value),
+            0x2000 => {
+                // The slave mode has no effect without another PPU, so it is only reported.
+                if !self.reg.controller.is_set(Controller::SLAVE)
+                    && u8::from(value) & Controller::SLAVE.bits() != 0
+                {
+                    self.lints
+                        .push(HardwareLint::PPUSlaveMode { frame: self.frames });
+                }
+                self.reg.write_controller(value)
+            }
             0x2001 => self.reg.mask = Mask::new(value),
             0x2003 => {
                 let addr: u16 = value.into();
@@ -437,14 +982,158 @@ impl PPU {
             0x2005 => self.reg.write_scroll(value),
             0x2006 => self.reg.write_vram_address(value),
             0x2007 => {
-                self.bus.write(self.reg.v.into(), value);
-                self.reg.incr_v();
+                bus.write(self.reg.v.into(), value);
+                if self.palette_write_through && self.rendering_line() {
+                    // v is incremented by the rendering counters instead
+                    // https://wiki.nesdev.com/w/index.php/PPU_scrolling#.242007_reads_and_writes
+                    self.reg.incr_coarse_x();
+                    self.reg.incr_y();
+                } else {
+                    self.reg.incr_v();
+                }
             }
             _ => {}
         }
     }
 }
 
+/// Snapshot of the frame buffer in the middle of rendering.
+///
+/// Pixels are palette indices (0x00-0x3F) in 256x240.
+/// The pixels at or after the scan position still hold the previous frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialFrame {
+    pub pixels: Vec<u8>,
+    pub line: u16,
+    pub dot: u16,
+}
+
+impl PartialFrame {
+    /// Returns true if the pixel has been drawn in the current frame.
+    pub fn is_drawn(&self, x: u16, y: u16) -> bool {
+        if HEIGHT <= self.line {
+            // Post render, VBLANK or Pre render
+            return self.line != MAX_LINE;
+        }
+        // a pixel of x is output at dot x + 2
+        y < self.line || (y == self.line && x + 2 < self.dot)
+    }
+}
+
+/// Registers and memory of the PPU.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PPUSnapshot {
+    /// PPUCTRL
+    pub controller: u8,
+    /// PPUMASK
+    pub mask: u8,
+    /// PPUSTATUS
+    pub status: u8,
+    /// OAMADDR
+    pub oam_address: u8,
+    /// current VRAM address
+    pub v: u16,
+    /// temporary VRAM address
+    pub t: u16,
+    pub fine_x: u8,
+    pub write_toggle: bool,
+    /// PPUDATA read buffer
+    pub read_buffer: u8,
+    pub open_bus: u8,
+    pub line: u16,
+    pub dot: u16,
+    pub frames: u64,
+    /// 256 bytes of OAM
+    pub oam: Vec<u8>,
+    /// $2000-$2FFF as seen through the current mirroring
+    pub name_tables: Vec<u8>,
+    /// $3F00-$3F1F
+    pub palette: Vec<u8>,
+}
+
+/// Mid-frame state injected by `PPU::with_state`.
+#[cfg(test)]
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct PPUState {
+    pub line: u16,
+    pub dot: u16,
+    pub frames: u64,
+    pub v: u16,
+    pub t: u16,
+    pub fine_x: u8,
+    pub controller: u8,
+    pub mask: u8,
+    // background shift registers
+    pub pattern_low: u16,
+    pub pattern_high: u16,
+    pub attr_low: u8,
+    pub attr_high: u8,
+}
+
+/// Host callback fired at the start (dot 0) of each scanline.
+pub type ScanlineHook = Box<dyn FnMut(&ScanlineEvent) + Send>;
+
+/// PPU state at the start of a scanline.
+///
+/// https://wiki.nesdev.com/w/index.php/PPU_scrolling#PPU_internal_registers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScanlineEvent {
+    /// 0-239 visible, 240 post render, 241-260 VBLANK, 261 pre render
+    pub line: u16,
+    pub frame: u64,
+    /// current VRAM address
+    pub v: u16,
+    /// temporary VRAM address
+    pub t: u16,
+    pub fine_x: u8,
+    /// PPUCTRL
+    pub controller: u8,
+    /// PPUMASK
+    pub mask: u8,
+}
+
+impl ScanlineEvent {
+    /// Horizontal scroll to be copied into `v` at dot 257 of this line.
+    pub fn scroll_x(&self) -> u16 {
+        (self.t & 0b11111) * 8 + self.fine_x as u16
+    }
+
+    /// Vertical scroll of this line, taken from `v`.
+    pub fn scroll_y(&self) -> u16 {
+        ((self.v >> 5) & 0b11111) * 8 + (self.v >> 12)
+    }
+
+    /// Nametable selected by `t` (0-3).
+    pub fn name_table(&self) -> u8 {
+        ((self.t >> 10) & 0b11) as u8
+    }
+
+    // `scroll_x` and `scroll_y` including the nametable
+    fn world_x(&self) -> u16 {
+        (self.t >> 10 & 1) * WIDTH + self.scroll_x()
+    }
+
+    fn world_y(&self) -> u16 {
+        (self.v >> 11 & 1) * HEIGHT + self.scroll_y()
+    }
+}
+
+/// A sprite in primary OAM, for sprite viewers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OAMEntry {
+    /// Index in OAM, 0-63.
+    pub index: u8,
+    pub x: u8,
+    /// Y position as stored in OAM, one line above the top of the sprite.
+    pub y: u8,
+    pub tile: u8,
+    /// Sprite palette 0-3, at $3F10-$3F1F.
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontally: bool,
+    pub flip_vertically: bool,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 struct Scan {
     dot: u16,
@@ -463,8 +1152,8 @@ impl Scan {
 
     fn next_dot(&mut self) -> ScanUpdate {
         self.dot = self.dot.wrapping_add(1);
-        if MAX_DOT <= self.dot {
-            self.dot %= MAX_DOT;
+        if MAX_DOT < self.dot {
+            self.dot = 0;
 
             self.line += 1;
             if MAX_LINE < self.line {
@@ -484,3 +1173,341 @@ enum ScanUpdate {
     Line,
     Frame,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Bus = Box<[u8; 0x10000]>;
+
+    fn new_bus() -> Bus {
+        Box::new([0; 0x10000])
+    }
+
+    fn step_to(ppu: &mut PPU, bus: &mut Bus, line: u16, dot: u16) {
+        while !(ppu.scan.line == line && ppu.scan.dot == dot) {
+            ppu.step(bus);
+        }
+    }
+
+    #[test]
+    fn dots_per_frame() {
+        let (mut ppu, mut bus) = (PPU::new(), new_bus());
+        ppu.write_register(0x2001, 0b00011000.into(), &mut bus);
+        let mut dots_of_frame = || {
+            let frames = ppu.frames;
+            let mut dots = 0;
+            while ppu.frames == frames {
+                ppu.step(&mut bus);
+                dots += 1;
+            }
+            dots
+        };
+        assert_eq!(dots_of_frame(), 341 * 262);
+        // the last dot of the pre-render line is skipped on odd frames
+        assert_eq!(dots_of_frame(), 341 * 262 - 1);
+        assert_eq!(dots_of_frame(), 341 * 262);
+    }
+
+    #[test]
+    fn clear_status_on_pre_render_line() {
+        let (mut ppu, mut bus) = (PPU::new(), new_bus());
+        step_to(&mut ppu, &mut bus, 241, 2);
+        assert!(ppu.reg.status.is_set(Status::VBLANK));
+        step_to(&mut ppu, &mut bus, 261, 2);
+        assert!(!ppu.reg.status.is_set(Status::VBLANK));
+    }
+
+    #[test]
+    fn fetch_pattern_of_tile_over_0x0f() {
+        let (mut ppu, mut bus) = (PPU::new(), new_bus());
+        bus.write(0x2000u16.into(), 0x20.into());
+        bus.write(0x0200u16.into(), 0xFF.into());
+        ppu.write_register(0x2001, 0b00011000.into(), &mut bus);
+
+        // fetches the low byte of the pattern at dot 6
+        step_to(&mut ppu, &mut bus, 0, 7);
+        assert_eq!(ppu.next_pattern.low, 0xFFu16.into());
+    }
+
+    #[test]
+    fn debug_nametables() {
+        let mut bus = new_bus();
+        // the tile 1 with color 3 at the top left pixel, placed at the tile (1, 2) of the 4th table
+        bus[0x1010] = 0x80;
+        bus[0x1018] = 0x80;
+        bus[0x2C00 + 2 * 32 + 1] = 1;
+        // the palette 2 for the bottom left tiles of the attribute byte
+        bus[0x2C00 + 0x3C0] = 0b10 << 4;
+        bus[0x3F00] = 0x0F;
+        bus[0x3F0B] = 0x16;
+        let mut ppu: PPU = PPU::new();
+        ppu.reg.write_controller(Controller::BG_TABLE_ADDR.bits());
+
+        let pixels = ppu.debug_nametables(&bus);
+        assert_eq!(pixels.len(), 512 * 480);
+        let width = NAMETABLES_WIDTH as usize;
+        assert_eq!(pixels[(240 + 16) * width + 256 + 8], 0x16);
+        assert_eq!(pixels[(240 + 16) * width + 256 + 9], 0x0F);
+        assert_eq!(pixels[0], 0x0F);
+    }
+
+    #[test]
+    fn debug_sprite() {
+        let mut bus = new_bus();
+        // the top right and bottom left pixels of the tiles 2 and 3
+        bus[0x1020] = 0x01;
+        bus[0x1030 + 15] = 0x80;
+        let mut ppu: PPU = PPU::new();
+        ppu.primary_oam[4..8].copy_from_slice(&[10, 0x03, 0b1100_0001, 20]);
+
+        let oam = ppu.debug_oam();
+        assert_eq!(oam.len(), 64);
+        let entry = oam[1];
+        assert_eq!(
+            entry,
+            OAMEntry {
+                index: 1,
+                x: 20,
+                y: 10,
+                tile: 3,
+                palette: 1,
+                behind_background: false,
+                flip_horizontally: true,
+                flip_vertically: true,
+            }
+        );
+
+        // 8x16 in the table selected by bit 0 of the tile, flipped both ways
+        ppu.reg.write_controller(Controller::SPRITE_SIZE.bits());
+        let pixels = ppu.debug_sprite(&entry, &bus);
+        assert_eq!(pixels.len(), 8 * 16);
+        assert_eq!(pixels[15 * 8], 1);
+        assert_eq!(pixels[7], 2);
+        assert_eq!(pixels.iter().filter(|p| **p != 0).count(), 2);
+    }
+
+    #[test]
+    fn with_state() {
+        let mut bus = new_bus();
+        bus[0x3F01] = 0x21;
+        bus[0x3F0E] = 0x16;
+        let mut ppu: PPU = PPU::with_state(PPUState {
+            line: 5,
+            dot: 10,
+            fine_x: 3,
+            mask: 0b00001000,
+            pattern_low: 0b0001_0000_0000_0000,
+            pattern_high: 0b0000_1000_0000_0000,
+            attr_low: 0b0000_1000,
+            attr_high: 0b0000_1000,
+            ..Default::default()
+        });
+
+        ppu.step(&mut bus);
+        assert_eq!(ppu.frame_buffer[5 * WIDTH as usize + 8], 0x21);
+        // shifted into the next pixel
+        ppu.step(&mut bus);
+        assert_eq!(ppu.frame_buffer[5 * WIDTH as usize + 9], 0x16);
+    }
+
+    #[test]
+    fn slave_mode() {
+        let (mut ppu, mut bus) = (PPU::new(), new_bus());
+        ppu.write_register(0x2000, 0xC0.into(), &mut bus);
+        // write-only registers read back the open bus
+        assert_eq!(ppu.read_register(0x2000, &mut bus), 0xC0.into());
+        ppu.write_register(0x2000, 0xC0.into(), &mut bus);
+        ppu.write_register(0x2000, 0x80.into(), &mut bus);
+        ppu.write_register(0x2000, 0x40.into(), &mut bus);
+        assert_eq!(
+            ppu.take_lints(),
+            vec![
+                HardwareLint::PPUSlaveMode { frame: 0 },
+                HardwareLint::PPUSlaveMode { frame: 0 },
+            ]
+        );
+        assert!(ppu.take_lints().is_empty());
+    }
+
+    #[test]
+    fn palette_write_through() {
+        let mut bus = new_bus();
+        bus[0x3F00] = 0x0F;
+        let mut ppu: PPU = PPU::with_state(PPUState {
+            line: 5,
+            dot: 10,
+            v: 0x3F05,
+            ..Default::default()
+        });
+        ppu.write_register(0x2007, 0x16.into(), &mut bus);
+        ppu.step(&mut bus);
+        assert_eq!(ppu.frame_buffer[5 * WIDTH as usize + 8], 0x0F);
+
+        ppu.set_palette_write_through(true);
+        ppu.step(&mut bus);
+        assert_eq!(ppu.frame_buffer[5 * WIDTH as usize + 9], 0x00);
+        ppu.reg.v = 0x3F05u16.into();
+        ppu.step(&mut bus);
+        assert_eq!(ppu.frame_buffer[5 * WIDTH as usize + 10], 0x16);
+
+        // rendering
+        ppu.reg.mask = Mask::new(0b0000_1000);
+        ppu.write_register(0x2007, 0x20.into(), &mut bus);
+        let v: u16 = ppu.reg.v.into();
+        assert_eq!(v, 0x3F05 + 1 + 0x1000);
+    }
+
+    fn sprite_ppu() -> (PPU, Bus) {
+        let mut bus = new_bus();
+        for (i, b) in bus[0x3F10..0x3F20].iter_mut().enumerate() {
+            *b = 0x20 + i as u8;
+        }
+        let mut ppu: PPU = PPU::with_state(PPUState {
+            mask: 0b00010000,
+            ..Default::default()
+        });
+        ppu.sprite_count = 2;
+        (ppu, bus)
+    }
+
+    #[test]
+    fn sprite_with_all_ff_fields() {
+        let (mut ppu, mut bus) = sprite_ppu();
+        // sprite 0 is off screen at x=$FF, and must not hide the following sprite
+        ppu.sprites[0] = Sprite::EMPTY;
+        ppu.sprites[1] = Sprite {
+            x: 16,
+            ..Default::default()
+        };
+        ppu.sprite_patterns[1] = (0xFF, 0x00);
+
+        let pixel = ppu.get_sprite_pixel(20, background::Pixel::ZERO, &mut bus);
+        assert!(pixel.enabled);
+        assert_eq!(pixel.color, 0x21);
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let (mut ppu, mut bus) = sprite_ppu();
+        // sprite 0 is behind the background and opaque only on its right half
+        ppu.sprites[0] = Sprite {
+            x: 16,
+            attr: 0b0010_0001.into(),
+            ..Default::default()
+        };
+        ppu.sprite_patterns[0] = (0b0000_1111, 0b0000_1111);
+        ppu.sprites[1] = Sprite {
+            x: 12,
+            attr: 0b0000_0010.into(),
+            ..Default::default()
+        };
+        ppu.sprite_patterns[1] = (0xFF, 0x00);
+
+        // transparent pixel of sprite 0
+        let pixel = ppu.get_sprite_pixel(17, background::Pixel::ZERO, &mut bus);
+        assert_eq!(pixel.color, 0x29);
+        assert!(!pixel.behide_background);
+
+        // opaque pixel of sprite 0 wins, with its priority
+        let pixel = ppu.get_sprite_pixel(20, background::Pixel::ZERO, &mut bus);
+        assert_eq!(pixel.color, 0x27);
+        assert!(pixel.behide_background);
+    }
+
+    #[test]
+    fn layers() {
+        let (mut ppu, mut bus) = sprite_ppu();
+        let bg = background::Pixel {
+            enabled: true,
+            color: 0x01,
+        };
+        let sprite = sprite::Pixel {
+            enabled: true,
+            color: 0x21,
+            behide_background: false,
+        };
+        let select = |ppu: &PPU, bus: &Bus| {
+            let (bg, sprite) = ppu.visible_pixels(bg, sprite);
+            ppu.select_pixel(bg, sprite, bus)
+        };
+        assert_eq!(select(&ppu, &bus), 0x21);
+
+        ppu.set_layer_enabled(Layer::Sprites, false);
+        assert!(!ppu.layer_enabled(Layer::Sprites));
+        assert_eq!(select(&ppu, &bus), 0x01);
+
+        ppu.set_layer_enabled(Layer::Background, false);
+        bus.write(0x3F00u16.into(), 0x0F.into());
+        assert_eq!(select(&ppu, &bus), 0x0F);
+
+        ppu.set_layer_enabled(Layer::Sprites, true);
+        assert_eq!(select(&ppu, &bus), 0x21);
+    }
+
+    #[test]
+    fn sprite_limit() {
+        let run = |limit: bool| {
+            let (mut ppu, mut bus) = (PPU::new(), new_bus());
+            ppu.set_sprite_limit_enabled(limit);
+            for (i, b) in ppu.primary_oam.iter_mut().enumerate() {
+                *b = 0xF0 | (i % 4) as u8;
+            }
+            // 10 sprites on line 10
+            for n in 0..10 {
+                ppu.primary_oam[n * 4..n * 4 + 4].copy_from_slice(&[8, n as u8, 0, n as u8]);
+            }
+            ppu.write_register(0x2001, 0b00011000.into(), &mut bus);
+            step_to(&mut ppu, &mut bus, 11, 0);
+            ppu
+        };
+
+        let ppu = run(true);
+        assert_eq!(ppu.sprite_count, SPRITE_LIMIT);
+        assert!(ppu.reg.status.is_set(Status::SPRITE_OVERFLOW));
+
+        let ppu = run(false);
+        assert_eq!(ppu.sprite_count, 10);
+        assert_eq!(ppu.sprites[9].tile_index, 9);
+        assert!(ppu.reg.status.is_set(Status::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn read_oam_data_during_rendering() {
+        let (mut ppu, mut bus) = (PPU::new(), new_bus());
+        for (i, b) in ppu.primary_oam.iter_mut().enumerate() {
+            *b = 0xF0 | (i % 4) as u8;
+        }
+        // sprite 1 is on line 10
+        ppu.primary_oam[4..8].copy_from_slice(&[8, 0x12, 0x34, 0x56]);
+        ppu.write_register(0x2001, 0b00011000.into(), &mut bus);
+
+        step_to(&mut ppu, &mut bus, 10, 30);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0xFF.into());
+
+        // reading Y of sprite 0
+        step_to(&mut ppu, &mut bus, 10, 65);
+        ppu.step(&mut bus);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0xF0.into());
+
+        // sprite 1 is found and copied
+        step_to(&mut ppu, &mut bus, 10, 257);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 8.into());
+        step_to(&mut ppu, &mut bus, 10, 258);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0x12.into());
+        step_to(&mut ppu, &mut bus, 10, 260);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0x56.into());
+        step_to(&mut ppu, &mut bus, 10, 264);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0x56.into());
+        assert_eq!(ppu.sprites[0].tile_index, 0x12);
+        assert_eq!(ppu.sprites[1], Sprite::EMPTY);
+
+        step_to(&mut ppu, &mut bus, 10, 330);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 8.into());
+
+        // not rendering
+        ppu.write_register(0x2001, 0x00.into(), &mut bus);
+        ppu.write_register(0x2003, 5.into(), &mut bus);
+        assert_eq!(ppu.read_register(0x2004, &mut bus), 0x12.into());
+    }
+}
@@ -2,7 +2,7 @@ use crate::types::{Byte, Word};
 
 pub(super) const NAME_TABLE_FIRST: Word = Word::new(0x2000u16);
 pub(super) const ATTRIBUTE_TABLE_FIRST: Word = Word::new(0x23C0u16);
-pub(super) const TILE_HEIGHT: Byte = Byte::new(8);
+pub(super) const TILE_HEIGHT: u8 = 8;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub(super) struct Pixel {
@@ -24,6 +24,21 @@ pub(super) struct Tile {
 }
 
 impl Tile {
+    #[cfg(test)]
+    pub fn new(pattern_low: u16, pattern_high: u16, attr_low: u8, attr_high: u8) -> Self {
+        Self {
+            pattern: TilePattern {
+                low: pattern_low.into(),
+                high: pattern_high.into(),
+            },
+            attr: TileAttribute {
+                low: attr_low.into(),
+                high: attr_high.into(),
+                ..Default::default()
+            },
+        }
+    }
+
     pub fn pixel_pallete(&self, x: u8) -> (Word, Word) {
         // http://wiki.nesdev.com/w/index.php/PPU_palettes#Memory_Map
         let p = 15u8.wrapping_sub(x);
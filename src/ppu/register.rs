@@ -1,7 +1,9 @@
+use crate::region::Region;
 use crate::types::{Byte, Word};
 use std::ops;
 
 use super::vram_address::VRAMAddress;
+use super::PPUSnapshot;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub(super) struct Register {
@@ -33,6 +35,39 @@ impl Register {
         self.data = 0x00.into();
     }
 
+    pub fn temp_vram_address(&self) -> VRAMAddress {
+        self.t
+    }
+
+    pub fn snapshot(&self, s: &mut PPUSnapshot) {
+        s.controller = self.controller.0;
+        s.mask = self.mask.0;
+        s.status = self.status.0;
+        s.oam_address = self.object_attribute_memory_address as u8;
+        s.v = self.v.into();
+        s.t = self.t.into();
+        s.fine_x = self.fine_x.into();
+        s.write_toggle = self.write_toggle;
+        s.read_buffer = self.data.into();
+    }
+
+    pub fn restore(&mut self, s: &PPUSnapshot) {
+        self.controller = Controller(s.controller);
+        self.mask = Mask(s.mask);
+        self.status = Status(s.status);
+        self.object_attribute_memory_address = s.oam_address as usize;
+        self.v = s.v.into();
+        self.t = s.t.into();
+        self.fine_x = s.fine_x.into();
+        self.write_toggle = s.write_toggle;
+        self.data = s.read_buffer.into();
+    }
+
+    #[cfg(test)]
+    pub fn set_temp_vram_address(&mut self, t: VRAMAddress) {
+        self.t = t;
+    }
+
     pub fn sprite_size(&self) -> i8 {
         if self.controller.is_set(Controller::SPRITE_SIZE) {
             16
@@ -154,8 +189,7 @@ impl Register {
         self.v = (self.v & !0b1111011_11100000) | (self.t & 0b1111011_11100000)
     }
 
-    #[allow(dead_code)]
-    fn background_pattern_table_addr_base(&self) -> impl Into<u16> {
+    pub fn background_pattern_table_addr_base(&self) -> impl Into<u16> {
         if self.controller.is_set(Controller::BG_TABLE_ADDR) {
             0x1000u16
         } else {
@@ -171,7 +205,6 @@ impl Controller {
     // NMI
     pub const NMI: Self = Self(1 << 7);
     // PPU master/slave (0: master, 1: slave)
-    #[allow(dead_code)]
     pub const SLAVE: Self = Self(1 << 6);
     // Sprite size
     pub const SPRITE_SIZE: Self = Self(1 << 5);
@@ -187,6 +220,10 @@ impl Controller {
     #[allow(dead_code)]
     pub const NAME_TABLE_ADDR_LOW: Self = Self(1 << 0);
 
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
     pub fn is_set(&self, Self(v): Self) -> bool {
         self.0 & v == v
     }
@@ -243,13 +280,10 @@ pub struct Mask(u8);
 
 impl Mask {
     // Emphasize blue
-    #[allow(dead_code)]
     const BLUE: Self = Self(1 << 7);
-    // Emphasize green
-    #[allow(dead_code)]
+    // Emphasize green (red on PAL)
     const GREEN: Self = Self(1 << 6);
-    // Emphasize red
-    #[allow(dead_code)]
+    // Emphasize red (green on PAL)
     const RED: Self = Self(1 << 5);
     // Show sprite
     const SPRITE: Self = Self(1 << 4);
@@ -267,9 +301,57 @@ impl Mask {
         Self(v.into())
     }
 
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
     pub fn is_set(&self, Self(v): Self) -> bool {
         self.0 & v == v
     }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_registers#Color_Control
+    pub fn emphasis(&self, region: Region) -> Emphasis {
+        let (red, green) = match region {
+            Region::NTSC => (Self::RED, Self::GREEN),
+            // PAL PPU swaps the red and green emphasis bits
+            Region::PAL => (Self::GREEN, Self::RED),
+        };
+        let mut e = Emphasis(0);
+        if self.is_set(red) {
+            e.0 |= Emphasis::RED.0;
+        }
+        if self.is_set(green) {
+            e.0 |= Emphasis::GREEN.0;
+        }
+        if self.is_set(Self::BLUE) {
+            e.0 |= Emphasis::BLUE.0;
+        }
+        e
+    }
+}
+
+/// Color emphasis in effect, already corrected for the region's bit layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Emphasis(u8);
+
+impl Emphasis {
+    pub const RED: Self = Self(1 << 0);
+    pub const GREEN: Self = Self(1 << 1);
+    pub const BLUE: Self = Self(1 << 2);
+
+    /// Emphasis from `BGR` in the low 3 bits, as `Emphasis::bits`.
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & 0b111)
+    }
+
+    pub fn is_set(&self, Self(v): Self) -> bool {
+        self.0 & v == v
+    }
+
+    /// Emphasis bits as `BGR` in the low 3 bits.
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
@@ -303,3 +385,23 @@ impl ops::BitOr for Status {
         Self(self.0 | rhs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emphasis() {
+        let mask = Mask::new(0b01100000u8);
+        let e = mask.emphasis(Region::NTSC);
+        assert!(e.is_set(Emphasis::RED));
+        assert!(e.is_set(Emphasis::GREEN));
+        assert!(!e.is_set(Emphasis::BLUE));
+
+        let mask = Mask::new(0b10100000u8);
+        let e = mask.emphasis(Region::NTSC);
+        assert_eq!(e, Emphasis(0b101));
+        let e = mask.emphasis(Region::PAL);
+        assert_eq!(e, Emphasis(0b110));
+    }
+}
@@ -17,6 +17,34 @@ impl Pixel {
     };
 }
 
+// State of the sprite evaluation through dots 65-256
+// https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Evaluation {
+    // sprite index in primary OAM
+    pub n: usize,
+    // byte index in the sprite
+    pub m: usize,
+    // the number of sprites found
+    pub count: usize,
+    // the value read from primary OAM
+    pub latch: u8,
+    pub done: bool,
+}
+
+impl Evaluation {
+    pub fn index(&self) -> usize {
+        (self.n * 4 + self.m) % OAM_SIZE
+    }
+
+    pub fn next_sprite(&mut self) {
+        self.n += 1;
+        if SPRITE_COUNT <= self.n {
+            self.done = true;
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub struct Sprite {
     // Y position of top
@@ -30,9 +58,12 @@ pub struct Sprite {
 }
 
 impl Sprite {
-    pub fn valid(&self) -> bool {
-        !(self.x == 0xFF && self.y == 0xFF && self.tile_index == 0xFF && self.attr.0 == 0xFF)
-    }
+    pub const EMPTY: Self = Self {
+        y: 0xFF,
+        tile_index: 0xFF,
+        attr: SpriteAttribute(0xFF),
+        x: 0xFF,
+    };
 
     pub fn row(&self, line: u16, sprite_height: i8) -> u16 {
         let row = (line as u16).wrapping_sub(self.y as u16).wrapping_sub(1);
@@ -57,8 +88,8 @@ impl Sprite {
 pub struct SpriteAttribute(u8);
 
 impl SpriteAttribute {
-    const FLIP_VERTICALLY: Self = Self(1 << 7);
-    const FLIP_HORIZONTALLY: Self = Self(1 << 6);
+    pub const FLIP_VERTICALLY: Self = Self(1 << 7);
+    pub const FLIP_HORIZONTALLY: Self = Self(1 << 6);
     // Priority
     pub const BEHIND_BACKGROUND: Self = Self(1 << 5);
 
@@ -28,7 +28,7 @@ impl VRAMAddress {
     }
 
     pub fn coarse_y_scroll(&self) -> Word {
-        self.0 & 0b11_11100000 >> 5
+        (self.0 & 0b11_11100000) >> 5
     }
 
     #[allow(dead_code)]
@@ -149,3 +149,15 @@ impl ops::BitXorAssign<u16> for VRAMAddress {
         *self = Self(self.0 ^ rhs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coarse_y_scroll() {
+        // fine Y 7, name table 2, coarse Y 29, coarse X 0
+        let v = VRAMAddress::from(0b0111_1011_1010_0000u16);
+        assert_eq!(v.coarse_y_scroll(), 29u16.into());
+    }
+}
@@ -0,0 +1,27 @@
+/// TV system of the console.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Region {
+    #[default]
+    NTSC,
+    PAL,
+}
+
+impl Region {
+    // https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+    pub(crate) fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Self::NTSC => 1_789_773.0,
+            Self::PAL => 1_662_607.0,
+        }
+    }
+
+    // frames per second as a fraction, about 60.0988 on NTSC and 50.0070 on PAL
+    pub(crate) fn frame_rate(&self) -> (u32, u32) {
+        match self {
+            Self::NTSC => (39_375_000, 655_171),
+            Self::PAL => (53_203_425, 1_063_920),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// Device of the registers written by a `RegisterWrite`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Device {
+    /// $2000-$3FFF
+    PPU,
+    /// $4000-$4013, $4015 and $4017
+    APU,
+    /// $4020-$5FFF and $8000-$FFFF, except the PRG-RAM in between
+    Mapper,
+}
+
+impl Device {
+    fn of(addr: u16) -> Option<Self> {
+        match addr {
+            0x2000..=0x3FFF => Some(Self::PPU),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => Some(Self::APU),
+            0x4020..=0x5FFF | 0x8000..=0xFFFF => Some(Self::Mapper),
+            _ => None,
+        }
+    }
+}
+
+/// A write to a register by the CPU, tagged with the position of the PPU when it happened, for
+/// locating mid-frame scroll splits and bank switches.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub frame: u64,
+    pub line: u16,
+    pub dot: u16,
+    pub device: Device,
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl fmt::Display for RegisterWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "frame {} line {:3} dot {:3}: {:?} ${:04X} = ${:02X}",
+            self.frame, self.line, self.dot, self.device, self.addr, self.value
+        )
+    }
+}
+
+// Shared by the CPU bus and the NES; records nothing until enabled
+#[derive(Debug, Default)]
+pub(crate) struct RegisterLog {
+    enabled: bool,
+    writes: Vec<RegisterWrite>,
+}
+
+impl RegisterLog {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.writes.clear();
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&mut self, (frame, line, dot): (u64, u16, u16), addr: u16, value: u8) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(device) = Device::of(addr) {
+            self.writes.push(RegisterWrite {
+                frame,
+                line,
+                dot,
+                device,
+                addr,
+                value,
+            });
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<RegisterWrite> {
+        std::mem::take(&mut self.writes)
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::nes::Snapshot;
+use crate::savestate::SaveState;
+
+/// Settings of the rewind buffer of `NES::set_rewind`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Frames between the states kept, the steps `NES::rewind` goes back by.
+    pub interval: u32,
+    /// Bytes of the states kept at most, the oldest dropped beyond it. A state of 7 KB to 25 KB
+    /// by the RAM of the cartridge every 2 frames keeps from 45 seconds to 2.5 minutes or so in
+    /// the default 32 MB.
+    pub memory_budget: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        Self {
+            interval: 2,
+            memory_budget: 32 << 20,
+        }
+    }
+}
+
+/// Ring of the states taken every `RewindConfig::interval` frames, dropping the oldest over the
+/// memory budget.
+#[derive(Clone)]
+pub(crate) struct RewindBuffer {
+    config: RewindConfig,
+    // states by the number of the frame they were taken after, the oldest first
+    states: VecDeque<(u64, Snapshot)>,
+    size: usize,
+    frame: u64,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        Self {
+            config,
+            states: VecDeque::new(),
+            size: 0,
+            frame: 0,
+        }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+        self.size = 0;
+        self.frame = 0;
+    }
+
+    /// Counts a frame run, returning true if its state is to be pushed.
+    pub fn count_frame(&mut self) -> bool {
+        self.frame += 1;
+        self.frame
+            .is_multiple_of(u64::from(self.config.interval.max(1)))
+    }
+
+    pub fn push(&mut self, snapshot: Snapshot) {
+        self.size += state_size(&snapshot.state);
+        self.states.push_back((self.frame, snapshot));
+        while self.config.memory_budget < self.size && 1 < self.states.len() {
+            if let Some((_, snapshot)) = self.states.pop_front() {
+                self.size -= state_size(&snapshot.state);
+            }
+        }
+    }
+
+    /// Drops the states taken in the last `frames` frames and returns the latest one left, kept
+    /// to go back to again, with the frames it goes back by. The oldest is never dropped, and
+    /// `None` if there is none.
+    pub fn rewind(&mut self, frames: u32) -> Option<(u32, Snapshot)> {
+        let target = self.frame.saturating_sub(u64::from(frames));
+        while let Some((frame, _)) = self.states.back() {
+            if *frame <= target || self.states.len() == 1 {
+                break;
+            }
+            if let Some((_, snapshot)) = self.states.pop_back() {
+                self.size -= state_size(&snapshot.state);
+            }
+        }
+        let (frame, snapshot) = self.states.back()?;
+        let rewound = (self.frame - frame) as u32;
+        self.frame = *frame;
+        Some((rewound, snapshot.clone()))
+    }
+
+    /// Frames `RewindBuffer::rewind` can go back by at most.
+    pub fn frames(&self) -> u64 {
+        self.states
+            .front()
+            .map_or(0, |(frame, _)| self.frame - frame)
+    }
+}
+
+fn state_size(state: &SaveState) -> usize {
+    mem::size_of::<SaveState>()
+        + state.ram.len()
+        + state.ppu.oam.len()
+        + state.ppu.name_tables.len()
+        + state.ppu.palette.len()
+        + state.metadata.thumbnail.pixels.len()
+        + state.apu.len()
+        + state.mapper.len()
+        + state.controllers.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::NES;
+
+    #[test]
+    fn rewind() {
+        let snapshot = NES::default().snapshot().unwrap();
+        let mut buffer = RewindBuffer::new(RewindConfig {
+            interval: 2,
+            memory_budget: state_size(&snapshot.state) * 4,
+        });
+        assert!(buffer.rewind(2).is_none());
+        for _ in 0..10 {
+            if buffer.count_frame() {
+                buffer.push(snapshot.clone());
+            }
+        }
+        // the states after the frames 4, 6, 8 and 10 in the budget
+        assert_eq!(buffer.states.len(), 4);
+        assert_eq!(buffer.frames(), 6);
+
+        assert_eq!(buffer.rewind(0).map(|(n, _)| n), Some(0));
+        assert_eq!(buffer.rewind(1).map(|(n, _)| n), Some(2));
+        assert_eq!(buffer.rewind(3).map(|(n, _)| n), Some(4));
+        assert_eq!(buffer.frames(), 0);
+        assert_eq!(buffer.states.len(), 1);
+        // stops at the oldest
+        assert_eq!(buffer.rewind(2).map(|(n, _)| n), Some(0));
+
+        buffer.clear();
+        assert!(buffer.rewind(0).is_none());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, Result};
+
+use crate::controller::Button;
+use crate::nes::{Frame, Snapshot, NES};
+
+/// Frames run ahead of the input of the peer at most, predicting it.
+pub const MAX_PREDICTION: usize = 8;
+
+/// Rollback of a `NES` played by two players over a connection, as GGPO does.
+///
+/// Both players run a `Rollback` on their own `NES` and send each other their input of every
+/// frame run, in order. The transport is up to the frontend. Until the input of the peer for a
+/// frame arrives, the input it sent last is predicted to be held. When an input arrives that
+/// differs from the prediction, the `NES` goes back to the state of that frame and runs the
+/// frames since again before the next one, so both players see the same game a few frames after
+/// the inputs were sent.
+///
+/// ```no_run
+/// # use rustnes::{Button, Rollback, NES};
+/// # fn send(_: Button) {}
+/// # fn receive() -> Option<Button> { None }
+/// # let mut nes = NES::default();
+/// let mut rollback = Rollback::new(0);
+/// loop {
+///     while let Some(buttons) = receive() {
+///         rollback.add_remote_input(buttons);
+///     }
+///     let local = Button::A;
+///     if rollback.advance_frame(&mut nes, local)?.is_some() {
+///         send(local);
+///     }
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Rollback {
+    local_port: usize,
+    // frames run
+    frame: u64,
+    // frames with the input of the peer
+    confirmed: u64,
+    // the input of the peer last received, the prediction of the frames after it
+    last_remote: Button,
+    // inputs of the peer for the frames not run yet
+    remote_ahead: VecDeque<Button>,
+    // frames run since the first one without the input of the peer or rolled back, the oldest
+    // first
+    history: VecDeque<Record>,
+    // the first frame run with a wrong prediction
+    rollback: Option<u64>,
+}
+
+struct Record {
+    // the state before the frame
+    snapshot: Snapshot,
+    local: Button,
+    remote: Button,
+}
+
+impl Rollback {
+    /// Starts from the current state of the `NES`, with the local player on the controller port
+    /// `local_port`, 0 or 1, and the peer on the other. Panics for another port.
+    pub fn new(local_port: usize) -> Self {
+        assert!(
+            local_port < 2,
+            "no controller port {} for netplay",
+            local_port
+        );
+        Self {
+            local_port,
+            frame: 0,
+            confirmed: 0,
+            last_remote: Button::empty(),
+            remote_ahead: VecDeque::new(),
+            history: VecDeque::new(),
+            rollback: None,
+        }
+    }
+
+    /// Frames run.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Frames run with the input of the peer received.
+    pub fn confirmed_frame(&self) -> u64 {
+        self.confirmed.min(self.frame)
+    }
+
+    /// Adds the input of the peer for its next frame.
+    pub fn add_remote_input(&mut self, buttons: Button) {
+        let frame = self.confirmed;
+        self.confirmed += 1;
+        self.last_remote = buttons;
+        if self.frame <= frame {
+            self.remote_ahead.push_back(buttons);
+            return;
+        }
+        let first = self.frame - self.history.len() as u64;
+        let record = &mut self.history[(frame - first) as usize];
+        if record.remote != buttons {
+            record.remote = buttons;
+            self.rollback = Some(self.rollback.map_or(frame, |f| f.min(frame)));
+        }
+    }
+
+    /// Runs the frames mispredicted again, then a frame with `local` as the input of the local
+    /// player, which is to be sent to the peer. `None` without running anything while
+    /// `MAX_PREDICTION` frames ahead of the peer, waiting for its input.
+    ///
+    /// Fails if the mapper of the cartridge cannot be restored.
+    pub fn advance_frame(&mut self, nes: &mut NES, local: Button) -> Result<Option<Frame>> {
+        self.roll_back(nes);
+        // the frames confirmed are never rolled back
+        while self.frame - self.confirmed_frame() < self.history.len() as u64 {
+            self.history.pop_front();
+        }
+        if MAX_PREDICTION <= self.history.len() {
+            return Ok(None);
+        }
+        let remote = match self.remote_ahead.pop_front() {
+            Some(remote) => remote,
+            None => {
+                let snapshot = nes
+                    .snapshot()
+                    .ok_or_else(|| anyhow!("The mapper of the cartridge cannot be rolled back"))?;
+                self.history.push_back(Record {
+                    snapshot,
+                    local,
+                    remote: self.last_remote,
+                });
+                self.last_remote
+            }
+        };
+        self.set_controllers(nes, local, remote);
+        self.frame += 1;
+        Ok(Some(nes.frame()))
+    }
+
+    // goes back to the first frame mispredicted and runs the frames since again with the inputs
+    // received and the new prediction
+    fn roll_back(&mut self, nes: &mut NES) {
+        let frame = match self.rollback.take() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let first = self.frame - self.history.len() as u64;
+        let from = (frame - first) as usize;
+        nes.restore(&self.history[from].snapshot);
+        for i in from..self.history.len() {
+            if self.confirmed <= first + i as u64 {
+                self.history[i].remote = self.last_remote;
+            }
+            if from < i {
+                if let Some(snapshot) = nes.snapshot() {
+                    self.history[i].snapshot = snapshot;
+                }
+            }
+            let record = &self.history[i];
+            self.set_controllers(nes, record.local, record.remote);
+            nes.run_frame_again();
+        }
+    }
+
+    fn set_controllers(&self, nes: &mut NES, local: Button, remote: Button) {
+        nes.set_controller(self.local_port, local);
+        nes.set_controller(1 - self.local_port, remote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::tests::counter_rom_bytes;
+    use crate::rom::ROM;
+
+    // adds the A button of the controllers 1 and 2 to $10 and $11 every frame
+    fn new_nes() -> NES {
+        let mut data = counter_rom_bytes();
+        let nmi = [
+            0xA9, 0x01, // LDA #1
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00, // LDA #0
+            0x8D, 0x16, 0x40, // STA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0x29, 0x01, // AND #1
+            0x18, // CLC
+            0x65, 0x10, // ADC $10
+            0x85, 0x10, // STA $10
+            0xAD, 0x17, 0x40, // LDA $4017
+            0x29, 0x01, // AND #1
+            0x18, // CLC
+            0x65, 0x11, // ADC $11
+            0x85, 0x11, // STA $11
+            0x40, // RTI
+        ];
+        data[0x10 + 0x10..0x10 + 0x10 + nmi.len()].copy_from_slice(&nmi);
+        let mut nes = NES::default();
+        nes.load(ROM::from_bytes(&data).unwrap()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes
+    }
+
+    #[test]
+    fn rollback() {
+        let inputs = |player: usize, frame: usize| {
+            let pressed = match player {
+                0 => frame.is_multiple_of(3),
+                _ => frame % 5 < 2,
+            };
+            if pressed {
+                Button::A
+            } else {
+                Button::empty()
+            }
+        };
+        const FRAMES: usize = 30;
+        const LATENCY: usize = 3;
+
+        let mut reference = new_nes();
+        for frame in 0..FRAMES {
+            reference.set_controller(0, inputs(0, frame));
+            reference.set_controller(1, inputs(1, frame));
+            reference.frame();
+        }
+        reference.frame();
+
+        let mut players = [(new_nes(), Rollback::new(0)), (new_nes(), Rollback::new(1))];
+        for frame in 0..FRAMES + LATENCY {
+            for (player, (nes, rollback)) in players.iter_mut().enumerate() {
+                // the input sent by the peer `LATENCY` frames ago arrives
+                if LATENCY <= frame {
+                    rollback.add_remote_input(inputs(1 - player, frame - LATENCY));
+                }
+                if frame < FRAMES {
+                    let local = inputs(player, frame);
+                    assert!(rollback.advance_frame(nes, local).unwrap().is_some());
+                }
+            }
+        }
+        for (player, (nes, rollback)) in players.iter_mut().enumerate() {
+            assert_eq!(rollback.confirmed_frame(), FRAMES as u64);
+            // the last inputs held
+            let local = inputs(player, FRAMES - 1);
+            rollback.advance_frame(nes, local).unwrap();
+            assert_eq!(nes.peek(0x10), reference.peek(0x10));
+            assert_eq!(nes.peek(0x11), reference.peek(0x11));
+        }
+        assert_ne!(reference.peek(0x10), 0);
+        assert_ne!(reference.peek(0x11), 0);
+    }
+
+    #[test]
+    fn max_prediction() {
+        let mut nes = new_nes();
+        let mut rollback = Rollback::new(0);
+        for _ in 0..MAX_PREDICTION {
+            assert!(rollback
+                .advance_frame(&mut nes, Button::A)
+                .unwrap()
+                .is_some());
+        }
+        assert!(rollback
+            .advance_frame(&mut nes, Button::A)
+            .unwrap()
+            .is_none());
+        rollback.add_remote_input(Button::A);
+        assert!(rollback
+            .advance_frame(&mut nes, Button::A)
+            .unwrap()
+            .is_some());
+        assert_eq!(rollback.frame(), MAX_PREDICTION as u64 + 1);
+        assert_eq!(rollback.confirmed_frame(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "no controller port 2")]
+    fn third_port() {
+        Rollback::new(2);
+    }
+}
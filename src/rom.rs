@@ -1,42 +1,632 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
+mod bank;
+mod bus_conflict;
+mod database;
+mod diagnostic;
+mod discrete;
+pub(crate) mod hash;
+mod ips;
 mod nesfile;
+mod registry;
 
+mod fme7;
 mod mapper_0;
+mod mapper_11;
+mod mapper_2;
+mod mapper_3;
+mod mapper_30;
+mod mapper_34;
+mod mapper_4;
+mod mapper_66;
+mod mapper_9;
+mod mmc3_irq;
+mod namco163;
+mod opll;
+mod sunsoft_5b;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod vrc_irq;
 
+use crate::chr::chr_sheet;
+use crate::screenshot::Screenshot;
 use crate::types::{Memory, Mirroring};
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use thiserror::Error;
 
-pub trait Mapper: Memory {
+pub use database::{GameDatabase, GameInfo};
+pub use diagnostic::ROMDiagnostic;
+pub use nesfile::ConsoleType;
+pub use registry::{Cartridge, MapperRegistry};
+
+/// Memory that a 1KB window of PPU $0000-$2FFF is mapped to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VRAMSource {
+    /// Pattern tables from the mapper, nametables from the console's VRAM by `Mapper::mirroring`.
+    Default,
+    /// The page (0 or 1) of the console's 2KB VRAM (CIRAM).
+    CIRAM(u8),
+    /// Read and written through the mapper.
+    CHR,
+}
+
+pub trait Mapper: Memory + Send {
     fn mirroring(&self) -> Mirroring;
+
+    /// Maps PPU $0000-$2FFF for mappers that put VRAM into the pattern tables or CHR into
+    /// the nametables, e.g. Namco 163.
+    fn vram_source(&self, _addr: u16) -> VRAMSource {
+        VRAMSource::Default
+    }
+
+    /// Notifies the address put on the PPU bus by pattern and nametable fetches,
+    /// which some mappers watch to switch banks or count scanlines.
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    /// Clocks the mapper every CPU cycle (M2), for IRQ counters driven by the CPU clock.
+    fn cpu_cycle(&mut self) {}
+
+    /// Returns true while the mapper asserts the IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Output of the expansion audio of the cartridge, mixed with the APU channels.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Returns the RAM of the cartridge in the raw layout of `.sav` files, which is shared by
+    /// other emulators such as FCEUX, Mesen and Nestopia.
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores the RAM from the layout of `Mapper::save_ram`.
+    fn load_save_ram(&mut self, _data: &[u8]) {}
+
+    /// Writes non-volatile data (e.g. self-flashed PRG) to disk if it has been changed.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// The whole state of the mapper, including its RAM, for save states, rewind and run-ahead,
+    /// written by `StateWriter`. `None` if the mapper cannot be saved, which disables them.
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores the state written by `Mapper::save_state`.
+    fn load_state(&mut self, _data: &[u8]) -> Result<()> {
+        Err(anyhow!("The mapper does not support save states"))
+    }
 }
 
 pub struct ROM {
-    pub mapper: Rc<RefCell<dyn Mapper>>,
+    pub mapper: Box<dyn Mapper>,
+    battery: bool,
+    crc32: u32,
+    sha1: [u8; 20],
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    console_type: ConsoleType,
+    diagnostics: Vec<ROMDiagnostic>,
+    game: Option<GameInfo>,
+    // `.sav` file next to the ROM file, the save states named after it
+    save_path: Option<PathBuf>,
+    // `.sav` file of the battery-backed RAM
+    sram_path: Option<PathBuf>,
 }
 
 impl ROM {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let save_path = path.as_ref().with_extension("sav");
+        let f = nesfile::NESFile::open(path)?;
+        Self::new(f, Some(save_path), None, None)
+    }
+
+    /// Loads a ROM, building its mapper by `registry` if registered.
+    pub fn load_with_registry<P: AsRef<Path>>(path: P, registry: &MapperRegistry) -> Result<Self> {
+        let save_path = path.as_ref().with_extension("sav");
         let f = nesfile::NESFile::open(path)?;
+        Self::new(f, Some(save_path), Some(registry), None)
+    }
+
+    /// Loads a ROM identified in `database`, with the mapper and mirroring of the database
+    /// instead of the header, which is often wrong in old dumps.
+    pub fn load_with_database<P: AsRef<Path>>(path: P, database: &GameDatabase) -> Result<Self> {
+        let save_path = path.as_ref().with_extension("sav");
+        let f = nesfile::NESFile::open(path)?;
+        Self::new(f, Some(save_path), None, Some(database))
+    }
+
+    /// Loads a ROM with an IPS patch, such as a translation or a ROM hack, applied.
+    ///
+    /// The save file is named after the ROM file, so the patched game shares it with the original.
+    pub fn load_with_patch<P: AsRef<Path>, Q: AsRef<Path>>(path: P, patch_path: Q) -> Result<Self> {
+        let save_path = path.as_ref().with_extension("sav");
+        let f = nesfile::NESFile::open_with_patch(path, patch_path)?;
+        Self::new(f, Some(save_path), None, None)
+    }
+
+    /// Same as `ROM::load_with_database`, but also loads ROM files without the iNES header if
+    /// they are found in `database`.
+    pub fn load_lenient<P: AsRef<Path>>(path: P, database: &GameDatabase) -> Result<Self> {
+        let save_path = path.as_ref().with_extension("sav");
+        let f = nesfile::NESFile::open_lenient(path, database)?;
+        Self::new(f, Some(save_path), None, Some(database))
+    }
+
+    /// Loads a ROM from the contents of an iNES file, without touching the filesystem.
+    ///
+    /// Non-volatile data of the cartridge is not persisted.
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let f = nesfile::NESFile::from_bytes(data.to_vec())?;
+        Self::new(f, None, None, None)
+    }
+
+    /// Same as `ROM::from_bytes`, with the IPS `patch` applied.
+    pub fn from_bytes_with_patch(data: &[u8], patch: &[u8]) -> Result<Self> {
+        let f = nesfile::NESFile::from_bytes_with_patch(data.to_vec(), patch)?;
+        Self::new(f, None, None, None)
+    }
+
+    /// Same as `ROM::from_bytes`, building its mapper by `registry` if registered.
+    pub fn from_bytes_with_registry(data: &[u8], registry: &MapperRegistry) -> Result<Self> {
+        let f = nesfile::NESFile::from_bytes(data.to_vec())?;
+        Self::new(f, None, Some(registry), None)
+    }
+
+    // With the battery flag, the save RAM is loaded from `save_path` if it exists.
+    // The trainer is then written to $7000-$71FF, for mappers with PRG-RAM there.
+    fn new(
+        mut f: nesfile::NESFile,
+        save_path: Option<PathBuf>,
+        registry: Option<&MapperRegistry>,
+        database: Option<&GameDatabase>,
+    ) -> Result<Self> {
+        let crc32 = hash::crc32(f.prg_chr_rom());
+        let sha1 = hash::sha1(f.prg_chr_rom());
+        let console_type = f.console_type();
+        if let ConsoleType::VSSystem | ConsoleType::Extended(_) = console_type {
+            return Err(MapperError::UnsupportedConsole(console_type).into());
+        }
+        let mut diagnostics = f.diagnostics().to_vec();
+        diagnostics.extend(f.overdumps());
+        let cartridge = registry::Cartridge::new(&f);
+        let prg_rom = cartridge.prg_rom;
+        let chr_rom = cartridge.chr_rom;
+        let game = database.and_then(|db| db.get(crc32)).cloned();
+        if let Some(game) = &game {
+            f.correct(game);
+        }
+
+        let battery = f.battery();
+        let sram_path = save_path.clone().filter(|_| battery);
+        let trainer = f.trainer().map(<[u8]>::to_vec);
+        let mapper = match registry.and_then(|r| r.build(&f)) {
+            Some(mapper) => mapper?,
+            None => Self::new_mapper(f, save_path.clone())?,
+        };
+        let mut rom = Self {
+            mapper,
+            battery,
+            crc32,
+            sha1,
+            prg_rom,
+            chr_rom,
+            console_type,
+            diagnostics,
+            game,
+            save_path,
+            sram_path,
+        };
+        rom.load_sram()?;
+        if let Some(trainer) = trainer {
+            for (i, b) in trainer.iter().enumerate() {
+                rom.mapper.write((0x7000 + i as u16).into(), (*b).into());
+            }
+        }
+        Ok(rom)
+    }
+
+    fn new_mapper(f: nesfile::NESFile, save_path: Option<PathBuf>) -> Result<Box<dyn Mapper>> {
         let mapper_no = f.mapper_no();
-        let mapper = if mapper_no == 0 {
-            Ok(mapper_0::Mapper0::new(f))
+        let mapper: Box<dyn Mapper> = match mapper_no {
+            0 => Box::new(mapper_0::Mapper0::new(f)),
+            2 => Box::new(mapper_2::Mapper2::new(f)),
+            3 => Box::new(mapper_3::Mapper3::new(f)),
+            4 => Box::new(mapper_4::Mapper4::new(f)),
+            9 => Box::new(mapper_9::Mapper9::new(f)),
+            11 => Box::new(mapper_11::Mapper11::new(f)),
+            19 => Box::new(namco163::Namco163::new(f)),
+            21 | 22 | 23 | 25 => Box::new(vrc4::VRC4::new(f)),
+            24 | 26 => Box::new(vrc6::VRC6::new(f)),
+            30 => Box::new(mapper_30::Mapper30::new(f, save_path)?),
+            34 => Box::new(mapper_34::Mapper34::new(f)),
+            66 => Box::new(mapper_66::Mapper66::new(f)),
+            69 => Box::new(fme7::FME7::new(f)),
+            85 => Box::new(vrc7::VRC7::new(f)),
+            _ => return Err(MapperError::UnsupportedMapper(mapper_no).into()),
+        };
+        Ok(mapper)
+    }
+
+    /// Returns true if the cartridge has the battery flag, keeping its save RAM while powered off.
+    pub fn battery(&self) -> bool {
+        self.battery
+    }
+
+    /// CRC-32 of PRG-ROM and CHR-ROM, excluding the header and trainer, as in NesCartDB.
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// SHA-1 of PRG-ROM and CHR-ROM, excluding the header and trainer, as in NesCartDB.
+    pub fn sha1(&self) -> [u8; 20] {
+        self.sha1
+    }
+
+    /// NES, or PlayChoice-10 whose INST-ROM is ignored. Other consoles are rejected on load.
+    pub fn console_type(&self) -> ConsoleType {
+        self.console_type
+    }
+
+    /// Problems found in the ROM file, such as overdumps and dirty headers.
+    pub fn diagnostics(&self) -> &[ROMDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// Removes the overdumps reported by `ROM::diagnostics` from the contents of an iNES file,
+    /// returning the file to load instead.
+    pub fn trim_overdump(data: &[u8]) -> Result<Vec<u8>> {
+        Ok(nesfile::NESFile::from_bytes(data.to_vec())?.trimmed())
+    }
+
+    /// PRG-ROM as in the ROM file.
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    /// CHR-ROM as in the ROM file, empty for cartridges with CHR RAM.
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    /// Writes PRG-ROM to `<prefix>.prg` and CHR-ROM to `<prefix>.chr`, for disassemblers and
+    /// build pipelines. The CHR file is not written for cartridges with CHR RAM.
+    pub fn export_prg_chr<P: AsRef<Path>>(&self, prefix: P) -> Result<()> {
+        let prefix = prefix.as_ref().as_os_str();
+        let path = |extension: &str| {
+            let mut path = prefix.to_os_string();
+            path.push(extension);
+            PathBuf::from(path)
+        };
+        std::fs::write(path(".prg"), &self.prg_rom)?;
+        if !self.chr_rom.is_empty() {
+            std::fs::write(path(".chr"), &self.chr_rom)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes the tiles of CHR-ROM into a grayscale sheet. `None` for cartridges with CHR RAM.
+    pub fn chr_sheet(&self) -> Option<Screenshot> {
+        if self.chr_rom.is_empty() {
+            None
         } else {
-            Err(MapperError::UnsupportedMapper(f.mapper_no()))
-        }?;
-        Ok(Self {
-            mapper: Rc::new(RefCell::new(mapper)),
-        })
+            Some(chr_sheet(&self.chr_rom))
+        }
+    }
+
+    /// The game identified by `ROM::load_with_database`.
+    pub fn game(&self) -> Option<&GameInfo> {
+        self.game.as_ref()
+    }
+
+    /// Path of the `.sav` file next to the ROM file, kept only for battery-backed cartridges.
+    pub fn sram_path(&self) -> Option<&Path> {
+        self.sram_path.as_deref()
+    }
+
+    /// Moves the `.sav` file of a battery-backed cartridge to `path`, such as into a directory of
+    /// saves, and loads the battery-backed RAM from it. Returns false if there is none.
+    ///
+    /// The save-state slots of any cartridge move next to `path`. Self-flashed PRG is still
+    /// written next to the ROM file.
+    pub fn set_sram_path<P: Into<PathBuf>>(&mut self, path: P) -> Result<bool> {
+        let path = path.into();
+        self.save_path = Some(path.clone());
+        if !self.battery {
+            return Ok(false);
+        }
+        self.sram_path = Some(path);
+        self.load_sram()
+    }
+
+    /// Path of the save state in the slot `n` by `NES::save_slot`, `<ROM name>.state<n>` next to
+    /// the `.sav` file. `None` for a ROM not loaded from a file.
+    pub fn slot_path(&self, n: usize) -> Option<PathBuf> {
+        let path = self.save_path.as_ref()?;
+        Some(path.with_extension(format!("state{}", n)))
+    }
+
+    /// Reloads the battery-backed RAM from the `.sav` file. Returns false if there is none.
+    pub fn load_sram(&mut self) -> Result<bool> {
+        let path = match &self.sram_path {
+            Some(path) if path.exists() && self.export_save_ram().is_some() => path,
+            _ => return Ok(false),
+        };
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read save file: {}", path.display()))?;
+        self.import_save_ram(&data)?;
+        Ok(true)
+    }
+
+    /// Writes the battery-backed RAM to the `.sav` file.
+    pub fn save_sram(&self) -> Result<()> {
+        if let (Some(path), Some(ram)) = (&self.sram_path, self.export_save_ram()) {
+            std::fs::write(path, ram)
+                .with_context(|| format!("Failed to write save file: {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Exports the save RAM in the raw `.sav` layout used by other emulators.
+    pub fn export_save_ram(&self) -> Option<Vec<u8>> {
+        self.mapper.save_ram()
+    }
+
+    /// Imports save RAM in the raw `.sav` layout used by other emulators.
+    ///
+    /// Data shorter than the RAM, such as saves without the internal RAM of some mappers,
+    /// is loaded to the head of the RAM.
+    pub fn import_save_ram(&mut self, data: &[u8]) -> Result<()> {
+        let expected = self.export_save_ram().ok_or(MapperError::NoSaveRAM)?.len();
+        if expected < data.len() {
+            return Err(MapperError::SaveRAMSize {
+                expected,
+                actual: data.len(),
+            }
+            .into());
+        }
+        self.mapper.load_save_ram(data);
+        Ok(())
     }
 }
 
+// Copies the saved data to the head of the RAM; the rest is left as is.
+fn copy_save_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
 #[derive(Debug, Error)]
 enum MapperError {
     #[error("Mapper no {0} does not supported")]
     UnsupportedMapper(u8),
+    #[error("{0:?} ROMs are not supported")]
+    UnsupportedConsole(ConsoleType),
+    #[error("The cartridge has no save RAM")]
+    NoSaveRAM,
+    #[error("The save data is {actual} bytes, larger than the save RAM of {expected} bytes")]
+    SaveRAMSize { expected: usize, actual: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Byte, Word};
+
+    fn rom_bytes(mapper_no: u8, flags6: u8) -> Vec<u8> {
+        let mut data = vec![
+            0x4E,
+            0x45,
+            0x53,
+            0x1A,
+            2,
+            1,
+            mapper_no << 4 | flags6,
+            mapper_no & 0xF0,
+        ];
+        data.extend(vec![0; 8]);
+        data.extend(vec![0; 0x8000 + 0x2000]);
+        data
+    }
+
+    fn new_rom(mapper_no: u8) -> ROM {
+        ROM::from_bytes(&rom_bytes(mapper_no, 0)).unwrap()
+    }
+
+    #[test]
+    fn trainer() {
+        let mut data = rom_bytes(0, 0b100);
+        let trainer: Vec<u8> = (0..0x200).map(|i| i as u8).collect();
+        data.splice(16..16, trainer);
+        data[16 + 0x200] = 0xA9;
+        let rom = ROM::from_bytes(&data).unwrap();
+        let mapper = &rom.mapper;
+        assert_eq!(mapper.peek(0x7000u16.into()), 0x00.into());
+        assert_eq!(mapper.peek(0x71FFu16.into()), 0xFF.into());
+        // PRG-ROM after the trainer
+        assert_eq!(mapper.peek(0x8000u16.into()), 0xA9.into());
+    }
+
+    #[test]
+    fn database() {
+        let dir = std::env::temp_dir().join(format!("rustnes-database-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.nes");
+        let data = rom_bytes(0, 0);
+        std::fs::write(&path, &data).unwrap();
+
+        let crc32 = hash::crc32(&data[16..]);
+        let db = GameDatabase::parse(&format!("{:08X},4,0,V,0,32,8,Game", crc32)).unwrap();
+        let rom = ROM::load_with_database(&path, &db).unwrap();
+        assert_eq!(rom.crc32(), crc32);
+        assert_eq!(rom.sha1(), hash::sha1(&data[16..]));
+        assert_eq!(rom.game().unwrap().name, "Game");
+        assert_eq!(rom.mapper.mirroring(), Mirroring::Vertical());
+        // MMC3 instead of NROM of the header
+        assert!(rom.export_save_ram().is_some());
+
+        let rom = ROM::load_with_database(&path, &GameDatabase::new()).unwrap();
+        assert!(rom.game().is_none());
+        assert_eq!(rom.mapper.mirroring(), Mirroring::Horizontal());
+
+        // headerless
+        std::fs::write(&path, &data[16..]).unwrap();
+        assert!(ROM::load_with_database(&path, &db).is_err());
+        let rom = ROM::load_lenient(&path, &db).unwrap();
+        assert_eq!(rom.crc32(), crc32);
+        assert_eq!(rom.mapper.mirroring(), Mirroring::Vertical());
+        assert!(ROM::load_lenient(&path, &GameDatabase::new()).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn patch() {
+        let mut patch = b"PATCH".to_vec();
+        // to mapper 4 at flags6 and flags7
+        patch.extend(&[0, 0, 6, 0, 2, 0x40, 0x00]);
+        patch.extend(&[0, 0, 16, 0, 1, 0xEA]);
+        patch.extend(b"EOF");
+
+        let rom = ROM::from_bytes_with_patch(&rom_bytes(0, 0), &patch).unwrap();
+        assert!(rom.export_save_ram().is_some());
+        assert_eq!(rom.mapper.peek(0x8000u16.into()), 0xEA.into());
+        assert!(ROM::from_bytes_with_patch(&rom_bytes(0, 0), b"PATCH").is_err());
+    }
+
+    #[test]
+    fn prg_chr() {
+        let mut data = rom_bytes(0, 0);
+        data[16] = 0x01;
+        data[16 + 0x8000] = 0x02;
+        let rom = ROM::from_bytes(&data).unwrap();
+        assert_eq!(rom.prg_rom().len(), 0x8000);
+        assert_eq!(rom.prg_rom()[0], 0x01);
+        assert_eq!(rom.chr_rom().len(), 0x2000);
+        assert_eq!(rom.chr_rom()[0], 0x02);
+
+        let dir = std::env::temp_dir().join(format!("rustnes-prg-chr-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        rom.export_prg_chr(dir.join("game")).unwrap();
+        assert_eq!(std::fs::read(dir.join("game.prg")).unwrap(), rom.prg_rom());
+        assert_eq!(std::fs::read(dir.join("game.chr")).unwrap(), rom.chr_rom());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overdump() {
+        let mut data = rom_bytes(0, 0);
+        data[16] = 0x01;
+        data[16 + 0x4000] = 0x01;
+        data.extend(&[0xFF; 4]);
+        let rom = ROM::from_bytes(&data).unwrap();
+        assert_eq!(
+            rom.diagnostics(),
+            &[
+                ROMDiagnostic::DuplicatedPRG {
+                    size: 0x8000,
+                    unique_size: 0x4000
+                },
+                ROMDiagnostic::TrailingData { size: 4 }
+            ]
+        );
+
+        let trimmed = ROM::trim_overdump(&data).unwrap();
+        assert_eq!(trimmed.len(), 16 + 0x4000 + 0x2000);
+        let rom = ROM::from_bytes(&trimmed).unwrap();
+        assert!(rom.diagnostics().is_empty());
+        assert_eq!(rom.mapper.peek(0xC000u16.into()), 0x01.into());
+    }
+
+    #[test]
+    fn sram() {
+        let dir = std::env::temp_dir().join(format!("rustnes-sram-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("battery.nes");
+        std::fs::write(&path, rom_bytes(0, 0b10)).unwrap();
+
+        let mut rom = ROM::load(&path).unwrap();
+        assert!(rom.battery());
+        assert_eq!(rom.sram_path(), Some(dir.join("battery.sav").as_path()));
+        assert_eq!(rom.slot_path(3), Some(dir.join("battery.state3")));
+        rom.mapper.write(0x6000u16.into(), 0x12.into());
+        rom.save_sram().unwrap();
+
+        let rom = ROM::load(&path).unwrap();
+        assert_eq!(rom.mapper.peek(0x6000u16.into()), 0x12.into());
+
+        let moved = dir.join("saves.sav");
+        let mut rom = ROM::load(&path).unwrap();
+        assert!(!rom.set_sram_path(&moved).unwrap());
+        rom.save_sram().unwrap();
+        assert_eq!(std::fs::read(&moved).unwrap()[0], 0x12);
+        std::fs::write(&moved, [0x34]).unwrap();
+        let mut rom = ROM::load(&path).unwrap();
+        assert!(rom.set_sram_path(&moved).unwrap());
+        assert_eq!(rom.mapper.peek(0x6000u16.into()), 0x34.into());
+        assert_eq!(rom.slot_path(0), Some(dir.join("saves.state0")));
+
+        // without the battery
+        std::fs::write(&path, rom_bytes(0, 0)).unwrap();
+        let rom = ROM::load(&path).unwrap();
+        assert!(!rom.battery());
+        assert_eq!(rom.sram_path(), None);
+        assert_eq!(rom.slot_path(0), Some(dir.join("battery.state0")));
+        assert_eq!(rom.mapper.peek(0x6000u16.into()), 0.into());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_ram() {
+        let mut rom = new_rom(4);
+        rom.mapper.write(0x6000u16.into(), 0x12.into());
+        let mut ram = rom.export_save_ram().unwrap();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(ram[0], 0x12);
+
+        ram[1] = 0x34;
+        rom.import_save_ram(&ram[..2]).unwrap();
+        assert_eq!(rom.mapper.peek(0x6001u16.into()), 0x34.into());
+        assert!(rom.import_save_ram(&[0; 0x2001]).is_err());
+
+        assert!(new_rom(0).export_save_ram().is_none());
+        assert!(new_rom(0).import_save_ram(&ram).is_err());
+    }
+
+    struct TestMapper(Cartridge);
+
+    impl Memory for TestMapper {
+        fn peek(&self, _addr: Word) -> Byte {
+            self.0.mapper_no.into()
+        }
+
+        fn write(&mut self, _addr: Word, _value: Byte) {}
+    }
+
+    impl Mapper for TestMapper {
+        fn mirroring(&self) -> Mirroring {
+            self.0.mirroring
+        }
+    }
+
+    #[test]
+    fn registry() {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x81, 0xC0];
+        data.extend(vec![0; 8]);
+        data.extend(vec![0; 0x8000 + 0x2000]);
+        assert!(ROM::from_bytes(&data).is_err());
+
+        let mut registry = MapperRegistry::new();
+        registry.register(200, |c| Ok(TestMapper(c.clone())));
+        let rom = ROM::from_bytes_with_registry(&data, &registry).unwrap();
+        assert_eq!(rom.mapper.peek(0x8000u16.into()), 200.into());
+        assert_eq!(rom.mapper.mirroring(), Mirroring::Vertical());
+    }
 }
@@ -0,0 +1,111 @@
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+
+// Switchable windows over PRG/CHR memory shared by mappers.
+//
+// The address space of `window_count * bank_size` bytes is divided into windows,
+// and each window is mapped to a bank of `bank_size` bytes.
+pub(super) struct Banks {
+    data: Vec<u8>,
+    bank_size: usize,
+    windows: Vec<usize>,
+    // writable and saved in save states, ROM otherwise
+    ram: bool,
+}
+
+impl Banks {
+    pub fn new(data: Vec<u8>, window_count: usize, bank_size: usize) -> Self {
+        let mut banks = Self {
+            data,
+            bank_size,
+            windows: vec![0; window_count],
+            ram: false,
+        };
+        for w in 0..window_count {
+            banks.select(w, w as isize);
+        }
+        banks
+    }
+
+    /// Makes the data RAM, such as CHR-RAM. Writes to ROM are ignored.
+    pub fn set_ram(&mut self, ram: bool) {
+        self.ram = ram;
+    }
+
+    pub fn ram(&self) -> bool {
+        self.ram
+    }
+
+    pub fn bank_count(&self) -> usize {
+        (self.data.len() / self.bank_size).max(1)
+    }
+
+    /// Maps the bank to the window. Negative banks count from the last bank.
+    pub fn select(&mut self, window: usize, bank: isize) {
+        let count = self.bank_count() as isize;
+        let bank = bank.rem_euclid(count) as usize;
+        self.windows[window] = bank * self.bank_size;
+    }
+
+    fn index(&self, addr: usize) -> usize {
+        let window = (addr / self.bank_size) % self.windows.len();
+        (self.windows[window] + addr % self.bank_size) % self.data.len()
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        self.data[self.index(addr)]
+    }
+
+    pub fn write(&mut self, addr: usize, value: u8) {
+        if !self.ram {
+            return;
+        }
+        let i = self.index(addr);
+        self.data[i] = value;
+    }
+
+    /// Writes the banks selected, and the data too if it is RAM.
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for window in &self.windows {
+            w.u32(*window as u32);
+        }
+        if self.ram {
+            w.bytes(&self.data);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        for window in self.windows.iter_mut() {
+            *window = r.u32()? as usize % self.data.len();
+        }
+        if self.ram {
+            r.bytes_into(&mut self.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select() {
+        let data = (0..4).flat_map(|n| vec![n; 0x400]).collect();
+        let mut banks = Banks::new(data, 2, 0x400);
+        assert_eq!(banks.read(0x000), 0);
+        assert_eq!(banks.read(0x400), 1);
+
+        banks.select(0, 6);
+        assert_eq!(banks.read(0x3FF), 2);
+        banks.select(1, -1);
+        assert_eq!(banks.read(0x400), 3);
+
+        banks.write(0x401, 9);
+        assert_eq!(banks.read(0x401), 3);
+        banks.set_ram(true);
+        banks.write(0x401, 9);
+        assert_eq!(banks.read(0x401), 9);
+    }
+}
@@ -0,0 +1,27 @@
+// https://wiki.nesdev.com/w/index.php/Bus_conflict
+//
+// On discrete boards the ROM keeps driving the data bus while the CPU writes to a register mapped
+// over it, so the register latches the written value ANDed with the ROM byte at that address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum BusConflict {
+    None,
+    And,
+}
+
+impl BusConflict {
+    // Submapper 1 has no bus conflicts, 2 has AND-type bus conflicts.
+    // Unspecified (0) falls back to AND, as most of the original carts.
+    pub fn from_submapper(submapper: u8) -> Self {
+        match submapper {
+            1 => Self::None,
+            _ => Self::And,
+        }
+    }
+
+    pub fn apply(self, value: u8, rom: u8) -> u8 {
+        match self {
+            Self::None => value,
+            Self::And => value & rom,
+        }
+    }
+}
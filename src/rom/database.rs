@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::types::Mirroring;
+
+/// A game identified by the CRC-32 of its PRG-ROM and CHR-ROM, with the board it runs on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameInfo {
+    pub name: String,
+    pub mapper_no: u8,
+    pub submapper_no: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    /// Size of PRG-ROM in bytes.
+    pub prg_rom_size: usize,
+    /// Size of CHR-ROM in bytes, 0 for CHR RAM.
+    pub chr_rom_size: usize,
+}
+
+/// Games by CRC-32, for identifying ROMs and correcting bad iNES headers.
+///
+/// The database is written one game per line, in comma-separated fields of
+/// `crc32,mapper,submapper,mirroring,battery,prg_kb,chr_kb,name` as exported from NesCartDB:
+///
+/// ```text
+/// # comment
+/// 0123ABCD,4,0,V,1,128,128,Some Game (USA)
+/// ```
+///
+/// The mirroring is `H` (horizontal), `V` (vertical) or `4` (four-screen), and the battery is
+/// `0` or `1`. The ROM sizes are in KB. The name may contain commas.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameDatabase {
+    games: HashMap<u32, GameInfo>,
+}
+
+impl GameDatabase {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let s = std::fs::read_to_string(path.as_ref()).with_context(|| {
+            format!(
+                "Failed to open game database: {}",
+                path.as_ref().to_str().unwrap_or("unknown")
+            )
+        })?;
+        Self::parse(&s)
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut db = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (crc32, game) =
+                parse_line(line).ok_or(GameDatabaseError::InvalidLine { line: i + 1 })?;
+            db.insert(crc32, game);
+        }
+        Ok(db)
+    }
+
+    pub fn insert(&mut self, crc32: u32, game: GameInfo) {
+        self.games.insert(crc32, game);
+    }
+
+    pub fn get(&self, crc32: u32) -> Option<&GameInfo> {
+        self.games.get(&crc32)
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u32, GameInfo)> {
+    let mut fields = line.splitn(8, ',').map(str::trim);
+    let crc32 = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let mapper_no = fields.next()?.parse().ok()?;
+    let submapper_no = fields.next()?.parse().ok()?;
+    let mirroring = match fields.next()? {
+        "H" => Mirroring::Horizontal(),
+        "V" => Mirroring::Vertical(),
+        "4" => Mirroring::FourScreen(),
+        _ => return None,
+    };
+    let battery = match fields.next()? {
+        "0" => false,
+        "1" => true,
+        _ => return None,
+    };
+    let prg_rom_size = fields.next()?.parse::<usize>().ok()? * 0x400;
+    let chr_rom_size = fields.next()?.parse::<usize>().ok()? * 0x400;
+    let name = fields.next()?.to_string();
+    Some((
+        crc32,
+        GameInfo {
+            name,
+            mapper_no,
+            submapper_no,
+            mirroring,
+            battery,
+            prg_rom_size,
+            chr_rom_size,
+        },
+    ))
+}
+
+#[derive(Debug, Error)]
+enum GameDatabaseError {
+    #[error("Invalid game at line {line} of the database")]
+    InvalidLine { line: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let db =
+            GameDatabase::parse("# games\n\n0123abcd, 4, 1, 4, 1, 128, 0, Foo, Bar\n").unwrap();
+        assert_eq!(db.len(), 1);
+        assert_eq!(
+            db.get(0x0123ABCD),
+            Some(&GameInfo {
+                name: "Foo, Bar".to_string(),
+                mapper_no: 4,
+                submapper_no: 1,
+                mirroring: Mirroring::FourScreen(),
+                battery: true,
+                prg_rom_size: 0x20000,
+                chr_rom_size: 0,
+            })
+        );
+
+        assert!(GameDatabase::parse("0123abcd,4,0,X,0,32,8,Foo").is_err());
+        assert!(GameDatabase::parse("0123abcd,4,0,H,0,32,8").is_err());
+    }
+}
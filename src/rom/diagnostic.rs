@@ -0,0 +1,54 @@
+use std::fmt;
+
+/// A problem found in a ROM file that was loaded anyway, such as a bad dump or a dirty header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ROMDiagnostic {
+    /// PRG-ROM consists of copies of its first `unique_size` bytes, as dumped from a larger chip.
+    DuplicatedPRG { size: usize, unique_size: usize },
+    /// CHR-ROM consists of copies of its first `unique_size` bytes, as dumped from a larger chip.
+    DuplicatedCHR { size: usize, unique_size: usize },
+    /// Data after PRG-ROM and CHR-ROM, which is ignored.
+    TrailingData { size: usize },
+    /// Bytes 7-15 of the iNES header have garbage such as `DiskDude!`, so they are ignored.
+    DirtyHeader { text: String },
+    /// The header is in the archaic iNES format, whose bytes 7-15 are ignored.
+    ArchaicHeader,
+    /// The header says PRG-ROM is 0KB, so it is taken as the rest of the file.
+    ZeroPRGSize { inferred_size: usize },
+}
+
+impl fmt::Display for ROMDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DuplicatedPRG { size, unique_size } => write!(
+                f,
+                "PRG-ROM of {}KB repeats the first {}KB; the file may be overdumped",
+                size / 0x400,
+                unique_size / 0x400
+            ),
+            Self::DuplicatedCHR { size, unique_size } => write!(
+                f,
+                "CHR-ROM of {}KB repeats the first {}KB; the file may be overdumped",
+                size / 0x400,
+                unique_size / 0x400
+            ),
+            Self::TrailingData { size } => {
+                write!(f, "{} bytes after CHR-ROM are ignored", size)
+            }
+            Self::DirtyHeader { text } => write!(
+                f,
+                "the header has garbage \"{}\"; the upper mapper bits are ignored",
+                text
+            ),
+            Self::ArchaicHeader => write!(
+                f,
+                "the header is in the archaic iNES format; the upper mapper bits are ignored"
+            ),
+            Self::ZeroPRGSize { inferred_size } => write!(
+                f,
+                "the header has no PRG-ROM size; taken as {}KB from the file size",
+                inferred_size / 0x400
+            ),
+        }
+    }
+}
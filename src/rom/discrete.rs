@@ -0,0 +1,96 @@
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::Mirroring;
+
+use super::bank::Banks;
+use super::bus_conflict::BusConflict;
+use super::nesfile::NESFile;
+
+// Discrete boards with a single latch at $8000-$FFFF selecting a 32KB PRG bank and an 8KB CHR bank,
+// such as GxROM and Color Dreams. Each board decodes the latched value differently.
+pub(super) struct DiscreteLatch {
+    prg: Banks,
+    chr: Banks,
+    pub mirroring: Mirroring,
+    bus_conflict: BusConflict,
+}
+
+impl DiscreteLatch {
+    pub fn new(rom: &NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut mapper = Self::with_banks(
+            prg,
+            chr,
+            rom.mirroring(),
+            BusConflict::from_submapper(rom.submapper_no()),
+        );
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    pub fn with_banks(
+        prg: Vec<u8>,
+        chr: Vec<u8>,
+        mirroring: Mirroring,
+        bus_conflict: BusConflict,
+    ) -> Self {
+        Self {
+            prg: Banks::new(prg, 1, 0x8000),
+            chr: Banks::new(chr, 1, 0x2000),
+            mirroring,
+            bus_conflict,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+    }
+
+    /// Returns the value latched by a write to the register.
+    pub fn write(&mut self, addr: u16, value: u8) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.chr.write(addr as usize, value);
+                None
+            }
+            0x8000..=0xFFFF => {
+                let rom = self.prg.read(addr as usize - 0x8000);
+                Some(self.bus_conflict.apply(value, rom))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn select(&mut self, prg: u8, chr: u8) {
+        self.prg.select(0, prg as isize);
+        self.chr.select(0, chr as isize);
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.prg.save_state(w);
+        self.chr.save_state(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.prg.load_state(r)?;
+        self.chr.load_state(r)
+    }
+}
+
+#[cfg(test)]
+pub(super) fn test_latch() -> DiscreteLatch {
+    // 32KB PRG banks and 8KB CHR banks filled with its bank number
+    let prg = (0..4).flat_map(|n| vec![n; 0x8000]).collect();
+    let chr = (0..16).flat_map(|n| vec![n; 0x2000]).collect();
+    DiscreteLatch::with_banks(prg, chr, Mirroring::Vertical(), BusConflict::None)
+}
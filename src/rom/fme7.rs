@@ -0,0 +1,270 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::nesfile::NESFile;
+use super::sunsoft_5b::Sunsoft5B;
+use super::{copy_save_ram, Mapper};
+
+// Sunsoft FME-7 (mapper 69)
+// https://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
+pub struct FME7 {
+    prg: Banks,
+    chr: Banks,
+    prg_ram: [u8; 0x2000],
+    mirroring: Mirroring,
+
+    command: u8,
+    // $6000-$7FFF
+    prg_6000: u8,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5B,
+}
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+impl FME7 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut mapper = Self::with_banks(prg, chr, rom.mirroring());
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    fn with_banks(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        // the window 0 is $6000-$7FFF when it maps ROM
+        let mut prg = Banks::new(prg, 5, PRG_BANK_SIZE);
+        prg.select(4, -1);
+        Self {
+            prg,
+            chr: Banks::new(chr, 8, CHR_BANK_SIZE),
+            prg_ram: [0; 0x2000],
+            mirroring,
+            command: 0,
+            prg_6000: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5B::new(),
+        }
+    }
+
+    fn prg_ram_selected(&self) -> bool {
+        self.prg_6000 & 0x40 != 0
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_6000 & 0xC0 == 0xC0
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            n @ 0x0..=0x7 => self.chr.select(n as usize, value as isize),
+            0x8 => {
+                self.prg_6000 = value;
+                self.prg.select(0, (value & 0x3F) as isize);
+            }
+            n @ 0x9..=0xB => self.prg.select(n as usize - 8, (value & 0x3F) as isize),
+            0xC => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical(),
+                    1 => Mirroring::Horizontal(),
+                    n => Mirroring::SingleScreen(n - 2),
+                };
+            }
+            0xD => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = self.irq_counter & 0xFF00 | value as u16,
+            0xF => self.irq_counter = self.irq_counter & 0x00FF | (value as u16) << 8,
+            _ => {}
+        }
+    }
+}
+
+impl Memory for FME7 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[addr as usize - 0x6000],
+            0x6000..=0x7FFF if self.prg_ram_selected() => 0,
+            0x6000..=0xFFFF => self.prg.read(addr as usize - 0x6000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.write(addr as usize, value),
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[addr as usize - 0x6000] = value;
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.write_address(value),
+            0xE000..=0xFFFF => self.audio.write_data(value),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for FME7 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn cpu_cycle(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.step();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        Some(self.prg_ram.to_vec())
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.prg.save_state(&mut w);
+        self.chr.save_state(&mut w);
+        w.bytes(&self.prg_ram);
+        w.mirroring(self.mirroring);
+        w.u8(self.command);
+        w.u8(self.prg_6000);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_counter_enabled);
+        w.u16(self.irq_counter);
+        w.bool(self.irq_pending);
+        self.audio.save_state(&mut w);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg.load_state(&mut r)?;
+        self.chr.load_state(&mut r)?;
+        r.bytes_into(&mut self.prg_ram)?;
+        self.mirroring = r.mirroring()?;
+        self.command = r.u8()? & 0x0F;
+        self.prg_6000 = r.u8()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_counter_enabled = r.bool()?;
+        self.irq_counter = r.u16()?;
+        self.irq_pending = r.bool()?;
+        self.audio.load_state(&mut r)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> FME7 {
+        // 8KB PRG banks and 1KB CHR banks filled with its bank number
+        let prg = (0..16).flat_map(|n| vec![n; PRG_BANK_SIZE]).collect();
+        let chr = (0..64).flat_map(|n| vec![n; CHR_BANK_SIZE]).collect();
+        FME7::with_banks(prg, chr, Mirroring::Vertical())
+    }
+
+    fn write(m: &mut FME7, addr: u16, value: u8) {
+        m.write(addr.into(), value.into());
+    }
+
+    fn read(m: &FME7, addr: u16) -> u8 {
+        m.peek(addr.into()).into()
+    }
+
+    fn command(m: &mut FME7, command: u8, parameter: u8) {
+        write(m, 0x8000, command);
+        write(m, 0xA000, parameter);
+    }
+
+    #[test]
+    fn banks() {
+        let mut m = mapper();
+        command(&mut m, 0x9, 3);
+        command(&mut m, 0xB, 5);
+        command(&mut m, 0x2, 40);
+        assert_eq!(read(&m, 0x8000), 3);
+        assert_eq!(read(&m, 0xC000), 5);
+        assert_eq!(read(&m, 0xE000), 15);
+        assert_eq!(read(&m, 0x0800), 40);
+
+        // $6000: ROM, then RAM
+        command(&mut m, 0x8, 7);
+        assert_eq!(read(&m, 0x6000), 7);
+        command(&mut m, 0x8, 0xC0);
+        write(&mut m, 0x6000, 0x55);
+        assert_eq!(read(&m, 0x6000), 0x55);
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = mapper();
+        command(&mut m, 0xE, 1);
+        command(&mut m, 0xF, 0);
+        command(&mut m, 0xD, 0x81);
+        m.cpu_cycle();
+        assert!(!m.irq());
+        m.cpu_cycle();
+        assert!(m.irq());
+
+        // acknowledged by writing the control
+        command(&mut m, 0xD, 0x81);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn audio() {
+        let mut m = mapper();
+        for (r, v) in [(0x0, 0x10), (0x7, 0b110), (0x8, 0x0F)].iter() {
+            write(&mut m, 0xC000, *r);
+            write(&mut m, 0xE000, *v);
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..16 * 0x20 {
+            m.cpu_cycle();
+            outputs.push(m.audio_output());
+        }
+        // square wave of 0x10 ticks each
+        assert!(outputs.contains(&0.0));
+        assert!(outputs.iter().any(|o| 0.09 < *o));
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use thiserror::Error;
+
+// IPS patches
+// https://zerosoft.zophar.net/ips.php
+//
+// Offsets are into the whole ROM file including the iNES header.
+
+const MAGIC_NUMBER: &[u8] = b"PATCH";
+const EOF: u32 = 0x454F46;
+
+pub(super) fn apply(data: &mut Vec<u8>, patch: &[u8]) -> Result<()> {
+    if !patch.starts_with(MAGIC_NUMBER) {
+        return Err(IPSError::InvalidHeader.into());
+    }
+    let mut r = Reader {
+        patch,
+        pos: MAGIC_NUMBER.len(),
+    };
+    loop {
+        let offset = r.read(3)? as usize;
+        if offset as u32 == EOF {
+            break;
+        }
+        let size = r.read(2)? as usize;
+        if size == 0 {
+            // run-length encoded record
+            let count = r.read(2)? as usize;
+            let value = r.read(1)? as u8;
+            fill(data, offset, count, value);
+        } else {
+            let bytes = r.bytes(size)?;
+            if data.len() < offset + size {
+                data.resize(offset + size, 0);
+            }
+            data[offset..offset + size].copy_from_slice(bytes);
+        }
+    }
+    // the extension truncating the file
+    if let Ok(len) = r.read(3) {
+        data.truncate(len as usize);
+    }
+    Ok(())
+}
+
+fn fill(data: &mut Vec<u8>, offset: usize, count: usize, value: u8) {
+    if data.len() < offset + count {
+        data.resize(offset + count, 0);
+    }
+    for b in &mut data[offset..offset + count] {
+        *b = value;
+    }
+}
+
+struct Reader<'a> {
+    patch: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .patch
+            .get(self.pos..self.pos + n)
+            .ok_or(IPSError::UnexpectedEnd)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    // big endian
+    fn read(&mut self, n: usize) -> Result<u32> {
+        Ok(self
+            .bytes(n)?
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | *b as u32))
+    }
+}
+
+#[derive(Debug, Error)]
+enum IPSError {
+    #[error("The patch is not in IPS format")]
+    InvalidHeader,
+    #[error("The patch ends unexpectedly")]
+    UnexpectedEnd,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch() {
+        let mut data = vec![0; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        // RLE
+        patch.extend(&[0, 0, 6, 0, 0, 0, 4, 0xCC]);
+        patch.extend(b"EOF");
+        apply(&mut data, &patch).unwrap();
+        assert_eq!(data, vec![0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+
+        patch.extend(&[0, 0, 3]);
+        apply(&mut data, &patch).unwrap();
+        assert_eq!(data, vec![0, 0xAA, 0xBB]);
+
+        assert!(apply(&mut data, b"PATCH\0\0\0\0\x02\0").is_err());
+        assert!(apply(&mut data, b"PATCHED").is_err());
+        assert!(apply(&mut data, b"EOF").is_err());
+    }
+}
@@ -1,18 +1,24 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
 use crate::types::{Byte, Memory, Mirroring, Word};
 
-use super::nesfile::{NESFile, NESFileHeader};
-use super::Mapper;
+use super::nesfile::NESFile;
+use super::{copy_save_ram, Mapper};
 
 pub struct Mapper0 {
     prg: Vec<u8>,
     chr: Vec<u8>,
     mirroring: Mirroring,
     mirrored: bool,
+    chr_ram: bool,
+    // only on boards with the battery, such as Family BASIC, or for the trainer
+    prg_ram: Option<Vec<u8>>,
 }
 
 impl Mapper0 {
     pub fn new(rom: NESFile) -> Self {
-        let (prg, next) = rom.read_prg_rom(NESFileHeader::SIZE, 0x4000);
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
         let chr = if let Some((prg, _)) = rom.read_chr_rom(next, 0x2000) {
             prg
         } else {
@@ -24,6 +30,12 @@ impl Mapper0 {
             chr,
             mirroring: rom.mirroring(),
             mirrored,
+            chr_ram: rom.chr_ram(),
+            prg_ram: if rom.battery() || rom.trainer().is_some() {
+                Some(vec![0; 0x2000])
+            } else {
+                None
+            },
         }
     }
 
@@ -38,10 +50,14 @@ impl Mapper0 {
 }
 
 impl Memory for Mapper0 {
-    fn read(&self, addr: Word) -> Byte {
+    fn peek(&self, addr: Word) -> Byte {
         let addr: u16 = addr.into();
         match addr {
             0x0000..=0x1FFF => self.chr[addr as usize],
+            0x6000..=0x7FFF => match &self.prg_ram {
+                Some(ram) => ram[addr as usize - 0x6000],
+                None => 0,
+            },
             0x8000..=0xFFFF => self.prg[self.prg_addr(addr)],
             _ => 0,
         }
@@ -50,8 +66,14 @@ impl Memory for Mapper0 {
 
     fn write(&mut self, addr: Word, value: Byte) {
         let addr: u16 = addr.into();
-        if let 0x0000..=0x1FFF = addr {
-            self.chr[addr as usize] = value.into()
+        match addr {
+            0x0000..=0x1FFF if self.chr_ram => self.chr[addr as usize] = value.into(),
+            0x6000..=0x7FFF => {
+                if let Some(ram) = self.prg_ram.as_mut() {
+                    ram[addr as usize - 0x6000] = value.into()
+                }
+            }
+            _ => {}
         }
     }
 }
@@ -60,4 +82,36 @@ impl Mapper for Mapper0 {
     fn mirroring(&self) -> Mirroring {
         self.mirroring
     }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        if let Some(ram) = self.prg_ram.as_mut() {
+            copy_save_ram(ram, data);
+        }
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        if self.chr_ram {
+            w.bytes(&self.chr);
+        }
+        if let Some(ram) = &self.prg_ram {
+            w.bytes(ram);
+        }
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        if self.chr_ram {
+            r.bytes_into(&mut self.chr)?;
+        }
+        if let Some(ram) = self.prg_ram.as_mut() {
+            r.bytes_into(ram)?;
+        }
+        Ok(())
+    }
 }
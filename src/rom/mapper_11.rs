@@ -0,0 +1,69 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::discrete::DiscreteLatch;
+use super::nesfile::NESFile;
+use super::Mapper;
+
+// Color Dreams
+// https://wiki.nesdev.com/w/index.php/GxROM
+pub struct Mapper11 {
+    latch: DiscreteLatch,
+}
+
+impl Mapper11 {
+    pub fn new(rom: NESFile) -> Self {
+        Self {
+            latch: DiscreteLatch::new(&rom),
+        }
+    }
+}
+
+impl Memory for Mapper11 {
+    fn peek(&self, addr: Word) -> Byte {
+        self.latch.read(addr.into()).into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        if let Some(value) = self.latch.write(addr.into(), value.into()) {
+            // CCCC --PP
+            self.latch.select(value & 0b11, value >> 4);
+        }
+    }
+}
+
+impl Mapper for Mapper11 {
+    fn mirroring(&self) -> Mirroring {
+        self.latch.mirroring
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.latch.save_state(&mut w);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        self.latch.load_state(&mut StateReader::new(data))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::discrete::test_latch;
+
+    #[test]
+    fn bank_select() {
+        let mut m = Mapper11 {
+            latch: test_latch(),
+        };
+        m.write(0x8000u16.into(), 0xA1.into());
+        assert_eq!(m.read(0x8000u16.into()), 1.into());
+        assert_eq!(m.read(0xFFFFu16.into()), 1.into());
+        assert_eq!(m.read(0x1FFFu16.into()), 0xA.into());
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bus_conflict::BusConflict;
+use super::nesfile::NESFile;
+use super::Mapper;
+
+// UxROM
+// https://wiki.nesdev.com/w/index.php/UxROM
+pub struct Mapper2 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    mirroring: Mirroring,
+    bus_conflict: BusConflict,
+    chr_ram: bool,
+
+    bank: usize,
+    last_bank: usize,
+}
+
+const BANK_SIZE: usize = 0x4000;
+
+impl Mapper2 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), BANK_SIZE);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let last_bank = (prg.len() / BANK_SIZE).saturating_sub(1);
+        Self {
+            prg,
+            chr,
+            mirroring: rom.mirroring(),
+            bus_conflict: BusConflict::from_submapper(rom.submapper_no()),
+            chr_ram: rom.chr_ram(),
+            bank: 0,
+            last_bank,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank = if addr < 0xC000 {
+            self.bank
+        } else {
+            self.last_bank
+        };
+        bank * BANK_SIZE + (addr as usize & (BANK_SIZE - 1))
+    }
+}
+
+impl Memory for Mapper2 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr[addr as usize],
+            0x8000..=0xFFFF => self.prg[self.prg_addr(addr)],
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF if self.chr_ram => self.chr[addr as usize] = value.into(),
+            0x8000..=0xFFFF => {
+                let rom = self.prg[self.prg_addr(addr)];
+                let value = self.bus_conflict.apply(value.into(), rom);
+                self.bank = value as usize % (self.last_bank + 1);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper2 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        w.u32(self.bank as u32);
+        if self.chr_ram {
+            w.bytes(&self.chr);
+        }
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.bank = r.u32()? as usize % (self.last_bank + 1);
+        if self.chr_ram {
+            r.bytes_into(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper(bus_conflict: BusConflict) -> Mapper2 {
+        // 4 banks filled with its bank number, except a byte used as the write target
+        let mut prg: Vec<u8> = (0..4).flat_map(|n| vec![n; BANK_SIZE]).collect();
+        prg[0] = 0b01;
+        Mapper2 {
+            prg,
+            chr: vec![0; 0x2000],
+            mirroring: Mirroring::Vertical(),
+            bus_conflict,
+            chr_ram: true,
+            bank: 0,
+            last_bank: 3,
+        }
+    }
+
+    #[test]
+    fn bank_switch() {
+        let mut m = mapper(BusConflict::None);
+        assert_eq!(m.read(0xC000u16.into()), 3.into());
+
+        m.write(0x8000u16.into(), 2.into());
+        assert_eq!(m.read(0x8001u16.into()), 2.into());
+        assert_eq!(m.read(0xFFFFu16.into()), 3.into());
+    }
+
+    #[test]
+    fn bus_conflict() {
+        let mut m = mapper(BusConflict::And);
+        m.write(0x8000u16.into(), 0b10.into());
+        assert_eq!(m.bank, 0);
+
+        // write to a ROM byte holding the same value
+        m.write(0xC000u16.into(), 0b10.into());
+        assert_eq!(m.bank, 2);
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::bus_conflict::BusConflict;
+use super::nesfile::NESFile;
+use super::Mapper;
+
+// CNROM
+// https://wiki.nesdev.com/w/index.php/CNROM
+pub struct Mapper3 {
+    prg: Banks,
+    chr: Banks,
+    mirroring: Mirroring,
+    bus_conflict: BusConflict,
+}
+
+impl Mapper3 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut chr = Banks::new(chr, 1, 0x2000);
+        chr.set_ram(rom.chr_ram());
+        Self {
+            // 16KB PRG is mirrored at $C000
+            prg: Banks::new(prg, 2, 0x4000),
+            chr,
+            mirroring: rom.mirroring(),
+            bus_conflict: BusConflict::from_submapper(rom.submapper_no()),
+        }
+    }
+}
+
+impl Memory for Mapper3 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.write(addr as usize, value.into()),
+            0x8000..=0xFFFF => {
+                let rom = self.prg.read(addr as usize - 0x8000);
+                let value = self.bus_conflict.apply(value.into(), rom);
+                self.chr.select(0, value as isize);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper3 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.chr.save_state(&mut w);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        self.chr.load_state(&mut StateReader::new(data))?;
+        Ok(())
+    }
+}
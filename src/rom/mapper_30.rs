@@ -0,0 +1,467 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bus_conflict::BusConflict;
+use super::nesfile::NESFile;
+use super::{Mapper, VRAMSource};
+
+// UNROM 512
+// https://wiki.nesdev.com/w/index.php/UNROM_512
+pub struct Mapper30 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    mirroring: Mirroring,
+    nametables: Nametables,
+    chr_ram: bool,
+
+    prg_bank: usize,
+    last_bank: usize,
+    chr_bank: usize,
+    // CIRAM page for one-screen mirroring
+    screen: u8,
+
+    // Flashable boards have the battery flag, and no bus conflicts
+    flash: Option<Flash>,
+}
+
+// Nametable arrangement by the header's four-screen and mirroring bits
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Nametables {
+    // Horizontal or vertical by the mirroring bit
+    Fixed,
+    // Switched by the bit 7 of the bank select
+    OneScreen,
+    // The last 8KB of CHR RAM
+    FourScreen,
+}
+
+impl Nametables {
+    fn new(rom: &NESFile) -> Self {
+        match (rom.four_screen(), rom.hardwired_mirroring()) {
+            (false, _) => Self::Fixed,
+            (true, Mirroring::Horizontal()) => Self::OneScreen,
+            (true, _) => Self::FourScreen,
+        }
+    }
+}
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x8000;
+
+impl Mapper30 {
+    pub fn new(rom: NESFile, save_path: Option<PathBuf>) -> Result<Self> {
+        let (mut prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), PRG_BANK_SIZE);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, CHR_BANK_SIZE) {
+            chr
+        } else {
+            vec![0; CHR_RAM_SIZE]
+        };
+
+        let flash = if rom.battery() {
+            if let Some(path) = &save_path {
+                if path.exists() {
+                    let saved = fs::read(path)?;
+                    if saved.len() == prg.len() {
+                        prg = saved;
+                    }
+                }
+            }
+            Some(Flash::new(save_path))
+        } else {
+            None
+        };
+
+        let last_bank = (prg.len() / PRG_BANK_SIZE).saturating_sub(1);
+        Ok(Self {
+            prg,
+            chr,
+            mirroring: rom.hardwired_mirroring(),
+            nametables: Nametables::new(&rom),
+            chr_ram: rom.chr_ram(),
+            prg_bank: 0,
+            last_bank,
+            chr_bank: 0,
+            screen: 0,
+            flash,
+        })
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank = if addr < 0xC000 {
+            self.prg_bank
+        } else {
+            self.last_bank
+        };
+        bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        (self.chr_bank * CHR_BANK_SIZE + addr as usize) % self.chr.len()
+    }
+
+    fn four_screen_addr(&self, addr: u16) -> usize {
+        self.chr.len().saturating_sub(0x2000) + (addr as usize & 0x0FFF)
+    }
+
+    // 7  bit  0
+    // ---- ----
+    // MCCP PPPP
+    // |||+-++++- Select 16 KB PRG ROM bank at $8000
+    // |++------- Select 8 KB CHR RAM bank at $0000
+    // +--------- Select 1 KB VRAM page for all 4 nametables (one-screen mirroring)
+    fn write_bank_select(&mut self, value: u8) {
+        self.prg_bank = (value & 0b11111) as usize % (self.last_bank + 1);
+        self.chr_bank = ((value >> 5) & 0b11) as usize;
+        self.screen = value >> 7;
+    }
+}
+
+impl Memory for Mapper30 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr[self.chr_addr(addr)],
+            0x2000..=0x2FFF => self.chr[self.four_screen_addr(addr)],
+            0x8000..=0xFFFF => {
+                let chip_addr = self.prg_addr(addr);
+                match &self.flash {
+                    Some(flash) if flash.software_id => Flash::software_id(chip_addr),
+                    _ => self.prg[chip_addr],
+                }
+            }
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        match addr {
+            0x0000..=0x1FFF if self.chr_ram => {
+                let i = self.chr_addr(addr);
+                self.chr[i] = value;
+            }
+            0x2000..=0x2FFF if self.chr_ram => {
+                let i = self.four_screen_addr(addr);
+                self.chr[i] = value;
+            }
+            0x8000..=0xBFFF if self.flash.is_some() => {
+                let chip_addr = self.prg_addr(addr);
+                if let Some(flash) = self.flash.as_mut() {
+                    flash.write(&mut self.prg, chip_addr, value);
+                }
+            }
+            0xC000..=0xFFFF if self.flash.is_some() => self.write_bank_select(value),
+            0x8000..=0xFFFF => {
+                let rom = self.prg[self.prg_addr(addr)];
+                self.write_bank_select(BusConflict::And.apply(value, rom));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper30 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn vram_source(&self, addr: u16) -> VRAMSource {
+        match self.nametables {
+            _ if addr < 0x2000 => VRAMSource::Default,
+            Nametables::Fixed => VRAMSource::Default,
+            Nametables::OneScreen => VRAMSource::CIRAM(self.screen),
+            Nametables::FourScreen => VRAMSource::CHR,
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(flash) = self.flash.as_mut() {
+            flash.flush(&self.prg)?;
+        }
+        Ok(())
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        w.u8(self.prg_bank as u8);
+        w.u8(self.chr_bank as u8);
+        w.u8(self.screen);
+        if self.chr_ram {
+            w.bytes(&self.chr);
+        }
+        if let Some(flash) = &self.flash {
+            flash.save_state(&mut w, &self.prg);
+        }
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg_bank = r.u8()? as usize % (self.last_bank + 1);
+        self.chr_bank = r.u8()? as usize & 0b11;
+        self.screen = r.u8()? & 1;
+        if self.chr_ram {
+            r.bytes_into(&mut self.chr)?;
+        }
+        if let Some(flash) = self.flash.as_mut() {
+            flash.load_state(&mut r, &mut self.prg)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mapper30 {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+// SST39SF040
+// https://wiki.nesdev.com/w/index.php/UNROM_512#Flash_ROM_Programming
+struct Flash {
+    state: FlashState,
+    software_id: bool,
+    save_path: Option<PathBuf>,
+    dirty: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FlashState {
+    Ready,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseUnlock0,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+// by the value of each state in save states
+const FLASH_STATES: [FlashState; 7] = [
+    FlashState::Ready,
+    FlashState::Unlock1,
+    FlashState::Unlock2,
+    FlashState::Program,
+    FlashState::EraseUnlock0,
+    FlashState::EraseUnlock1,
+    FlashState::EraseUnlock2,
+];
+
+const SECTOR_SIZE: usize = 0x1000;
+
+impl Flash {
+    fn new(save_path: Option<PathBuf>) -> Self {
+        Self {
+            state: FlashState::Ready,
+            software_id: false,
+            save_path,
+            dirty: false,
+        }
+    }
+
+    fn software_id(chip_addr: usize) -> u8 {
+        if chip_addr & 1 == 0 {
+            0xBF // Manufacturer ID
+        } else {
+            0xB7 // Device ID
+        }
+    }
+
+    fn write(&mut self, prg: &mut [u8], chip_addr: usize, value: u8) {
+        use FlashState::*;
+
+        if value == 0xF0 {
+            self.state = Ready;
+            self.software_id = false;
+            return;
+        }
+
+        let command = chip_addr & 0x7FFF;
+        self.state = match (self.state, command, value) {
+            (Ready, 0x5555, 0xAA) => Unlock1,
+            (Unlock1, 0x2AAA, 0x55) => Unlock2,
+            (Unlock2, 0x5555, 0xA0) => Program,
+            (Unlock2, 0x5555, 0x80) => EraseUnlock0,
+            (Unlock2, 0x5555, 0x90) => {
+                self.software_id = true;
+                Ready
+            }
+            (Program, _, _) => {
+                // Programming can only clear bits
+                prg[chip_addr] &= value;
+                self.dirty = true;
+                Ready
+            }
+            (EraseUnlock0, 0x5555, 0xAA) => EraseUnlock1,
+            (EraseUnlock1, 0x2AAA, 0x55) => EraseUnlock2,
+            (EraseUnlock2, _, 0x30) => {
+                let first = chip_addr & !(SECTOR_SIZE - 1);
+                let last = (first + SECTOR_SIZE).min(prg.len());
+                prg[first..last].iter_mut().for_each(|b| *b = 0xFF);
+                self.dirty = true;
+                Ready
+            }
+            (EraseUnlock2, 0x5555, 0x10) => {
+                prg.iter_mut().for_each(|b| *b = 0xFF);
+                self.dirty = true;
+                Ready
+            }
+            _ => Ready,
+        };
+    }
+
+    // the flashed PRG is saved too, as it may differ from the save file until flushed
+    fn save_state(&self, w: &mut StateWriter, prg: &[u8]) {
+        w.u8(self.state as u8);
+        w.bool(self.software_id);
+        w.bytes(prg);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader, prg: &mut [u8]) -> Result<()> {
+        self.state = *FLASH_STATES
+            .get(r.u8()? as usize)
+            .ok_or_else(|| anyhow!("Invalid flash state"))?;
+        self.software_id = r.bool()?;
+        let saved = r.bytes(prg.len())?;
+        if saved.len() != prg.len() {
+            return Err(anyhow!("The size of the flashed PRG does not match"));
+        }
+        // written to the save file by the next flush
+        if saved[..] != prg[..] {
+            prg.copy_from_slice(&saved);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, prg: &[u8]) -> Result<()> {
+        if let (true, Some(path)) = (self.dirty, &self.save_path) {
+            fs::write(path, prg)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> Mapper30 {
+        Mapper30 {
+            prg: vec![0xFF; PRG_BANK_SIZE * 4],
+            chr: vec![0; CHR_RAM_SIZE],
+            mirroring: Mirroring::Vertical(),
+            nametables: Nametables::Fixed,
+            chr_ram: true,
+            prg_bank: 0,
+            last_bank: 3,
+            chr_bank: 0,
+            screen: 0,
+            flash: Some(Flash::new(None)),
+        }
+    }
+
+    fn command(m: &mut Mapper30, bank: u8, addr: u16, value: u8) {
+        m.write(0xC000u16.into(), bank.into());
+        m.write(addr.into(), value.into());
+    }
+
+    fn unlock(m: &mut Mapper30) {
+        command(m, 1, 0x9555, 0xAA);
+        command(m, 0, 0xAAAA, 0x55);
+    }
+
+    #[test]
+    fn program_and_erase() {
+        let mut m = mapper();
+
+        unlock(&mut m);
+        command(&mut m, 1, 0x9555, 0xA0);
+        command(&mut m, 2, 0x8010, 0x12);
+        assert_eq!(m.prg[2 * PRG_BANK_SIZE + 0x10], 0x12);
+        assert_eq!(m.read(0x8010u16.into()), 0x12.into());
+        assert!(m.flash.as_ref().unwrap().dirty);
+
+        unlock(&mut m);
+        command(&mut m, 1, 0x9555, 0x80);
+        unlock(&mut m);
+        command(&mut m, 2, 0x8000, 0x30);
+        assert_eq!(m.prg[2 * PRG_BANK_SIZE + 0x10], 0xFF);
+    }
+
+    #[test]
+    fn save_state_leaves_save_file() {
+        let path = std::env::temp_dir().join(format!("rustnes-flash-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut m = mapper();
+        m.flash = Some(Flash::new(Some(path.clone())));
+        unlock(&mut m);
+        command(&mut m, 1, 0x9555, 0xA0);
+        command(&mut m, 2, 0x8010, 0x12);
+
+        // taken and dropped without a flush
+        let state = m.save_state().unwrap();
+        drop(m.save_state());
+        assert!(!path.exists());
+        m.flush().unwrap();
+        let flushed = fs::read(&path).unwrap();
+        assert_eq!(flushed[2 * PRG_BANK_SIZE + 0x10], 0x12);
+
+        // the flashed PRG loaded back is written by the flush on drop
+        unlock(&mut m);
+        command(&mut m, 1, 0x9555, 0xA0);
+        command(&mut m, 2, 0x8011, 0x34);
+        m.load_state(&state).unwrap();
+        assert_eq!(m.prg[2 * PRG_BANK_SIZE + 0x11], 0xFF);
+        drop(m);
+        assert_eq!(fs::read(&path).unwrap(), flushed);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn software_id() {
+        let mut m = mapper();
+        unlock(&mut m);
+        command(&mut m, 1, 0x9555, 0x90);
+        assert_eq!(m.read(0x8000u16.into()), 0xBF.into());
+        assert_eq!(m.read(0x8001u16.into()), 0xB7.into());
+
+        m.write(0x8000u16.into(), 0xF0.into());
+        assert_eq!(m.read(0x8000u16.into()), 0xFF.into());
+    }
+
+    #[test]
+    fn chr_bank() {
+        let mut m = mapper();
+        m.write(0xC000u16.into(), 0b0100_0000.into());
+        m.write(0x0000u16.into(), 0x55.into());
+        assert_eq!(m.chr[2 * CHR_BANK_SIZE], 0x55);
+    }
+
+    #[test]
+    fn nametables() {
+        let mut m = mapper();
+        assert_eq!(m.vram_source(0x2400), VRAMSource::Default);
+
+        m.nametables = Nametables::OneScreen;
+        m.write(0xC000u16.into(), 0x80.into());
+        assert_eq!(m.vram_source(0x2000), VRAMSource::CIRAM(1));
+        assert_eq!(m.vram_source(0x2C00), VRAMSource::CIRAM(1));
+        assert_eq!(m.vram_source(0x0000), VRAMSource::Default);
+
+        m.nametables = Nametables::FourScreen;
+        assert_eq!(m.vram_source(0x2C00), VRAMSource::CHR);
+        m.write(0x2C00u16.into(), 0x55.into());
+        assert_eq!(m.chr[CHR_RAM_SIZE - 0x2000 + 0xC00], 0x55);
+        assert_eq!(m.read(0x2C00u16.into()), 0x55.into());
+    }
+}
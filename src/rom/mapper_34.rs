@@ -0,0 +1,192 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::bus_conflict::BusConflict;
+use super::nesfile::NESFile;
+use super::{copy_save_ram, Mapper};
+
+// BNROM and NINA-001
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_034
+//
+// Both boards switch 32KB PRG banks, but by different registers:
+// BNROM by a latch at $8000-$FFFF with CHR-RAM, NINA-001 by registers at $7FFD-$7FFF which also
+// switch two 4KB CHR-ROM banks.
+pub struct Mapper34 {
+    board: Board,
+    prg: Banks,
+    chr: Banks,
+    prg_ram: [u8; 0x2000],
+    mirroring: Mirroring,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Board {
+    BNROM,
+    NINA001,
+}
+
+impl Board {
+    // Submapper 1 is NINA-001 and 2 is BNROM. Otherwise only NINA-001 has CHR-ROM larger than 8KB.
+    fn detect(submapper_no: u8, chr_size: usize) -> Self {
+        match submapper_no {
+            1 => Self::NINA001,
+            2 => Self::BNROM,
+            _ if 0x2000 < chr_size => Self::NINA001,
+            _ => Self::BNROM,
+        }
+    }
+}
+
+impl Mapper34 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let board = Board::detect(rom.submapper_no(), chr.len());
+        let mut mapper = Self::with_banks(board, prg, chr, rom.mirroring());
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    fn with_banks(board: Board, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        Self {
+            board,
+            prg: Banks::new(prg, 1, 0x8000),
+            chr: Banks::new(chr, 2, 0x1000),
+            prg_ram: [0; 0x2000],
+            mirroring,
+        }
+    }
+}
+
+impl Memory for Mapper34 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x6000..=0x7FFF if self.board == Board::NINA001 => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        match (self.board, addr) {
+            (_, 0x0000..=0x1FFF) => self.chr.write(addr as usize, value),
+            (Board::NINA001, 0x6000..=0x7FFF) => {
+                self.prg_ram[addr as usize - 0x6000] = value;
+                match addr {
+                    0x7FFD => self.prg.select(0, (value & 1) as isize),
+                    0x7FFE => self.chr.select(0, (value & 0x0F) as isize),
+                    0x7FFF => self.chr.select(1, (value & 0x0F) as isize),
+                    _ => {}
+                }
+            }
+            (Board::BNROM, 0x8000..=0xFFFF) => {
+                let rom = self.prg.read(addr as usize - 0x8000);
+                let value = BusConflict::And.apply(value, rom);
+                self.prg.select(0, value as isize);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        match self.board {
+            Board::NINA001 => Some(self.prg_ram.to_vec()),
+            Board::BNROM => None,
+        }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.prg.save_state(&mut w);
+        self.chr.save_state(&mut w);
+        w.bytes(&self.prg_ram);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg.load_state(&mut r)?;
+        self.chr.load_state(&mut r)?;
+        r.bytes_into(&mut self.prg_ram)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper(board: Board) -> Mapper34 {
+        // 32KB PRG banks and 4KB CHR banks filled with its bank number
+        let prg = (0..4).flat_map(|n| vec![n; 0x8000]).collect();
+        let chr = (0..16).flat_map(|n| vec![n; 0x1000]).collect();
+        Mapper34::with_banks(board, prg, chr, Mirroring::Vertical())
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Board::detect(0, 0x2000), Board::BNROM);
+        assert_eq!(Board::detect(0, 0x10000), Board::NINA001);
+        assert_eq!(Board::detect(1, 0x2000), Board::NINA001);
+        assert_eq!(Board::detect(2, 0x10000), Board::BNROM);
+    }
+
+    #[test]
+    fn bnrom() {
+        let mut m = mapper(Board::BNROM);
+        m.write(0x8000u16.into(), 0x03.into());
+        // bus conflict with the bank 0 filled with 0
+        assert_eq!(m.read(0x8000u16.into()), 0.into());
+
+        let mut m = mapper(Board::BNROM);
+        m.prg.select(0, 3);
+        m.write(0x8000u16.into(), 0x02.into());
+        assert_eq!(m.read(0xFFFFu16.into()), 2.into());
+
+        // NINA-001 registers are ignored
+        m.write(0x7FFDu16.into(), 0x01.into());
+        assert_eq!(m.read(0x8000u16.into()), 2.into());
+    }
+
+    #[test]
+    fn nina001() {
+        let mut m = mapper(Board::NINA001);
+        m.write(0x7FFDu16.into(), 0x01.into());
+        m.write(0x7FFEu16.into(), 0x05.into());
+        m.write(0x7FFFu16.into(), 0x0A.into());
+        assert_eq!(m.read(0x8000u16.into()), 1.into());
+        assert_eq!(m.read(0x0000u16.into()), 5.into());
+        assert_eq!(m.read(0x1000u16.into()), 10.into());
+
+        // the registers are also written to PRG-RAM
+        assert_eq!(m.read(0x7FFEu16.into()), 5.into());
+        m.write(0x6000u16.into(), 0x42.into());
+        assert_eq!(m.read(0x6000u16.into()), 0x42.into());
+
+        // the latch at $8000 is not on the board
+        m.write(0x8000u16.into(), 0x00.into());
+        assert_eq!(m.read(0x8000u16.into()), 1.into());
+    }
+}
@@ -0,0 +1,340 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::mmc3_irq::{IRQCounter, IRQVariant};
+use super::nesfile::NESFile;
+use super::{copy_save_ram, Mapper};
+
+// MMC3
+// https://wiki.nesdev.com/w/index.php/MMC3
+pub struct Mapper4 {
+    prg: Banks,
+    chr: Banks,
+    prg_ram: [u8; 0x2000],
+    mirroring: Mirroring,
+
+    bank_select: u8,
+    registers: [u8; 8],
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+
+    irq: IRQCounter,
+    a12: bool,
+    a12_low_accesses: u8,
+}
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// A rise of A12 is ignored unless A12 has been low for a while (about 3 CPU cycles),
+// so the rises by sprite fetches in a row count once. PPU fetches every 2 dots, so it is
+// approximated by the number of fetches.
+const A12_LOW_ACCESSES: u8 = 3;
+
+impl Mapper4 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut mapper = Self {
+            prg: Banks::new(prg, 4, PRG_BANK_SIZE),
+            chr: Banks::new(chr, 8, CHR_BANK_SIZE),
+            prg_ram: [0; 0x2000],
+            mirroring: rom.mirroring(),
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq: IRQCounter::new(IRQVariant::from_submapper(rom.submapper_no())),
+            a12: false,
+            a12_low_accesses: 0,
+        };
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper.update_banks();
+        mapper
+    }
+
+    fn update_banks(&mut self) {
+        let r = self.registers;
+
+        // PRG ROM bank mode
+        if self.bank_select & 0b0100_0000 == 0 {
+            self.prg.select(0, r[6] as isize);
+            self.prg.select(2, -2);
+        } else {
+            self.prg.select(0, -2);
+            self.prg.select(2, r[6] as isize);
+        }
+        self.prg.select(1, r[7] as isize);
+        self.prg.select(3, -1);
+
+        // CHR A12 inversion
+        let (two_kb, one_kb) = if self.bank_select & 0b1000_0000 == 0 {
+            (0, 4)
+        } else {
+            (4, 0)
+        };
+        self.chr.select(two_kb, (r[0] & 0xFE) as isize);
+        self.chr.select(two_kb + 1, (r[0] | 1) as isize);
+        self.chr.select(two_kb + 2, (r[1] & 0xFE) as isize);
+        self.chr.select(two_kb + 3, (r[1] | 1) as isize);
+        for i in 0..4 {
+            self.chr.select(one_kb + i, r[2 + i] as isize);
+        }
+    }
+}
+
+impl Memory for Mapper4 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        let even = addr & 1 == 0;
+        match addr {
+            0x0000..=0x1FFF => self.chr.write(addr as usize, value),
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protected => {
+                self.prg_ram[addr as usize - 0x6000] = value;
+            }
+            0x8000..=0x9FFF => {
+                if even {
+                    self.bank_select = value;
+                } else {
+                    self.registers[(self.bank_select & 0b111) as usize] = value;
+                }
+                self.update_banks();
+            }
+            0xA000..=0xBFFF => {
+                // four-screen boards ignore the mirroring register
+                if even && self.mirroring != Mirroring::FourScreen() {
+                    self.mirroring = if value & 1 == 0 {
+                        Mirroring::Vertical()
+                    } else {
+                        Mirroring::Horizontal()
+                    };
+                } else if !even {
+                    self.prg_ram_enabled = value & 0b1000_0000 != 0;
+                    self.prg_ram_write_protected = value & 0b0100_0000 != 0;
+                }
+            }
+            0xC000..=0xDFFF => {
+                if even {
+                    self.irq.write_latch(value);
+                } else {
+                    self.irq.write_reload();
+                }
+            }
+            0xE000..=0xFFFF => {
+                if even {
+                    self.irq.disable();
+                } else {
+                    self.irq.enable();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper4 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        match (self.a12, a12) {
+            (false, true) if A12_LOW_ACCESSES <= self.a12_low_accesses => self.irq.a12_rise(),
+            (true, false) => {
+                self.irq.a12_fall();
+                self.a12_low_accesses = 0;
+            }
+            _ => {}
+        }
+        if !a12 {
+            self.a12_low_accesses = self.a12_low_accesses.saturating_add(1);
+        }
+        self.a12 = a12;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        Some(self.prg_ram.to_vec())
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        w.bytes(&self.prg_ram);
+        if self.chr.ram() {
+            self.chr.save_state(&mut w);
+        }
+        w.mirroring(self.mirroring);
+        w.u8(self.bank_select);
+        w.bytes(&self.registers);
+        w.bool(self.prg_ram_enabled);
+        w.bool(self.prg_ram_write_protected);
+        self.irq.save_state(&mut w);
+        w.bool(self.a12);
+        w.u8(self.a12_low_accesses);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        r.bytes_into(&mut self.prg_ram)?;
+        if self.chr.ram() {
+            self.chr.load_state(&mut r)?;
+        }
+        self.mirroring = r.mirroring()?;
+        self.bank_select = r.u8()?;
+        r.bytes_into(&mut self.registers)?;
+        self.prg_ram_enabled = r.bool()?;
+        self.prg_ram_write_protected = r.bool()?;
+        self.irq.load_state(&mut r)?;
+        self.a12 = r.bool()?;
+        self.a12_low_accesses = r.u8()?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> Mapper4 {
+        // 8KB banks filled with its bank number
+        let prg = (0..8).flat_map(|n| vec![n; PRG_BANK_SIZE]).collect();
+        let chr = (0..16).flat_map(|n| vec![n; CHR_BANK_SIZE]).collect();
+        let mut m = Mapper4 {
+            prg: Banks::new(prg, 4, PRG_BANK_SIZE),
+            chr: Banks::new(chr, 8, CHR_BANK_SIZE),
+            prg_ram: [0; 0x2000],
+            mirroring: Mirroring::Vertical(),
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq: IRQCounter::new(IRQVariant::New),
+            a12: false,
+            a12_low_accesses: 0,
+        };
+        m.chr.set_ram(true);
+        m.update_banks();
+        m
+    }
+
+    fn write(m: &mut Mapper4, addr: u16, value: u8) {
+        m.write(addr.into(), value.into());
+    }
+
+    fn read(m: &Mapper4, addr: u16) -> u8 {
+        m.peek(addr.into()).into()
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut m = mapper();
+        write(&mut m, 0x8000, 6);
+        write(&mut m, 0x8001, 3);
+        assert_eq!(read(&m, 0x8000), 3);
+        assert_eq!(read(&m, 0xC000), 6);
+        assert_eq!(read(&m, 0xE000), 7);
+
+        // swap $8000 and $C000
+        write(&mut m, 0x8000, 0b0100_0110);
+        assert_eq!(read(&m, 0x8000), 6);
+        assert_eq!(read(&m, 0xC000), 3);
+    }
+
+    #[test]
+    fn chr_banks() {
+        let mut m = mapper();
+        write(&mut m, 0x8000, 0);
+        write(&mut m, 0x8001, 9);
+        assert_eq!(read(&m, 0x0000), 8);
+        assert_eq!(read(&m, 0x0400), 9);
+
+        write(&mut m, 0x8000, 0b1000_0010);
+        write(&mut m, 0x8001, 13);
+        assert_eq!(read(&m, 0x0000), 13);
+        assert_eq!(read(&m, 0x1000), 8);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut m = mapper();
+        write(&mut m, 0xA000, 1);
+        assert!(matches!(m.mirroring(), Mirroring::Horizontal()));
+    }
+
+    #[test]
+    fn scanline_irq() {
+        let mut m = mapper();
+        write(&mut m, 0xC000, 1);
+        write(&mut m, 0xC001, 0);
+        write(&mut m, 0xE001, 0);
+
+        let scanline = |m: &mut Mapper4| {
+            // background from $0000, then 8 sprites from $1000
+            for _ in 0..8 {
+                m.ppu_address(0x2000);
+                m.ppu_address(0x0000);
+            }
+            for _ in 0..8 {
+                m.ppu_address(0x2000);
+                m.ppu_address(0x1000);
+                m.ppu_address(0x1008);
+            }
+        };
+        scanline(&mut m); // reload to 1
+        assert!(!m.irq());
+        scanline(&mut m);
+        assert!(m.irq());
+
+        write(&mut m, 0xE000, 0);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn save_state() {
+        let mut m = mapper();
+        write(&mut m, 0x8000, 0b0100_0110);
+        write(&mut m, 0x8001, 3);
+        write(&mut m, 0x6000, 0x42);
+        write(&mut m, 0x0000, 0x55);
+        write(&mut m, 0xC000, 5);
+        write(&mut m, 0xE001, 0);
+        let state = m.save_state().unwrap();
+
+        let mut other = mapper();
+        other.load_state(&state).unwrap();
+        assert_eq!(read(&other, 0xC000), 3);
+        assert_eq!(read(&other, 0x6000), 0x42);
+        assert_eq!(read(&other, 0x0000), 0x55);
+        assert_eq!(other.save_state().unwrap(), state);
+        assert!(other.load_state(&state[..state.len() - 1]).is_err());
+    }
+}
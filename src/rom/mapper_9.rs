@@ -0,0 +1,171 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::nesfile::NESFile;
+use super::Mapper;
+
+// MMC2
+// https://wiki.nesdev.com/w/index.php/MMC2
+pub struct Mapper9 {
+    prg: Banks,
+    chr: Banks,
+    mirroring: Mirroring,
+
+    // CHR banks for each 4KB window, selected by the latch of $FD or $FE
+    chr_banks: [[u8; 2]; 2],
+    latches: [Latch; 2],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Latch {
+    FD,
+    FE,
+}
+
+impl Mapper9 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut prg = Banks::new(prg, 4, 0x2000);
+        prg.select(0, 0);
+        prg.select(1, -3);
+        prg.select(2, -2);
+        prg.select(3, -1);
+        Self {
+            prg,
+            chr: Banks::new(chr, 2, 0x1000),
+            mirroring: rom.mirroring(),
+            chr_banks: [[0; 2]; 2],
+            latches: [Latch::FE; 2],
+        }
+    }
+
+    fn update_chr_banks(&mut self) {
+        for window in 0..2 {
+            let bank = self.chr_banks[window][self.latches[window] as usize];
+            self.chr.select(window, bank as isize);
+        }
+    }
+}
+
+impl Memory for Mapper9 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.write(addr as usize, value),
+            0xA000..=0xAFFF => self.prg.select(0, (value & 0b1111) as isize),
+            0xB000..=0xEFFF => {
+                let window = (addr as usize - 0xB000) / 0x2000;
+                let latch = ((addr as usize - 0xB000) / 0x1000) % 2;
+                self.chr_banks[window][latch] = value & 0b11111;
+                self.update_chr_banks();
+            }
+            0xF000..=0xFFFF => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical()
+                } else {
+                    Mirroring::Horizontal()
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mapper9 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // The latches are switched after the tile $FD or $FE is fetched
+    fn ppu_address(&mut self, addr: u16) {
+        let (window, latch) = match addr {
+            0x0FD8 => (0, Latch::FD),
+            0x0FE8 => (0, Latch::FE),
+            0x1FD8..=0x1FDF => (1, Latch::FD),
+            0x1FE8..=0x1FEF => (1, Latch::FE),
+            _ => return,
+        };
+        self.latches[window] = latch;
+        self.update_chr_banks();
+    }
+
+    // boards with MMC2 only have CHR ROM
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.prg.save_state(&mut w);
+        w.mirroring(self.mirroring);
+        for (banks, latch) in self.chr_banks.iter().zip(self.latches.iter()) {
+            w.bytes(banks);
+            w.u8(*latch as u8);
+        }
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg.load_state(&mut r)?;
+        self.mirroring = r.mirroring()?;
+        for window in 0..2 {
+            r.bytes_into(&mut self.chr_banks[window])?;
+            self.latches[window] = if r.u8()? == Latch::FD as u8 {
+                Latch::FD
+            } else {
+                Latch::FE
+            };
+        }
+        self.update_chr_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chr_latch() {
+        let prg = vec![0; 0x2000 * 4];
+        let chr = (0..8).flat_map(|n| vec![n; 0x1000]).collect();
+        let mut m = Mapper9 {
+            prg: Banks::new(prg, 4, 0x2000),
+            chr: Banks::new(chr, 2, 0x1000),
+            mirroring: Mirroring::Vertical(),
+            chr_banks: [[0; 2]; 2],
+            latches: [Latch::FE; 2],
+        };
+        m.write(0xB000u16.into(), 1.into()); // $0000 FD
+        m.write(0xC000u16.into(), 2.into()); // $0000 FE
+        m.write(0xD000u16.into(), 3.into()); // $1000 FD
+        m.write(0xE000u16.into(), 4.into()); // $1000 FE
+        assert_eq!(m.read(0x0000u16.into()), 2.into());
+        assert_eq!(m.read(0x1000u16.into()), 4.into());
+
+        m.ppu_address(0x0FD8);
+        assert_eq!(m.read(0x0000u16.into()), 1.into());
+        assert_eq!(m.read(0x1000u16.into()), 4.into());
+
+        m.ppu_address(0x1FDA);
+        assert_eq!(m.read(0x1000u16.into()), 3.into());
+        m.ppu_address(0x1FE8);
+        assert_eq!(m.read(0x1000u16.into()), 4.into());
+    }
+}
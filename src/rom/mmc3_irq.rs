@@ -0,0 +1,201 @@
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+
+// MMC3 scanline counter
+// https://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(super) enum IRQVariant {
+    // Sharp MMC3: IRQ whenever the counter is 0 after clocking
+    New,
+    // NEC MMC3A and MMC6 ("alternate"): IRQ only when the counter becomes 0 from a non-zero value
+    // or by the reload
+    Old,
+    // Acclaim MC-ACC: clocked by every 8th falling edge of PPU A12 instead of the rising edges
+    MCACC,
+}
+
+impl IRQVariant {
+    // https://wiki.nesdev.com/w/index.php/NES_2.0_submappers#004:_MMC3
+    pub fn from_submapper(submapper: u8) -> Self {
+        match submapper {
+            1 | 4 => Self::Old,
+            3 => Self::MCACC,
+            _ => Self::New,
+        }
+    }
+}
+
+pub(super) struct IRQCounter {
+    variant: IRQVariant,
+    latch: u8,
+    counter: u8,
+    reload: bool,
+    enabled: bool,
+    pending: bool,
+    // falling edges of A12 counted for MC-ACC
+    prescaler: u8,
+}
+
+impl IRQCounter {
+    pub fn new(variant: IRQVariant) -> Self {
+        Self {
+            variant,
+            latch: 0,
+            counter: 0,
+            reload: false,
+            enabled: false,
+            pending: false,
+            prescaler: 0,
+        }
+    }
+
+    // $C000
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // $C001
+    pub fn write_reload(&mut self) {
+        self.counter = 0;
+        self.reload = true;
+        self.prescaler = 0;
+    }
+
+    // $E000
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        self.pending = false;
+    }
+
+    // $E001
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.latch);
+        w.u8(self.counter);
+        w.bool(self.reload);
+        w.bool(self.enabled);
+        w.bool(self.pending);
+        w.u8(self.prescaler);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.latch = r.u8()?;
+        self.counter = r.u8()?;
+        self.reload = r.bool()?;
+        self.enabled = r.bool()?;
+        self.pending = r.bool()?;
+        self.prescaler = r.u8()?;
+        Ok(())
+    }
+
+    /// PPU A12 went from low to high (filtered).
+    pub fn a12_rise(&mut self) {
+        if self.variant != IRQVariant::MCACC {
+            self.clock();
+        }
+    }
+
+    /// PPU A12 went from high to low.
+    pub fn a12_fall(&mut self) {
+        if self.variant == IRQVariant::MCACC {
+            self.prescaler = (self.prescaler + 1) % 8;
+            if self.prescaler == 0 {
+                self.clock();
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        let before = self.counter;
+        let reloaded = self.reload;
+        if self.counter == 0 || self.reload {
+            self.counter = self.latch;
+            self.reload = false;
+        } else {
+            self.counter -= 1;
+        }
+
+        let fire = match self.variant {
+            IRQVariant::New | IRQVariant::MCACC => self.counter == 0,
+            IRQVariant::Old => self.counter == 0 && (before != 0 || reloaded),
+        };
+        if fire && self.enabled {
+            self.pending = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_scanlines() {
+        let mut irq = IRQCounter::new(IRQVariant::New);
+        irq.write_latch(2);
+        irq.write_reload();
+        irq.enable();
+
+        irq.a12_rise(); // reload to 2
+        irq.a12_rise();
+        assert!(!irq.pending());
+        irq.a12_rise();
+        assert!(irq.pending());
+
+        irq.disable();
+        assert!(!irq.pending());
+    }
+
+    #[test]
+    fn latch_zero() {
+        // New: fires on every clock while the latch is 0
+        let mut irq = IRQCounter::new(IRQVariant::New);
+        irq.enable();
+        irq.a12_rise();
+        assert!(irq.pending());
+
+        // Old: needs the reload, or the transition from non-zero
+        let mut irq = IRQCounter::new(IRQVariant::Old);
+        irq.enable();
+        irq.a12_rise();
+        assert!(!irq.pending());
+        irq.write_reload();
+        irq.a12_rise();
+        assert!(irq.pending());
+    }
+
+    #[test]
+    fn mc_acc() {
+        let mut irq = IRQCounter::new(IRQVariant::MCACC);
+        irq.write_latch(1);
+        irq.write_reload();
+        irq.enable();
+
+        irq.a12_rise();
+        for _ in 0..8 {
+            irq.a12_fall(); // reload to 1
+        }
+        assert!(!irq.pending());
+        for _ in 0..7 {
+            irq.a12_fall();
+        }
+        assert!(!irq.pending());
+        irq.a12_fall();
+        assert!(irq.pending());
+    }
+
+    #[test]
+    fn submapper() {
+        assert_eq!(IRQVariant::from_submapper(0), IRQVariant::New);
+        assert_eq!(IRQVariant::from_submapper(3), IRQVariant::MCACC);
+        assert_eq!(IRQVariant::from_submapper(4), IRQVariant::Old);
+    }
+}
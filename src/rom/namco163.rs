@@ -0,0 +1,307 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::nesfile::NESFile;
+use super::{copy_save_ram, Mapper, VRAMSource};
+
+// Namco 129/163 (mapper 19)
+// https://wiki.nesdev.com/w/index.php/Namco_163
+//
+// The expansion audio is not emulated, but its RAM is readable and writable.
+pub struct Namco163 {
+    prg: Banks,
+    // 8 pattern table windows and 4 nametable windows of 1KB
+    chr: Banks,
+    chr_registers: [u8; 12],
+    // disables CIRAM in the pattern tables at $0000-$0FFF and $1000-$1FFF
+    ciram_disabled: [bool; 2],
+
+    prg_ram: [u8; 0x2000],
+    write_protect: u8,
+
+    // RAM shared with the audio
+    internal_ram: [u8; 0x80],
+    internal_address: u8,
+    auto_increment: bool,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+impl Namco163 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut mapper = Self::with_banks(prg, chr);
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    fn with_banks(prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let mut prg = Banks::new(prg, 4, PRG_BANK_SIZE);
+        prg.select(3, -1);
+        let mut mapper = Self {
+            prg,
+            chr: Banks::new(chr, 12, CHR_BANK_SIZE),
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7, 0xE0, 0xE1, 0xE0, 0xE1],
+            ciram_disabled: [false; 2],
+            prg_ram: [0; 0x2000],
+            write_protect: 0,
+            internal_ram: [0; 0x80],
+            internal_address: 0,
+            auto_increment: false,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+        };
+        mapper.update_chr_banks();
+        mapper
+    }
+
+    fn update_chr_banks(&mut self) {
+        for (i, r) in self.chr_registers.iter().enumerate() {
+            self.chr.select(i, *r as isize);
+        }
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let page = (addr - 0x6000) / 0x800;
+        self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << page) == 0
+    }
+
+    fn access_internal_ram(&mut self) -> usize {
+        let addr = self.internal_address as usize;
+        if self.auto_increment {
+            self.internal_address = (self.internal_address + 1) & 0x7F;
+        }
+        addr
+    }
+}
+
+impl Memory for Namco163 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x2FFF => self.chr.read(addr as usize),
+            0x4800..=0x4FFF => self.internal_ram[self.internal_address as usize],
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        match addr {
+            0x0000..=0x2FFF => self.chr.write(addr as usize, value),
+            0x4800..=0x4FFF => {
+                let i = self.access_internal_ram();
+                self.internal_ram[i] = value;
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = self.irq_counter & 0x7F00 | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = self.irq_counter & 0x00FF | ((value & 0x7F) as u16) << 8;
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(addr) => {
+                self.prg_ram[addr as usize - 0x6000] = value;
+            }
+            0x8000..=0xDFFF => {
+                self.chr_registers[(addr as usize - 0x8000) / 0x800] = value;
+                self.update_chr_banks();
+            }
+            0xE000..=0xE7FF => self.prg.select(0, (value & 0x3F) as isize),
+            0xE800..=0xEFFF => {
+                self.prg.select(1, (value & 0x3F) as isize);
+                self.ciram_disabled = [value & 0x40 != 0, value & 0x80 != 0];
+            }
+            0xF000..=0xF7FF => self.prg.select(2, (value & 0x3F) as isize),
+            0xF800..=0xFFFF => {
+                self.write_protect = value;
+                self.internal_address = value & 0x7F;
+                self.auto_increment = value & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn mirroring(&self) -> Mirroring {
+        // the nametables are mapped by `vram_source`
+        Mirroring::Vertical()
+    }
+
+    fn vram_source(&self, addr: u16) -> VRAMSource {
+        let window = (addr as usize / CHR_BANK_SIZE) % self.chr_registers.len();
+        let value = self.chr_registers[window];
+        let ciram = if window < 8 {
+            !self.ciram_disabled[window / 4] && 0xE0 <= value
+        } else {
+            0xE0 <= value
+        };
+        if ciram {
+            VRAMSource::CIRAM(value & 1)
+        } else {
+            VRAMSource::CHR
+        }
+    }
+
+    fn cpu_cycle(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // the internal RAM follows the PRG RAM as in FCEUX
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        let mut ram = self.prg_ram.to_vec();
+        ram.extend_from_slice(&self.internal_ram);
+        Some(ram)
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+        if let Some(internal) = data.get(self.prg_ram.len()..) {
+            copy_save_ram(&mut self.internal_ram, internal);
+        }
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.prg.save_state(&mut w);
+        self.chr.save_state(&mut w);
+        w.bytes(&self.chr_registers);
+        w.bool(self.ciram_disabled[0]);
+        w.bool(self.ciram_disabled[1]);
+        w.bytes(&self.prg_ram);
+        w.u8(self.write_protect);
+        w.bytes(&self.internal_ram);
+        w.u8(self.internal_address);
+        w.bool(self.auto_increment);
+        w.u16(self.irq_counter);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg.load_state(&mut r)?;
+        self.chr.load_state(&mut r)?;
+        r.bytes_into(&mut self.chr_registers)?;
+        self.ciram_disabled = [r.bool()?, r.bool()?];
+        r.bytes_into(&mut self.prg_ram)?;
+        self.write_protect = r.u8()?;
+        r.bytes_into(&mut self.internal_ram)?;
+        self.internal_address = r.u8()? & 0x7F;
+        self.auto_increment = r.bool()?;
+        self.irq_counter = r.u16()? & 0x7FFF;
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> Namco163 {
+        // 8KB PRG banks and 1KB CHR banks filled with its bank number
+        let prg = (0..16).flat_map(|n| vec![n; PRG_BANK_SIZE]).collect();
+        let chr = (0..64).flat_map(|n| vec![n; CHR_BANK_SIZE]).collect();
+        Namco163::with_banks(prg, chr)
+    }
+
+    fn write(m: &mut Namco163, addr: u16, value: u8) {
+        m.write(addr.into(), value.into());
+    }
+
+    fn read(m: &Namco163, addr: u16) -> u8 {
+        m.peek(addr.into()).into()
+    }
+
+    #[test]
+    fn banks() {
+        let mut m = mapper();
+        write(&mut m, 0xE000, 3);
+        write(&mut m, 0xF000, 5);
+        assert_eq!(read(&m, 0x8000), 3);
+        assert_eq!(read(&m, 0xC000), 5);
+        assert_eq!(read(&m, 0xE000), 15);
+
+        write(&mut m, 0x8800, 40);
+        assert_eq!(read(&m, 0x0400), 40);
+    }
+
+    #[test]
+    fn vram_source() {
+        let mut m = mapper();
+        assert_eq!(m.vram_source(0x2400), VRAMSource::CIRAM(1));
+
+        // CHR ROM as a nametable
+        write(&mut m, 0xC800, 20);
+        assert_eq!(m.vram_source(0x2400), VRAMSource::CHR);
+        assert_eq!(read(&m, 0x2400), 20);
+
+        // CIRAM in the pattern tables unless disabled
+        write(&mut m, 0x9000, 0xE1);
+        assert_eq!(m.vram_source(0x0800), VRAMSource::CIRAM(1));
+        write(&mut m, 0xE800, 0x40);
+        assert_eq!(m.vram_source(0x0800), VRAMSource::CHR);
+    }
+
+    #[test]
+    fn internal_ram() {
+        let mut m = mapper();
+        write(&mut m, 0xF800, 0x80 | 0x10);
+        write(&mut m, 0x4800, 0x12);
+        write(&mut m, 0x4800, 0x34);
+        write(&mut m, 0xF800, 0x11);
+        assert_eq!(read(&m, 0x4800), 0x34);
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = mapper();
+        write(&mut m, 0x5000, 0xFE);
+        write(&mut m, 0x5800, 0xFF);
+        m.cpu_cycle();
+        assert!(m.irq());
+        assert_eq!(read(&m, 0x5000), 0xFF);
+
+        // stops at $7FFF
+        m.cpu_cycle();
+        assert_eq!(read(&m, 0x5000), 0xFF);
+
+        write(&mut m, 0x5800, 0x80);
+        assert!(!m.irq());
+    }
+}
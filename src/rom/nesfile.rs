@@ -1,7 +1,6 @@
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{BufReader, SeekFrom};
 use std::path::Path;
 
 use anyhow::{Context, Result};
@@ -9,34 +8,229 @@ use thiserror::Error;
 
 use crate::types::Mirroring;
 
+use super::database::{GameDatabase, GameInfo};
+use super::diagnostic::ROMDiagnostic;
+use super::hash::crc32;
+use super::ips;
+
 pub struct NESFile {
     header: NESFileHeader,
     row_data: Vec<u8>,
+    // problems of the header recovered from
+    diagnostics: Vec<ROMDiagnostic>,
 }
 
 impl NESFile {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<NESFile> {
-        let f = File::open(path.as_ref()).with_context(|| {
+        Self::from_bytes(read_file(path)?)
+    }
+
+    pub fn open_with_patch<P: AsRef<Path>, Q: AsRef<Path>>(path: P, patch: Q) -> Result<NESFile> {
+        let patch = std::fs::read(patch.as_ref()).with_context(|| {
             format!(
-                "Failed to open ROM file: {}",
-                path.as_ref().to_str().unwrap_or("unknown")
+                "Failed to open patch file: {}",
+                patch.as_ref().to_str().unwrap_or("unknown")
             )
         })?;
-        let mut b = BufReader::new(f);
-        let mut header_bytes = [0; NESFileHeader::SIZE];
+        Self::from_bytes_with_patch(read_file(path)?, &patch)
+    }
+
+    /// Zip archives are also accepted with the `zip` feature, loading the first `.nes` entry.
+    pub fn from_bytes(row_data: Vec<u8>) -> Result<NESFile> {
+        Self::parse(unpack(row_data)?)
+    }
+
+    /// Applies an IPS `patch` to the ROM file before parsing it.
+    pub fn from_bytes_with_patch(row_data: Vec<u8>, patch: &[u8]) -> Result<NESFile> {
+        let mut row_data = unpack(row_data)?;
+        ips::apply(&mut row_data, patch)?;
+        Self::parse(row_data)
+    }
 
-        b.read_exact(&mut header_bytes)?;
-        let header = NESFileHeader::parse(&header_bytes);
-        if !header.valid() {
-            return Err(From::from(NESFileError::InvalidHeader));
+    /// Same as `NESFile::open`, but a file without a valid header is looked up in `database` by
+    /// the CRC-32 of the whole data, which is then taken as PRG-ROM and CHR-ROM of the game.
+    pub fn open_lenient<P: AsRef<Path>>(path: P, database: &GameDatabase) -> Result<NESFile> {
+        let row_data = unpack(read_file(path)?)?;
+        if NESFileHeader::read(&row_data).is_some() {
+            return Self::parse(row_data);
+        }
+        match database.get(crc32(&row_data)) {
+            Some(game) if game.prg_rom_size + game.chr_rom_size == row_data.len() => {
+                Ok(Self::headerless(row_data, game))
+            }
+            _ => Err(From::from(NESFileError::InvalidHeader)),
+        }
+    }
+
+    fn parse(row_data: Vec<u8>) -> Result<NESFile> {
+        let mut header = NESFileHeader::read(&row_data).ok_or(NESFileError::InvalidHeader)?;
+        let mut diagnostics = header.repair();
+
+        let trainer_size = if header.flags6 & 0b100 == 0 {
+            0
+        } else {
+            TRAINER_SIZE
+        };
+        let prg_offset = NESFileHeader::SIZE + trainer_size;
+        let chr_size = header.chr_size_of_unit * 0x2000;
+        if header.prg_size_of_unit == 0 {
+            // taken as the rest of the file
+            let size = row_data.len().saturating_sub(prg_offset + chr_size);
+            let units = size / 0x4000;
+            if size == 0 || !size.is_multiple_of(0x4000) || 0xFF < units {
+                return Err(NESFileError::EmptyPRG.into());
+            }
+            header.prg_size_of_unit = units;
+            diagnostics.push(ROMDiagnostic::ZeroPRGSize {
+                inferred_size: size,
+            });
         }
+        let expected = prg_offset + header.prg_size_of_unit * 0x4000 + chr_size;
+        if row_data.len() < expected {
+            return Err(NESFileError::Truncated {
+                expected,
+                actual: row_data.len(),
+            }
+            .into());
+        }
+
+        Ok(Self {
+            header,
+            row_data,
+            diagnostics,
+        })
+    }
+
+    pub(super) fn diagnostics(&self) -> &[ROMDiagnostic] {
+        &self.diagnostics
+    }
+
+    // Puts an iNES header for the board of `game` in front of PRG-ROM and CHR-ROM.
+    fn headerless(rom: Vec<u8>, game: &GameInfo) -> NESFile {
+        let mut row_data = NESFileHeader::MAGIC_NUMBER.to_vec();
+        row_data.push((game.prg_rom_size / 0x4000) as u8);
+        row_data.push((game.chr_rom_size / 0x2000) as u8);
+        row_data.resize(NESFileHeader::SIZE, 0);
+        row_data.extend(rom);
+
+        let header = NESFileHeader::read(&row_data).unwrap();
+        let mut f = Self {
+            header,
+            row_data,
+            diagnostics: Vec::new(),
+        };
+        f.correct(game);
+        f
+    }
 
-        b.seek(SeekFrom::Start(0))?;
+    // https://wiki.nesdev.com/w/index.php/INES#Trainer
+    pub(super) fn trainer(&self) -> Option<&[u8]> {
+        if self.header.flags6 & 0b100 == 0 {
+            return None;
+        }
+        self.row_data
+            .get(NESFileHeader::SIZE..NESFileHeader::SIZE + TRAINER_SIZE)
+    }
 
-        let mut row_data = Vec::new();
-        b.read_to_end(&mut row_data)?;
+    // PRG-ROM follows the trainer if present
+    pub(super) fn prg_rom_offset(&self) -> usize {
+        NESFileHeader::SIZE + self.trainer().map_or(0, |t| t.len())
+    }
 
-        Ok(Self { header, row_data })
+    // PRG-ROM and CHR-ROM, which identify the game regardless of the header
+    pub(super) fn prg_chr_rom(&self) -> &[u8] {
+        let first = self.prg_rom_offset().min(self.row_data.len());
+        let size = self.header.prg_size_of_unit * 0x4000 + self.header.chr_size_of_unit * 0x2000;
+        &self.row_data[first..(first + size).min(self.row_data.len())]
+    }
+
+    // Rewrites the header with the board of `game`, as NES 2.0 for the submapper.
+    pub(super) fn correct(&mut self, game: &GameInfo) {
+        let header = &mut self.header;
+        let trainer = header.flags6 & 0b100;
+        let four_screen = if game.mirroring == Mirroring::FourScreen() {
+            0b1000
+        } else {
+            0
+        };
+        let vertical = if game.mirroring == Mirroring::Vertical() {
+            1
+        } else {
+            0
+        };
+        header.flags6 =
+            (game.mapper_no << 4) | four_screen | trainer | ((game.battery as u8) << 1) | vertical;
+        header.flags7 = (game.mapper_no & 0xF0) | 0b1000;
+        header._flags8 = game.submapper_no << 4;
+        header._flags9 = 0;
+        header._flags10 = 0;
+    }
+
+    // Overdumps detected by the contents, see `NESFile::trimmed`.
+    pub(super) fn overdumps(&self) -> Vec<ROMDiagnostic> {
+        let mut diagnostics = Vec::new();
+        let (prg, chr, trailing) = self.sections();
+        let prg_size = unique_size(prg, 0x4000);
+        if prg_size < prg.len() {
+            diagnostics.push(ROMDiagnostic::DuplicatedPRG {
+                size: prg.len(),
+                unique_size: prg_size,
+            });
+        }
+        let chr_size = unique_size(chr, 0x2000);
+        if chr_size < chr.len() {
+            diagnostics.push(ROMDiagnostic::DuplicatedCHR {
+                size: chr.len(),
+                unique_size: chr_size,
+            });
+        }
+        if !trailing.is_empty() {
+            diagnostics.push(ROMDiagnostic::TrailingData {
+                size: trailing.len(),
+            });
+        }
+        diagnostics
+    }
+
+    // The file without the overdumps, with the repaired header updated to the sizes.
+    pub(super) fn trimmed(&self) -> Vec<u8> {
+        let (prg, chr, _) = self.sections();
+        let prg = &prg[..unique_size(prg, 0x4000)];
+        let chr = &chr[..unique_size(chr, 0x2000)];
+
+        let mut data = self.header.to_bytes().to_vec();
+        data[4] = (prg.len() / 0x4000) as u8;
+        data[5] = (chr.len() / 0x2000) as u8;
+        data.extend_from_slice(self.trainer().unwrap_or_default());
+        data.extend_from_slice(prg);
+        data.extend_from_slice(chr);
+        data
+    }
+
+    // PRG-ROM, CHR-ROM and the rest, excluding the INST-ROM and PROM of PlayChoice-10
+    fn sections(&self) -> (&[u8], &[u8], &[u8]) {
+        let data = &self.row_data[self.prg_rom_offset().min(self.row_data.len())..];
+        let (prg, rest) = data.split_at((self.header.prg_size_of_unit * 0x4000).min(data.len()));
+        let (chr, rest) = rest.split_at((self.header.chr_size_of_unit * 0x2000).min(rest.len()));
+        let rest = if self.console_type() == ConsoleType::PlayChoice10 {
+            &rest[PLAYCHOICE_DATA_SIZE.min(rest.len())..]
+        } else {
+            rest
+        };
+        (prg, chr, rest)
+    }
+
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#Console_Type
+    pub(super) fn console_type(&self) -> ConsoleType {
+        match self.header.flags7 & 0b11 {
+            0 => ConsoleType::NES,
+            1 => ConsoleType::VSSystem,
+            2 => ConsoleType::PlayChoice10,
+            // with the extended console type in byte 13 of NES 2.0
+            _ if self.header.nes2() => ConsoleType::Extended(self.header.padding[2] & 0x0F),
+            // both bits in iNES, taken as VS. System
+            _ => ConsoleType::VSSystem,
+        }
     }
 
     fn read_bytes(&self, first: usize, count: usize) -> (Vec<u8>, usize) {
@@ -56,7 +250,21 @@ impl NESFile {
         }
     }
 
+    /// Returns true if the board has CHR RAM instead of CHR ROM.
+    pub(super) fn chr_ram(&self) -> bool {
+        self.header.chr_size_of_unit == 0
+    }
+
     pub(super) fn mirroring(&self) -> Mirroring {
+        if self.four_screen() {
+            Mirroring::FourScreen()
+        } else {
+            self.hardwired_mirroring()
+        }
+    }
+
+    // by the mirroring bit, which some boards use with the four-screen bit for other meanings
+    pub(super) fn hardwired_mirroring(&self) -> Mirroring {
         if self.header.flags6 & 1 == 0 {
             Mirroring::Horizontal()
         } else {
@@ -64,11 +272,96 @@ impl NESFile {
         }
     }
 
+    pub(super) fn four_screen(&self) -> bool {
+        self.header.flags6 & 0b1000 != 0
+    }
+
+    pub(super) fn battery(&self) -> bool {
+        self.header.flags6 & 0b10 != 0
+    }
+
     pub(super) fn mapper_no(&self) -> u8 {
         (self.header.flags7 & 0b11110000) + (self.header.flags6 >> 4)
     }
+
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#Submappers
+    pub(super) fn submapper_no(&self) -> u8 {
+        if self.header.nes2() {
+            self.header._flags8 >> 4
+        } else {
+            0
+        }
+    }
+}
+
+const ZIP_MAGIC_NUMBER: &[u8] = b"PK\x03\x04";
+
+// The size of the head of `data` which the rest repeats, down to `min`.
+fn unique_size(data: &[u8], min: usize) -> usize {
+    let mut size = data.len();
+    while min < size && size.is_multiple_of(2) && data[..size / 2] == data[size / 2..size] {
+        size /= 2;
+    }
+    size
+}
+
+fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let mut f = File::open(path.as_ref()).with_context(|| {
+        format!(
+            "Failed to open ROM file: {}",
+            path.as_ref().to_str().unwrap_or("unknown")
+        )
+    })?;
+    let mut data = Vec::new();
+    f.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn unpack(data: Vec<u8>) -> Result<Vec<u8>> {
+    if data.starts_with(ZIP_MAGIC_NUMBER) {
+        unzip(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+#[cfg(feature = "zip")]
+fn unzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_file() && entry.name().to_ascii_lowercase().ends_with(".nes") {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            return Ok(data);
+        }
+    }
+    Err(NESFileError::NoNESFileInArchive.into())
+}
+
+#[cfg(not(feature = "zip"))]
+fn unzip(_data: &[u8]) -> Result<Vec<u8>> {
+    Err(NESFileError::ZipUnsupported.into())
 }
 
+/// Hardware that a ROM file is dumped from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConsoleType {
+    NES,
+    /// VS. System arcade boards, with their own palettes and coin inputs.
+    VSSystem,
+    /// PlayChoice-10 arcade boards, running NES games with an INST-ROM of the instructions.
+    PlayChoice10,
+    /// The extended console type of NES 2.0, such as Famiclone VT01.
+    Extended(u8),
+}
+
+// 8KB INST-ROM followed by 16 bytes of PROM data and 16 bytes of PROM CounterOut
+const PLAYCHOICE_DATA_SIZE: usize = 0x2000 + 32;
+
+/// Size of the trainer, loaded to $7000-$71FF.
+pub(super) const TRAINER_SIZE: usize = 0x200;
+
 pub struct NESFileHeader {
     magic: [u8; 4],
     prg_size_of_unit: usize,
@@ -100,8 +393,71 @@ impl NESFileHeader {
         }
     }
 
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#Identification
+    fn nes2(&self) -> bool {
+        self.flags7 & 0b1100 == 0b1000
+    }
+
     fn valid(&self) -> bool {
-        self.magic == Self::MAGIC_NUMBER && self.padding == Self::PADDING
+        self.magic == Self::MAGIC_NUMBER
+    }
+
+    // Ignores bytes 7-15 of iNES headers with garbage there, such as "DiskDude!" written by old
+    // dumping tools, which would corrupt the mapper number.
+    // https://wiki.nesdev.com/w/index.php/INES#Variant_comparison
+    fn repair(&mut self) -> Vec<ROMDiagnostic> {
+        if self.nes2() {
+            return Vec::new();
+        }
+        let diagnostic = if self.padding != Self::PADDING {
+            let bytes = &self.to_bytes()[7..];
+            ROMDiagnostic::DirtyHeader {
+                text: bytes
+                    .iter()
+                    .map(|b| {
+                        if b.is_ascii_graphic() {
+                            *b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect(),
+            }
+        } else if self.flags7 & 0b1100 == 0b0100 {
+            ROMDiagnostic::ArchaicHeader
+        } else {
+            return Vec::new();
+        };
+        self.flags7 = 0;
+        self._flags8 = 0;
+        self._flags9 = 0;
+        self._flags10 = 0;
+        self.padding = Self::PADDING;
+        vec![diagnostic]
+    }
+
+    fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..4].copy_from_slice(&self.magic);
+        bytes[4] = self.prg_size_of_unit as u8;
+        bytes[5] = self.chr_size_of_unit as u8;
+        bytes[6] = self.flags6;
+        bytes[7] = self.flags7;
+        bytes[8] = self._flags8;
+        bytes[9] = self._flags9;
+        bytes[10] = self._flags10;
+        bytes[11..].copy_from_slice(&self.padding);
+        bytes
+    }
+
+    // the header at the head of `data`
+    fn read(data: &[u8]) -> Option<Self> {
+        let header = Self::parse(data.get(..Self::SIZE)?.try_into().unwrap());
+        if header.valid() {
+            Some(header)
+        } else {
+            None
+        }
     }
 }
 
@@ -109,6 +465,16 @@ impl NESFileHeader {
 enum NESFileError {
     #[error("The ROM file has invalid header")]
     InvalidHeader,
+    #[error("The ROM file has no PRG-ROM")]
+    EmptyPRG,
+    #[error("The ROM file is {actual} bytes, shorter than {expected} bytes in the header")]
+    Truncated { expected: usize, actual: usize },
+    #[cfg(feature = "zip")]
+    #[error("The archive has no .nes file")]
+    NoNESFileInArchive,
+    #[cfg(not(feature = "zip"))]
+    #[error("Zip archives are not supported without the zip feature")]
+    ZipUnsupported,
 }
 
 #[cfg(test)]
@@ -146,6 +512,91 @@ mod tests {
         assert!(!header.valid());
     }
 
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip() {
+        use std::io::{Cursor, Write};
+        use zip::write::{FileOptions, ZipWriter};
+
+        let mut nes = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0];
+        nes.extend(vec![0; 10 + 0x4000]);
+        let mut w = ZipWriter::new(Cursor::new(Vec::new()));
+        w.start_file("readme.txt", FileOptions::default()).unwrap();
+        w.write_all(b"hello").unwrap();
+        w.start_file("game.NES", FileOptions::default()).unwrap();
+        w.write_all(&nes).unwrap();
+        let archive = w.finish().unwrap().into_inner();
+
+        let f = NESFile::from_bytes(archive).unwrap();
+        assert_eq!(f.row_data, nes);
+
+        let mut w = ZipWriter::new(Cursor::new(Vec::new()));
+        w.start_file("readme.txt", FileOptions::default()).unwrap();
+        let archive = w.finish().unwrap().into_inner();
+        assert!(NESFile::from_bytes(archive).is_err());
+    }
+
+    #[test]
+    fn from_bytes() {
+        assert!(NESFile::from_bytes(vec![0x4E, 0x45, 0x53, 0x1A]).is_err());
+
+        let path = Path::new(file!()).parent().unwrap().join("sample.nes");
+        let nesfile = NESFile::from_bytes(std::fs::read(path).unwrap()).unwrap();
+        assert!(nesfile.header.valid());
+    }
+
+    #[test]
+    fn repair() {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 0, 0, 0x10];
+        data.extend(b"DiskDude!");
+        data.extend(vec![0; 0x4000]);
+        let f = NESFile::from_bytes(data.clone()).unwrap();
+        assert_eq!(f.mapper_no(), 1);
+        assert_eq!(f.header.prg_size_of_unit, 1);
+        assert_eq!(
+            f.diagnostics(),
+            &[
+                ROMDiagnostic::DirtyHeader {
+                    text: "DiskDude!".to_string()
+                },
+                ROMDiagnostic::ZeroPRGSize {
+                    inferred_size: 0x4000
+                }
+            ]
+        );
+        assert_eq!(
+            &f.trimmed()[4..16],
+            &[1, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // NES 2.0 uses the bytes
+        data[7] = 0x08;
+        let f = NESFile::from_bytes(data.clone()).unwrap();
+        assert_eq!(f.diagnostics().len(), 1);
+
+        data[4] = 2;
+        assert!(NESFile::from_bytes(data).is_err());
+    }
+
+    #[test]
+    fn console_type() {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 0, 0, 0b10];
+        data.extend(vec![0; 8 + 0x4000]);
+        data.extend(vec![0xFF; PLAYCHOICE_DATA_SIZE]);
+        let f = NESFile::from_bytes(data.clone()).unwrap();
+        assert_eq!(f.console_type(), ConsoleType::PlayChoice10);
+        assert!(f.overdumps().is_empty());
+
+        data[7] = 0b01;
+        let f = NESFile::from_bytes(data.clone()).unwrap();
+        assert_eq!(f.console_type(), ConsoleType::VSSystem);
+
+        data[7] = 0b1011;
+        data[13] = 0x03;
+        let f = NESFile::from_bytes(data).unwrap();
+        assert_eq!(f.console_type(), ConsoleType::Extended(3));
+    }
+
     #[test]
     fn load_sample_rom() {
         use std::path::Path;
@@ -0,0 +1,497 @@
+use std::f32::consts::PI;
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+
+// YM2413 (OPLL) derived FM synthesizer of VRC7: 6 channels of a modulator and a carrier
+// https://wiki.nesdev.com/w/index.php/VRC7_audio
+//
+// The operators are computed in floating point, so the output is close to the chip but not
+// bit-exact.
+
+// CPU cycles per sample
+pub(super) const CLOCK_DIVIDER: u32 = 36;
+
+const CHANNEL_COUNT: usize = 6;
+
+// Built-in instruments 1-15
+// https://wiki.nesdev.com/w/index.php/VRC7_audio#Instruments
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+// key scale level in dB at block 7 for 6 dB/octave, by the upper 4 bits of F-Number
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+// attenuation in dB at which an operator is silent
+const MAX_ATTENUATION: f32 = 48.0;
+
+// seconds of a full attack and a 96 dB decay at rate 4 (R = 1), halved every 4 rates
+const ATTACK_TIME: f32 = 2.826;
+const DECAY_TIME: f32 = 39.28;
+
+const SAMPLE_RATE: f32 = 1_789_773.0 / CLOCK_DIVIDER as f32;
+
+// tremolo (AM) and vibrato (FM)
+const AM_FREQUENCY: f32 = 3.7;
+const AM_DEPTH: f32 = 4.8;
+const FM_FREQUENCY: f32 = 6.4;
+// about 7 cents
+const FM_DEPTH: f32 = 0.004;
+
+// output level of a channel at full volume, relative to the APU
+const CHANNEL_LEVEL: f32 = 0.12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+// by the value of each state in save states
+const ENVELOPE_STATES: [EnvelopeState; 5] = [
+    EnvelopeState::Attack,
+    EnvelopeState::Decay,
+    EnvelopeState::Sustain,
+    EnvelopeState::Release,
+    EnvelopeState::Off,
+];
+
+// parameters of an operator in an instrument
+#[derive(Debug, Copy, Clone)]
+struct Patch {
+    am: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: f32,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl Patch {
+    // `op` is 0 for the modulator, 1 for the carrier
+    fn new(bytes: &[u8; 8], op: usize) -> Self {
+        let flags = bytes[op];
+        Self {
+            am: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0F) as usize],
+            key_scale_level: bytes[2 + op] >> 6,
+            rectified: bytes[3] & (0x08 << op) != 0,
+            attack: bytes[4 + op] >> 4,
+            decay: bytes[4 + op] & 0x0F,
+            sustain_level: bytes[6 + op] >> 4,
+            release: bytes[6 + op] & 0x0F,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Operator {
+    // in cycles of the wave
+    phase: f32,
+    state: EnvelopeState,
+    // in dB
+    envelope: f32,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            phase: 0.0,
+            state: EnvelopeState::Off,
+            envelope: MAX_ATTENUATION,
+        }
+    }
+}
+
+impl Operator {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.f32(self.phase);
+        w.u8(self.state as u8);
+        w.f32(self.envelope);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.phase = r.f32()?;
+        self.state = *ENVELOPE_STATES
+            .get(r.u8()? as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid envelope"))?;
+        self.envelope = r.f32()?;
+        Ok(())
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    fn advance(&mut self, patch: &Patch, step: f32, vibrato: f32) {
+        let vibrato = if patch.vibrato { vibrato } else { 1.0 };
+        self.phase = (self.phase + step * patch.multiplier * vibrato).fract();
+    }
+
+    fn clock_envelope(&mut self, patch: &Patch, key_scale: u8, sustain: bool) {
+        match self.state {
+            EnvelopeState::Attack => {
+                let rate = rate(patch.attack, key_scale);
+                if 60 <= rate {
+                    self.envelope = 0.0;
+                } else if 0 < rate {
+                    self.envelope -= MAX_ATTENUATION / (duration(ATTACK_TIME, rate) * SAMPLE_RATE);
+                }
+                if self.envelope <= 0.0 {
+                    self.envelope = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.envelope += decay_step(patch.decay, key_scale);
+                let sustain_level = patch.sustain_level as f32 * 3.0;
+                if sustain_level <= self.envelope {
+                    self.envelope = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain => {
+                // percussive instruments keep decaying while the key is on
+                if !patch.sustained {
+                    self.envelope += decay_step(patch.release, key_scale);
+                }
+            }
+            EnvelopeState::Release => {
+                let release = if sustain {
+                    5
+                } else if patch.sustained {
+                    patch.release
+                } else {
+                    7
+                };
+                self.envelope += decay_step(release, key_scale);
+            }
+            EnvelopeState::Off => {}
+        }
+        if MAX_ATTENUATION <= self.envelope {
+            self.envelope = MAX_ATTENUATION;
+            if self.state != EnvelopeState::Attack {
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+
+    // `modulation` shifts the phase in radians
+    fn output(&self, patch: &Patch, modulation: f32, attenuation: f32) -> f32 {
+        let attenuation = self.envelope + attenuation;
+        if MAX_ATTENUATION <= attenuation {
+            return 0.0;
+        }
+        let wave = (2.0 * PI * self.phase + modulation).sin();
+        let wave = if patch.rectified && wave < 0.0 {
+            0.0
+        } else {
+            wave
+        };
+        wave * 10f32.powf(-attenuation / 20.0)
+    }
+}
+
+fn rate(r: u8, key_scale: u8) -> u8 {
+    if r == 0 {
+        0
+    } else {
+        (r * 4 + key_scale).min(63)
+    }
+}
+
+fn duration(time: f32, rate: u8) -> f32 {
+    time / 2f32.powf((rate as f32 - 4.0) / 4.0)
+}
+
+fn decay_step(r: u8, key_scale: u8) -> f32 {
+    let rate = rate(r, key_scale);
+    if rate == 0 {
+        0.0
+    } else {
+        96.0 / (duration(DECAY_TIME, rate) * SAMPLE_RATE)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Channel {
+    f_number: u16,
+    block: u8,
+    key_on: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+
+    modulator: Operator,
+    carrier: Operator,
+    // the last two outputs of the modulator
+    feedback: [f32; 2],
+}
+
+impl Channel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.f_number);
+        w.u8(self.block);
+        w.bool(self.key_on);
+        w.bool(self.sustain);
+        w.u8(self.instrument);
+        w.u8(self.volume);
+        self.modulator.save_state(w);
+        self.carrier.save_state(w);
+        w.f32(self.feedback[0]);
+        w.f32(self.feedback[1]);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.f_number = r.u16()? & 0x1FF;
+        self.block = r.u8()? & 0b111;
+        self.key_on = r.bool()?;
+        self.sustain = r.bool()?;
+        self.instrument = r.u8()? & 0x0F;
+        self.volume = r.u8()? & 0x0F;
+        self.modulator.load_state(r)?;
+        self.carrier.load_state(r)?;
+        self.feedback = [r.f32()?, r.f32()?];
+        Ok(())
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.f_number = self.f_number & 0xFF | ((value & 1) as u16) << 8;
+        self.block = (value >> 1) & 0b111;
+        self.sustain = value & 0x20 != 0;
+        let key_on = value & 0x10 != 0;
+        if key_on && !self.key_on {
+            self.modulator.key_on();
+            self.carrier.key_on();
+        } else if !key_on && self.key_on {
+            self.modulator.key_off();
+            self.carrier.key_off();
+        }
+        self.key_on = key_on;
+    }
+
+    fn key_scale(&self, patch: &Patch) -> u8 {
+        let rks = self.block << 1 | (self.f_number >> 8) as u8;
+        if patch.key_scale_rate {
+            rks
+        } else {
+            rks >> 2
+        }
+    }
+
+    fn key_scale_level(&self, patch: &Patch) -> f32 {
+        if patch.key_scale_level == 0 {
+            return 0.0;
+        }
+        let level = KEY_SCALE_LEVELS[(self.f_number >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        level.max(0.0) / (1 << (3 - patch.key_scale_level)) as f32
+    }
+
+    fn clock(&mut self, bytes: &[u8; 8], am: f32, vibrato: f32) -> f32 {
+        let m = Patch::new(bytes, 0);
+        let c = Patch::new(bytes, 1);
+
+        let step = self.f_number as f32 * (1 << self.block) as f32 / (1 << 19) as f32;
+        self.modulator.advance(&m, step, vibrato);
+        self.carrier.advance(&c, step, vibrato);
+        let (mks, cks) = (self.key_scale(&m), self.key_scale(&c));
+        self.modulator.clock_envelope(&m, mks, self.sustain);
+        self.carrier.clock_envelope(&c, cks, self.sustain);
+
+        let am_of = |p: &Patch| if p.am { am } else { 0.0 };
+
+        let feedback_level = bytes[3] & 0b111;
+        let feedback = if feedback_level == 0 {
+            0.0
+        } else {
+            (self.feedback[0] + self.feedback[1]) / 2.0 * PI * 2f32.powi(feedback_level as i32 - 5)
+        };
+        // total level of the modulator in 0.75 dB
+        let total_level = (bytes[2] & 0x3F) as f32 * 0.75;
+        let modulator = self.modulator.output(
+            &m,
+            feedback,
+            total_level + self.key_scale_level(&m) + am_of(&m),
+        );
+        self.feedback = [self.feedback[1], modulator];
+
+        let volume = self.volume as f32 * 3.0;
+        self.carrier.output(
+            &c,
+            modulator * 4.0 * PI,
+            volume + self.key_scale_level(&c) + am_of(&c),
+        )
+    }
+}
+
+pub(super) struct OPLL {
+    address: u8,
+    custom: [u8; 8],
+    channels: [Channel; CHANNEL_COUNT],
+    // samples for the LFOs
+    lfo_clock: u32,
+    output: f32,
+}
+
+impl OPLL {
+    pub fn new() -> Self {
+        Self {
+            address: 0,
+            custom: [0; 8],
+            channels: [Default::default(); CHANNEL_COUNT],
+            lfo_clock: 0,
+            output: 0.0,
+        }
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        let a = self.address as usize;
+        match a {
+            0x00..=0x07 => self.custom[a] = value,
+            0x10..=0x15 => {
+                let ch = &mut self.channels[a - 0x10];
+                ch.f_number = ch.f_number & 0x100 | value as u16;
+            }
+            0x20..=0x25 => self.channels[a - 0x20].write_control(value),
+            0x30..=0x35 => {
+                let ch = &mut self.channels[a - 0x30];
+                ch.instrument = value >> 4;
+                ch.volume = value & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    // called every `CLOCK_DIVIDER` CPU cycles
+    pub fn clock(&mut self) {
+        let t = self.lfo_clock as f32 / SAMPLE_RATE;
+        self.lfo_clock = self.lfo_clock.wrapping_add(1);
+        let am = (1.0 - (2.0 * PI * AM_FREQUENCY * t).cos()) / 2.0 * AM_DEPTH;
+        let vibrato = 1.0 + FM_DEPTH * (2.0 * PI * FM_FREQUENCY * t).sin();
+
+        let custom = self.custom;
+        let mut sum = 0.0;
+        for ch in self.channels.iter_mut() {
+            let bytes = match ch.instrument {
+                0 => &custom,
+                n => &PATCHES[n as usize - 1],
+            };
+            sum += ch.clock(bytes, am, vibrato);
+        }
+        self.output = sum * CHANNEL_LEVEL;
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.address);
+        w.bytes(&self.custom);
+        for ch in &self.channels {
+            ch.save_state(w);
+        }
+        w.u32(self.lfo_clock);
+        w.f32(self.output);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.address = r.u8()?;
+        r.bytes_into(&mut self.custom)?;
+        for ch in self.channels.iter_mut() {
+            ch.load_state(r)?;
+        }
+        self.lfo_clock = r.u32()?;
+        self.output = r.f32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(opll: &mut OPLL, addr: u8, value: u8) {
+        opll.write_address(addr);
+        opll.write_data(value);
+    }
+
+    fn peak(opll: &mut OPLL, samples: usize) -> f32 {
+        (0..samples).fold(0.0, |peak, _| {
+            opll.clock();
+            peak.max(opll.output().abs())
+        })
+    }
+
+    #[test]
+    fn key_on_and_off() {
+        let mut opll = OPLL::new();
+        assert_eq!(peak(&mut opll, 100), 0.0);
+
+        // instrument 3 at full volume, A4
+        write(&mut opll, 0x30, 0x30);
+        write(&mut opll, 0x10, 0x22);
+        write(&mut opll, 0x20, 0x19);
+        assert!(0.01 < peak(&mut opll, 1000));
+
+        write(&mut opll, 0x20, 0x09);
+        peak(&mut opll, SAMPLE_RATE as usize * 2);
+        assert_eq!(peak(&mut opll, 100), 0.0);
+    }
+
+    #[test]
+    fn volume() {
+        let mut loud = OPLL::new();
+        let mut quiet = OPLL::new();
+        for (opll, volume) in [(&mut loud, 0), (&mut quiet, 4)].iter_mut() {
+            write(opll, 0x30, 0x30 | *volume);
+            write(opll, 0x10, 0x22);
+            write(opll, 0x20, 0x19);
+        }
+        assert!(peak(&mut quiet, 1000) < peak(&mut loud, 1000));
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::types::Mirroring;
+
+use super::nesfile::NESFile;
+use super::Mapper;
+
+/// Contents of an iNES file, given to the mappers registered in a `MapperRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    pub mapper_no: u8,
+    pub submapper_no: u8,
+    pub prg_rom: Vec<u8>,
+    /// Empty if the cartridge has CHR RAM instead.
+    pub chr_rom: Vec<u8>,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+}
+
+impl Cartridge {
+    pub(super) fn new(rom: &NESFile) -> Self {
+        let (prg_rom, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr_rom = rom
+            .read_chr_rom(next, 0x2000)
+            .map_or_else(Vec::new, |(chr, _)| chr);
+        Self {
+            mapper_no: rom.mapper_no(),
+            submapper_no: rom.submapper_no(),
+            prg_rom,
+            chr_rom,
+            mirroring: rom.mirroring(),
+            battery: rom.battery(),
+        }
+    }
+}
+
+type Constructor = Box<dyn Fn(&Cartridge) -> Result<Box<dyn Mapper>>>;
+
+/// User-defined mappers by mapper number, which take precedence over the built-in mappers.
+///
+/// ```
+/// # use rustnes::{Byte, Cartridge, Mapper, MapperRegistry, Memory, Mirroring, Word};
+/// struct MyBoard(Cartridge);
+///
+/// impl Memory for MyBoard {
+///     fn peek(&self, addr: Word) -> Byte {
+///         let addr: u16 = addr.into();
+///         match addr {
+///             0x8000..=0xFFFF => self.0.prg_rom[addr as usize & 0x7FFF].into(),
+///             _ => 0.into(),
+///         }
+///     }
+///
+///     fn write(&mut self, _addr: Word, _value: Byte) {}
+/// }
+///
+/// impl Mapper for MyBoard {
+///     fn mirroring(&self) -> Mirroring {
+///         self.0.mirroring
+///     }
+/// }
+///
+/// let mut registry = MapperRegistry::new();
+/// registry.register(200, |cartridge| Ok(MyBoard(cartridge.clone())));
+/// ```
+#[derive(Default)]
+pub struct MapperRegistry {
+    constructors: HashMap<u8, Constructor>,
+}
+
+impl MapperRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers `constructor` to build the mapper for ROMs with `mapper_no`.
+    pub fn register<M, F>(&mut self, mapper_no: u8, constructor: F)
+    where
+        M: Mapper + 'static,
+        F: Fn(&Cartridge) -> Result<M> + 'static,
+    {
+        self.constructors.insert(
+            mapper_no,
+            Box::new(move |cartridge| {
+                let mapper: Box<dyn Mapper> = Box::new(constructor(cartridge)?);
+                Ok(mapper)
+            }),
+        );
+    }
+
+    pub(super) fn build(&self, rom: &NESFile) -> Option<Result<Box<dyn Mapper>>> {
+        self.constructors
+            .get(&rom.mapper_no())
+            .map(|constructor| constructor(&Cartridge::new(rom)))
+    }
+}
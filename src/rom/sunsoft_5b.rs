@@ -0,0 +1,121 @@
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+
+// Sunsoft 5B expansion audio, a YM2149F (AY-3-8910) in the FME-7
+// https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+//
+// Only the 3 square channels are emulated; the noise and the envelope are rarely used by games.
+
+// CPU cycles per tick of the tone counters
+const CLOCK_DIVIDER: u8 = 16;
+
+// output level of a channel at full volume, relative to the APU
+const CHANNEL_LEVEL: f32 = 0.1;
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Tone {
+    period: u16,
+    counter: u16,
+    high: bool,
+    volume: u8,
+    enabled: bool,
+}
+
+impl Tone {
+    fn tick(&mut self) {
+        self.counter += 1;
+        if self.period.max(1) <= self.counter {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+
+    fn output(&self) -> f32 {
+        if !self.enabled || !self.high || self.volume == 0 {
+            return 0.0;
+        }
+        // 3 dB per step
+        10f32.powf(-3.0 * (15 - self.volume) as f32 / 20.0)
+    }
+}
+
+pub(super) struct Sunsoft5B {
+    address: u8,
+    tones: [Tone; 3],
+    divider: u8,
+}
+
+impl Sunsoft5B {
+    pub fn new() -> Self {
+        Self {
+            address: 0,
+            tones: [Default::default(); 3],
+            divider: 0,
+        }
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value & 0x0F;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        match self.address {
+            a @ 0x0..=0x5 => {
+                let tone = &mut self.tones[a as usize / 2];
+                tone.period = if a % 2 == 0 {
+                    tone.period & 0xF00 | value as u16
+                } else {
+                    tone.period & 0x0FF | ((value & 0x0F) as u16) << 8
+                };
+            }
+            0x7 => {
+                for (i, tone) in self.tones.iter_mut().enumerate() {
+                    tone.enabled = value & (1 << i) == 0;
+                }
+            }
+            a @ 0x8..=0xA => self.tones[a as usize - 8].volume = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    // called every CPU cycle
+    pub fn step(&mut self) {
+        self.divider += 1;
+        if CLOCK_DIVIDER <= self.divider {
+            self.divider = 0;
+            for tone in self.tones.iter_mut() {
+                tone.tick();
+            }
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        self.tones.iter().map(Tone::output).sum::<f32>() * CHANNEL_LEVEL
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.address);
+        for tone in &self.tones {
+            w.u16(tone.period);
+            w.u16(tone.counter);
+            w.bool(tone.high);
+            w.u8(tone.volume);
+            w.bool(tone.enabled);
+        }
+        w.u8(self.divider);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.address = r.u8()? & 0x0F;
+        for tone in self.tones.iter_mut() {
+            tone.period = r.u16()? & 0xFFF;
+            tone.counter = r.u16()?;
+            tone.high = r.bool()?;
+            tone.volume = r.u8()? & 0x0F;
+            tone.enabled = r.bool()?;
+        }
+        self.divider = r.u8()?;
+        Ok(())
+    }
+}
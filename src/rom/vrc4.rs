@@ -0,0 +1,344 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::nesfile::NESFile;
+use super::vrc_irq::IRQCounter;
+use super::{copy_save_ram, Mapper};
+
+// Konami VRC2 and VRC4 (mapper 21, 22, 23, 25)
+// https://wiki.nesdev.com/w/index.php/VRC2_and_VRC4
+//
+// The boards differ in which CPU address lines select the registers in each $1000 block.
+// VRC2 is a subset of VRC4 without IRQ, PRG swap mode and single-screen mirroring.
+pub struct VRC4 {
+    variant: Variant,
+    prg: Banks,
+    chr: Banks,
+    prg_ram: [u8; 0x2000],
+    mirroring: Mirroring,
+
+    prg_registers: [u8; 2],
+    prg_swap: bool,
+    chr_registers: [u16; 8],
+
+    irq: IRQCounter,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Variant {
+    vrc2: bool,
+    // address bits of the register bit 0 and bit 1, ORed when the board is ambiguous
+    a0_mask: u16,
+    a1_mask: u16,
+    // VRC2a ignores the lowest bit of CHR banks
+    chr_shift: u8,
+}
+
+impl Variant {
+    // https://wiki.nesdev.com/w/index.php/NES_2.0_submappers#021:_VRC4a.2FVRC4c
+    fn new(mapper_no: u8, submapper_no: u8) -> Self {
+        let (vrc2, a0_mask, a1_mask, chr_shift) = match (mapper_no, submapper_no) {
+            (21, 1) => (false, 0x02, 0x04, 0), // VRC4a
+            (21, 2) => (false, 0x40, 0x80, 0), // VRC4c
+            (21, _) => (false, 0x42, 0x84, 0),
+            (22, _) => (true, 0x02, 0x01, 1),  // VRC2a
+            (23, 1) => (false, 0x01, 0x02, 0), // VRC4f
+            (23, 2) => (false, 0x04, 0x08, 0), // VRC4e
+            (23, 3) => (true, 0x01, 0x02, 0),  // VRC2b
+            (23, _) => (false, 0x05, 0x0A, 0),
+            (25, 1) => (false, 0x02, 0x01, 0), // VRC4b
+            (25, 2) => (false, 0x08, 0x04, 0), // VRC4d
+            (25, 3) => (true, 0x02, 0x01, 0),  // VRC2c
+            _ => (false, 0x0A, 0x05, 0),
+        };
+        Self {
+            vrc2,
+            a0_mask,
+            a1_mask,
+            chr_shift,
+        }
+    }
+
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.a0_mask != 0) as u16;
+        let a1 = (addr & self.a1_mask != 0) as u16;
+        addr & 0xF000 | a1 << 1 | a0
+    }
+}
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+impl VRC4 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut mapper = Self::with_banks(
+            Variant::new(rom.mapper_no(), rom.submapper_no()),
+            prg,
+            chr,
+            rom.mirroring(),
+        );
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    fn with_banks(variant: Variant, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        let mut mapper = Self {
+            variant,
+            prg: Banks::new(prg, 4, PRG_BANK_SIZE),
+            chr: Banks::new(chr, 8, CHR_BANK_SIZE),
+            prg_ram: [0; 0x2000],
+            mirroring,
+            prg_registers: [0, 1],
+            prg_swap: false,
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            irq: IRQCounter::new(),
+        };
+        mapper.update_banks();
+        mapper
+    }
+
+    fn update_banks(&mut self) {
+        let [r0, r1] = self.prg_registers;
+        if self.prg_swap {
+            self.prg.select(0, -2);
+            self.prg.select(2, r0 as isize);
+        } else {
+            self.prg.select(0, r0 as isize);
+            self.prg.select(2, -2);
+        }
+        self.prg.select(1, r1 as isize);
+        self.prg.select(3, -1);
+
+        for (i, r) in self.chr_registers.iter().enumerate() {
+            self.chr.select(i, (r >> self.variant.chr_shift) as isize);
+        }
+    }
+
+    fn write_chr_register(&mut self, register: u16, value: u8) {
+        // $B000-$E003: low and high nibbles of 2 banks in each block
+        let bank = ((register >> 12) - 0xB) as usize * 2 + (register as usize >> 1 & 1);
+        let r = &mut self.chr_registers[bank];
+        *r = if register & 1 == 0 {
+            *r & 0x1F0 | value as u16 & 0x0F
+        } else {
+            *r & 0x00F | (value as u16 & 0x1F) << 4
+        };
+        self.update_banks();
+    }
+}
+
+impl Memory for VRC4 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        if addr < 0x8000 {
+            match addr {
+                0x0000..=0x1FFF => self.chr.write(addr as usize, value),
+                0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = value,
+                _ => {}
+            }
+            return;
+        }
+
+        let register = self.variant.register(addr);
+        match register {
+            0x8000..=0x8003 => {
+                self.prg_registers[0] = value & 0x1F;
+                self.update_banks();
+            }
+            0x9000..=0x9003 if self.variant.vrc2 => {
+                self.mirroring = if value & 1 == 0 {
+                    Mirroring::Vertical()
+                } else {
+                    Mirroring::Horizontal()
+                };
+            }
+            0x9000..=0x9001 => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical(),
+                    1 => Mirroring::Horizontal(),
+                    n => Mirroring::SingleScreen(n - 2),
+                };
+            }
+            0x9002..=0x9003 => {
+                self.prg_swap = value & 0b10 != 0;
+                self.update_banks();
+            }
+            0xA000..=0xA003 => {
+                self.prg_registers[1] = value & 0x1F;
+                self.update_banks();
+            }
+            0xB000..=0xEFFF => self.write_chr_register(register, value),
+            0xF000..=0xFFFF if self.variant.vrc2 => {}
+            0xF000 => self.irq.write_latch_low(value),
+            0xF001 => self.irq.write_latch_high(value),
+            0xF002 => self.irq.write_control(value),
+            0xF003 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for VRC4 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.step();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        Some(self.prg_ram.to_vec())
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        w.bytes(&self.prg_ram);
+        if self.chr.ram() {
+            self.chr.save_state(&mut w);
+        }
+        w.mirroring(self.mirroring);
+        w.bytes(&self.prg_registers);
+        w.bool(self.prg_swap);
+        for r in &self.chr_registers {
+            w.u16(*r);
+        }
+        self.irq.save_state(&mut w);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        r.bytes_into(&mut self.prg_ram)?;
+        if self.chr.ram() {
+            self.chr.load_state(&mut r)?;
+        }
+        self.mirroring = r.mirroring()?;
+        r.bytes_into(&mut self.prg_registers)?;
+        self.prg_swap = r.bool()?;
+        for register in self.chr_registers.iter_mut() {
+            *register = r.u16()?;
+        }
+        self.irq.load_state(&mut r)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper(mapper_no: u8, submapper_no: u8) -> VRC4 {
+        // 8KB PRG banks and 1KB CHR banks filled with its bank number
+        let prg = (0..16).flat_map(|n| vec![n; PRG_BANK_SIZE]).collect();
+        let chr = (0..64).flat_map(|n| vec![n; CHR_BANK_SIZE]).collect();
+        VRC4::with_banks(
+            Variant::new(mapper_no, submapper_no),
+            prg,
+            chr,
+            Mirroring::Vertical(),
+        )
+    }
+
+    fn write(m: &mut VRC4, addr: u16, value: u8) {
+        m.write(addr.into(), value.into());
+    }
+
+    fn read(m: &VRC4, addr: u16) -> u8 {
+        m.peek(addr.into()).into()
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut m = mapper(21, 1);
+        write(&mut m, 0x8000, 3);
+        write(&mut m, 0xA000, 5);
+        assert_eq!(read(&m, 0x8000), 3);
+        assert_eq!(read(&m, 0xA000), 5);
+        assert_eq!(read(&m, 0xC000), 14);
+        assert_eq!(read(&m, 0xE000), 15);
+
+        // VRC4a selects $9002 by A2
+        write(&mut m, 0x9004, 0b10);
+        assert_eq!(read(&m, 0x8000), 14);
+        assert_eq!(read(&m, 0xC000), 3);
+    }
+
+    #[test]
+    fn chr_banks() {
+        // VRC4e: A2 and A3
+        let mut m = mapper(23, 2);
+        write(&mut m, 0xC008, 0x0A);
+        write(&mut m, 0xC00C, 0x02);
+        assert_eq!(read(&m, 0x0C00), 0x2A);
+
+        // VRC2a ignores the lowest bit; A1 and A0 are swapped
+        let mut m = mapper(22, 0);
+        write(&mut m, 0xB000, 0x07);
+        assert_eq!(read(&m, 0x0000), 0x03);
+        write(&mut m, 0xB001, 0x07);
+        assert_eq!(read(&m, 0x0400), 0x03);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut m = mapper(25, 0);
+        write(&mut m, 0x9000, 1);
+        assert!(matches!(m.mirroring(), Mirroring::Horizontal()));
+        write(&mut m, 0x9000, 3);
+        assert_eq!(m.mirroring(), Mirroring::SingleScreen(1));
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = mapper(25, 1);
+        // VRC4b: $F001 is A1, $F002 is A0
+        write(&mut m, 0xF000, 0x0E);
+        write(&mut m, 0xF002, 0x0F);
+        write(&mut m, 0xF001, 0b110);
+        m.cpu_cycle();
+        assert!(!m.irq());
+        m.cpu_cycle();
+        assert!(m.irq());
+        write(&mut m, 0xF003, 0);
+        assert!(!m.irq());
+
+        // VRC2 has no IRQ
+        let mut m = mapper(25, 3);
+        write(&mut m, 0xF002, 0b110);
+        for _ in 0..0x200 {
+            m.cpu_cycle();
+        }
+        assert!(!m.irq());
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::nesfile::NESFile;
+use super::vrc_irq::IRQCounter;
+use super::{copy_save_ram, Mapper};
+
+// Konami VRC6 (mapper 24, 26)
+// https://wiki.nesdev.com/w/index.php/VRC6
+//
+// The expansion audio ($9000-$B002) is not emulated.
+pub struct VRC6 {
+    // mapper 26 swaps A0 and A1
+    swapped: bool,
+    prg: Banks,
+    chr: Banks,
+    prg_ram: [u8; 0x2000],
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+
+    banking_mode: u8,
+    chr_registers: [u8; 8],
+
+    irq: IRQCounter,
+}
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+impl VRC6 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        let mut mapper = Self::with_banks(rom.mapper_no() == 26, prg, chr, rom.mirroring());
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    fn with_banks(swapped: bool, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        let mut mapper = Self {
+            swapped,
+            prg: Banks::new(prg, 4, PRG_BANK_SIZE),
+            chr: Banks::new(chr, 8, CHR_BANK_SIZE),
+            prg_ram: [0; 0x2000],
+            prg_ram_enabled: false,
+            mirroring,
+            banking_mode: 0,
+            chr_registers: [0, 1, 2, 3, 4, 5, 6, 7],
+            irq: IRQCounter::new(),
+        };
+        mapper.prg.select(0, 0);
+        mapper.prg.select(1, 1);
+        mapper.prg.select(3, -1);
+        mapper.update_chr_banks();
+        mapper
+    }
+
+    fn update_chr_banks(&mut self) {
+        let r = self.chr_registers;
+        match self.banking_mode & 0b11 {
+            0 => {
+                for (i, bank) in r.iter().enumerate() {
+                    self.chr.select(i, *bank as isize);
+                }
+            }
+            1 => {
+                // 2KB banks, PPU A10 selects the 1KB half
+                for (i, bank) in r.iter().take(4).enumerate() {
+                    self.chr.select(i * 2, (bank & 0xFE) as isize);
+                    self.chr.select(i * 2 + 1, (bank | 1) as isize);
+                }
+            }
+            _ => {
+                for (i, bank) in r.iter().take(4).enumerate() {
+                    self.chr.select(i, *bank as isize);
+                }
+                for (i, bank) in r[4..6].iter().enumerate() {
+                    self.chr.select(4 + i * 2, (bank & 0xFE) as isize);
+                    self.chr.select(5 + i * 2, (bank | 1) as isize);
+                }
+            }
+        }
+    }
+}
+
+impl Memory for VRC6 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        let register = if self.swapped {
+            addr & 0xF000 | (addr & 1) << 1 | (addr >> 1) & 1
+        } else {
+            addr & 0xF003
+        };
+        match addr {
+            0x0000..=0x1FFF => self.chr.write(addr as usize, value),
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.prg_ram[addr as usize - 0x6000] = value;
+            }
+            0x8000..=0xFFFF => match register {
+                0x8000..=0x8003 => {
+                    // 16KB bank
+                    let bank = (value & 0x0F) as isize * 2;
+                    self.prg.select(0, bank);
+                    self.prg.select(1, bank + 1);
+                }
+                0xB003 => {
+                    self.banking_mode = value & 0b11;
+                    self.mirroring = match value >> 2 & 0b11 {
+                        0 => Mirroring::Vertical(),
+                        1 => Mirroring::Horizontal(),
+                        n => Mirroring::SingleScreen(n - 2),
+                    };
+                    self.prg_ram_enabled = value & 0x80 != 0;
+                    self.update_chr_banks();
+                }
+                0xC000..=0xC003 => self.prg.select(2, (value & 0x1F) as isize),
+                0xD000..=0xE003 => {
+                    let i = ((register - 0xD000) >> 10 | register & 0b11) as usize;
+                    self.chr_registers[i] = value;
+                    self.update_chr_banks();
+                }
+                0xF000 => self.irq.write_latch(value),
+                0xF001 => self.irq.write_control(value),
+                0xF002 => self.irq.acknowledge(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for VRC6 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.step();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        Some(self.prg_ram.to_vec())
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.prg.save_state(&mut w);
+        self.chr.save_state(&mut w);
+        w.bytes(&self.prg_ram);
+        w.bool(self.prg_ram_enabled);
+        w.mirroring(self.mirroring);
+        w.u8(self.banking_mode);
+        w.bytes(&self.chr_registers);
+        self.irq.save_state(&mut w);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg.load_state(&mut r)?;
+        self.chr.load_state(&mut r)?;
+        r.bytes_into(&mut self.prg_ram)?;
+        self.prg_ram_enabled = r.bool()?;
+        self.mirroring = r.mirroring()?;
+        self.banking_mode = r.u8()? & 0b11;
+        r.bytes_into(&mut self.chr_registers)?;
+        self.irq.load_state(&mut r)?;
+        self.update_chr_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper(swapped: bool) -> VRC6 {
+        // 8KB PRG banks and 1KB CHR banks filled with its bank number
+        let prg = (0..16).flat_map(|n| vec![n; PRG_BANK_SIZE]).collect();
+        let chr = (0..64).flat_map(|n| vec![n; CHR_BANK_SIZE]).collect();
+        VRC6::with_banks(swapped, prg, chr, Mirroring::Vertical())
+    }
+
+    fn write(m: &mut VRC6, addr: u16, value: u8) {
+        m.write(addr.into(), value.into());
+    }
+
+    fn read(m: &VRC6, addr: u16) -> u8 {
+        m.peek(addr.into()).into()
+    }
+
+    #[test]
+    fn prg_banks() {
+        let mut m = mapper(false);
+        write(&mut m, 0x8000, 3);
+        write(&mut m, 0xC000, 9);
+        assert_eq!(read(&m, 0x8000), 6);
+        assert_eq!(read(&m, 0xA000), 7);
+        assert_eq!(read(&m, 0xC000), 9);
+        assert_eq!(read(&m, 0xE000), 15);
+    }
+
+    #[test]
+    fn chr_banks() {
+        let mut m = mapper(false);
+        write(&mut m, 0xD001, 20);
+        write(&mut m, 0xE002, 30);
+        assert_eq!(read(&m, 0x0400), 20);
+        assert_eq!(read(&m, 0x1800), 30);
+
+        // 2KB banks
+        write(&mut m, 0xB003, 0b01);
+        assert_eq!(read(&m, 0x0800), 20);
+        assert_eq!(read(&m, 0x0C00), 21);
+    }
+
+    #[test]
+    fn swapped_address_lines() {
+        let mut m = mapper(true);
+        // $B003 is at $B003 either way, $D001 is at $D002 on mapper 26
+        write(&mut m, 0xD002, 20);
+        assert_eq!(read(&m, 0x0400), 20);
+
+        write(&mut m, 0xB003, 0x84);
+        assert!(matches!(m.mirroring(), Mirroring::Horizontal()));
+        write(&mut m, 0x6000, 0x55);
+        assert_eq!(read(&m, 0x6000), 0x55);
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = mapper(true);
+        write(&mut m, 0xF000, 0xFE);
+        // $F001 is at $F002 on mapper 26
+        write(&mut m, 0xF002, 0b110);
+        m.cpu_cycle();
+        m.cpu_cycle();
+        assert!(m.irq());
+        write(&mut m, 0xF001, 0);
+        assert!(!m.irq());
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::Result;
+
+use crate::savestate::{StateReader, StateWriter};
+use crate::types::{Byte, Memory, Mirroring, Word};
+
+use super::bank::Banks;
+use super::nesfile::NESFile;
+use super::opll::{CLOCK_DIVIDER, OPLL};
+use super::vrc_irq::IRQCounter;
+use super::{copy_save_ram, Mapper};
+
+// Konami VRC7 (mapper 85)
+// https://wiki.nesdev.com/w/index.php/VRC7
+pub struct VRC7 {
+    // address line selecting the second register of each pair; A4 on VRC7a, A3 on VRC7b
+    a0_mask: u16,
+    prg: Banks,
+    chr: Banks,
+    prg_ram: [u8; 0x2000],
+    prg_ram_enabled: bool,
+    mirroring: Mirroring,
+
+    irq: IRQCounter,
+
+    audio: OPLL,
+    audio_silenced: bool,
+    audio_cycles: u32,
+}
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+impl VRC7 {
+    pub fn new(rom: NESFile) -> Self {
+        let (prg, next) = rom.read_prg_rom(rom.prg_rom_offset(), 0x4000);
+        let chr = if let Some((chr, _)) = rom.read_chr_rom(next, 0x2000) {
+            chr
+        } else {
+            [0; 0x2000].into()
+        };
+        // https://wiki.nesdev.com/w/index.php/NES_2.0_submappers#085:_VRC7
+        let a0_mask = match rom.submapper_no() {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        let mut mapper = Self::with_banks(a0_mask, prg, chr, rom.mirroring());
+        mapper.chr.set_ram(rom.chr_ram());
+        mapper
+    }
+
+    fn with_banks(a0_mask: u16, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        let mut mapper = Self {
+            a0_mask,
+            prg: Banks::new(prg, 4, PRG_BANK_SIZE),
+            chr: Banks::new(chr, 8, CHR_BANK_SIZE),
+            prg_ram: [0; 0x2000],
+            prg_ram_enabled: false,
+            mirroring,
+            irq: IRQCounter::new(),
+            audio: OPLL::new(),
+            audio_silenced: false,
+            audio_cycles: 0,
+        };
+        mapper.prg.select(3, -1);
+        mapper
+    }
+}
+
+impl Memory for VRC7 {
+    fn peek(&self, addr: Word) -> Byte {
+        let addr: u16 = addr.into();
+        match addr {
+            0x0000..=0x1FFF => self.chr.read(addr as usize),
+            0x6000..=0x7FFF if self.prg_ram_enabled => self.prg_ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.prg.read(addr as usize - 0x8000),
+            _ => 0,
+        }
+        .into()
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        let addr: u16 = addr.into();
+        let value: u8 = value.into();
+        let second = addr & self.a0_mask != 0;
+        match addr {
+            0x0000..=0x1FFF => self.chr.write(addr as usize, value),
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.prg_ram[addr as usize - 0x6000] = value;
+            }
+            // audio ports are decoded by A4 and A5
+            0x9000..=0x9FFF if addr & 0x30 == 0x10 => self.audio.write_address(value),
+            0x9000..=0x9FFF if addr & 0x30 == 0x30 => self.audio.write_data(value),
+            0x8000..=0x8FFF => self.prg.select(second as usize, (value & 0x3F) as isize),
+            0x9000..=0x9FFF if !second => self.prg.select(2, (value & 0x3F) as isize),
+            0xA000..=0xDFFF => {
+                let window = ((addr >> 12) - 0xA) as usize * 2 + second as usize;
+                self.chr.select(window, value as isize);
+            }
+            0xE000..=0xEFFF if !second => {
+                self.mirroring = match value & 0b11 {
+                    0 => Mirroring::Vertical(),
+                    1 => Mirroring::Horizontal(),
+                    n => Mirroring::SingleScreen(n - 2),
+                };
+                self.audio_silenced = value & 0x40 != 0;
+                self.prg_ram_enabled = value & 0x80 != 0;
+            }
+            0xE000..=0xEFFF => self.irq.write_latch(value),
+            0xF000..=0xFFFF if !second => self.irq.write_control(value),
+            0xF000..=0xFFFF => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for VRC7 {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.step();
+        self.audio_cycles += 1;
+        if CLOCK_DIVIDER <= self.audio_cycles {
+            self.audio_cycles = 0;
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_silenced {
+            0.0
+        } else {
+            self.audio.output()
+        }
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        Some(self.prg_ram.to_vec())
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        copy_save_ram(&mut self.prg_ram, data);
+    }
+
+    fn save_state(&self) -> Option<Vec<u8>> {
+        let mut w = StateWriter::new();
+        self.prg.save_state(&mut w);
+        self.chr.save_state(&mut w);
+        w.bytes(&self.prg_ram);
+        w.bool(self.prg_ram_enabled);
+        w.mirroring(self.mirroring);
+        self.irq.save_state(&mut w);
+        self.audio.save_state(&mut w);
+        w.bool(self.audio_silenced);
+        w.u32(self.audio_cycles);
+        Some(w.into_bytes())
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = StateReader::new(data);
+        self.prg.load_state(&mut r)?;
+        self.chr.load_state(&mut r)?;
+        r.bytes_into(&mut self.prg_ram)?;
+        self.prg_ram_enabled = r.bool()?;
+        self.mirroring = r.mirroring()?;
+        self.irq.load_state(&mut r)?;
+        self.audio.load_state(&mut r)?;
+        self.audio_silenced = r.bool()?;
+        self.audio_cycles = r.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> VRC7 {
+        // 8KB PRG banks and 1KB CHR banks filled with its bank number
+        let prg = (0..16).flat_map(|n| vec![n; PRG_BANK_SIZE]).collect();
+        let chr = (0..64).flat_map(|n| vec![n; CHR_BANK_SIZE]).collect();
+        VRC7::with_banks(0x18, prg, chr, Mirroring::Vertical())
+    }
+
+    fn write(m: &mut VRC7, addr: u16, value: u8) {
+        m.write(addr.into(), value.into());
+    }
+
+    fn read(m: &VRC7, addr: u16) -> u8 {
+        m.peek(addr.into()).into()
+    }
+
+    #[test]
+    fn banks() {
+        let mut m = mapper();
+        write(&mut m, 0x8000, 3);
+        write(&mut m, 0x8010, 4);
+        write(&mut m, 0x9000, 5);
+        assert_eq!(read(&m, 0x8000), 3);
+        assert_eq!(read(&m, 0xA000), 4);
+        assert_eq!(read(&m, 0xC000), 5);
+        assert_eq!(read(&m, 0xE000), 15);
+
+        // VRC7b selects the second register by A3
+        write(&mut m, 0xB008, 40);
+        assert_eq!(read(&m, 0x0C00), 40);
+    }
+
+    #[test]
+    fn audio() {
+        let mut m = mapper();
+        write(&mut m, 0x9010, 0x30);
+        write(&mut m, 0x9030, 0x30);
+        write(&mut m, 0x9010, 0x10);
+        write(&mut m, 0x9030, 0x22);
+        write(&mut m, 0x9010, 0x20);
+        write(&mut m, 0x9030, 0x19);
+        // the address port does not change PRG banks
+        assert_eq!(read(&m, 0xC000), 2);
+
+        let mut peak = 0f32;
+        for _ in 0..CLOCK_DIVIDER * 1000 {
+            m.cpu_cycle();
+            peak = peak.max(m.audio_output().abs());
+        }
+        assert!(0.0 < peak);
+
+        write(&mut m, 0xE000, 0x40);
+        assert_eq!(m.audio_output(), 0.0);
+    }
+}
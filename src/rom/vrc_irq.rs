@@ -0,0 +1,157 @@
+use std::io;
+
+use crate::savestate::{StateReader, StateWriter};
+
+// Konami VRC IRQ counter, shared by VRC4, VRC6 and VRC7
+// https://wiki.nesdev.com/w/index.php/VRC_IRQ
+pub(super) struct IRQCounter {
+    latch: u8,
+    counter: u8,
+    // prescaler dividing CPU cycles into scanlines (341 dots / 3 dots per cycle)
+    prescaler: i16,
+    enabled: bool,
+    enabled_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl IRQCounter {
+    pub fn new() -> Self {
+        Self {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            enabled: false,
+            enabled_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = self.latch & 0xF0 | value & 0x0F;
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = self.latch & 0x0F | (value & 0x0F) << 4;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.enabled_after_ack = value & 0b001 != 0;
+        self.enabled = value & 0b010 != 0;
+        self.cycle_mode = value & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enabled_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.latch);
+        w.u8(self.counter);
+        w.u16(self.prescaler as u16);
+        w.bool(self.enabled);
+        w.bool(self.enabled_after_ack);
+        w.bool(self.cycle_mode);
+        w.bool(self.pending);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.latch = r.u8()?;
+        self.counter = r.u8()?;
+        self.prescaler = r.u16()? as i16;
+        self.enabled = r.bool()?;
+        self.enabled_after_ack = r.bool()?;
+        self.cycle_mode = r.bool()?;
+        self.pending = r.bool()?;
+        Ok(())
+    }
+
+    // called every CPU cycle
+    pub fn step(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock();
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_mode() {
+        let mut irq = IRQCounter::new();
+        irq.write_latch_low(0xD);
+        irq.write_latch_high(0xF);
+        irq.write_control(0b111);
+
+        irq.step();
+        irq.step();
+        assert!(!irq.pending());
+        irq.step();
+        assert!(irq.pending());
+
+        irq.acknowledge();
+        assert!(!irq.pending());
+        for _ in 0..3 {
+            irq.step();
+        }
+        assert!(irq.pending());
+    }
+
+    #[test]
+    fn scanline_mode() {
+        let mut irq = IRQCounter::new();
+        irq.write_latch_low(0xF);
+        irq.write_latch_high(0xF);
+        irq.write_control(0b010);
+
+        // 113 2/3 CPU cycles per scanline
+        for _ in 0..113 {
+            irq.step();
+        }
+        assert!(!irq.pending());
+        irq.step();
+        assert!(irq.pending());
+
+        // disabled by the acknowledge
+        irq.acknowledge();
+        for _ in 0..1000 {
+            irq.step();
+        }
+        assert!(!irq.pending());
+    }
+}
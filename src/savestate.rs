@@ -0,0 +1,608 @@
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+use crate::cpu::{CPUCycle, CPUSnapshot};
+use crate::interrupt::Interrupt;
+use crate::ppu::PPUSnapshot;
+use crate::screenshot::Screenshot;
+use crate::types::Mirroring;
+
+/// Shrink factor of the thumbnail in a save state, 128x120 from the 256x240 frame.
+pub const SAVE_STATE_THUMBNAIL_SCALE: u32 = 2;
+
+/// Slots of `NES::save_slot`, numbered from 0.
+pub const SAVE_SLOTS: usize = 10;
+
+// "RustNES Save State"
+const MAGIC: [u8; 4] = *b"RNSS";
+// raised only when the sections can no longer be read as before
+const VERSION: u16 = 1;
+// the mapper with 512KB of flashed PRG and its CHR RAM at most
+const MAX_SECTION_LEN: usize = 0x100000;
+
+const META: [u8; 4] = *b"META";
+const CPU: [u8; 4] = *b"CPU ";
+const RAM: [u8; 4] = *b"RAM ";
+const PPU: [u8; 4] = *b"PPU ";
+const APU: [u8; 4] = *b"APU ";
+const MAPPER: [u8; 4] = *b"MAPR";
+const CONTROLLERS: [u8; 4] = *b"CTRL";
+const INTERRUPT: [u8; 4] = *b"INT ";
+const END: [u8; 4] = *b"END ";
+
+/// Information about a save state for a slot picker, stored at the head of the state so it can
+/// be read without the rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveStateMetadata {
+    /// Seconds since the Unix epoch when the state was saved.
+    pub timestamp: u64,
+    pub thumbnail: Screenshot,
+}
+
+/// The state of a `NES` taken by `NES::save_state`: the CPU, PPU, APU, RAM, the mapper with the
+/// RAM of the cartridge, the controllers and the pending interrupts.
+///
+/// `SaveState::write` writes the magic `RNSS`, a little-endian u16 version of the format, the
+/// SHA-1 of the ROM and then the sections of the state, each a 4-byte tag and the u32 length of
+/// its contents, up to the `END ` section. Fields added to the end of the known sections are
+/// skipped, and so are sections of unknown tags starting with a lowercase letter, so that later
+/// versions add to the state without raising the version. A section of an unknown tag starting
+/// with an uppercase letter holds state that cannot be left out, and the state is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub(crate) rom_sha1: [u8; 20],
+    pub(crate) metadata: SaveStateMetadata,
+    pub(crate) cpu: CPUSnapshot,
+    pub(crate) ppu: PPUSnapshot,
+    pub(crate) ram: Vec<u8>,
+    // by `APU::save_state`, `Mapper::save_state` and `ControllerPorts::save_state`
+    pub(crate) apu: Vec<u8>,
+    pub(crate) mapper: Vec<u8>,
+    pub(crate) controllers: Vec<u8>,
+    pub(crate) interrupt: InterruptState,
+}
+
+/// The interrupts pending in a `NES` and the cycles when they were requested.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct InterruptState {
+    pub interrupt: Interrupt,
+    pub cycles: u128,
+    pub reset_requested: CPUCycle,
+    pub nmi_requested: CPUCycle,
+    pub irq_requested: CPUCycle,
+}
+
+impl SaveStateMetadata {
+    /// Metadata of a state saved now.
+    pub(crate) fn now(thumbnail: Screenshot) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            timestamp,
+            thumbnail,
+        }
+    }
+
+    /// Metadata of a state kept in memory only, such as for run-ahead and rewinding, without the
+    /// time and the thumbnail.
+    pub(crate) fn none() -> Self {
+        Self {
+            timestamp: 0,
+            thumbnail: Screenshot {
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+            },
+        }
+    }
+}
+
+impl SaveState {
+    pub fn metadata(&self) -> &SaveStateMetadata {
+        &self.metadata
+    }
+
+    /// `ROM::sha1` of the ROM the state was taken with, all zeros without a ROM.
+    pub fn rom_sha1(&self) -> [u8; 20] {
+        self.rom_sha1
+    }
+
+    /// Reads only the metadata from the head of a state written by `SaveState::write`.
+    pub fn read_metadata<R: Read>(mut r: R) -> io::Result<SaveStateMetadata> {
+        read_header(&mut r)?;
+        let (tag, section) = read_section(&mut r)?;
+        if tag != META {
+            return Err(invalid_data(SaveStateError::MissingSection(META)));
+        }
+        read_metadata_section(&section[..])
+    }
+
+    /// Reads a state written by `SaveState::write`, up to its end so that other data can follow
+    /// it.
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        let rom_sha1 = read_header(&mut r)?;
+        let (mut metadata, mut cpu, mut ram, mut ppu) = (None, None, None, None);
+        let (mut apu, mut mapper, mut controllers, mut interrupt) = (None, None, None, None);
+        loop {
+            let (tag, section) = read_section(&mut r)?;
+            // fields added to the end of a section by later versions are ignored
+            let s = &mut &section[..];
+            match tag {
+                META => metadata = Some(read_metadata_section(s)?),
+                CPU => cpu = Some(read_cpu_section(s)?),
+                RAM => ram = Some(read_bytes(s, 0x0800)?),
+                PPU => ppu = Some(read_ppu_section(s)?),
+                APU => apu = Some(read_bytes(s, MAX_SECTION_LEN)?),
+                MAPPER => mapper = Some(read_bytes(s, MAX_SECTION_LEN)?),
+                CONTROLLERS => controllers = Some(read_bytes(s, MAX_SECTION_LEN)?),
+                INTERRUPT => interrupt = Some(read_interrupt_section(s)?),
+                END => break,
+                // sections added by later versions, optional unless the tag is uppercase
+                _ if tag[0].is_ascii_uppercase() => {
+                    return Err(invalid_data(SaveStateError::UnknownSection(tag)));
+                }
+                _ => {}
+            }
+        }
+        let missing = |tag| invalid_data(SaveStateError::MissingSection(tag));
+        Ok(Self {
+            rom_sha1,
+            metadata: metadata.ok_or_else(|| missing(META))?,
+            cpu: cpu.ok_or_else(|| missing(CPU))?,
+            ppu: ppu.ok_or_else(|| missing(PPU))?,
+            ram: ram.ok_or_else(|| missing(RAM))?,
+            apu: apu.ok_or_else(|| missing(APU))?,
+            mapper: mapper.ok_or_else(|| missing(MAPPER))?,
+            controllers: controllers.ok_or_else(|| missing(CONTROLLERS))?,
+            interrupt: interrupt.ok_or_else(|| missing(INTERRUPT))?,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.rom_sha1)?;
+
+        let mut section = Vec::new();
+        let thumbnail = &self.metadata.thumbnail;
+        section.extend_from_slice(&self.metadata.timestamp.to_le_bytes());
+        section.extend_from_slice(&thumbnail.width.to_le_bytes());
+        section.extend_from_slice(&thumbnail.height.to_le_bytes());
+        section.extend_from_slice(&thumbnail.pixels);
+        write_section(&mut w, META, &mut section)?;
+
+        let cpu = &self.cpu;
+        section.extend_from_slice(&[cpu.a, cpu.x, cpu.y, cpu.s, cpu.p]);
+        section.extend_from_slice(&cpu.pc.to_le_bytes());
+        section.extend_from_slice(&cpu.cycles.to_le_bytes());
+        write_section(&mut w, CPU, &mut section)?;
+
+        write_bytes(&mut section, &self.ram)?;
+        write_section(&mut w, RAM, &mut section)?;
+
+        let ppu = &self.ppu;
+        section.extend_from_slice(&[
+            ppu.controller,
+            ppu.mask,
+            ppu.status,
+            ppu.oam_address,
+            ppu.fine_x,
+            ppu.write_toggle as u8,
+            ppu.read_buffer,
+            ppu.open_bus,
+        ]);
+        for v in &[ppu.v, ppu.t, ppu.line, ppu.dot] {
+            section.extend_from_slice(&v.to_le_bytes());
+        }
+        section.extend_from_slice(&ppu.frames.to_le_bytes());
+        write_bytes(&mut section, &ppu.oam)?;
+        write_bytes(&mut section, &ppu.name_tables)?;
+        write_bytes(&mut section, &ppu.palette)?;
+        write_section(&mut w, PPU, &mut section)?;
+
+        write_bytes(&mut section, &self.apu)?;
+        write_section(&mut w, APU, &mut section)?;
+
+        write_bytes(&mut section, &self.mapper)?;
+        write_section(&mut w, MAPPER, &mut section)?;
+
+        write_bytes(&mut section, &self.controllers)?;
+        write_section(&mut w, CONTROLLERS, &mut section)?;
+
+        let interrupt = &self.interrupt;
+        section.push(interrupt.interrupt.bits());
+        for cycles in &[
+            interrupt.cycles,
+            interrupt.reset_requested,
+            interrupt.nmi_requested,
+            interrupt.irq_requested,
+        ] {
+            section.extend_from_slice(&cycles.to_le_bytes());
+        }
+        write_section(&mut w, INTERRUPT, &mut section)?;
+
+        write_section(&mut w, END, &mut section)
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum SaveStateError {
+    #[error("The file is not a save state")]
+    InvalidMagic,
+    #[error("Save state version {0} is not supported")]
+    UnsupportedVersion(u16),
+    #[error("The save state has no {} section", String::from_utf8_lossy(.0).trim_end())]
+    MissingSection([u8; 4]),
+    #[error("The save state has an unknown {} section", String::from_utf8_lossy(.0).trim_end())]
+    UnknownSection([u8; 4]),
+    #[error("The save state section {} is too long", String::from_utf8_lossy(.0).trim_end())]
+    SectionTooLong([u8; 4]),
+    #[error("The save state was taken with another ROM")]
+    ROMMismatch,
+}
+
+fn invalid_data(e: SaveStateError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// returns the SHA-1 of the ROM
+fn read_header<R: Read>(r: &mut R) -> io::Result<[u8; 20]> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid_data(SaveStateError::InvalidMagic));
+    }
+    let version = read_u16(r)?;
+    if version != VERSION {
+        return Err(invalid_data(SaveStateError::UnsupportedVersion(version)));
+    }
+    let mut rom_sha1 = [0; 20];
+    r.read_exact(&mut rom_sha1)?;
+    Ok(rom_sha1)
+}
+
+fn read_section<R: Read>(r: &mut R) -> io::Result<([u8; 4], Vec<u8>)> {
+    let mut tag = [0; 4];
+    r.read_exact(&mut tag)?;
+    let len = read_u32(r)? as usize;
+    if MAX_SECTION_LEN < len {
+        return Err(invalid_data(SaveStateError::SectionTooLong(tag)));
+    }
+    let mut section = vec![0; len];
+    r.read_exact(&mut section)?;
+    Ok((tag, section))
+}
+
+// writes and clears `section`
+fn write_section<W: Write>(w: &mut W, tag: [u8; 4], section: &mut Vec<u8>) -> io::Result<()> {
+    w.write_all(&tag)?;
+    write_bytes(w, section)?;
+    section.clear();
+    Ok(())
+}
+
+fn read_metadata_section<R: Read>(mut r: R) -> io::Result<SaveStateMetadata> {
+    let timestamp = read_u64(&mut r)?;
+    let width = read_u32(&mut r)?;
+    let height = read_u32(&mut r)?;
+    if 256 < width || 240 < height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid thumbnail size",
+        ));
+    }
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    r.read_exact(&mut pixels)?;
+    Ok(SaveStateMetadata {
+        timestamp,
+        thumbnail: Screenshot {
+            width,
+            height,
+            pixels,
+        },
+    })
+}
+
+fn read_cpu_section<R: Read>(mut r: R) -> io::Result<CPUSnapshot> {
+    let mut regs = [0; 5];
+    r.read_exact(&mut regs)?;
+    let [a, x, y, s, p] = regs;
+    Ok(CPUSnapshot {
+        a,
+        x,
+        y,
+        s,
+        p,
+        pc: read_u16(&mut r)?,
+        cycles: read_u128(&mut r)?,
+    })
+}
+
+fn read_ppu_section<R: Read>(mut r: R) -> io::Result<PPUSnapshot> {
+    let mut regs = [0; 8];
+    r.read_exact(&mut regs)?;
+    let [controller, mask, status, oam_address, fine_x, write_toggle, read_buffer, open_bus] = regs;
+    Ok(PPUSnapshot {
+        controller,
+        mask,
+        status,
+        oam_address,
+        fine_x,
+        write_toggle: write_toggle != 0,
+        read_buffer,
+        open_bus,
+        v: read_u16(&mut r)?,
+        t: read_u16(&mut r)?,
+        line: read_u16(&mut r)?,
+        dot: read_u16(&mut r)?,
+        frames: read_u64(&mut r)?,
+        oam: read_bytes(&mut r, 0x100)?,
+        name_tables: read_bytes(&mut r, 0x1000)?,
+        palette: read_bytes(&mut r, 0x20)?,
+    })
+}
+
+fn read_interrupt_section<R: Read>(mut r: R) -> io::Result<InterruptState> {
+    let mut interrupt = [0; 1];
+    r.read_exact(&mut interrupt)?;
+    Ok(InterruptState {
+        interrupt: Interrupt::from_bits(interrupt[0]),
+        cycles: read_u128(&mut r)?,
+        reset_requested: read_u128(&mut r)?,
+        nmi_requested: read_u128(&mut r)?,
+        irq_requested: read_u128(&mut r)?,
+    })
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn read_u128<R: Read>(r: &mut R) -> io::Result<u128> {
+    let mut b = [0; 16];
+    r.read_exact(&mut b)?;
+    Ok(u128::from_le_bytes(b))
+}
+
+// length-prefixed bytes, up to `max` bytes
+fn read_bytes<R: Read>(r: &mut R, max: usize) -> io::Result<Vec<u8>> {
+    let len = read_u32(r)? as usize;
+    if max < len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "too long"));
+    }
+    let mut b = vec![0; len];
+    r.read_exact(&mut b)?;
+    Ok(b)
+}
+
+fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> io::Result<()> {
+    w.write_all(&(b.len() as u32).to_le_bytes())?;
+    w.write_all(b)
+}
+
+/// Writes the state of a part of the NES in little endian, such as the registers and RAM of a
+/// `Mapper` for `Mapper::save_state`.
+#[derive(Debug, Default)]
+pub struct StateWriter(Vec<u8>);
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.0.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, v: u128) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, v: f32) {
+        self.u32(v.to_bits());
+    }
+
+    /// Writes the length and then the bytes.
+    pub fn bytes(&mut self, b: &[u8]) {
+        self.u32(b.len() as u32);
+        self.0.extend_from_slice(b);
+    }
+
+    pub fn mirroring(&mut self, m: Mirroring) {
+        self.u8(match m {
+            Mirroring::Vertical() => 0,
+            Mirroring::Horizontal() => 1,
+            Mirroring::SingleScreen(page) => 2 + (page & 1),
+            Mirroring::FourScreen() => 4,
+        });
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Reads the state written by `StateWriter` in the same order.
+#[derive(Debug)]
+pub struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        let mut b = [0; 1];
+        self.0.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    pub fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        read_u16(&mut self.0)
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        read_u32(&mut self.0)
+    }
+
+    pub fn u64(&mut self) -> io::Result<u64> {
+        read_u64(&mut self.0)
+    }
+
+    pub fn u128(&mut self) -> io::Result<u128> {
+        read_u128(&mut self.0)
+    }
+
+    pub fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /// Reads the bytes written by `StateWriter::bytes`, up to `max` bytes.
+    pub fn bytes(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        read_bytes(&mut self.0, max)
+    }
+
+    /// Reads the bytes written by `StateWriter::bytes` into `buf`, failing unless they are as
+    /// long as `buf`.
+    pub fn bytes_into(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if read_u32(&mut self.0)? as usize != buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "length mismatch",
+            ));
+        }
+        self.0.read_exact(buf)
+    }
+
+    pub fn mirroring(&mut self) -> io::Result<Mirroring> {
+        Ok(match self.u8()? {
+            0 => Mirroring::Vertical(),
+            1 => Mirroring::Horizontal(),
+            n @ 2..=3 => Mirroring::SingleScreen(n - 2),
+            4 => Mirroring::FourScreen(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid mirroring",
+                ))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::tests::{counter_rom, counter_rom_bytes};
+    use crate::nes::NES;
+    use crate::rom::ROM;
+
+    #[test]
+    fn round_trip() {
+        let mut nes = NES::default();
+        nes.power_on();
+        nes.frame();
+        let state = nes.save_state().unwrap();
+        assert_eq!(state.metadata().thumbnail.width, 128);
+        assert_eq!(state.metadata().thumbnail.height, 120);
+
+        let mut bytes = Vec::new();
+        state.write(&mut bytes).unwrap();
+        assert_eq!(
+            &SaveState::read_metadata(&bytes[..]).unwrap(),
+            state.metadata()
+        );
+        assert_eq!(SaveState::read(&bytes[..]).unwrap(), state);
+        assert!(SaveState::read(&bytes[..bytes.len() - 1]).is_err());
+
+        nes.frame();
+        nes.load_state(&state).unwrap();
+        let mut restored = nes.save_state().unwrap();
+        restored.metadata = state.metadata.clone();
+        assert_eq!(restored, state);
+    }
+
+    #[test]
+    fn format() {
+        let mut nes = NES::default();
+        nes.load(counter_rom()).unwrap();
+        nes.power_on();
+        nes.reset();
+        nes.frame();
+        let state = nes.save_state().unwrap();
+        assert_eq!(state.rom_sha1(), counter_rom().sha1());
+        let mut bytes = Vec::new();
+        state.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..6], b"RNSS\x01\x00");
+
+        // followed by other data
+        let mut r = &[&bytes[..], b"next"].concat()[..];
+        assert_eq!(SaveState::read(&mut r).unwrap(), state);
+        assert_eq!(r, b"next");
+
+        // sections of a later version before the end, skipped only if optional
+        let end = bytes.len() - 8;
+        let later = |tag: &[u8]| {
+            let section = [tag, b"\x02\x00\x00\x00\x01\x02"].concat();
+            [&bytes[..end], &section[..], &bytes[end..]].concat()
+        };
+        assert_eq!(SaveState::read(&later(b"next")[..]).unwrap(), state);
+        assert!(SaveState::read(&later(b"NEXT")[..]).is_err());
+
+        let mut invalid = bytes.clone();
+        invalid[0] = b'X';
+        assert!(SaveState::read(&invalid[..]).is_err());
+        let mut invalid = bytes.clone();
+        invalid[4] = 2;
+        assert!(SaveState::read(&invalid[..]).is_err());
+
+        // refused by another ROM
+        let mut other = NES::default();
+        assert!(other.load_state(&state).is_err());
+        let mut rom = counter_rom_bytes();
+        rom[0x10] = 0xEA;
+        other.load(ROM::from_bytes(&rom).unwrap()).unwrap();
+        other.power_on();
+        let ram = other.ram();
+        assert!(other.load_state(&state).is_err());
+        assert_eq!(other.ram(), ram);
+    }
+}
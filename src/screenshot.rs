@@ -0,0 +1,318 @@
+use std::io::{self, Write};
+
+use crate::nes::NES;
+use crate::palette::Palette;
+use crate::png;
+use crate::ppu::Emphasis;
+use crate::region::Region;
+use crate::rom::ROM;
+
+/// The number of frames to boot a ROM before taking its thumbnail, 3 seconds on NTSC.
+pub const THUMBNAIL_FRAMES: u32 = 180;
+
+/// Pixels cropped from each edge of the frame output, hidden by the bezel of most TVs.
+///
+/// Games often leave garbage in the edges, such as the tiles of a scroll seam.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Overscan {
+    pub const NONE: Self = Self {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+
+    /// The usual overscan of the TVs of `region`: 8 lines at the top and the bottom on NTSC,
+    /// none on PAL, which shows all 240 lines.
+    pub fn for_region(region: Region) -> Self {
+        match region {
+            Region::NTSC => Self {
+                top: 8,
+                bottom: 8,
+                ..Self::NONE
+            },
+            Region::PAL => Self::NONE,
+        }
+    }
+
+    // left, top, width and height left visible in an image of `width` x `height`
+    pub(crate) fn visible_rect(
+        &self,
+        width: u32,
+        height: u32,
+        x_scale: u32,
+    ) -> (u32, u32, u32, u32) {
+        let left = (self.left * x_scale).min(width);
+        let right = (self.right * x_scale).min(width - left);
+        let top = self.top.min(height);
+        let bottom = self.bottom.min(height - top);
+        (left, top, width - left - right, height - top - bottom)
+    }
+}
+
+/// RGBA image of a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA 8 bits per channel, row-major
+    pub pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    pub(crate) fn new(width: u32, height: u32, indices: &[u8], palette: &Palette) -> Self {
+        Self::with_emphasis(width, height, indices, &[], palette)
+    }
+
+    // `emphasis` of each pixel as `Emphasis::bits`, none for pixels beyond
+    pub(crate) fn with_emphasis(
+        width: u32,
+        height: u32,
+        indices: &[u8],
+        emphasis: &[u8],
+        palette: &Palette,
+    ) -> Self {
+        let mut pixels = Vec::with_capacity(indices.len() * 4);
+        for (n, i) in indices.iter().enumerate() {
+            let e = Emphasis::from_bits(emphasis.get(n).copied().unwrap_or(0));
+            let [r, g, b] = palette.rgb_with_emphasis(*i, e);
+            pixels.extend_from_slice(&[r, g, b, 0xFF]);
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Encodes the image in PNG.
+    pub fn write_png<W: Write>(&self, w: W) -> io::Result<()> {
+        png::write_rgba(w, self.width, self.height, &self.pixels)
+    }
+
+    /// Crops `overscan` from the edges, scaling the left and right by `x_scale` for images
+    /// wider than the PPU output.
+    pub fn crop(&self, overscan: Overscan, x_scale: u32) -> Self {
+        let (left, top, width, height) = overscan.visible_rect(self.width, self.height, x_scale);
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in top..top + height {
+            let i = ((y * self.width + left) * 4) as usize;
+            pixels.extend_from_slice(&self.pixels[i..i + (width * 4) as usize]);
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// 64-bit FNV-1a hash of the size and pixels, stable across platforms and releases, for
+    /// asserting pixel-exact output in tests without storing images.
+    pub fn hash(&self) -> u64 {
+        let mut h = Fnv1a::new();
+        h.write(&self.width.to_le_bytes());
+        h.write(&self.height.to_le_bytes());
+        h.write(&self.pixels);
+        h.finish()
+    }
+
+    /// Compares with `other` pixel by pixel, `None` if they are identical.
+    ///
+    /// Images of different sizes differ in all the pixels of the larger one.
+    pub fn diff(&self, other: &Screenshot) -> Option<FrameDiff> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Some(FrameDiff {
+                pixels: (self.width.max(other.width) * self.height.max(other.height)) as usize,
+                left: 0,
+                top: 0,
+                right: self.width.max(other.width),
+                bottom: self.height.max(other.height),
+            });
+        }
+        let mut diff: Option<FrameDiff> = None;
+        let pairs = self.pixels.chunks(4).zip(other.pixels.chunks(4));
+        for (i, _) in pairs.enumerate().filter(|(_, (a, b))| a != b) {
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            let d = diff.get_or_insert(FrameDiff {
+                pixels: 0,
+                left: x,
+                top: y,
+                right: x + 1,
+                bottom: y + 1,
+            });
+            d.pixels += 1;
+            d.left = d.left.min(x);
+            d.right = d.right.max(x + 1);
+            d.bottom = y + 1;
+        }
+        diff
+    }
+
+    /// Draws `other` over this image with the opacity `alpha` (0.0-1.0).
+    pub fn blend(&mut self, other: &Screenshot, alpha: f32) {
+        let alpha = alpha.clamp(0.0, 1.0);
+        for (p, o) in self.pixels.iter_mut().zip(other.pixels.iter()) {
+            *p = (*p as f32 * (1.0 - alpha) + *o as f32 * alpha).round() as u8;
+        }
+    }
+
+    /// Shrinks the image by averaging each `factor` x `factor` block.
+    pub fn downscale(&self, factor: u32) -> Self {
+        let factor = factor.max(1);
+        let width = self.width / factor;
+        let height = self.height / factor;
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 4];
+                for dy in 0..factor {
+                    for dx in 0..factor {
+                        let i = (((y * factor + dy) * self.width + x * factor + dx) * 4) as usize;
+                        for (s, p) in sum.iter_mut().zip(&self.pixels[i..i + 4]) {
+                            *s += *p as u32;
+                        }
+                    }
+                }
+                pixels.extend(sum.iter().map(|s| (s / (factor * factor)) as u8));
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Differences between two images found by `Screenshot::diff`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameDiff {
+    /// The number of pixels that differ.
+    pub pixels: usize,
+    /// Bounding box of the pixels that differ, exclusive of `right` and `bottom`.
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+// http://www.isthe.com/chongo/tech/comp/fnv/
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Boots the ROM headlessly for `frames` frames and takes a screenshot, for previews in ROM browsers.
+pub fn thumbnail(rom: ROM, frames: u32) -> Screenshot {
+    let mut nes = NES::default();
+    nes.load(rom)
+        .expect("a new NES has no cartridge to write the save data of");
+    nes.power_on();
+    nes.reset();
+    for _ in 0..frames {
+        nes.frame();
+    }
+    nes.screenshot()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscale() {
+        let palette = Palette::default();
+        let s = Screenshot::new(2, 2, &[0x30, 0x0F, 0x0F, 0x30], &palette);
+        assert_eq!(s.pixels.len(), 16);
+        assert_eq!(&s.pixels[0..4], &[0xFF, 0xFE, 0xFF, 0xFF]);
+
+        let s = s.downscale(2);
+        assert_eq!((s.width, s.height), (1, 1));
+        assert_eq!(s.pixels, vec![0x7F, 0x7F, 0x7F, 0xFF]);
+    }
+
+    #[test]
+    fn crop() {
+        let indices: Vec<u8> = (0..12).collect();
+        let s = Screenshot::new(4, 3, &indices, &Palette::default());
+        let overscan = Overscan {
+            top: 1,
+            left: 1,
+            right: 2,
+            ..Overscan::NONE
+        };
+        let cropped = s.crop(overscan, 1);
+        assert_eq!((cropped.width, cropped.height), (1, 2));
+        assert_eq!(&cropped.pixels[..4], &s.pixels[(5 * 4)..(6 * 4)]);
+
+        let cropped = s.crop(overscan, 2);
+        assert_eq!((cropped.width, cropped.height), (0, 2));
+        assert_eq!(s.crop(Overscan::for_region(Region::PAL), 1), s);
+    }
+
+    #[test]
+    fn hash() {
+        let mut h = Fnv1a::new();
+        h.write(b"a");
+        assert_eq!(h.finish(), 0xAF63_DC4C_8601_EC8C);
+
+        let palette = Palette::default();
+        let a = Screenshot::new(2, 2, &[0x0F; 4], &palette);
+        let mut b = a.clone();
+        assert_eq!(a.hash(), b.hash());
+        assert_eq!(a.diff(&b), None);
+
+        b.pixels[3 * 4] = 0xFF;
+        assert_ne!(a.hash(), b.hash());
+        let expected = FrameDiff {
+            pixels: 1,
+            left: 1,
+            top: 1,
+            right: 2,
+            bottom: 2,
+        };
+        assert_eq!(a.diff(&b), Some(expected));
+        assert_eq!(
+            a.diff(&a.crop(
+                Overscan {
+                    top: 1,
+                    ..Overscan::NONE
+                },
+                1
+            ))
+            .map(|d| d.pixels),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn blend() {
+        let palette = Palette::default();
+        let mut s = Screenshot::new(1, 1, &[0x0F], &palette);
+        s.blend(&Screenshot::new(1, 1, &[0x30], &palette), 0.25);
+        assert_eq!(s.pixels, vec![0x40, 0x40, 0x40, 0xFF]);
+    }
+}
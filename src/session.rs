@@ -0,0 +1,232 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::nes::{Config, Frame, NES};
+use crate::rom::ROM;
+use crate::savestate::SaveState;
+
+/// The number of ROMs kept in the recent list.
+pub const RECENT_ROMS: usize = 10;
+
+/// Lifecycle of ROMs played on a `NES`, shared by frontends.
+///
+/// Files are kept in the data directory:
+///
+/// - `recent.txt`: paths of the recently opened ROMs, the latest first
+/// - `configs/<ROM name>.toml`: `Config` of each game, in TOML by serde
+/// - `autosaves/<ROM name>.state`: the state saved periodically and on close
+///
+/// The save data of the cartridge is flushed when the ROM is closed, including on drop.
+///
+/// Only with the `serde` feature.
+pub struct Session {
+    nes: NES,
+    dir: PathBuf,
+    rom_path: Option<PathBuf>,
+    recent: Vec<PathBuf>,
+
+    autosave_interval: Option<u32>,
+    frames_since_autosave: u32,
+}
+
+impl Session {
+    /// Starts a session with the data directory `dir`, created if missing.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(dir.join("configs"))?;
+        fs::create_dir_all(dir.join("autosaves"))?;
+        let recent = match fs::read_to_string(dir.join("recent.txt")) {
+            Ok(s) => s
+                .lines()
+                .filter(|l| !l.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Ok(Self {
+            nes: NES::default(),
+            dir,
+            rom_path: None,
+            recent,
+            autosave_interval: None,
+            frames_since_autosave: 0,
+        })
+    }
+
+    pub fn nes(&self) -> &NES {
+        &self.nes
+    }
+
+    pub fn nes_mut(&mut self) -> &mut NES {
+        &mut self.nes
+    }
+
+    /// Path of the active ROM.
+    pub fn rom_path(&self) -> Option<&Path> {
+        self.rom_path.as_deref()
+    }
+
+    pub fn recent_roms(&self) -> &[PathBuf] {
+        &self.recent
+    }
+
+    /// Saves the state every `frames` frames run by `Session::frame`, or never with `None`.
+    pub fn set_autosave_interval(&mut self, frames: Option<u32>) {
+        self.autosave_interval = frames;
+        self.frames_since_autosave = 0;
+    }
+
+    /// Closes the active ROM, then loads `path` with its config and powers on. Fails if the config
+    /// cannot be read.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.close()?;
+
+        let path = path.as_ref().to_path_buf();
+        let rom = ROM::load(&path)?;
+        let config_path = self.file_path(&path, "configs", "toml");
+        let config = match fs::read_to_string(&config_path) {
+            Ok(s) => toml::from_str(&s)
+                .with_context(|| format!("Failed to read config: {}", config_path.display()))?,
+            Err(_) => Config::default(),
+        };
+        self.nes = NES::default();
+        self.nes.apply_config(&config);
+        self.nes.load(rom)?;
+        self.nes.power_on();
+        self.nes.reset();
+        self.frames_since_autosave = 0;
+
+        self.recent.retain(|p| *p != path);
+        self.recent.insert(0, path.clone());
+        self.recent.truncate(RECENT_ROMS);
+        self.rom_path = Some(path);
+        self.write_recent()
+    }
+
+    /// Writes the config of the active ROM, loaded by the next `Session::open` of it.
+    pub fn save_config(&self) -> Result<()> {
+        if let Some(path) = &self.rom_path {
+            let config = toml::to_string(&self.nes.config())?;
+            fs::write(self.file_path(path, "configs", "toml"), config)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a frame, saving the state if the autosave interval has passed.
+    pub fn frame(&mut self) -> Result<Frame> {
+        let frame = self.nes.frame();
+        if let Some(interval) = self.autosave_interval {
+            self.frames_since_autosave += 1;
+            if interval <= self.frames_since_autosave {
+                self.autosave()?;
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Saves the state of the active ROM to its autosave file.
+    pub fn autosave(&mut self) -> Result<()> {
+        self.frames_since_autosave = 0;
+        if let Some(path) = &self.rom_path {
+            let path = self.file_path(path, "autosaves", "state");
+            // written aside first so that the last autosave stays whole until replaced
+            let temp = path.with_extension("state.tmp");
+            let mut w = BufWriter::new(File::create(&temp)?);
+            self.nes.save_state()?.write(&mut w)?;
+            w.flush()?;
+            fs::rename(&temp, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the autosave of the active ROM. Returns false if there is none.
+    pub fn resume(&mut self) -> Result<bool> {
+        let path = match &self.rom_path {
+            Some(path) => self.file_path(path, "autosaves", "state"),
+            None => return Ok(false),
+        };
+        if !path.exists() {
+            return Ok(false);
+        }
+        let state = SaveState::read(BufReader::new(File::open(path)?))?;
+        self.nes.load_state(&state)?;
+        Ok(true)
+    }
+
+    /// Autosaves and flushes the save data of the active ROM, if any.
+    pub fn close(&mut self) -> Result<()> {
+        if self.rom_path.is_none() {
+            return Ok(());
+        }
+        if self.autosave_interval.is_some() {
+            self.autosave()?;
+        }
+        self.nes.flush_save_data()?;
+        self.rom_path = None;
+        Ok(())
+    }
+
+    fn file_path(&self, rom_path: &Path, dir: &str, extension: &str) -> PathBuf {
+        let name = rom_path.file_stem().unwrap_or_default();
+        self.dir.join(dir).join(name).with_extension(extension)
+    }
+
+    fn write_recent(&self) -> Result<()> {
+        let lines: Vec<String> = self
+            .recent
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        fs::write(self.dir.join("recent.txt"), lines.join("\n"))?;
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::tests::counter_rom_bytes;
+    use crate::region::Region;
+
+    #[test]
+    fn lifecycle() {
+        let dir = std::env::temp_dir().join(format!("rustnes-session-{}", std::process::id()));
+        let rom_path = dir.join("counter.nes");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&rom_path, counter_rom_bytes()).unwrap();
+
+        {
+            let mut session = Session::new(&dir).unwrap();
+            session.open(&rom_path).unwrap();
+            session.nes_mut().set_region(Region::PAL);
+            session.save_config().unwrap();
+            session.set_autosave_interval(Some(2));
+            session.frame().unwrap();
+            session.frame().unwrap();
+            assert!(dir.join("autosaves/counter.state").exists());
+            assert!(!dir.join("autosaves/counter.state.tmp").exists());
+        }
+
+        let mut session = Session::new(&dir).unwrap();
+        assert_eq!(session.recent_roms(), std::slice::from_ref(&rom_path));
+        session.open(&rom_path).unwrap();
+        assert_eq!(session.nes().region(), Region::PAL);
+        assert!(session.resume().unwrap());
+        session.close().unwrap();
+
+        fs::write(dir.join("configs/counter.toml"), "region = \"secam\"").unwrap();
+        assert!(session.open(&rom_path).is_err());
+        drop(session);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use crate::region::Region;
+
+// period the stats are averaged over
+const WINDOW: Duration = Duration::from_millis(500);
+
+/// Performance of the emulation, measured over the frames run by `NES::frame` in the last half
+/// second or so.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Stats {
+    /// Frames run per second of real time.
+    pub fps: f64,
+    /// Speed of the emulation against the console, 1.0 in real time and 2.0 twice as fast.
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StatsCounter {
+    stats: Stats,
+    window_start: Option<Instant>,
+    frames: u32,
+}
+
+impl StatsCounter {
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn record_frame(&mut self, now: Instant, region: Region) {
+        let start = match self.window_start {
+            Some(start) => start,
+            None => {
+                self.window_start = Some(now);
+                return;
+            }
+        };
+        self.frames += 1;
+        let elapsed = now - start;
+        if elapsed < WINDOW {
+            return;
+        }
+        let (num, den) = region.frame_rate();
+        let fps = f64::from(self.frames) / elapsed.as_secs_f64();
+        self.stats = Stats {
+            fps,
+            speed: fps * f64::from(den) / f64::from(num),
+        };
+        self.window_start = Some(now);
+        self.frames = 0;
+    }
+
+    /// Starts measuring again, such as after pausing.
+    pub fn restart(&mut self) {
+        self.window_start = None;
+        self.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_frame() {
+        let mut counter = StatsCounter::default();
+        let start = Instant::now();
+        let period = Duration::from_micros(16_639);
+        for i in 0..=31 {
+            counter.record_frame(start + period * i, Region::NTSC);
+        }
+        let stats = counter.stats();
+        assert!((stats.fps - 60.1).abs() < 0.01, "{:?}", stats);
+        assert!((stats.speed - 1.0).abs() < 0.001, "{:?}", stats);
+
+        // twice as fast on PAL
+        counter.restart();
+        let start = start + Duration::from_secs(1);
+        for i in 0..=50 {
+            counter.record_frame(start + Duration::from_millis(10) * i, Region::PAL);
+        }
+        let stats = counter.stats();
+        assert!((stats.fps - 100.0).abs() < 0.01, "{:?}", stats);
+        assert!((stats.speed - 2.0).abs() < 0.001, "{:?}", stats);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cpu::Trace;
+
+/// Line format of a `TraceLog`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// Same as nestest.log
+    #[default]
+    Nestest,
+    /// A JSON object per line
+    Json,
+}
+
+/// Writes CPU traces to a file, rotating it by size.
+///
+/// When the file reaches `limit` bytes, it is renamed to `<path>.1` (replacing the older one) and
+/// a new file is started, so at most about twice the limit is kept on disk.
+pub struct TraceLog {
+    path: PathBuf,
+    limit: u64,
+    format: TraceFormat,
+
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl TraceLog {
+    pub fn create<P: AsRef<Path>>(path: P, limit: u64, format: TraceFormat) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            limit,
+            format,
+            file,
+            written: 0,
+        })
+    }
+
+    pub fn write(&mut self, trace: &Trace) -> io::Result<()> {
+        let line = match self.format {
+            TraceFormat::Nestest => trace.to_string(),
+            TraceFormat::Json => trace.to_json(),
+        };
+        let len = line.len() as u64 + 1;
+        if 0 < self.written && self.limit < self.written + len {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += len;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Parses a size like `100M` with an optional K, M or G suffix (powers of 1024).
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (digits, unit) = match s.chars().last()?.to_ascii_uppercase() {
+        'K' => (&s[..s.len() - 1], 1 << 10),
+        'M' => (&s[..s.len() - 1], 1 << 20),
+        'G' => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("4k"), Some(4096));
+        assert_eq!(parse_size("100M"), Some(100 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("1T"), None);
+    }
+
+    #[test]
+    fn rotation() {
+        let trace = Trace::trace(&CPU::default(), &[0u8; 0x10000]);
+        let line = trace.to_string().len() as u64 + 1;
+
+        let path = std::env::temp_dir().join(format!("rustnes-trace-{}.log", std::process::id()));
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+
+        let mut log = TraceLog::create(&path, line * 3, TraceFormat::Nestest).unwrap();
+        for _ in 0..5 {
+            log.write(&trace).unwrap();
+        }
+        log.flush().unwrap();
+
+        assert_eq!(fs::metadata(&rotated).unwrap().len(), line * 3);
+        assert_eq!(fs::metadata(&path).unwrap().len(), line * 2);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+}
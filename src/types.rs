@@ -1,15 +1,57 @@
 use std::cmp::Ordering;
 use std::ops;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Mirroring {
     Vertical(),
     Horizontal(),
-}
-
+    /// All nametables map to the page (0 or 1) of the VRAM.
+    SingleScreen(u8),
+    /// 4KB VRAM with the extra 2KB on the cartridge, no mirroring.
+    FourScreen(),
+}
+
+/// Address space seen from the CPU, PPU or a cartridge.
+///
+/// `Mapped`, `Mirrored`, `RamRegion` and `RomRegion` compose into a custom bus without
+/// match-based routing:
+///
+/// ```
+/// use rustnes::{Mapped, Memory, Mirrored, RamRegion, RomRegion};
+///
+/// let mut bus = Mapped::new()
+///     // 2KB internal RAM mirrored up to $1FFF
+///     .map(0x0000..=0x1FFF, Mirrored::new(RamRegion::new(0x0800), 0x0800))
+///     .map(0x8000..=0xFFFF, RomRegion::new(vec![0xEA; 0x8000]));
+///
+/// bus.write(0x0001u16.into(), 0x42u8.into());
+/// assert_eq!(bus.read(0x0801u16.into()), 0x42u8.into());
+/// assert_eq!(bus.read(0x8000u16.into()), 0xEAu8.into());
+/// ```
 pub trait Memory {
-    fn read(&self, addr: Word) -> Byte;
+    /// Reads without side effects, for debuggers and inspection.
+    fn peek(&self, addr: Word) -> Byte;
     fn write(&mut self, addr: Word, value: Byte);
+
+    /// Reads as the CPU or PPU does, with the side effects on the devices such as clearing a
+    /// status flag or shifting a controller register. Same as `peek` by default.
+    fn read(&mut self, addr: Word) -> Byte {
+        self.peek(addr)
+    }
+}
+
+impl<M: Memory + ?Sized> Memory for Box<M> {
+    fn peek(&self, addr: Word) -> Byte {
+        (**self).peek(addr)
+    }
+
+    fn write(&mut self, addr: Word, value: Byte) {
+        (**self).write(addr, value)
+    }
+
+    fn read(&mut self, addr: Word) -> Byte {
+        (**self).read(addr)
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
@@ -0,0 +1,215 @@
+use crate::screenshot::Screenshot;
+
+const WORLD_WIDTH: i64 = 512;
+const WORLD_HEIGHT: i64 = 480;
+
+/// Experimental canvas stitching frames by their scroll positions into a map of the level,
+/// like wideNES.
+///
+/// The scroll is sampled at a reference line each frame, and its delta from the last frame moves
+/// the position on the canvas, which grows to contain every frame drawn.
+/// Status bars, which do not scroll with the level, can be cropped out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WideCanvas {
+    reference_line: u16,
+    crop_top: u32,
+    crop_bottom: u32,
+
+    last_scroll: Option<(u16, u16)>,
+    // position of the current frame in the world, relative to the first frame
+    x: i64,
+    y: i64,
+
+    // world position of the top-left pixel of the canvas
+    left: i64,
+    top: i64,
+    image: Screenshot,
+}
+
+impl Default for WideCanvas {
+    fn default() -> Self {
+        Self {
+            reference_line: 120,
+            crop_top: 0,
+            crop_bottom: 0,
+            last_scroll: None,
+            x: 0,
+            y: 0,
+            left: 0,
+            top: 0,
+            image: Screenshot {
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+            },
+        }
+    }
+}
+
+impl WideCanvas {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the scanline whose scroll positions the frame, below the status bar if any.
+    pub fn set_reference_line(&mut self, line: u16) {
+        self.reference_line = line;
+    }
+
+    pub fn reference_line(&self) -> u16 {
+        self.reference_line
+    }
+
+    /// Excludes `top` and `bottom` lines of each frame from the canvas.
+    pub fn set_crop(&mut self, top: u32, bottom: u32) {
+        self.crop_top = top;
+        self.crop_bottom = bottom;
+    }
+
+    /// The stitched image so far. Pixels not drawn by any frame are transparent.
+    pub fn image(&self) -> &Screenshot {
+        &self.image
+    }
+
+    /// Forgets the stitched image, to start another map e.g. on a room change.
+    pub fn clear(&mut self) {
+        *self = Self {
+            reference_line: self.reference_line,
+            crop_top: self.crop_top,
+            crop_bottom: self.crop_bottom,
+            ..Default::default()
+        };
+    }
+
+    /// Draws `frame` scrolled to `scroll` in the 512x480 space of the four nametables.
+    pub fn update(&mut self, scroll: (u16, u16), frame: &Screenshot) {
+        if let Some((last_x, last_y)) = self.last_scroll {
+            self.x += wrapped_delta(scroll.0 as i64 - last_x as i64, WORLD_WIDTH);
+            self.y += wrapped_delta(scroll.1 as i64 - last_y as i64, WORLD_HEIGHT);
+        }
+        self.last_scroll = Some(scroll);
+
+        let first_row = self.crop_top.min(frame.height);
+        let last_row = frame.height.saturating_sub(self.crop_bottom).max(first_row);
+        if first_row == last_row {
+            return;
+        }
+        self.grow(
+            self.x,
+            self.y + first_row as i64,
+            self.x + frame.width as i64,
+            self.y + last_row as i64,
+        );
+
+        let row_len = frame.width as usize * 4;
+        for row in first_row..last_row {
+            let src = row as usize * row_len;
+            let dst_x = (self.x - self.left) as usize;
+            let dst_y = (self.y + row as i64 - self.top) as usize;
+            let dst = (dst_y * self.image.width as usize + dst_x) * 4;
+            self.image.pixels[dst..dst + row_len]
+                .copy_from_slice(&frame.pixels[src..src + row_len]);
+        }
+    }
+
+    // Extends the canvas to contain the rectangle in world coordinates.
+    fn grow(&mut self, left: i64, top: i64, right: i64, bottom: i64) {
+        let (width, height) = (self.image.width as i64, self.image.height as i64);
+        if width != 0
+            && self.left <= left
+            && self.top <= top
+            && right <= self.left + width
+            && bottom <= self.top + height
+        {
+            return;
+        }
+        let (new_left, new_top, new_right, new_bottom) = if width == 0 {
+            (left, top, right, bottom)
+        } else {
+            (
+                self.left.min(left),
+                self.top.min(top),
+                (self.left + width).max(right),
+                (self.top + height).max(bottom),
+            )
+        };
+
+        let new_width = (new_right - new_left) as usize;
+        let mut pixels = vec![0; new_width * (new_bottom - new_top) as usize * 4];
+        let row_len = width as usize * 4;
+        for row in 0..height {
+            let src = row as usize * row_len;
+            let dst_y = (self.top + row - new_top) as usize;
+            let dst = (dst_y * new_width + (self.left - new_left) as usize) * 4;
+            pixels[dst..dst + row_len].copy_from_slice(&self.image.pixels[src..src + row_len]);
+        }
+
+        self.left = new_left;
+        self.top = new_top;
+        self.image = Screenshot {
+            width: new_width as u32,
+            height: (new_bottom - new_top) as u32,
+            pixels,
+        };
+    }
+}
+
+// The shortest move between two positions in the wrapping space of `size`.
+fn wrapped_delta(delta: i64, size: i64) -> i64 {
+    let d = delta.rem_euclid(size);
+    if size / 2 <= d {
+        d - size
+    } else {
+        d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(value: u8) -> Screenshot {
+        Screenshot {
+            width: 4,
+            height: 2,
+            pixels: vec![value; 4 * 2 * 4],
+        }
+    }
+
+    fn pixel(canvas: &WideCanvas, x: u32, y: u32) -> u8 {
+        canvas.image().pixels[((y * canvas.image().width + x) * 4) as usize]
+    }
+
+    #[test]
+    fn stitch() {
+        let mut canvas = WideCanvas::new();
+        canvas.update((510, 0), &frame(1));
+        assert_eq!((canvas.image().width, canvas.image().height), (4, 2));
+
+        // scrolled right across the wrap-around
+        canvas.update((2, 0), &frame(2));
+        assert_eq!((canvas.image().width, canvas.image().height), (8, 2));
+        assert_eq!(pixel(&canvas, 3, 0), 1);
+        assert_eq!(pixel(&canvas, 4, 0), 2);
+
+        // scrolled up
+        canvas.update((2, 479), &frame(3));
+        assert_eq!((canvas.image().width, canvas.image().height), (8, 3));
+        assert_eq!(pixel(&canvas, 4, 0), 3);
+        assert_eq!(pixel(&canvas, 0, 0), 0);
+        assert_eq!(pixel(&canvas, 0, 1), 1);
+
+        canvas.clear();
+        canvas.set_crop(1, 0);
+        canvas.update((0, 0), &frame(4));
+        assert_eq!((canvas.image().width, canvas.image().height), (4, 1));
+    }
+
+    #[test]
+    fn delta() {
+        assert_eq!(wrapped_delta(2 - 510, 512), 4);
+        assert_eq!(wrapped_delta(510 - 2, 512), -4);
+        assert_eq!(wrapped_delta(479, 480), -1);
+        assert_eq!(wrapped_delta(0, 480), 0);
+    }
+}